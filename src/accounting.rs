@@ -0,0 +1,142 @@
+//! Accounting of the traffic of each address of a network, e.g. for captive portals and billing.
+
+use crate::builder::RuleBuilder;
+use crate::parse::Counters;
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+use std::net::IpAddr;
+
+/// Largest number of addresses accounted in a single chain, i.e. a /16 IPv4 network.
+pub const MAX_ADDRESSES: u32 = 65536;
+
+/// A chain counting the traffic sent and received by each address of a network, with two
+/// counting rules (without a target) for each address.
+///
+/// Packets reach the chain through a jump to it (e.g. from FORWARD); they all fall through the
+/// chain, which only updates the counters.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PerIpAccounting {
+    /// Table of the chain.
+    pub table: String,
+
+    /// Name of the chain, 'ACCOUNTING' by default.
+    pub chain: String,
+
+    /// Accounted addresses, in the order of their rules.
+    pub addresses: Vec<IpAddr>,
+}
+
+/// Traffic of an address, as reported by `IPTables::accounting_usage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpUsage {
+    /// The accounted address.
+    pub address: IpAddr,
+
+    /// Packets sent by the address.
+    pub sent: Counters,
+
+    /// Packets received by the address.
+    pub received: Counters,
+}
+
+/// Creates the accounting of every address of the network `cidr` (e.g. '192.168.1.0/24') in
+/// the `table`. The network can't have more than `MAX_ADDRESSES` addresses.
+pub fn per_ip(table: &str, cidr: &str) -> Result<PerIpAccounting, Box<dyn Error>> {
+    Ok(PerIpAccounting {
+        table: table.to_string(),
+        chain: "ACCOUNTING".to_string(),
+        addresses: network_addresses(cidr)?,
+    })
+}
+
+impl PerIpAccounting {
+    /// Returns the rules of the chain, counting the packets sent then received by each
+    /// address.
+    pub fn rules(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut rules = Vec::with_capacity(self.addresses.len() * 2);
+        for address in &self.addresses {
+            let address = address.to_string();
+            rules.push(RuleBuilder::new().source(&address).build()?);
+            rules.push(RuleBuilder::new().destination(&address).build()?);
+        }
+        Ok(rules)
+    }
+}
+
+impl IPTables {
+    /// Atomically replaces the rules of the accounting chain, which is created if it does not
+    /// exist yet. The counters of all the addresses start from zero again.
+    pub fn install_accounting(&self, accounting: &PerIpAccounting) -> Result<(), Box<dyn Error>> {
+        self.replace_chain_rules(&accounting.table, &accounting.chain, &accounting.rules()?)
+    }
+
+    /// Returns the traffic of each address of the accounting chain.
+    pub fn accounting_usage(
+        &self,
+        accounting: &PerIpAccounting,
+    ) -> Result<Vec<IpUsage>, Box<dyn Error>> {
+        let counters = self.counters(&accounting.table, &accounting.chain)?;
+        if counters.len() != accounting.addresses.len() * 2 {
+            return Err(error_from_str(&format!(
+                "chain {} has {} rules instead of {}",
+                accounting.chain,
+                counters.len(),
+                accounting.addresses.len() * 2
+            )));
+        }
+        Ok(accounting
+            .addresses
+            .iter()
+            .zip(counters.chunks(2))
+            .map(|(address, counters)| IpUsage {
+                address: *address,
+                sent: counters[0],
+                received: counters[1],
+            })
+            .collect())
+    }
+}
+
+/// Returns the addresses of the network `cidr`, in ascending order.
+fn network_addresses(cidr: &str) -> Result<Vec<IpAddr>, Box<dyn Error>> {
+    let invalid = || error_from_str(&format!("invalid network {}", cidr));
+    let (address, prefix) = match cidr.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (cidr, None),
+    };
+    let address: IpAddr = address.parse().map_err(|_| invalid())?;
+    let bits = match address {
+        IpAddr::V4(_) => 32,
+        IpAddr::V6(_) => 128,
+    };
+    let prefix: u32 = match prefix {
+        Some(prefix) => prefix.parse().map_err(|_| invalid())?,
+        None => bits,
+    };
+    if prefix > bits {
+        return Err(invalid());
+    }
+    if bits - prefix > MAX_ADDRESSES.trailing_zeros() {
+        return Err(error_from_str(&format!(
+            "network {} has more than {} addresses",
+            cidr, MAX_ADDRESSES
+        )));
+    }
+
+    let host_mask = (1u128 << (bits - prefix)) - 1;
+    let addresses = match address {
+        IpAddr::V4(address) => {
+            let first = u32::from(address) as u128 & !host_mask;
+            (first..=first | host_mask)
+                .map(|a| IpAddr::from((a as u32).to_be_bytes()))
+                .collect()
+        }
+        IpAddr::V6(address) => {
+            let first = u128::from(address) & !host_mask;
+            (first..=first | host_mask)
+                .map(|a| IpAddr::from(a.to_be_bytes()))
+                .collect()
+        }
+    };
+    Ok(addresses)
+}
@@ -0,0 +1,296 @@
+//! Backends which execute the commands of the iptables utilities.
+
+use std::error::Error;
+use std::ffi::OsString;
+use std::panic::RefUnwindSafe;
+use std::process::Output;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+#[cfg(feature = "test-backend")]
+use std::collections::VecDeque;
+#[cfg(feature = "test-backend")]
+use std::process::ExitStatus;
+
+#[cfg(target_os = "linux")]
+use crate::error::IPTError;
+#[cfg(target_os = "linux")]
+use std::io::{self, Read, Write};
+#[cfg(target_os = "linux")]
+use std::process::{Command, Stdio};
+#[cfg(target_os = "linux")]
+use std::thread;
+#[cfg(target_os = "linux")]
+use std::time::Instant;
+
+/// Executes the commands of the iptables utilities on behalf of `IPTables`.
+pub trait Backend: Send + Sync + RefUnwindSafe {
+    /// Runs `program` with `args`, writing `input` to its standard input if given.
+    /// The command must be killed if it does not finish within `timeout`.
+    fn run(
+        &self,
+        program: &str,
+        args: &[OsString],
+        input: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>>;
+
+    /// Returns true if the commands are spawned on this system, in its network namespace, so
+    /// that other programs (e.g. `nft monitor`) may be spawned alongside them.
+    fn is_local(&self) -> bool {
+        false
+    }
+}
+
+/// Backend which spawns the iptables utilities as child processes.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemBackend;
+
+#[cfg(not(target_os = "linux"))]
+impl Backend for SystemBackend {
+    fn run(
+        &self,
+        _program: &str,
+        _args: &[OsString],
+        _input: Option<&str>,
+        _timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        Err("iptables only works on Linux".into())
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Backend for SystemBackend {
+    fn run(
+        &self,
+        program: &str,
+        args: &[OsString],
+        input: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(match input {
+                Some(_) => Stdio::piped(),
+                None => Stdio::null(),
+            })
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+        // Write the input in the background, otherwise a child which does not read it blocks
+        // the caller past the timeout
+        let writer = match input {
+            Some(input) => {
+                let mut stdin = child
+                    .stdin
+                    .take()
+                    .ok_or("unable to open stdin of the child process")?;
+                let input = input.to_string();
+                Some(thread::spawn(move || stdin.write_all(input.as_bytes())))
+            }
+            None => None,
+        };
+
+        let timeout = match timeout {
+            Some(timeout) => timeout,
+            None => {
+                let output = child.wait_with_output()?;
+                join_writer(writer)?;
+                return Ok(output);
+            }
+        };
+
+        // Drain the pipes in the background, otherwise the child may block on a full pipe
+        let stdout = child.stdout.take().map(read_in_background);
+        let stderr = child.stderr.take().map(read_in_background);
+
+        let deadline = Instant::now() + timeout;
+        let status = loop {
+            if let Some(status) = child.try_wait()? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                child.kill()?;
+                child.wait()?;
+                return Err(Box::new(IPTError::Timeout(timeout)));
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        join_writer(writer)?;
+
+        Ok(Output {
+            status,
+            stdout: stdout.and_then(|h| h.join().ok()).unwrap_or_default(),
+            stderr: stderr.and_then(|h| h.join().ok()).unwrap_or_default(),
+        })
+    }
+
+    fn is_local(&self) -> bool {
+        true
+    }
+}
+
+/// Waits for the thread writing the input of a child which exited. A child exiting before
+/// reading its whole input reports its failure through its exit status.
+#[cfg(target_os = "linux")]
+fn join_writer(writer: Option<thread::JoinHandle<io::Result<()>>>) -> Result<(), Box<dyn Error>> {
+    match writer.map(thread::JoinHandle::join) {
+        Some(Ok(Err(e))) if e.kind() != io::ErrorKind::BrokenPipe => Err(Box::new(e)),
+        Some(Ok(_)) => Ok(()),
+        Some(Err(_)) => Err("unable to write stdin of the child process".into()),
+        None => Ok(()),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn read_in_background<R: Read + Send + 'static>(mut reader: R) -> thread::JoinHandle<Vec<u8>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        let _ = reader.read_to_end(&mut buf);
+        buf
+    })
+}
+
+/// Backend wrapping another one, which runs a single command at a time. Handles sharing it
+/// (e.g. clones of an `IPTables`) never run their commands concurrently, even when the
+/// utilities do not wait for the xtables lock.
+pub struct SerialBackend {
+    backend: Arc<dyn Backend>,
+    lock: Mutex<()>,
+}
+
+impl SerialBackend {
+    /// Creates the wrapper running the commands with `backend`.
+    pub fn new(backend: Arc<dyn Backend>) -> SerialBackend {
+        SerialBackend {
+            backend,
+            lock: Mutex::new(()),
+        }
+    }
+}
+
+impl Backend for SerialBackend {
+    fn run(
+        &self,
+        program: &str,
+        args: &[OsString],
+        input: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        // A command panicking in another thread does not leave anything to clean up
+        let _guard = self
+            .lock
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        self.backend.run(program, args, input, timeout)
+    }
+
+    fn is_local(&self) -> bool {
+        self.backend.is_local()
+    }
+}
+
+/// Backend wrapping another one, which runs the commands in a network namespace with
+/// `ip netns exec`, so that they manage the firewall of that namespace.
+pub struct NetnsBackend {
+    backend: Arc<dyn Backend>,
+    namespace: String,
+}
+
+impl NetnsBackend {
+    /// Creates the wrapper running the commands in the existing `namespace` with `backend`.
+    pub fn new(backend: Arc<dyn Backend>, namespace: &str) -> NetnsBackend {
+        NetnsBackend {
+            backend,
+            namespace: namespace.to_string(),
+        }
+    }
+}
+
+impl Backend for NetnsBackend {
+    fn run(
+        &self,
+        program: &str,
+        args: &[OsString],
+        input: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        let mut netns_args = ["netns", "exec", &self.namespace, program]
+            .iter()
+            .map(OsString::from)
+            .collect::<Vec<_>>();
+        netns_args.extend_from_slice(args);
+        self.backend.run("ip", &netns_args, input, timeout)
+    }
+}
+
+/// Backend which records the commands instead of running them and replies with queued outputs.
+/// Commands succeed with an empty output once the queue is exhausted.
+#[cfg(feature = "test-backend")]
+#[derive(Debug, Default)]
+pub struct MockBackend {
+    calls: Mutex<Vec<Vec<String>>>,
+    inputs: Mutex<Vec<Option<String>>>,
+    outputs: Mutex<VecDeque<(i32, String, String)>>,
+}
+
+#[cfg(feature = "test-backend")]
+impl MockBackend {
+    /// Creates a new `MockBackend` without any queued output.
+    pub fn new() -> MockBackend {
+        MockBackend::default()
+    }
+
+    /// Queues the exit `code`, `stdout` and `stderr` of the next command.
+    pub fn push_output(&self, code: i32, stdout: &str, stderr: &str) {
+        self.outputs
+            .lock()
+            .unwrap()
+            .push_back((code, stdout.to_string(), stderr.to_string()));
+    }
+
+    /// Returns the commands which were run, each one as the program followed by its arguments.
+    pub fn calls(&self) -> Vec<Vec<String>> {
+        self.calls.lock().unwrap().clone()
+    }
+
+    /// Returns the standard input written to each of the commands which were run.
+    pub fn inputs(&self) -> Vec<Option<String>> {
+        self.inputs.lock().unwrap().clone()
+    }
+}
+
+#[cfg(feature = "test-backend")]
+impl Backend for MockBackend {
+    fn run(
+        &self,
+        program: &str,
+        args: &[OsString],
+        input: Option<&str>,
+        _timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        self.inputs.lock().unwrap().push(input.map(String::from));
+        let mut call = vec![program.to_string()];
+        call.extend(args.iter().map(|arg| arg.to_string_lossy().into_owned()));
+        self.calls.lock().unwrap().push(call);
+
+        let (code, stdout, stderr) = self.outputs.lock().unwrap().pop_front().unwrap_or_default();
+        Ok(Output {
+            status: exit_status(code),
+            stdout: stdout.into_bytes(),
+            stderr: stderr.into_bytes(),
+        })
+    }
+}
+
+#[cfg(all(feature = "test-backend", unix))]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::unix::process::ExitStatusExt;
+    ExitStatus::from_raw((code & 0xff) << 8)
+}
+
+#[cfg(all(feature = "test-backend", windows))]
+fn exit_status(code: i32) -> ExitStatus {
+    use std::os::windows::process::ExitStatusExt;
+    ExitStatus::from_raw(code as u32)
+}
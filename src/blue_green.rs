@@ -0,0 +1,115 @@
+//! Blue/green switching between two complete versions of a chain.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::parse::Rule;
+use crate::IPTables;
+use std::error::Error;
+
+/// One of the two versions of a blue/green chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Color {
+    /// The blue version.
+    Blue,
+
+    /// The green version.
+    Green,
+}
+
+impl Color {
+    /// Returns the suffix of the chain of the version.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Color::Blue => "BLUE",
+            Color::Green => "GREEN",
+        }
+    }
+
+    /// Returns the other version.
+    pub fn other(&self) -> Color {
+        match self {
+            Color::Blue => Color::Green,
+            Color::Green => Color::Blue,
+        }
+    }
+}
+
+/// A chain whose single rule jumps to either its blue (`CHAIN-BLUE`) or its green
+/// (`CHAIN-GREEN`) version. New rules are loaded into the inactive version, then the traffic
+/// is switched to it atomically, and switched back if the new rules misbehave.
+pub struct BlueGreen<'a> {
+    ipt: &'a IPTables,
+    table: String,
+    chain: String,
+    active: Color,
+}
+
+impl IPTables {
+    /// Creates the chain and its two versions in the table if they do not exist yet.
+    /// The active version is read from the chain, blue by default.
+    pub fn blue_green(&self, table: &str, chain: &str) -> Result<BlueGreen<'_>, Box<dyn Error>> {
+        let chain = &self.prefixed_chain(chain);
+        let mut blue_green = BlueGreen {
+            ipt: self,
+            table: table.to_string(),
+            chain: chain.to_string(),
+            active: Color::Blue,
+        };
+        for chain in [
+            blue_green.chain.clone(),
+            blue_green.chain_of(Color::Blue),
+            blue_green.chain_of(Color::Green),
+        ] {
+            if !self.chain_exists(table, &chain)? {
+                self.new_chain(table, &chain)?;
+            }
+        }
+
+        let jump = self
+            .chain_rules(table, chain)?
+            .first()
+            .and_then(|rule| Rule::parse(rule).target());
+        match jump {
+            Some(target) if target == blue_green.chain_of(Color::Green) => {
+                blue_green.active = Color::Green
+            }
+            Some(target) if target == blue_green.chain_of(Color::Blue) => {}
+            _ => blue_green.activate(Color::Blue)?,
+        }
+        Ok(blue_green)
+    }
+}
+
+impl BlueGreen<'_> {
+    /// Returns the name of the chain of the `color` version.
+    pub fn chain_of(&self, color: Color) -> String {
+        format!("{}-{}", self.chain, color.as_str())
+    }
+
+    /// Returns the version receiving the traffic.
+    pub fn active(&self) -> Color {
+        self.active
+    }
+
+    /// Atomically replaces the rules (without the leading `-A CHAIN`) of the inactive version.
+    pub fn load_inactive(&self, rules: &[String]) -> Result<(), Box<dyn Error>> {
+        self.ipt
+            .replace_chain_rules(&self.table, &self.chain_of(self.active.other()), rules)
+    }
+
+    /// Atomically switches the traffic to the inactive version.
+    /// Returns the newly active version.
+    pub fn switch_active(&mut self) -> Result<Color, Box<dyn Error>> {
+        self.activate(self.active.other())?;
+        Ok(self.active)
+    }
+
+    fn activate(&mut self, color: Color) -> Result<(), Box<dyn Error>> {
+        let jump = RuleBuilder::new()
+            .target(Target::Jump(self.chain_of(color)))
+            .build()?;
+        self.ipt
+            .replace_chain_rules(&self.table, &self.chain, &[jump])?;
+        self.active = color;
+        Ok(())
+    }
+}
@@ -0,0 +1,721 @@
+//! Builder of rules from typed options, which takes care of quoting the arguments.
+
+use crate::conntrack::Ct;
+use crate::error_from_str;
+use crate::icmp::IcmpType;
+use crate::ipset::AddSet;
+use crate::ipv6::{Fragment, Ipv6Header, Npt};
+use crate::kmod::required_modules;
+use crate::mark::Mark;
+use crate::nfacct::MAX_NAME_LEN;
+use crate::nfqueue::Nfqueue;
+use crate::parse::join_rule;
+use crate::qos::Dscp;
+use crate::statistic::Statistic;
+use crate::tcpmss::Mss;
+use crate::ttl::{TtlAction, TtlMatch};
+use crate::IPTables;
+use std::error::Error;
+use std::net::IpAddr;
+
+/// The target (`-j`) or the chain to go to (`-g`) of a rule.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// Lets the packet through.
+    Accept,
+
+    /// Drops the packet.
+    Drop,
+
+    /// Returns to the calling chain.
+    Return,
+
+    /// Drops the packet and replies with an error.
+    Reject,
+
+    /// Jumps to a user-defined chain or to a target without options.
+    Jump(String),
+
+    /// Goes to a user-defined chain, without returning to the current one.
+    Goto(String),
+
+    /// Rewrites the destination address (and port) of the connection, only in the nat table.
+    Dnat(String),
+
+    /// Rewrites the source address (and port) of the connection, only in the nat table.
+    Snat(String),
+
+    /// Rewrites the source address of the connection to the address of the output interface,
+    /// only in the nat table.
+    Masquerade,
+
+    /// Passes the packet to userspace through NFQUEUE.
+    Nfqueue(Nfqueue),
+
+    /// Changes the MSS option of TCP SYN packets.
+    Tcpmss(Mss),
+
+    /// Adds the addresses of the packet to an ipset.
+    AddSet(AddSet),
+
+    /// Emits a kernel audit record for the packet.
+    Audit(AuditType),
+
+    /// Sets up the connection tracking of the packet, only in the raw table.
+    Ct(Ct),
+
+    /// Sets the bits of the mask of the packet mark (`--set-xmark`), only in the mangle table.
+    Mark(Mark),
+
+    /// Changes the TTL of IPv4 packets, only in the mangle table.
+    Ttl(TtlAction),
+
+    /// Changes the hop limit of IPv6 packets, only in the mangle table.
+    Hl(TtlAction),
+
+    /// Sets the DSCP of the packet, only in the mangle table.
+    Dscp(Dscp),
+
+    /// Translates the prefix of the source address of IPv6 packets, only in the mangle table.
+    Snpt(Npt),
+
+    /// Translates the prefix of the destination address of IPv6 packets, only in the mangle
+    /// table.
+    Dnpt(Npt),
+}
+
+/// Type of the audit records emitted by the AUDIT target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuditType {
+    /// The packet is accepted.
+    Accept,
+
+    /// The packet is dropped.
+    Drop,
+
+    /// The packet is rejected.
+    Reject,
+}
+
+impl AuditType {
+    /// Returns the value of the `--type` option.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuditType::Accept => "accept",
+            AuditType::Drop => "drop",
+            AuditType::Reject => "reject",
+        }
+    }
+}
+
+/// Link-layer type of a packet, matched by `RuleBuilder::pkt_type`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PktType {
+    /// The packet is addressed to a single host.
+    Unicast,
+
+    /// The packet is addressed to every host of the link.
+    Broadcast,
+
+    /// The packet is addressed to a group of hosts.
+    Multicast,
+}
+
+impl PktType {
+    /// Returns the value of the `--pkt-type` option.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PktType::Unicast => "unicast",
+            PktType::Broadcast => "broadcast",
+            PktType::Multicast => "multicast",
+        }
+    }
+}
+
+impl Target {
+    /// Checks the options of the target.
+    fn validate(&self) -> Result<(), String> {
+        match self {
+            Target::Nfqueue(nfqueue) => nfqueue.validate(),
+            Target::AddSet(add_set) => add_set.validate(),
+            Target::Ct(ct) => ct.validate(),
+            Target::Mark(mark) => mark.validate(),
+            Target::Ttl(action) | Target::Hl(action) => action.validate(),
+            Target::Dscp(dscp) => dscp.validate(),
+            Target::Snpt(npt) | Target::Dnpt(npt) => npt.validate(),
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns `Some(true)` if the target only exists in ip6tables, `Some(false)` if it only
+    /// exists in iptables, `None` otherwise.
+    fn is_ipv6(&self) -> Option<bool> {
+        match self {
+            Target::Ttl(_) => Some(false),
+            Target::Hl(_) | Target::Snpt(_) | Target::Dnpt(_) => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Returns the arguments of the target, starting with `-j` or `-g`.
+    pub fn args(&self) -> Vec<String> {
+        let jump = |target: &str| vec!["-j".to_string(), target.to_string()];
+        match self {
+            Target::Accept => jump("ACCEPT"),
+            Target::Drop => jump("DROP"),
+            Target::Return => jump("RETURN"),
+            Target::Reject => jump("REJECT"),
+            Target::Jump(chain) => jump(chain),
+            Target::Goto(chain) => vec!["-g".to_string(), chain.clone()],
+            Target::Dnat(destination) => {
+                let mut args = jump("DNAT");
+                args.extend(["--to-destination".to_string(), destination.clone()]);
+                args
+            }
+            Target::Snat(source) => {
+                let mut args = jump("SNAT");
+                args.extend(["--to-source".to_string(), source.clone()]);
+                args
+            }
+            Target::Masquerade => jump("MASQUERADE"),
+            Target::Nfqueue(nfqueue) => {
+                let mut args = jump("NFQUEUE");
+                args.extend(nfqueue.args());
+                args
+            }
+            Target::Tcpmss(mss) => {
+                let mut args = jump("TCPMSS");
+                args.extend(mss.args());
+                args
+            }
+            Target::AddSet(add_set) => {
+                let mut args = jump("SET");
+                args.extend(add_set.args());
+                args
+            }
+            Target::Audit(audit_type) => {
+                let mut args = jump("AUDIT");
+                args.extend(["--type".to_string(), audit_type.as_str().to_string()]);
+                args
+            }
+            Target::Ct(ct) => {
+                let mut args = jump("CT");
+                args.extend(ct.args());
+                args
+            }
+            Target::Mark(mark) => {
+                let mut args = jump("MARK");
+                args.extend(mark.target_args());
+                args
+            }
+            Target::Ttl(action) => {
+                let mut args = jump("TTL");
+                args.extend(action.args("ttl"));
+                args
+            }
+            Target::Hl(action) => {
+                let mut args = jump("HL");
+                args.extend(action.args("hl"));
+                args
+            }
+            Target::Dscp(dscp) => {
+                let mut args = jump("DSCP");
+                args.extend(dscp.target_args());
+                args
+            }
+            Target::Snpt(npt) => {
+                let mut args = jump("SNPT");
+                args.extend(npt.args());
+                args
+            }
+            Target::Dnpt(npt) => {
+                let mut args = jump("DNPT");
+                args.extend(npt.args());
+                args
+            }
+        }
+    }
+}
+
+/// Checks the name of a network interface like the kernel does, allowing a trailing '+' as a
+/// wildcard.
+pub fn validate_interface(interface: &str) -> Result<(), String> {
+    let name = interface.strip_suffix('+').unwrap_or(interface);
+    if name.is_empty() && !interface.is_empty() {
+        // A single '+' matches every interface
+        return Ok(());
+    }
+    if name.is_empty() || name == "." || name == ".." {
+        return Err(format!("invalid interface name '{}'", interface));
+    }
+    // IFNAMSIZ includes the trailing NUL byte
+    if name.len() > 15 {
+        return Err(format!(
+            "interface name '{}' is longer than 15 characters",
+            interface
+        ));
+    }
+    if name.contains(|c: char| c == '/' || c == ':' || c.is_whitespace() || c.is_control()) {
+        return Err(format!(
+            "interface name '{}' contains an invalid character",
+            interface
+        ));
+    }
+    Ok(())
+}
+
+/// Formats an address, with the prefix length of its network if given (e.g. '10.0.0.0/8').
+fn network(address: IpAddr, prefix: Option<u8>) -> String {
+    match prefix {
+        Some(prefix) => format!("{}/{}", address, prefix),
+        None => address.to_string(),
+    }
+}
+
+/// Formats the `pattern` for `--hex-string`, writing the bytes other than ASCII letters and
+/// digits in hexadecimal between pipes.
+fn hex_string(pattern: &[u8]) -> String {
+    let mut hex = String::new();
+    let mut in_hex = false;
+    for b in pattern {
+        if b.is_ascii_alphanumeric() {
+            if in_hex {
+                hex.push('|');
+                in_hex = false;
+            }
+            hex.push(*b as char);
+            continue;
+        }
+        match in_hex {
+            true => hex.push(' '),
+            false => hex.push('|'),
+        }
+        hex.push_str(&format!("{:02x}", b));
+        in_hex = true;
+    }
+    if in_hex {
+        hex.push('|');
+    }
+    hex
+}
+
+/// Checks the format of BPF bytecode, as printed by `nfbpf_compile`.
+fn validate_bytecode(bytecode: &str) -> Result<(), String> {
+    let invalid = || format!("invalid BPF bytecode '{}'", bytecode);
+    let mut parts = bytecode.split(',');
+    let count = parts
+        .next()
+        .and_then(|count| count.trim().parse::<usize>().ok())
+        .ok_or_else(invalid)?;
+    let mut instructions = 0;
+    for instruction in parts {
+        let fields = instruction.split_whitespace().collect::<Vec<_>>();
+        if fields.len() != 4 || fields.iter().any(|field| field.parse::<u32>().is_err()) {
+            return Err(invalid());
+        }
+        instructions += 1;
+    }
+    if instructions == 0 || instructions != count {
+        return Err(invalid());
+    }
+    Ok(())
+}
+
+/// Builds a rule from typed options.
+///
+/// # Example
+/// ```
+/// use iptables::builder::{RuleBuilder, Target};
+///
+/// let rule = RuleBuilder::new()
+///     .protocol("tcp")
+///     .dport(22)
+///     .comment("ssh access")
+///     .target(Target::Accept)
+///     .build()
+///     .unwrap();
+/// assert_eq!(rule, "-p tcp --dport 22 -m comment --comment \"ssh access\" -j ACCEPT");
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct RuleBuilder {
+    args: Vec<String>,
+    target: Option<Target>,
+    errors: Vec<String>,
+    families: Vec<(bool, String)>,
+}
+
+impl RuleBuilder {
+    /// Creates an empty rule, matching every packet.
+    pub fn new() -> RuleBuilder {
+        RuleBuilder::default()
+    }
+
+    /// Appends raw arguments to the rule, for options without a dedicated method.
+    pub fn arg<S: AsRef<str>>(mut self, args: &[S]) -> RuleBuilder {
+        self.args
+            .extend(args.iter().map(|arg| arg.as_ref().to_string()));
+        self
+    }
+
+    /// Matches the protocol (`-p`), e.g. 'tcp' or 'udp'.
+    pub fn protocol(self, protocol: &str) -> RuleBuilder {
+        self.arg(&["-p", protocol])
+    }
+
+    /// Matches the source address (`-s`). The family of the rule is the one of the address,
+    /// unless it is a host name.
+    pub fn source(self, source: &str) -> RuleBuilder {
+        self.address("-s", source)
+    }
+
+    /// Matches the destination address (`-d`). The family of the rule is the one of the
+    /// address, unless it is a host name.
+    pub fn destination(self, destination: &str) -> RuleBuilder {
+        self.address("-d", destination)
+    }
+
+    /// Matches the source address (`-s`), or the network of the given `prefix` length.
+    pub fn source_ip(self, address: IpAddr, prefix: Option<u8>) -> RuleBuilder {
+        self.source(&network(address, prefix))
+    }
+
+    /// Matches the destination address (`-d`), or the network of the given `prefix` length.
+    pub fn destination_ip(self, address: IpAddr, prefix: Option<u8>) -> RuleBuilder {
+        self.destination(&network(address, prefix))
+    }
+
+    fn address(mut self, option: &str, addresses: &str) -> RuleBuilder {
+        // Several addresses can be given, separated by commas
+        for address in addresses.split(',') {
+            let ip = address.split('/').next().unwrap_or_default();
+            if let Ok(ip) = ip.parse::<IpAddr>() {
+                self = self.family(ip.is_ipv6(), &format!("address {}", address));
+            }
+        }
+        self.arg(&[option, addresses])
+    }
+
+    /// Matches the input interface (`-i`). A trailing '+' matches every interface starting
+    /// with the name (e.g. 'eth+').
+    pub fn in_interface(self, interface: &str) -> RuleBuilder {
+        self.interface("-i", interface)
+    }
+
+    /// Matches the output interface (`-o`). A trailing '+' matches every interface starting
+    /// with the name (e.g. 'eth+').
+    pub fn out_interface(self, interface: &str) -> RuleBuilder {
+        self.interface("-o", interface)
+    }
+
+    /// Matches the bridge port which received the packet (`-m physdev --physdev-in`), for
+    /// the packets of a bridge. A trailing '+' is a wildcard like in `in_interface`.
+    pub fn in_bridge_port(self, interface: &str) -> RuleBuilder {
+        self.interface("--physdev-in", interface)
+    }
+
+    /// Matches the bridge port which sends the packet (`-m physdev --physdev-out`), for the
+    /// packets of a bridge. A trailing '+' is a wildcard like in `out_interface`.
+    pub fn out_bridge_port(self, interface: &str) -> RuleBuilder {
+        self.interface("--physdev-out", interface)
+    }
+
+    fn interface(mut self, option: &str, interface: &str) -> RuleBuilder {
+        if let Err(msg) = validate_interface(interface) {
+            self.errors.push(msg);
+        }
+        if option.starts_with("--physdev") && !self.args.iter().any(|arg| arg == "physdev") {
+            self.args.extend(["-m".to_string(), "physdev".to_string()]);
+        }
+        self.arg(&[option, interface])
+    }
+
+    /// Matches the source port (`--sport`), requires a protocol with ports.
+    pub fn sport(self, port: u16) -> RuleBuilder {
+        self.arg(&["--sport", &port.to_string()])
+    }
+
+    /// Matches the destination port (`--dport`), requires a protocol with ports.
+    pub fn dport(self, port: u16) -> RuleBuilder {
+        self.arg(&["--dport", &port.to_string()])
+    }
+
+    /// Matches the ICMP messages of the `icmp_type` (`-p icmp --icmp-type`), for iptables.
+    /// ICMPv6 only types (e.g. neighbour solicitation) are rejected.
+    pub fn icmp_type(mut self, icmp_type: IcmpType) -> RuleBuilder {
+        if let Err(msg) = icmp_type.validate(false) {
+            self.errors.push(msg);
+        }
+        self.protocol("icmp")
+            .arg(&["-m", "icmp", "--icmp-type", icmp_type.as_str()])
+            .family(false, "match 'icmp'")
+    }
+
+    /// Matches the ICMPv6 messages of the `icmp_type` (`-p ipv6-icmp --icmpv6-type`), for
+    /// ip6tables. ICMP only types (e.g. timestamp request) are rejected.
+    pub fn icmpv6_type(mut self, icmp_type: IcmpType) -> RuleBuilder {
+        if let Err(msg) = icmp_type.validate(true) {
+            self.errors.push(msg);
+        }
+        self.protocol("ipv6-icmp")
+            .arg(&["-m", "icmp6", "--icmpv6-type", icmp_type.as_str()])
+            .family(true, "match 'icmp6'")
+    }
+
+    /// Matches the packets containing the `pattern` (`-m string --algo bm`).
+    /// Patterns made of printable ASCII characters are given with `--string`, others with
+    /// `--hex-string` in which the other bytes are written in hexadecimal (e.g. 'GET|20 2f|').
+    pub fn string(self, pattern: &[u8]) -> RuleBuilder {
+        let printable = pattern.iter().all(|b| b.is_ascii_graphic() || *b == b' ');
+        let rule = self.arg(&["-m", "string", "--algo", "bm"]);
+        match printable && !pattern.is_empty() {
+            true => rule.arg(&["--string", &String::from_utf8_lossy(pattern)]),
+            false => rule.arg(&["--hex-string", &hex_string(pattern)]),
+        }
+    }
+
+    /// Matches the packets accepted by the BPF program (`-m bpf --bytecode`), given in the
+    /// format of `nfbpf_compile`: the number of instructions, then each instruction as four
+    /// numbers, separated by commas (e.g. '4,48 0 0 9,21 0 1 6,6 0 0 1,6 0 0 0').
+    pub fn bpf(mut self, bytecode: &str) -> RuleBuilder {
+        if let Err(msg) = validate_bytecode(bytecode) {
+            self.errors.push(msg);
+        }
+        self.arg(&["-m", "bpf", "--bytecode", bytecode])
+    }
+
+    /// Matches the packets of the processes of the cgroup v2 given by its `path` relative to
+    /// the root of the hierarchy (`-m cgroup --path`), only for the local traffic.
+    pub fn cgroup(self, path: &str) -> RuleBuilder {
+        self.arg(&["-m", "cgroup", "--path", path])
+    }
+
+    /// Attaches a comment (`-m comment --comment`) to the rule.
+    pub fn comment(self, comment: &str) -> RuleBuilder {
+        self.arg(&["-m", "comment", "--comment", comment])
+    }
+
+    /// Matches the packets whose layer 3 length (including the IP header) is between `min` and
+    /// `max` bytes (`-m length --length`).
+    pub fn length(mut self, min: u16, max: u16) -> RuleBuilder {
+        if min > max {
+            self.errors
+                .push(format!("invalid length range {}:{}", min, max));
+        }
+        let length = match min == max {
+            true => min.to_string(),
+            false => format!("{}:{}", min, max),
+        };
+        self.arg(&["-m", "length", "--length", &length])
+    }
+
+    /// Matches the link-layer type of the packets (`-m pkttype --pkt-type`).
+    pub fn pkt_type(self, pkt_type: PktType) -> RuleBuilder {
+        self.arg(&["-m", "pkttype", "--pkt-type", pkt_type.as_str()])
+    }
+
+    /// Matches the packets until the rule has matched `bytes` bytes (`-m quota --quota`).
+    /// The quota is only reset by recreating the rule.
+    pub fn quota(self, bytes: u64) -> RuleBuilder {
+        self.arg(&["-m", "quota", "--quota", &bytes.to_string()])
+    }
+
+    /// Counts the packets in the nfacct accounting object `name` (`-m nfacct --nfacct-name`),
+    /// which must exist before the rule is added. Always matches.
+    pub fn nfacct(mut self, name: &str) -> RuleBuilder {
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            self.errors.push(format!(
+                "invalid nfacct name {}, it must have between 1 and {} characters",
+                name, MAX_NAME_LEN
+            ));
+        }
+        self.arg(&["-m", "nfacct", "--nfacct-name", name])
+    }
+
+    /// Matches the packets whose mark has the value of `mark` in the bits of its mask
+    /// (`-m mark --mark`).
+    pub fn mark(mut self, mark: Mark) -> RuleBuilder {
+        if let Err(msg) = mark.validate() {
+            self.errors.push(msg);
+        }
+        self.args.extend(mark.match_args());
+        self
+    }
+
+    /// Matches the TTL of IPv4 packets (`-m ttl`).
+    pub fn ttl(mut self, ttl: TtlMatch) -> RuleBuilder {
+        if let Err(msg) = ttl.validate() {
+            self.errors.push(msg);
+        }
+        self.args.extend(ttl.args("ttl"));
+        self.family(false, "match 'ttl'")
+    }
+
+    /// Matches the hop limit of IPv6 packets (`-m hl`).
+    pub fn hop_limit(mut self, hop_limit: TtlMatch) -> RuleBuilder {
+        if let Err(msg) = hop_limit.validate() {
+            self.errors.push(msg);
+        }
+        self.args.extend(hop_limit.args("hl"));
+        self.family(true, "match 'hl'")
+    }
+
+    /// Matches the IPv6 packets with a routing header of the type `rt_type`
+    /// (`-m rt --rt-type`).
+    pub fn rt_type(self, rt_type: u8) -> RuleBuilder {
+        self.family(true, "match 'rt'")
+            .arg(&["-m", "rt", "--rt-type", &rt_type.to_string()])
+    }
+
+    /// Matches the IPv6 packets whose routing header has between `min` and `max` segments
+    /// left (`-m rt --rt-segsleft`).
+    pub fn rt_segments_left(mut self, min: u32, max: u32) -> RuleBuilder {
+        if min > max {
+            self.errors
+                .push(format!("invalid segments left range {}:{}", min, max));
+        }
+        let segments = match min == max {
+            true => min.to_string(),
+            false => format!("{}:{}", min, max),
+        };
+        self.family(true, "match 'rt'")
+            .arg(&["-m", "rt", "--rt-segsleft", &segments])
+    }
+
+    /// Matches the fragments of IPv6 packets (`-m frag`).
+    pub fn fragment(mut self, fragment: Fragment) -> RuleBuilder {
+        self.args.extend(fragment.args());
+        self.family(true, "match 'frag'")
+    }
+
+    /// Matches the IPv6 packets with all the extension `headers` (`-m ipv6header --header`):
+    /// only them when `soft` is `false`, at least them when it is `true` (`--soft`).
+    pub fn ipv6_headers(mut self, headers: &[Ipv6Header], soft: bool) -> RuleBuilder {
+        if headers.is_empty() {
+            self.errors.push("no IPv6 extension header".to_string());
+        }
+        let headers = headers
+            .iter()
+            .map(Ipv6Header::as_str)
+            .collect::<Vec<_>>()
+            .join(",");
+        let rule = self.family(true, "match 'ipv6header'").arg(&[
+            "-m",
+            "ipv6header",
+            "--header",
+            &headers,
+        ]);
+        match soft {
+            true => rule.arg(&["--soft"]),
+            false => rule,
+        }
+    }
+
+    /// Records that the option `required_by` only exists in ip6tables (`is_ipv6`) or in
+    /// iptables.
+    fn family(mut self, is_ipv6: bool, required_by: &str) -> RuleBuilder {
+        self.families.push((is_ipv6, required_by.to_string()));
+        self
+    }
+
+    /// Matches the packets selected by the `statistic` match.
+    pub fn statistic(mut self, statistic: Statistic) -> RuleBuilder {
+        if let Err(msg) = statistic.validate() {
+            self.errors.push(msg);
+        }
+        self.args.extend(statistic.args());
+        self
+    }
+
+    /// Sets the target of the rule, replacing the previous one.
+    pub fn target(mut self, target: Target) -> RuleBuilder {
+        if let Err(msg) = target.validate() {
+            self.errors.push(msg);
+        }
+        self.target = Some(target);
+        self
+    }
+
+    /// Returns the arguments of the rule, for the methods accepting separate arguments
+    /// (e.g. `append_os`).
+    pub fn args(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        if !self.errors.is_empty() {
+            return Err(error_from_str(&self.errors.join(", ")));
+        }
+        let mut args = self.args.clone();
+        if let Some(target) = &self.target {
+            args.extend(target.args());
+        }
+        Ok(args)
+    }
+
+    /// Returns the rule as a string, quoted as printed by `iptables -S`.
+    pub fn build(&self) -> Result<String, Box<dyn Error>> {
+        self.args().map(|args| join_rule(&args))
+    }
+
+    /// Returns `Some(true)` if the rule can only be used with ip6tables, `Some(false)` if it can
+    /// only be used with iptables, `None` if it can be used with both. Fails if it mixes
+    /// options of both families.
+    pub fn is_ipv6(&self) -> Result<Option<bool>, Box<dyn Error>> {
+        let families = self.families();
+        let ipv4 = families.iter().find(|(is_ipv6, _)| !is_ipv6);
+        let ipv6 = families.iter().find(|(is_ipv6, _)| *is_ipv6);
+        match (ipv4, ipv6) {
+            (Some((_, ipv4)), Some((_, ipv6))) => Err(error_from_str(&format!(
+                "the rule mixes {} of iptables with {} of ip6tables",
+                ipv4, ipv6
+            ))),
+            (Some(_), None) => Ok(Some(false)),
+            (None, Some(_)) => Ok(Some(true)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Checks that the rule can be used with `ipt`, e.g. that a rule matching IPv6 extension
+    /// headers is not given to an iptables handle.
+    pub fn check_family(&self, ipt: &IPTables) -> Result<(), Box<dyn Error>> {
+        let is_ipv6 = ipt.cmd == "ip6tables";
+        match self.is_ipv6()? {
+            Some(required) if required != is_ipv6 => {
+                let (_, required_by) = self.families().swap_remove(0);
+                let cmd = match required {
+                    true => "ip6tables",
+                    false => "iptables",
+                };
+                Err(error_from_str(&format!(
+                    "{} can only be used with {}",
+                    required_by, cmd
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Returns the options and the target of the rule which only exist in ip6tables (`true`)
+    /// or in iptables (`false`).
+    fn families(&self) -> Vec<(bool, String)> {
+        let mut families = self.families.clone();
+        if let Some(target) = &self.target {
+            if let Some(is_ipv6) = target.is_ipv6() {
+                families.push((is_ipv6, format!("target '{}'", target.args()[1])));
+            }
+        }
+        families
+    }
+
+    /// Checks that the kernel modules of the matches and the target of the rule are available
+    /// to `ipt`, so that a missing module is reported by name rather than by a generic error of
+    /// iptables when the rule is applied.
+    pub fn check_modules(&self, ipt: &IPTables) -> Result<(), Box<dyn Error>> {
+        let is_ipv6 = ipt.cmd == "ip6tables";
+        for (required_by, module) in required_modules(&self.args()?, is_ipv6) {
+            if !ipt.module_available(&module) {
+                return Err(error_from_str(&format!(
+                    "{} requires kernel module {}",
+                    required_by, module
+                )));
+            }
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,81 @@
+//! Dry runs of new rules, measuring the traffic they would affect before enforcing them.
+
+use crate::parse::{join_rule, split_rule, Counters};
+use crate::IPTables;
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+/// The traffic matched by each rule of a canary run.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CanaryReport {
+    /// Each rule along with the counters of the packets it matched during the run.
+    pub rules: Vec<(String, Counters)>,
+}
+
+impl CanaryReport {
+    /// Returns the rules which matched at least one packet.
+    pub fn affecting(&self) -> impl Iterator<Item = &(String, Counters)> {
+        self.rules
+            .iter()
+            .filter(|(_, counters)| counters.packets > 0)
+    }
+}
+
+/// Removes the target (`-j` or `-g`) and its options from the `rule`, keeping its matches.
+fn without_target(rule: &str) -> String {
+    let args = split_rule(rule);
+    let end = args
+        .iter()
+        .position(|arg| matches!(arg.as_str(), "-j" | "--jump" | "-g" | "--goto"))
+        .unwrap_or(args.len());
+    join_rule(&args[..end])
+}
+
+impl IPTables {
+    /// Installs the matches of `rules` (without their targets, so they only count packets) in
+    /// a shadow chain called first by the table/chain, waits for `duration`, then removes them
+    /// and reports how many packets each rule matched.
+    /// Since no rule terminates, the packets matched by a rule are also counted by the next
+    /// ones, unlike once the rules are enforced.
+    pub fn canary_apply(
+        &self,
+        table: &str,
+        chain: &str,
+        rules: &[&str],
+        duration: Duration,
+    ) -> Result<CanaryReport, Box<dyn Error>> {
+        let shadow = self.prefixed_chain(&format!("{}-CANARY", chain));
+        let jump = format!("-j {}", shadow);
+        self.new_chain(table, &shadow)?;
+
+        let installed = rules
+            .iter()
+            .try_for_each(|rule| self.append(table, &shadow, &without_target(rule)))
+            .and_then(|_| self.insert(table, chain, &jump, 1));
+        let result = match installed {
+            Ok(()) => {
+                thread::sleep(duration);
+                let counters = self.counters(table, &shadow);
+                // The shadow chain is left in place if the jump to it can't be removed
+                self.delete(table, chain, &jump)?;
+                counters
+            }
+            Err(e) => Err(e),
+        };
+
+        let cleanup = self
+            .flush_chain(table, &shadow)
+            .and_then(|_| self.delete_chain(table, &shadow));
+        let counters = result?;
+        cleanup?;
+
+        Ok(CanaryReport {
+            rules: rules
+                .iter()
+                .map(|rule| rule.to_string())
+                .zip(counters)
+                .collect(),
+        })
+    }
+}
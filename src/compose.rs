@@ -0,0 +1,218 @@
+//! Composition of a ruleset from several sources, e.g. a base policy, the overrides of a host
+//! and the fragments of the applications.
+
+use crate::error_from_str;
+use crate::parse::{join_rule, normalize_rule, parse_save, split_rule, Rule, SavedTable};
+use std::cmp::Reverse;
+use std::error::Error;
+use std::fmt;
+
+#[cfg(not(feature = "parse-only"))]
+use crate::parse::format_save;
+#[cfg(not(feature = "parse-only"))]
+use crate::restore::RestoreOptions;
+#[cfg(not(feature = "parse-only"))]
+use crate::IPTables;
+
+/// Two sources of the same precedence which disagree, found by `Composer::conflicts`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Conflict {
+    /// Table of the conflict.
+    pub table: String,
+
+    /// Chain of the conflict.
+    pub chain: String,
+
+    /// Names of the sources, in the order they were added.
+    pub sources: (String, String),
+
+    /// What the sources disagree on.
+    pub reason: String,
+}
+
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} and {} {} in {}/{}",
+            self.sources.0, self.sources.1, self.reason, self.table, self.chain
+        )
+    }
+}
+
+struct Source {
+    name: String,
+    precedence: u32,
+    tables: Vec<SavedTable>,
+}
+
+/// Merges rulesets in the format of `iptables-save` (or from `json::from_json`) into one.
+///
+/// Each source has a precedence. The policies of the built-in chains are the ones of the
+/// source with the highest precedence declaring them, and the rules of a chain are ordered by
+/// decreasing precedence of their sources, then by order of addition, so that the rules of the
+/// higher precedences are matched first. Rules found in several sources are kept once.
+///
+/// Sources of the same precedence conflict when they set different policies to a chain, or
+/// different targets to the same matches of a chain; nothing is composed until the conflicts
+/// are solved, e.g. by giving different precedences to the sources.
+#[derive(Default)]
+pub struct Composer {
+    sources: Vec<Source>,
+}
+
+impl Composer {
+    /// Creates a composer without any source.
+    pub fn new() -> Composer {
+        Composer::default()
+    }
+
+    /// Adds the `tables` of the source `name` with the given `precedence`.
+    pub fn add(&mut self, name: &str, precedence: u32, tables: Vec<SavedTable>) {
+        self.sources.push(Source {
+            name: name.to_string(),
+            precedence,
+            tables,
+        });
+    }
+
+    /// Adds the source `name` given in the format of `iptables-save`.
+    pub fn add_save(&mut self, name: &str, precedence: u32, data: &str) {
+        self.add(name, precedence, parse_save(data));
+    }
+
+    /// Returns the conflicts between the sources of the same precedence.
+    pub fn conflicts(&self) -> Vec<Conflict> {
+        let mut conflicts = Vec::new();
+        for (i, first) in self.sources.iter().enumerate() {
+            for second in &self.sources[i + 1..] {
+                if first.precedence == second.precedence {
+                    conflicts.extend(source_conflicts(first, second));
+                }
+            }
+        }
+        conflicts
+    }
+
+    /// Composes the ruleset, failing with all the conflicts if there are any.
+    pub fn compose(&self) -> Result<Vec<SavedTable>, Box<dyn Error>> {
+        let conflicts = self.conflicts();
+        if !conflicts.is_empty() {
+            let conflicts = conflicts.iter().map(Conflict::to_string);
+            return Err(error_from_str(&format!(
+                "conflicting sources: {}",
+                conflicts.collect::<Vec<_>>().join("; ")
+            )));
+        }
+
+        // The sort is stable, so sources of the same precedence keep their order
+        let mut sources = self.sources.iter().collect::<Vec<_>>();
+        sources.sort_by_key(|source| Reverse(source.precedence));
+        let mut composed: Vec<SavedTable> = Vec::new();
+        let mut normalized: Vec<Vec<String>> = Vec::new();
+        for table in sources.iter().flat_map(|source| &source.tables) {
+            let index = match composed.iter().position(|t| t.name == table.name) {
+                Some(index) => index,
+                None => {
+                    composed.push(SavedTable {
+                        name: table.name.clone(),
+                        ..Default::default()
+                    });
+                    normalized.push(Vec::new());
+                    composed.len() - 1
+                }
+            };
+            let target = &mut composed[index];
+            for chain in &table.chains {
+                if !target.chains.iter().any(|c| c.name == chain.name) {
+                    target.chains.push(chain.clone());
+                }
+            }
+            for rule in &table.rules {
+                let rule_normalized = normalize_rule(rule);
+                if !normalized[index].contains(&rule_normalized) {
+                    target.rules.push(rule.clone());
+                    normalized[index].push(rule_normalized);
+                }
+            }
+        }
+        Ok(composed)
+    }
+}
+
+#[cfg(not(feature = "parse-only"))]
+impl IPTables {
+    /// Composes the ruleset of the `composer`, then restores it. Nothing is applied if the
+    /// sources conflict.
+    pub fn apply_composed(
+        &self,
+        composer: &Composer,
+        options: RestoreOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        self.restore(&format_save(&composer.compose()?), options)
+    }
+}
+
+/// Returns the conflicts between two sources of the same precedence.
+fn source_conflicts(first: &Source, second: &Source) -> Vec<Conflict> {
+    let mut conflicts = Vec::new();
+    let conflict = |table: &str, chain: &str, reason: String| Conflict {
+        table: table.to_string(),
+        chain: chain.to_string(),
+        sources: (first.name.clone(), second.name.clone()),
+        reason,
+    };
+    for table in &first.tables {
+        let other = match second.tables.iter().find(|t| t.name == table.name) {
+            Some(other) => other,
+            None => continue,
+        };
+        for chain in &table.chains {
+            let policy = other
+                .chains
+                .iter()
+                .find(|c| c.name == chain.name)
+                .and_then(|c| c.policy.as_ref());
+            if let (Some(policy), Some(other_policy)) = (&chain.policy, policy) {
+                if policy != other_policy {
+                    let reason = format!("set different policies ({}, {})", policy, other_policy);
+                    conflicts.push(conflict(&table.name, &chain.name, reason));
+                }
+            }
+        }
+        let other_rules = other
+            .rules
+            .iter()
+            .map(|r| split_target(r))
+            .collect::<Vec<_>>();
+        for (chain, matches, target) in table.rules.iter().map(|r| split_target(r)) {
+            let different = other_rules
+                .iter()
+                .find(|(c, m, t)| *c == chain && *m == matches && *t != target);
+            if let Some((_, _, other_target)) = different {
+                let reason = format!(
+                    "set different targets ({}, {}) to '{}'",
+                    target, other_target, matches
+                );
+                conflicts.push(conflict(&table.name, &chain, reason));
+            }
+        }
+    }
+    conflicts
+}
+
+/// Splits a rule in the `-A CHAIN ...` form into its chain, its matches and its target (from
+/// `-j` or `-g`), normalized by `normalize_rule`.
+fn split_target(rule: &str) -> (String, String, String) {
+    let rule = Rule::parse(rule);
+    let args = split_rule(&rule.spec);
+    let index = args
+        .iter()
+        .position(|arg| matches!(arg.as_str(), "-j" | "--jump" | "-g" | "--goto"))
+        .unwrap_or(args.len());
+    (
+        rule.chain,
+        join_rule(&args[..index]),
+        join_rule(&args[index..]),
+    )
+}
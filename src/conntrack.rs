@@ -0,0 +1,66 @@
+//! Conntrack zones and helpers assigned by the CT target of the raw table.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::IPTables;
+use std::error::Error;
+
+/// Options of the CT target, which sets up the connection tracking of the packet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ct {
+    /// Conntrack zone of the packet (`--zone`), separating the connections of different
+    /// networks even if their addresses overlap.
+    pub zone: Option<u16>,
+
+    /// Conntrack helper of the connection (`--helper`), e.g. 'ftp' or 'sip'.
+    pub helper: Option<String>,
+}
+
+impl Ct {
+    /// Returns the arguments of the target, without the leading `-j CT`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if let Some(zone) = self.zone {
+            args.extend(["--zone".to_string(), zone.to_string()]);
+        }
+        if let Some(helper) = &self.helper {
+            args.extend(["--helper".to_string(), helper.clone()]);
+        }
+        args
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match &self.helper {
+            Some(helper) if helper.is_empty() || helper.contains(char::is_whitespace) => {
+                Err(format!("invalid conntrack helper '{}'", helper))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl IPTables {
+    /// Appends a rule, matching `matches`, with the CT target to the chain of the raw table
+    /// (the only table where the CT target is valid).
+    pub fn append_ct(
+        &self,
+        chain: &str,
+        matches: RuleBuilder,
+        ct: Ct,
+    ) -> Result<(), Box<dyn Error>> {
+        let rule = matches.target(Target::Ct(ct)).build()?;
+        self.append("raw", chain, &rule)
+    }
+
+    /// Assigns the packets received on `interface` to the conntrack `zone`.
+    /// Succeeds without doing anything if the rule already exists.
+    pub fn set_ct_zone(&self, interface: &str, zone: u16) -> Result<(), Box<dyn Error>> {
+        let rule = RuleBuilder::new()
+            .in_interface(interface)
+            .target(Target::Ct(Ct {
+                zone: Some(zone),
+                helper: None,
+            }))
+            .build()?;
+        self.append_idempotent("raw", "PREROUTING", &rule)
+    }
+}
@@ -0,0 +1,59 @@
+//! Copy of rules between chains and tables.
+
+use crate::identity::strip_identity;
+use crate::parse::{parse_rules, Rule};
+use crate::IPTables;
+use std::error::Error;
+
+impl IPTables {
+    /// Appends the rules of the `src_table`/`src_chain` matching the `filter` to the
+    /// `dst_table`/`dst_chain` in a single transaction, e.g. to duplicate the protections of
+    /// INPUT onto FORWARD. Identity comments are not copied; new ones are generated if
+    /// `auto_identity` is enabled.
+    /// Returns the number of copied rules.
+    pub fn copy_rules<P>(
+        &self,
+        src_table: &str,
+        src_chain: &str,
+        dst_table: &str,
+        dst_chain: &str,
+        filter: P,
+    ) -> Result<usize, Box<dyn Error>>
+    where
+        P: Fn(&Rule) -> bool,
+    {
+        self.copy_rules_with(src_table, src_chain, dst_table, dst_chain, |rule| {
+            filter(&rule).then_some(rule)
+        })
+    }
+
+    /// Copies the rules of the `src_table`/`src_chain` to the `dst_table`/`dst_chain` like
+    /// `copy_rules`, passing each of them through the `transform`, which may rewrite the rule
+    /// (e.g. its interfaces, addresses or comment) or skip it by returning `None`.
+    /// Returns the number of copied rules.
+    pub fn copy_rules_with<T>(
+        &self,
+        src_table: &str,
+        src_chain: &str,
+        dst_table: &str,
+        dst_chain: &str,
+        mut transform: T,
+    ) -> Result<usize, Box<dyn Error>>
+    where
+        T: FnMut(Rule) -> Option<Rule>,
+    {
+        let rules = parse_rules(&self.list(src_table, src_chain)?.join("\n"))
+            .into_iter()
+            .filter(|rule| rule.chain == src_chain)
+            .filter_map(&mut transform)
+            .map(|rule| {
+                self.with_identity(&strip_identity(&rule.spec))
+                    .map(|spec| spec.into_owned())
+            })
+            .collect::<Result<Vec<String>, Box<dyn Error>>>()?;
+        if !rules.is_empty() {
+            self.append_chain_rules(dst_table, dst_chain, &rules)?;
+        }
+        Ok(rules.len())
+    }
+}
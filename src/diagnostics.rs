@@ -0,0 +1,134 @@
+//! Report of the state of the netfilter environment, e.g. for support bundles.
+
+use crate::error::IPTError;
+use crate::restore::XTABLES_LOCK;
+use crate::variant::Variant;
+use crate::IPTables;
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Directories searched for the utilities besides the `PATH`, as they are often missing from
+/// the `PATH` of unprivileged users.
+const SBIN_DIRS: [&str; 4] = ["/usr/sbin", "/sbin", "/usr/local/sbin", "/usr/bin"];
+
+/// An iptables utility found by `IPTables::diagnostics`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Utility {
+    /// Name of the utility (e.g. 'iptables-restore').
+    pub name: String,
+
+    /// Path of the utility, `None` if it was not found.
+    pub path: Option<PathBuf>,
+
+    /// Version printed by the utility, `None` if it could not be run.
+    pub version: Option<String>,
+}
+
+/// State of the netfilter environment, as reported by `IPTables::diagnostics`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostics {
+    /// iptables, ip6tables and their save and restore utilities.
+    pub utilities: Vec<Utility>,
+
+    /// The netfilter backend used by the command of the handle, `None` if it is unknown.
+    pub variant: Option<Variant>,
+
+    /// Path of the xtables lock file.
+    pub lock_file: PathBuf,
+
+    /// Whether the lock file can be opened for writing, as iptables needs to; `None` if it does
+    /// not exist yet, as it is created by the first command waiting for the lock.
+    pub lock_accessible: Option<bool>,
+
+    /// Tables loaded by the kernel for the legacy backend, from `/proc/net/ip_tables_names`
+    /// (or `ip6_tables_names`); `None` if the file does not exist, i.e. the `ip_tables` module
+    /// is not loaded.
+    pub kernel_tables: Option<Vec<String>>,
+
+    /// Whether the rules can be listed, i.e. the process has the needed privileges.
+    pub permitted: bool,
+
+    /// Error of listing the rules, if any.
+    pub permission_error: Option<String>,
+}
+
+impl IPTables {
+    /// Collects the state of the netfilter environment. Failing checks are reported rather than
+    /// returned as errors, so the report can be gathered on broken systems.
+    pub fn diagnostics(&self) -> Diagnostics {
+        let utilities = ["iptables", "ip6tables"]
+            .iter()
+            .flat_map(|cmd| ["", "-save", "-restore"].map(|suffix| format!("{}{}", cmd, suffix)))
+            .map(|name| Utility {
+                path: find_program(&name),
+                version: self.utility_version(&name),
+                name,
+            })
+            .collect();
+
+        let names = match self.cmd {
+            "ip6tables" => "/proc/net/ip6_tables_names",
+            _ => "/proc/net/ip_tables_names",
+        };
+        let kernel_tables = fs::read_to_string(names)
+            .ok()
+            .map(|names| names.lines().map(String::from).collect());
+
+        let variant = self.variant().ok();
+        let listing = self.run_checked(&["-S"]);
+        Diagnostics {
+            utilities,
+            variant,
+            lock_file: PathBuf::from(XTABLES_LOCK),
+            lock_accessible: match OpenOptions::new().write(true).open(XTABLES_LOCK) {
+                Ok(_) => Some(true),
+                Err(error) if error.kind() == ErrorKind::NotFound => None,
+                Err(_) => Some(false),
+            },
+            kernel_tables,
+            permitted: listing.is_ok(),
+            permission_error: listing
+                .err()
+                .map(|error| match error.downcast_ref::<IPTError>() {
+                    Some(error) if error.is_permission() => {
+                        format!("missing privileges: {}", error)
+                    }
+                    _ => error.to_string(),
+                }),
+        }
+    }
+
+    /// Returns the first line printed by the utility `name` with `--version`.
+    fn utility_version(&self, name: &str) -> Option<String> {
+        let output = self.run_program(name, &["--version"], None).ok()?;
+        if !output.status.success() {
+            return None;
+        }
+        let version = String::from_utf8_lossy(&output.stdout);
+        version.lines().next().map(|line| line.trim().to_string())
+    }
+}
+
+/// Searches the program `name` in the `PATH` and the usual directories of the utilities.
+fn find_program(name: &str) -> Option<PathBuf> {
+    let path = env::var_os("PATH").unwrap_or_default();
+    env::split_paths(&path)
+        .chain(SBIN_DIRS.iter().map(PathBuf::from))
+        .map(|dir| dir.join(name))
+        .find(|candidate| is_executable(candidate))
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    fs::metadata(path)
+        .map(|metadata| metadata.is_file() && metadata.permissions().mode() & 0o111 != 0)
+        .unwrap_or(false)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
@@ -0,0 +1,155 @@
+//! Detection of the changes made to the ruleset since a baseline, e.g. by other tools or by
+//! hand.
+
+use crate::parse::{normalize_rule, parse_save, subtract, SavedTable};
+use crate::provenance::Provenance;
+use crate::IPTables;
+use std::error::Error;
+
+/// A ruleset stored as a baseline, e.g. after applying the configuration.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Snapshot {
+    /// Tables of the ruleset.
+    pub tables: Vec<SavedTable>,
+}
+
+impl Snapshot {
+    /// Parses a ruleset stored in the format of `iptables-save`.
+    pub fn parse(data: &str) -> Snapshot {
+        Snapshot {
+            tables: parse_save(data),
+        }
+    }
+}
+
+/// Differences between the live ruleset and a baseline, as reported by `IPTables::check_drift`.
+/// Chains are given as (table, chain) and rules as (table, rule) in the `-A CHAIN ...` form.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DriftReport {
+    /// Chains which are not in the baseline.
+    pub added_chains: Vec<(String, String)>,
+
+    /// Chains of the baseline which no longer exist.
+    pub missing_chains: Vec<(String, String)>,
+
+    /// Rules which are not in the baseline, e.g. added by others.
+    pub added_rules: Vec<(String, String)>,
+
+    /// Rules of the baseline which no longer exist.
+    pub missing_rules: Vec<(String, String)>,
+
+    /// Chains whose rules are in another order than in the baseline, leaving aside the added
+    /// and missing rules.
+    pub reordered_chains: Vec<(String, String)>,
+}
+
+impl DriftReport {
+    /// Indicates if the live ruleset is the same as the baseline.
+    pub fn is_empty(&self) -> bool {
+        self.added_chains.is_empty()
+            && self.missing_chains.is_empty()
+            && self.added_rules.is_empty()
+            && self.missing_rules.is_empty()
+            && self.reordered_chains.is_empty()
+    }
+
+    /// Returns the added and missing rules which record their provenance, as (table, rule,
+    /// provenance), e.g. to tell which source a missing rule came from.
+    pub fn provenances(&self) -> Vec<(&str, &str, Provenance)> {
+        self.added_rules
+            .iter()
+            .chain(&self.missing_rules)
+            .filter_map(|(table, rule)| {
+                Provenance::of_rule(rule)
+                    .map(|provenance| (table.as_str(), rule.as_str(), provenance))
+            })
+            .collect()
+    }
+}
+
+impl IPTables {
+    /// Takes a snapshot of the live ruleset, to be used later as a baseline.
+    pub fn snapshot(&self) -> Result<Snapshot, Box<dyn Error>> {
+        Ok(Snapshot::parse(&self.save_ruleset()?))
+    }
+
+    /// Compares the live ruleset to the `baseline`. Rules are compared as normalized by
+    /// `normalize_rule`; tables which are not in the baseline are only reported if they have
+    /// rules, as the kernel creates the tables when they are first used.
+    pub fn check_drift(&self, baseline: &Snapshot) -> Result<DriftReport, Box<dyn Error>> {
+        Ok(drift(&baseline.tables, &self.snapshot()?.tables))
+    }
+}
+
+/// Compares the `live` tables to the `baseline` ones.
+pub(crate) fn drift(baseline: &[SavedTable], live: &[SavedTable]) -> DriftReport {
+    let empty = SavedTable::default();
+    let mut report = DriftReport::default();
+    let live_only = live
+        .iter()
+        .filter(|t| !t.rules.is_empty() && !baseline.iter().any(|b| b.name == t.name));
+    for table in baseline.iter().chain(live_only) {
+        let old = baseline
+            .iter()
+            .find(|t| t.name == table.name)
+            .unwrap_or(&empty);
+        let new = live.iter().find(|t| t.name == table.name).unwrap_or(&empty);
+
+        for chain in &new.chains {
+            if !old.chains.iter().any(|c| c.name == chain.name) {
+                report
+                    .added_chains
+                    .push((table.name.clone(), chain.name.clone()));
+            }
+        }
+        for chain in &old.chains {
+            if !new.chains.iter().any(|c| c.name == chain.name) {
+                report
+                    .missing_chains
+                    .push((table.name.clone(), chain.name.clone()));
+            }
+        }
+
+        let old_rules = old
+            .rules
+            .iter()
+            .map(|r| normalize_rule(r))
+            .collect::<Vec<_>>();
+        let new_rules = new
+            .rules
+            .iter()
+            .map(|r| normalize_rule(r))
+            .collect::<Vec<_>>();
+        let added = subtract(new_rules.clone(), &old_rules);
+        let missing = subtract(old_rules.clone(), &new_rules);
+
+        // Rules present in both versions must be in the same order in each chain
+        for chain in old
+            .chains
+            .iter()
+            .filter(|c| new.chains.iter().any(|n| n.name == c.name))
+        {
+            let prefix = format!("-A {} ", chain.name);
+            let kept = |rules: &[String], removed: &[String]| -> Vec<String> {
+                let in_chain = rules
+                    .iter()
+                    .filter(|r| r.starts_with(&prefix))
+                    .cloned()
+                    .collect();
+                subtract(in_chain, removed)
+            };
+            if kept(&old_rules, &missing) != kept(&new_rules, &added) {
+                report
+                    .reordered_chains
+                    .push((table.name.clone(), chain.name.clone()));
+            }
+        }
+
+        let with_table = |rule: String| (table.name.clone(), rule);
+        report.added_rules.extend(added.into_iter().map(with_table));
+        report
+            .missing_rules
+            .extend(missing.into_iter().map(with_table));
+    }
+    report
+}
@@ -1,20 +1,22 @@
+#[cfg(not(feature = "parse-only"))]
+use crate::parse::join_rule;
 use std::convert::From;
 use std::error::Error;
-use std::fmt;
-use std::process::Output;
+#[cfg(not(feature = "parse-only"))]
+use std::ffi::OsStr;
+use std::io;
+use std::process::{ExitCode, Output};
+use std::time::Duration;
+use thiserror::Error;
 
-#[derive(Debug)]
+/// Error reported by iptables, through its exit code and standard error.
+#[derive(Debug, Error)]
+#[error("code: {code}, msg: {msg}")]
 pub struct IptablesError {
     pub code: i32,
     pub msg: String,
 }
 
-impl fmt::Display for IptablesError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "code: {}, msg: {}", self.code, self.msg)
-    }
-}
-
 impl From<Output> for IptablesError {
     fn from(output: Output) -> Self {
         Self {
@@ -24,4 +26,252 @@ impl From<Output> for IptablesError {
     }
 }
 
-impl Error for IptablesError {}
+/// Errors raised by this crate itself rather than reported by iptables.
+/// Both error types are `Send + Sync + 'static`, so they can be wrapped by other error types.
+#[derive(Debug, Error)]
+#[non_exhaustive]
+pub enum IPTError {
+    /// The command did not finish within the configured timeout and was killed.
+    #[error("command timed out after {0:?}")]
+    Timeout(Duration),
+
+    /// The named operation succeeded but its effect could not be found afterwards.
+    #[error("{0} did not take effect")]
+    VerificationFailed(String),
+
+    /// The xtables lock was still held by another process after the last attempt.
+    #[error("unable to acquire the xtables lock")]
+    Locked,
+
+    /// The command was rejected by the `PolicyFilter` of the handle, e.g. because it touches a
+    /// chain which is not allowed.
+    #[error("denied by policy: {0}")]
+    PolicyDenied(String),
+
+    /// The rule given as a string was rejected before running any command, e.g. because it
+    /// contains control characters or unbalanced quotes.
+    #[error("invalid rule: {0}")]
+    InvalidRule(String),
+
+    /// The changes of `IPTables::apply_and_probe` failed (or the probe did), then restoring
+    /// the previous ruleset failed too.
+    #[error("{cause}, then the rollback failed: {source}")]
+    RollbackFailed {
+        /// Why the ruleset was rolled back, e.g. the error of the changes.
+        cause: String,
+
+        /// The error of the rollback.
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+
+    /// An executed command failed, e.g. the `source` is the error reported by iptables.
+    #[error("{operation} failed ({command}): {source}")]
+    Command {
+        /// The operation which ran the command, e.g. "append nat MYCHAIN".
+        operation: String,
+
+        /// The program and its arguments, quoted as a shell would need them.
+        command: String,
+
+        /// The reason of the failure.
+        #[source]
+        source: Box<dyn Error + Send + Sync>,
+    },
+}
+
+impl IptablesError {
+    /// Classifies the error using the message printed by iptables, as the exit codes are too
+    /// coarse to tell the failures apart.
+    fn kind(&self) -> io::ErrorKind {
+        let msg = self.msg.to_lowercase();
+        if msg.contains("holding the xtables lock") || msg.contains("temporarily unavailable") {
+            io::ErrorKind::WouldBlock
+        } else if msg.contains("permission denied") || msg.contains("must be root") {
+            io::ErrorKind::PermissionDenied
+        } else if msg.contains("no chain/target/match by that name")
+            || msg.contains("does a matching rule exist")
+            || msg.contains("does not exist")
+        {
+            io::ErrorKind::NotFound
+        } else {
+            io::ErrorKind::Other
+        }
+    }
+}
+
+impl IPTError {
+    /// Checks if the operation may succeed when retried later, e.g. after a timeout or while
+    /// another process was holding the xtables lock.
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self.kind(),
+            io::ErrorKind::TimedOut | io::ErrorKind::WouldBlock | io::ErrorKind::Interrupted
+        )
+    }
+
+    /// Checks if the operation failed because of missing privileges (e.g. CAP_NET_ADMIN).
+    pub fn is_permission(&self) -> bool {
+        self.kind() == io::ErrorKind::PermissionDenied
+    }
+
+    /// Checks if the operation failed because the table, chain, rule or program does not exist.
+    pub fn is_not_found(&self) -> bool {
+        self.kind() == io::ErrorKind::NotFound
+    }
+
+    /// Checks if the operation was rejected by a `PolicyFilter`.
+    pub fn is_policy_denied(&self) -> bool {
+        match self {
+            IPTError::PolicyDenied(_) => true,
+            IPTError::Command { source, .. } => matches!(
+                source.downcast_ref::<IPTError>(),
+                Some(IPTError::PolicyDenied(_))
+            ),
+            _ => false,
+        }
+    }
+
+    /// Suggests an exit code for a command-line tool failing with this error: 75 (EX_TEMPFAIL)
+    /// if it is retryable, 77 (EX_NOPERM) for missing privileges, otherwise the exit code of
+    /// iptables if it failed, or 1.
+    pub fn exit_code(&self) -> ExitCode {
+        if self.is_retryable() {
+            return ExitCode::from(75);
+        }
+        if self.is_permission() {
+            return ExitCode::from(77);
+        }
+        let code = match self {
+            IPTError::Command { source, .. } => source
+                .downcast_ref::<IptablesError>()
+                .and_then(|error| u8::try_from(error.code).ok())
+                .filter(|code| *code != 0),
+            _ => None,
+        };
+        ExitCode::from(code.unwrap_or(1))
+    }
+
+    /// Classifies the error, looking through the source of `IPTError::Command`.
+    fn kind(&self) -> io::ErrorKind {
+        match self {
+            IPTError::Timeout(_) => io::ErrorKind::TimedOut,
+            IPTError::Locked => io::ErrorKind::WouldBlock,
+            IPTError::PolicyDenied(_) => io::ErrorKind::PermissionDenied,
+            IPTError::InvalidRule(_) => io::ErrorKind::InvalidInput,
+            IPTError::Command { source, .. } => {
+                if let Some(error) = source.downcast_ref::<IptablesError>() {
+                    error.kind()
+                } else if let Some(error) = source.downcast_ref::<IPTError>() {
+                    error.kind()
+                } else if let Some(error) = source.downcast_ref::<io::Error>() {
+                    error.kind()
+                } else {
+                    io::ErrorKind::Other
+                }
+            }
+            _ => io::ErrorKind::Other,
+        }
+    }
+
+    /// Wraps the `error` raised while running `program` with `args` in `IPTError::Command`,
+    /// unless it already carries the context of a command.
+    #[cfg(not(feature = "parse-only"))]
+    pub(crate) fn command<S: AsRef<OsStr>>(
+        program: &str,
+        args: &[S],
+        error: Box<dyn Error>,
+    ) -> Box<dyn Error> {
+        if matches!(error.downcast_ref(), Some(IPTError::Command { .. })) {
+            return error;
+        }
+        let args = args
+            .iter()
+            .map(|arg| arg.as_ref().to_string_lossy().into_owned())
+            .collect::<Vec<String>>();
+        let mut argv = vec![program.to_string()];
+        argv.extend(args.iter().cloned());
+        Box::new(IPTError::Command {
+            operation: operation_of(program, &args),
+            command: join_rule(&argv),
+            source: send_sync(error),
+        })
+    }
+
+    /// Wraps the `error` raised while rolling back after `cause` in `IPTError::RollbackFailed`.
+    #[cfg(not(feature = "parse-only"))]
+    pub(crate) fn rollback_failed(cause: &str, error: Box<dyn Error>) -> Box<dyn Error> {
+        Box::new(IPTError::RollbackFailed {
+            cause: cause.to_string(),
+            source: send_sync(error),
+        })
+    }
+}
+
+impl From<IPTError> for io::Error {
+    /// Converts the error to an `io::Error` whose kind follows the classification of `IPTError`,
+    /// e.g. `ErrorKind::PermissionDenied` if `is_permission` returns `true`.
+    fn from(error: IPTError) -> Self {
+        io::Error::new(error.kind(), error)
+    }
+}
+
+/// Names the operation performed by a command, e.g. "append nat MYCHAIN" for
+/// `iptables -t nat -A MYCHAIN ...`, or "ipset create" for `ipset create ...`.
+#[cfg(not(feature = "parse-only"))]
+fn operation_of(program: &str, args: &[String]) -> String {
+    let mut table = "filter";
+    let mut operation = None;
+    let mut iter = args.iter().peekable();
+    while let Some(arg) = iter.next() {
+        let name = match arg.as_str() {
+            "-t" | "--table" => {
+                table = iter.next().map(String::as_str).unwrap_or(table);
+                continue;
+            }
+            "-A" | "--append" => "append",
+            "-C" | "--check" => "check",
+            "-D" | "--delete" => "delete",
+            "-I" | "--insert" => "insert",
+            "-R" | "--replace" => "replace",
+            "-S" | "--list-rules" => "list",
+            "-L" | "--list" => "list",
+            "-F" | "--flush" => "flush",
+            "-Z" | "--zero" => "zero",
+            "-N" | "--new-chain" => "new chain",
+            "-X" | "--delete-chain" => "delete chain",
+            "-P" | "--policy" => "set policy",
+            "-E" | "--rename-chain" => "rename chain",
+            _ => continue,
+        };
+        let chain = iter.next_if(|arg| !arg.starts_with('-'));
+        operation = Some((name, chain));
+        break;
+    }
+    match operation {
+        Some((name, Some(chain))) => format!("{} {} {}", name, table, chain),
+        Some((name, None)) => format!("{} {}", name, table),
+        None => match args.first().filter(|arg| !arg.starts_with('-')) {
+            Some(subcommand) => format!("{} {}", program, subcommand),
+            None => program.to_string(),
+        },
+    }
+}
+
+/// Converts the `error` to a thread-safe error, preserving the known error types of this crate
+/// and of the standard library, and keeping only the message of the others.
+#[cfg(not(feature = "parse-only"))]
+fn send_sync(error: Box<dyn Error>) -> Box<dyn Error + Send + Sync> {
+    let error = match error.downcast::<IptablesError>() {
+        Ok(error) => return error,
+        Err(error) => error,
+    };
+    let error = match error.downcast::<IPTError>() {
+        Ok(error) => return error,
+        Err(error) => error,
+    };
+    match error.downcast::<io::Error>() {
+        Ok(error) => error,
+        Err(error) => error.to_string().into(),
+    }
+}
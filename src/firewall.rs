@@ -0,0 +1,131 @@
+//! The handles of both families behind a single interface, dispatching each rule to the
+//! family of its addresses.
+
+use crate::builder::RuleBuilder;
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+
+/// Handles of iptables and ip6tables dispatching each rule to the handle of its family, as
+/// given by `RuleBuilder::is_ipv6`: rules with IPv4 addresses go to iptables, rules with IPv6
+/// addresses go to ip6tables, and rules without addresses nor options of a single family go
+/// to both. Rules mixing both families are rejected before running any command.
+pub struct Firewall {
+    /// The iptables handle.
+    pub ipv4: IPTables,
+
+    /// The ip6tables handle.
+    pub ipv6: IPTables,
+}
+
+impl Firewall {
+    /// Creates the handles of both families with `new`.
+    pub fn new() -> Result<Firewall, Box<dyn Error>> {
+        Firewall::from_handles(crate::new(false)?, crate::new(true)?)
+    }
+
+    /// Creates the firewall from existing handles, e.g. with custom backends.
+    pub fn from_handles(ipv4: IPTables, ipv6: IPTables) -> Result<Firewall, Box<dyn Error>> {
+        if ipv4.cmd != "iptables" || ipv6.cmd != "ip6tables" {
+            return Err(error_from_str(
+                "the handles must be an iptables handle and an ip6tables handle",
+            ));
+        }
+        Ok(Firewall { ipv4, ipv6 })
+    }
+
+    /// Returns the handles which the `rule` is dispatched to.
+    pub fn handles(&self, rule: &RuleBuilder) -> Result<Vec<&IPTables>, Box<dyn Error>> {
+        Ok(match rule.is_ipv6()? {
+            Some(true) => vec![&self.ipv6],
+            Some(false) => vec![&self.ipv4],
+            None => vec![&self.ipv4, &self.ipv6],
+        })
+    }
+
+    /// Checks for the existence of the `rule` in the table/chain of each of its handles.
+    pub fn exists(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &RuleBuilder,
+    ) -> Result<bool, Box<dyn Error>> {
+        let built = rule.build()?;
+        for ipt in self.handles(rule)? {
+            if !ipt.exists(table, chain, &built)? {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+
+    /// Inserts the `rule` at `position` in the table/chain of each of its handles.
+    pub fn insert(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &RuleBuilder,
+        position: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        let built = rule.build()?;
+        for ipt in self.handles(rule)? {
+            ipt.insert(table, chain, &built, position)?;
+        }
+        Ok(())
+    }
+
+    /// Appends the `rule` to the table/chain of each of its handles.
+    pub fn append(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &RuleBuilder,
+    ) -> Result<(), Box<dyn Error>> {
+        let built = rule.build()?;
+        for ipt in self.handles(rule)? {
+            ipt.append(table, chain, &built)?;
+        }
+        Ok(())
+    }
+
+    /// Appends the `rule` to the table/chain of each of its handles, unless it exists there.
+    pub fn append_idempotent(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &RuleBuilder,
+    ) -> Result<(), Box<dyn Error>> {
+        let built = rule.build()?;
+        for ipt in self.handles(rule)? {
+            ipt.append_idempotent(table, chain, &built)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the `rule` from the table/chain of each of its handles.
+    pub fn delete(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &RuleBuilder,
+    ) -> Result<(), Box<dyn Error>> {
+        let built = rule.build()?;
+        for ipt in self.handles(rule)? {
+            ipt.delete(table, chain, &built)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the `rule` from the table/chain of each of its handles, if it exists there.
+    pub fn delete_idempotent(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &RuleBuilder,
+    ) -> Result<(), Box<dyn Error>> {
+        let built = rule.build()?;
+        for ipt in self.handles(rule)? {
+            ipt.delete_idempotent(table, chain, &built)?;
+        }
+        Ok(())
+    }
+}
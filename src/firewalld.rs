@@ -0,0 +1,237 @@
+//! Passthrough of the commands to firewalld, so the rules survive its reloads.
+
+use crate::backend::Backend;
+use crate::error_from_str;
+use crate::parse::split_rule;
+use crate::IPTables;
+use std::error::Error;
+use std::ffi::OsString;
+use std::process::Output;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Backend routing the commands of iptables and ip6tables through
+/// `firewall-cmd --direct --passthrough`, instead of running them directly.
+///
+/// firewalld flushes the rules it does not know about when it reloads; the rules added
+/// through passthrough are kept. Saving the ruleset and probing the utilities go to the wrapped
+/// backend. Restoring is only supported without flushing the tables, and is then applied rule
+/// by rule, i.e. not atomically.
+pub struct FirewalldBackend {
+    backend: Arc<dyn Backend>,
+    permanent: bool,
+}
+
+impl FirewalldBackend {
+    /// Creates the passthrough, running `firewall-cmd` with the wrapped `backend`.
+    pub fn new(backend: Arc<dyn Backend>) -> FirewalldBackend {
+        FirewalldBackend {
+            backend,
+            permanent: false,
+        }
+    }
+
+    /// Also records the created chains and the appended or inserted rules in the permanent
+    /// configuration of firewalld when `permanent` is `true`, so they survive restarts of
+    /// firewalld and reboots. Deleting them removes them from the permanent configuration.
+    pub fn set_permanent(&mut self, permanent: bool) {
+        self.permanent = permanent;
+    }
+
+    /// Runs an iptables command given by `args` through firewall-cmd.
+    fn passthrough(
+        &self,
+        family: &str,
+        args: &[String],
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        // firewalld serializes the commands itself
+        let args = args
+            .iter()
+            .filter(|arg| *arg != "-w" && *arg != "--wait")
+            .cloned()
+            .collect::<Vec<_>>();
+        let mut command = vec!["--direct", "--passthrough", family];
+        command.extend(args.iter().map(String::as_str));
+        let output = self.run_firewall_cmd(&command, timeout)?;
+        if !self.permanent || !output.status.success() {
+            return Ok(output);
+        }
+        match permanent_args(&args) {
+            Some((operation, args)) => {
+                let mut command = vec!["--permanent", "--direct", operation, family];
+                command.extend(args.iter().map(String::as_str));
+                self.run_firewall_cmd(&command, timeout)
+            }
+            None => Ok(output),
+        }
+    }
+
+    fn run_firewall_cmd(
+        &self,
+        args: &[&str],
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        let args = args.iter().map(OsString::from).collect::<Vec<_>>();
+        self.backend.run("firewall-cmd", &args, None, timeout)
+    }
+
+    /// Applies the rules of the `input` of iptables-restore one by one.
+    fn restore(
+        &self,
+        family: &str,
+        args: &[String],
+        input: &str,
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        if !args.iter().any(|arg| arg == "-n" || arg == "--noflush") {
+            return Err(error_from_str(
+                "firewalld can't restore whole tables, restore with flush disabled",
+            ));
+        }
+        let mut table = "filter".to_string();
+        let mut output = None;
+        for line in input.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line == "COMMIT" {
+                continue;
+            }
+            let mut commands = Vec::new();
+            if let Some(name) = line.strip_prefix('*') {
+                table = name.to_string();
+                continue;
+            } else if let Some(declaration) = line.strip_prefix(':') {
+                // Declaring a chain creates it if needed, then flushes it
+                let chain = declaration.split_whitespace().next().unwrap_or_default();
+                let exists =
+                    self.passthrough(family, &args_of(&table, &["-S", chain, "1"]), timeout)?;
+                if !exists.status.success() {
+                    commands.push(args_of(&table, &["-N", chain]));
+                }
+                commands.push(args_of(&table, &["-F", chain]));
+            } else {
+                let rule = match line.strip_prefix('[') {
+                    Some(rest) => rest.split_once(']').map(|(_, rule)| rule).unwrap_or(line),
+                    None => line,
+                };
+                let mut command = args_of(&table, &[]);
+                command.extend(split_rule(rule));
+                commands.push(command);
+            }
+            for command in commands {
+                let result = self.passthrough(family, &command, timeout)?;
+                if !result.status.success() {
+                    return Ok(result);
+                }
+                output = Some(result);
+            }
+        }
+        output.ok_or_else(|| error_from_str("nothing to restore"))
+    }
+}
+
+impl Backend for FirewalldBackend {
+    fn run(
+        &self,
+        program: &str,
+        args: &[OsString],
+        input: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        let (family, utility) = match program.strip_prefix("ip6tables") {
+            Some(utility) => ("ipv6", utility),
+            None => match program.strip_prefix("iptables") {
+                Some(utility) => ("ipv4", utility),
+                None => return self.backend.run(program, args, input, timeout),
+            },
+        };
+        let strings = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        match (utility, input) {
+            ("", _) if has_command(&strings) => self.passthrough(family, &strings, timeout),
+            ("-restore", Some(input)) => self.restore(family, &strings, input, timeout),
+            _ => self.backend.run(program, args, input, timeout),
+        }
+    }
+}
+
+impl IPTables {
+    /// Routes the commands of this handle through firewalld if it is running, see
+    /// `FirewalldBackend`. Returns `true` if firewalld is running.
+    pub fn use_firewalld(&mut self, permanent: bool) -> bool {
+        let running = match self
+            .backend
+            .run("firewall-cmd", &["--state".into()], None, None)
+        {
+            Ok(output) => output.status.success(),
+            Err(_) => false,
+        };
+        if running {
+            let mut backend = FirewalldBackend::new(self.backend.clone());
+            backend.set_permanent(permanent);
+            self.set_backend(Arc::new(backend));
+        }
+        running
+    }
+}
+
+/// Returns the arguments of an iptables command on the `table`.
+fn args_of(table: &str, args: &[&str]) -> Vec<String> {
+    let mut command = vec!["-t".to_string(), table.to_string()];
+    command.extend(args.iter().map(|arg| arg.to_string()));
+    command
+}
+
+/// Returns the operation (`--add-passthrough` or `--remove-passthrough`) and the arguments of
+/// the passthrough recording the command in the permanent configuration, or `None` if it is not
+/// recorded. Deleting a rule or a chain removes the passthrough which appended or created it.
+fn permanent_args(args: &[String]) -> Option<(&'static str, Vec<String>)> {
+    let (index, operation, replacement) = args.iter().enumerate().find_map(|(i, arg)| match arg
+        .as_str()
+    {
+        "-A" | "--append" | "-I" | "--insert" | "-N" | "--new-chain" => {
+            Some((i, "--add-passthrough", arg.as_str()))
+        }
+        "-D" | "--delete" => Some((i, "--remove-passthrough", "-A")),
+        "-X" | "--delete-chain" => Some((i, "--remove-passthrough", "-N")),
+        _ => None,
+    })?;
+    let mut args = args.to_vec();
+    args[index] = replacement.to_string();
+    Some((operation, args))
+}
+
+/// Checks if the arguments contain a command, unlike e.g. `--version`.
+fn has_command(args: &[String]) -> bool {
+    args.iter().any(|arg| {
+        matches!(
+            arg.as_str(),
+            "-A" | "--append"
+                | "-C"
+                | "--check"
+                | "-D"
+                | "--delete"
+                | "-I"
+                | "--insert"
+                | "-R"
+                | "--replace"
+                | "-S"
+                | "--list-rules"
+                | "-L"
+                | "--list"
+                | "-F"
+                | "--flush"
+                | "-Z"
+                | "--zero"
+                | "-N"
+                | "--new-chain"
+                | "-X"
+                | "--delete-chain"
+                | "-P"
+                | "--policy"
+                | "-E"
+                | "--rename-chain"
+        )
+    })
+}
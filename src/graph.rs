@@ -0,0 +1,125 @@
+//! Graph of the jumps between the chains of a table.
+
+use crate::parse::Rule;
+
+/// A jump (`-j`) or goto (`-g`) from a rule to a user-defined chain.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Jump {
+    /// Chain containing the rule.
+    pub from: String,
+
+    /// Chain which the rule jumps to.
+    pub to: String,
+
+    /// Position (starting from 1) of the rule in its chain.
+    pub position: i32,
+}
+
+/// The chains of a table and the jumps between them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainGraph {
+    /// Names of the chains of the table.
+    pub chains: Vec<String>,
+
+    /// Jumps between the chains, in the order of the rules.
+    pub jumps: Vec<Jump>,
+}
+
+impl ChainGraph {
+    /// Builds the graph of the `chains` from the `rules` of their table.
+    /// Targets which are not one of the `chains` (e.g. ACCEPT or LOG) are not considered as jumps.
+    pub fn new(chains: Vec<String>, rules: &[Rule]) -> ChainGraph {
+        let jumps = rules
+            .iter()
+            .filter_map(|rule| {
+                rule.target()
+                    .filter(|target| chains.contains(target))
+                    .map(|to| Jump {
+                        from: rule.chain.clone(),
+                        to,
+                        position: rule.position,
+                    })
+            })
+            .collect();
+        ChainGraph { chains, jumps }
+    }
+
+    /// Returns the jumps from the `chain`.
+    pub fn jumps_from<'a>(&'a self, chain: &'a str) -> impl Iterator<Item = &'a Jump> {
+        self.jumps.iter().filter(move |jump| jump.from == chain)
+    }
+
+    /// Returns the jumps to the `chain`.
+    pub fn jumps_to<'a>(&'a self, chain: &'a str) -> impl Iterator<Item = &'a Jump> {
+        self.jumps.iter().filter(move |jump| jump.to == chain)
+    }
+
+    /// Returns the `root` chain followed by every chain reachable from it and only through it,
+    /// i.e. the chains which would be left unreferenced if the `root` chain was deleted.
+    pub fn subtree(&self, root: &str) -> Vec<String> {
+        let mut tree = vec![root.to_string()];
+        let mut i = 0;
+        while i < tree.len() {
+            let chain = tree[i].clone();
+            for jump in self.jumps_from(&chain) {
+                if !tree.contains(&jump.to) {
+                    tree.push(jump.to.clone());
+                }
+            }
+            i += 1;
+        }
+
+        // Prune the chains also referenced from outside, until no more chain is pruned
+        loop {
+            let shared = tree
+                .iter()
+                .skip(1)
+                .position(|chain| self.jumps_to(chain).any(|jump| !tree.contains(&jump.from)));
+            match shared {
+                Some(i) => {
+                    tree.remove(i + 1);
+                }
+                None => break,
+            }
+        }
+        tree
+    }
+
+    /// Finds a loop of jumps between the chains, which the kernel would reject.
+    /// Returns the chains of the loop, starting and ending with the same chain.
+    pub fn find_cycle(&self) -> Option<Vec<String>> {
+        // 0: not visited, 1: on the current path, 2: done
+        let mut states = vec![0u8; self.chains.len()];
+        let mut path = Vec::new();
+        (0..self.chains.len()).find_map(|i| self.visit(i, &mut states, &mut path))
+    }
+
+    fn visit(&self, i: usize, states: &mut [u8], path: &mut Vec<usize>) -> Option<Vec<String>> {
+        match states[i] {
+            1 => {
+                let start = path.iter().position(|&j| j == i).unwrap_or(0);
+                let mut cycle = path[start..]
+                    .iter()
+                    .map(|&j| self.chains[j].clone())
+                    .collect::<Vec<_>>();
+                cycle.push(self.chains[i].clone());
+                return Some(cycle);
+            }
+            2 => return None,
+            _ => {}
+        }
+
+        states[i] = 1;
+        path.push(i);
+        for jump in self.jumps_from(&self.chains[i]) {
+            if let Some(j) = self.chains.iter().position(|chain| *chain == jump.to) {
+                if let Some(cycle) = self.visit(j, states, path) {
+                    return Some(cycle);
+                }
+            }
+        }
+        path.pop();
+        states[i] = 2;
+        None
+    }
+}
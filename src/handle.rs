@@ -0,0 +1,180 @@
+//! Handles to rules created by this crate.
+
+use crate::identity::{identified, identity_of, IDENTITY_PREFIX};
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+
+/// A rule appended or inserted by `append_handle` or `insert_handle`.
+/// The handle keeps the rule as normalized by iptables, so it can be managed later without
+/// re-specifying the exact rule string. The rule is given an identity comment (see
+/// `identity_of`) unless it has one, which tells it apart from identical rules.
+pub struct RuleHandle<'a> {
+    ipt: &'a IPTables,
+    table: String,
+    chain: String,
+    rule: String,
+    tag: Option<String>,
+    position: i32,
+}
+
+impl RuleHandle<'_> {
+    /// Returns the table of the rule.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the chain of the rule.
+    pub fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    /// Returns the rule as printed by `iptables -S`.
+    pub fn rule(&self) -> &str {
+        &self.rule
+    }
+
+    /// Returns the identity of the rule, which is used to find it if present.
+    pub fn tag(&self) -> Option<&str> {
+        self.tag.as_deref()
+    }
+
+    /// Returns the position of the rule in the chain at the time it was created or replaced.
+    pub fn position(&self) -> i32 {
+        self.position
+    }
+
+    /// Checks for the existence of the rule in the table/chain.
+    pub fn exists(&self) -> Result<bool, Box<dyn Error>> {
+        Ok(self.find_position()?.is_some())
+    }
+
+    /// Deletes the rule from the table/chain.
+    pub fn delete(self) -> Result<(), Box<dyn Error>> {
+        self.ipt.delete(&self.table, &self.chain, &self.rule)
+    }
+
+    /// Replaces the rule with `new_rule` at its current position. The rule keeps its identity
+    /// unless `new_rule` has one.
+    pub fn replace(&mut self, new_rule: &str) -> Result<(), Box<dyn Error>> {
+        let position = self
+            .find_position()?
+            .ok_or_else(|| error_from_str("the rule does not exist in the table/chain"))?;
+        let new_rule = match (&self.tag, identity_of(new_rule)) {
+            (Some(tag), None) => format!(
+                "-m comment --comment {}{} {}",
+                IDENTITY_PREFIX, tag, new_rule
+            ),
+            _ => new_rule.to_string(),
+        };
+        self.ipt
+            .replace(&self.table, &self.chain, &new_rule, position)?;
+
+        let normalized = self.ipt.rule_at(&self.table, &self.chain, position)?;
+        self.tag = identity_of(&normalized).map(String::from);
+        self.rule = normalized;
+        self.position = position;
+        Ok(())
+    }
+
+    /// Finds the current position of the rule, by its identity if present, otherwise by its
+    /// text. Fails if several rules match.
+    fn find_position(&self) -> Result<Option<i32>, Box<dyn Error>> {
+        let rules = self.ipt.chain_rules(&self.table, &self.chain)?;
+        let mut positions = rules
+            .iter()
+            .enumerate()
+            .filter(|(_, rule)| match &self.tag {
+                Some(tag) => identity_of(rule) == Some(tag.as_str()),
+                None => **rule == self.rule,
+            })
+            .map(|(i, _)| i as i32 + 1);
+        let position = positions.next();
+        if positions.next().is_some() {
+            return Err(error_from_str(
+                "the rule matches several rules of the table/chain",
+            ));
+        }
+        Ok(position)
+    }
+}
+
+impl IPTables {
+    /// Appends `rule` to the table/chain and returns a handle to it.
+    pub fn append_handle(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+    ) -> Result<RuleHandle<'_>, Box<dyn Error>> {
+        let rule = identified(rule)?;
+        self.append(table, chain, &rule)?;
+        self.handle_of(table, chain, &rule)
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain and returns a handle to it.
+    pub fn insert_handle(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+        position: i32,
+    ) -> Result<RuleHandle<'_>, Box<dyn Error>> {
+        let rule = identified(rule)?;
+        self.insert(table, chain, &rule, position)?;
+        self.handle_of(table, chain, &rule)
+    }
+
+    /// Returns a handle to the rule of the table/chain having the identity of `rule`, so the
+    /// rules added concurrently by other processes are never taken for it.
+    fn handle_of(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+    ) -> Result<RuleHandle<'_>, Box<dyn Error>> {
+        let tag = identity_of(rule);
+        let rules = self.chain_rules(table, chain)?;
+        let mut matches = rules
+            .into_iter()
+            .enumerate()
+            .filter(|(_, listed)| identity_of(listed) == tag);
+        let (i, listed) = matches
+            .next()
+            .ok_or_else(|| error_from_str("could not find the rule in the table/chain"))?;
+        if matches.next().is_some() {
+            return Err(error_from_str(
+                "the rule matches several rules of the table/chain",
+            ));
+        }
+        Ok(RuleHandle {
+            ipt: self,
+            table: table.to_string(),
+            chain: chain.to_string(),
+            tag: tag.map(String::from),
+            rule: listed,
+            position: i as i32 + 1,
+        })
+    }
+
+    /// Returns the rule at the `position` (starting from 1) of the table/chain.
+    fn rule_at(&self, table: &str, chain: &str, position: i32) -> Result<String, Box<dyn Error>> {
+        self.chain_rules(table, chain)?
+            .into_iter()
+            .nth((position - 1).max(0) as usize)
+            .ok_or_else(|| error_from_str("could not find the rule in the table/chain"))
+    }
+
+    /// Lists the rules of the table/chain without the leading `-A CHAIN`.
+    pub(crate) fn chain_rules(
+        &self,
+        table: &str,
+        chain: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let prefix = format!("-A {} ", chain);
+        Ok(self
+            .list(table, chain)?
+            .into_iter()
+            .filter_map(|rule| rule.strip_prefix(&prefix).map(String::from))
+            .collect())
+    }
+}
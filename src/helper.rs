@@ -0,0 +1,269 @@
+//! Split between a privileged helper daemon executing the iptables utilities and unprivileged
+//! clients, which talk to it over a unix socket.
+//!
+//! The daemon (`HelperServer`) only serves the peers whose credentials it allows, and only runs
+//! the iptables utilities. Clients get an `IPTables` handle whose backend (`HelperClient`)
+//! forwards every command to the daemon, so the whole API works without privileges.
+
+use crate::backend::{Backend, SystemBackend};
+use crate::{error_from_str, probe_features, IPTables};
+use nix::sys::socket::{getsockopt, sockopt::PeerCredentials};
+use std::error::Error;
+use std::ffi::OsString;
+use std::io::{self, Read, Write};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Output};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Programs run by the daemon unless allowed otherwise.
+const DEFAULT_PROGRAMS: [&str; 6] = [
+    "iptables",
+    "iptables-save",
+    "iptables-restore",
+    "ip6tables",
+    "ip6tables-save",
+    "ip6tables-restore",
+];
+
+/// Largest message accepted from a peer, to bound the memory used by a connection.
+const MAX_MESSAGE_LEN: u32 = 64 << 20;
+
+/// Longest wait for the command of a peer.
+const READ_TIMEOUT: Duration = Duration::from_secs(30);
+
+const RESPONSE_OUTPUT: u8 = 0;
+const RESPONSE_ERROR: u8 = 1;
+
+/// Creates an `IPTables` handle executing its commands through the helper daemon listening on
+/// `path`. The features of iptables are probed through the daemon.
+pub fn client<P: AsRef<Path>>(path: P, is_ipv6: bool) -> Result<IPTables, Box<dyn Error>> {
+    let cmd = if is_ipv6 { "ip6tables" } else { "iptables" };
+    let backend = HelperClient::new(path);
+    let mut ipt = IPTables::with_features(cmd, probe_features(&backend, cmd)?);
+    ipt.set_backend(Arc::new(backend));
+    Ok(ipt)
+}
+
+/// Backend forwarding the commands to the helper daemon, with a connection for each command.
+#[derive(Debug, Clone)]
+pub struct HelperClient {
+    path: PathBuf,
+}
+
+impl HelperClient {
+    /// Creates a backend talking to the helper daemon listening on `path`.
+    pub fn new<P: AsRef<Path>>(path: P) -> HelperClient {
+        HelperClient {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl Backend for HelperClient {
+    fn run(
+        &self,
+        program: &str,
+        args: &[OsString],
+        input: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        let mut stream = UnixStream::connect(&self.path)?;
+        write_bytes(&mut stream, program.as_bytes())?;
+        write_u32(&mut stream, args.len() as u32)?;
+        for arg in args {
+            write_bytes(&mut stream, arg.as_bytes())?;
+        }
+        write_optional(&mut stream, input.map(str::as_bytes))?;
+        let timeout = timeout.map(|timeout| (timeout.as_millis() as u64).to_be_bytes());
+        write_optional(&mut stream, timeout.as_ref().map(|timeout| &timeout[..]))?;
+
+        match read_u8(&mut stream)? {
+            RESPONSE_OUTPUT => Ok(Output {
+                status: ExitStatus::from_raw(read_u32(&mut stream)? as i32),
+                stdout: read_bytes(&mut stream)?,
+                stderr: read_bytes(&mut stream)?,
+            }),
+            RESPONSE_ERROR => Err(error_from_str(&String::from_utf8_lossy(&read_bytes(
+                &mut stream,
+            )?))),
+            _ => Err(error_from_str("invalid response of the helper daemon")),
+        }
+    }
+}
+
+/// The privileged helper daemon, executing the commands of the allowed peers.
+///
+/// By default only root is served; the socket should also be protected by the permissions
+/// of its directory.
+pub struct HelperServer {
+    listener: UnixListener,
+    allowed_uids: Vec<u32>,
+    allowed_gids: Vec<u32>,
+    programs: Vec<String>,
+    backend: Arc<dyn Backend>,
+}
+
+impl HelperServer {
+    /// Creates the daemon listening on a new unix socket at `path`.
+    pub fn bind<P: AsRef<Path>>(path: P) -> Result<HelperServer, Box<dyn Error>> {
+        Ok(HelperServer {
+            listener: UnixListener::bind(path)?,
+            allowed_uids: vec![0],
+            allowed_gids: Vec::new(),
+            programs: DEFAULT_PROGRAMS.iter().map(|p| p.to_string()).collect(),
+            backend: Arc::new(SystemBackend),
+        })
+    }
+
+    /// Serves the peers running as the user `uid`.
+    pub fn allow_uid(&mut self, uid: u32) {
+        self.allowed_uids.push(uid);
+    }
+
+    /// Serves the peers running with the primary group `gid`.
+    pub fn allow_gid(&mut self, gid: u32) {
+        self.allowed_gids.push(gid);
+    }
+
+    /// Allows the peers to run `program` besides the iptables utilities (e.g. 'ipset').
+    pub fn allow_program(&mut self, program: &str) {
+        self.programs.push(program.to_string());
+    }
+
+    /// Sets the backend which executes the commands of the peers, e.g. a `PolicyFilter`
+    /// limiting the chains they may touch.
+    pub fn set_backend(&mut self, backend: Arc<dyn Backend>) {
+        self.backend = backend;
+    }
+
+    /// Accepts the connections forever, serving each one in its own thread.
+    pub fn serve(&self) -> Result<(), Box<dyn Error>> {
+        for stream in self.listener.incoming() {
+            let stream = stream?;
+            if !self.is_allowed(&stream) {
+                continue;
+            }
+            // A peer which stops sending its command must not hold its thread forever
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            let backend = self.backend.clone();
+            let programs = self.programs.clone();
+            thread::spawn(move || serve_command(stream, backend.as_ref(), &programs));
+        }
+        Ok(())
+    }
+
+    /// Checks the credentials of the peer of `stream`.
+    fn is_allowed(&self, stream: &UnixStream) -> bool {
+        match getsockopt(stream, PeerCredentials) {
+            Ok(credentials) => {
+                self.allowed_uids.contains(&credentials.uid())
+                    || self.allowed_gids.contains(&credentials.gid())
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Runs the command received on `stream` and replies with its output. Failing to talk to the
+/// peer only ends the connection.
+fn serve_command(mut stream: UnixStream, backend: &dyn Backend, programs: &[String]) {
+    let result = read_command(&mut stream).and_then(|(program, args, input, timeout)| {
+        if !programs.contains(&program) {
+            return Err(error_from_str(&format!(
+                "{} is not allowed by the helper daemon",
+                program
+            )));
+        }
+        backend.run(&program, &args, input.as_deref(), timeout)
+    });
+    let _ = match result {
+        Ok(output) => write_u8(&mut stream, RESPONSE_OUTPUT)
+            .and_then(|_| write_u32(&mut stream, output.status.into_raw() as u32))
+            .and_then(|_| write_bytes(&mut stream, &output.stdout))
+            .and_then(|_| write_bytes(&mut stream, &output.stderr)),
+        Err(err) => write_u8(&mut stream, RESPONSE_ERROR)
+            .and_then(|_| write_bytes(&mut stream, err.to_string().as_bytes())),
+    };
+}
+
+type Command = (String, Vec<OsString>, Option<String>, Option<Duration>);
+
+fn read_command(stream: &mut UnixStream) -> Result<Command, Box<dyn Error>> {
+    let program = String::from_utf8(read_bytes(stream)?)?;
+    let count = read_u32(stream)?;
+    let mut args = Vec::new();
+    for _ in 0..count {
+        args.push(OsString::from_vec(read_bytes(stream)?));
+    }
+    let input = match read_optional(stream)? {
+        Some(input) => Some(String::from_utf8(input)?),
+        None => None,
+    };
+    let timeout = match read_optional(stream)? {
+        Some(millis) => {
+            let millis = millis
+                .try_into()
+                .map_err(|_| error_from_str("invalid timeout"))?;
+            Some(Duration::from_millis(u64::from_be_bytes(millis)))
+        }
+        None => None,
+    };
+    Ok((program, args, input, timeout))
+}
+
+fn write_u8(stream: &mut UnixStream, value: u8) -> io::Result<()> {
+    stream.write_all(&[value])
+}
+
+fn read_u8(stream: &mut UnixStream) -> io::Result<u8> {
+    let mut value = [0; 1];
+    stream.read_exact(&mut value)?;
+    Ok(value[0])
+}
+
+fn write_u32(stream: &mut UnixStream, value: u32) -> io::Result<()> {
+    stream.write_all(&value.to_be_bytes())
+}
+
+fn read_u32(stream: &mut UnixStream) -> io::Result<u32> {
+    let mut value = [0; 4];
+    stream.read_exact(&mut value)?;
+    Ok(u32::from_be_bytes(value))
+}
+
+fn write_bytes(stream: &mut UnixStream, bytes: &[u8]) -> io::Result<()> {
+    write_u32(stream, bytes.len() as u32)?;
+    stream.write_all(bytes)
+}
+
+fn read_bytes(stream: &mut UnixStream) -> io::Result<Vec<u8>> {
+    let len = read_u32(stream)?;
+    if len > MAX_MESSAGE_LEN {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "message too large",
+        ));
+    }
+    let mut bytes = vec![0; len as usize];
+    stream.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+fn write_optional(stream: &mut UnixStream, bytes: Option<&[u8]>) -> io::Result<()> {
+    match bytes {
+        Some(bytes) => write_u8(stream, 1).and_then(|_| write_bytes(stream, bytes)),
+        None => write_u8(stream, 0),
+    }
+}
+
+fn read_optional(stream: &mut UnixStream) -> io::Result<Option<Vec<u8>>> {
+    match read_u8(stream)? {
+        0 => Ok(None),
+        _ => read_bytes(stream).map(Some),
+    }
+}
@@ -0,0 +1,146 @@
+//! Types of ICMP and ICMPv6 messages, and rules accepting the essential ones.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::IPTables;
+use std::error::Error;
+
+/// Type of an ICMP (IPv4) or ICMPv6 (IPv6) message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IcmpType {
+    /// Echo request, sent by ping.
+    EchoRequest,
+
+    /// Echo reply, the answer to ping.
+    EchoReply,
+
+    /// The destination can't be reached.
+    DestinationUnreachable,
+
+    /// The TTL (or hop limit) of the packet expired, used by traceroute.
+    TimeExceeded,
+
+    /// The header of the packet is invalid.
+    ParameterProblem,
+
+    /// A better route is available.
+    Redirect,
+
+    /// Request of the routers of the link.
+    RouterSolicitation,
+
+    /// Announcement of a router of the link.
+    RouterAdvertisement,
+
+    /// The packet is larger than the MTU of the path (ICMPv6 only).
+    PacketTooBig,
+
+    /// Request of the link-layer address of a neighbour (ICMPv6 only).
+    NeighbourSolicitation,
+
+    /// Announcement of the link-layer address of a neighbour (ICMPv6 only).
+    NeighbourAdvertisement,
+
+    /// Request to slow down (ICMP only, deprecated).
+    SourceQuench,
+
+    /// Timestamp request (ICMP only).
+    TimestampRequest,
+
+    /// Timestamp reply (ICMP only).
+    TimestampReply,
+}
+
+impl IcmpType {
+    /// Returns the name of the type, as given to `--icmp-type` or `--icmpv6-type`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            IcmpType::EchoRequest => "echo-request",
+            IcmpType::EchoReply => "echo-reply",
+            IcmpType::DestinationUnreachable => "destination-unreachable",
+            IcmpType::TimeExceeded => "time-exceeded",
+            IcmpType::ParameterProblem => "parameter-problem",
+            IcmpType::Redirect => "redirect",
+            IcmpType::RouterSolicitation => "router-solicitation",
+            IcmpType::RouterAdvertisement => "router-advertisement",
+            IcmpType::PacketTooBig => "packet-too-big",
+            IcmpType::NeighbourSolicitation => "neighbour-solicitation",
+            IcmpType::NeighbourAdvertisement => "neighbour-advertisement",
+            IcmpType::SourceQuench => "source-quench",
+            IcmpType::TimestampRequest => "timestamp-request",
+            IcmpType::TimestampReply => "timestamp-reply",
+        }
+    }
+
+    /// Checks if the type exists in ICMP, i.e. can be used with iptables.
+    pub fn is_ipv4(&self) -> bool {
+        !matches!(
+            self,
+            IcmpType::PacketTooBig
+                | IcmpType::NeighbourSolicitation
+                | IcmpType::NeighbourAdvertisement
+        )
+    }
+
+    /// Checks if the type exists in ICMPv6, i.e. can be used with ip6tables.
+    pub fn is_ipv6(&self) -> bool {
+        !matches!(
+            self,
+            IcmpType::SourceQuench | IcmpType::TimestampRequest | IcmpType::TimestampReply
+        )
+    }
+
+    /// Returns the types of the neighbour discovery protocol, which IPv6 does not work without.
+    pub fn neighbour_discovery() -> [IcmpType; 4] {
+        [
+            IcmpType::RouterSolicitation,
+            IcmpType::RouterAdvertisement,
+            IcmpType::NeighbourSolicitation,
+            IcmpType::NeighbourAdvertisement,
+        ]
+    }
+
+    pub(crate) fn validate(&self, ipv6: bool) -> Result<(), String> {
+        match ipv6 {
+            false if !self.is_ipv4() => Err(format!("{} is an ICMPv6 only type", self.as_str())),
+            true if !self.is_ipv6() => Err(format!("{} is an ICMP only type", self.as_str())),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl IPTables {
+    /// Accepts the echo requests (ping) received by this host, at most `rate_limit` per
+    /// second if given. The rule is appended to the filter INPUT chain, unless it exists.
+    pub fn allow_ping(&self, rate_limit: Option<u32>) -> Result<(), Box<dyn Error>> {
+        let mut rule = self.icmp_matches(IcmpType::EchoRequest);
+        if let Some(rate_limit) = rate_limit {
+            rule = rule.arg(&["-m", "limit", "--limit", &format!("{}/second", rate_limit)]);
+        }
+        self.append_idempotent("filter", "INPUT", &rule.target(Target::Accept).build()?)
+    }
+
+    /// Accepts the neighbour discovery messages received by this host (ip6tables only), so
+    /// that a restrictive INPUT policy does not break IPv6. Only the messages sent from the
+    /// link itself (with a hop limit of 255) are accepted.
+    /// The rules are appended to the filter INPUT chain, unless they exist.
+    pub fn allow_neighbour_discovery(&self) -> Result<(), Box<dyn Error>> {
+        for icmp_type in IcmpType::neighbour_discovery() {
+            let rule = self
+                .icmp_matches(icmp_type)
+                .arg(&["-m", "hl", "--hl-eq", "255"])
+                .target(Target::Accept)
+                .build()?;
+            self.append_idempotent("filter", "INPUT", &rule)?;
+        }
+        Ok(())
+    }
+
+    /// Matches the ICMP or ICMPv6 messages of the `icmp_type`, depending on the family of this
+    /// handle.
+    fn icmp_matches(&self, icmp_type: IcmpType) -> RuleBuilder {
+        match self.cmd {
+            "ip6tables" => RuleBuilder::new().icmpv6_type(icmp_type),
+            _ => RuleBuilder::new().icmp_type(icmp_type),
+        }
+    }
+}
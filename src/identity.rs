@@ -0,0 +1,238 @@
+//! Identity comments attached to rules when `auto_identity` is enabled or when they are adopted.
+
+use crate::parse::{fingerprint_rules, join_rule, normalize_rule, split_rule};
+use crate::provenance::{with_provenance, Provenance};
+use crate::{error_from_str, IPTables};
+use std::borrow::Cow;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+use std::fs::File;
+use std::io::Read;
+
+/// Prefix of the comments used to identify rules created by this crate.
+pub const IDENTITY_PREFIX: &str = "ipt-rs:";
+
+/// Checks if `uuid` is a lowercase UUID (e.g. '0f8fad5b-d9cb-469f-a165-70867728950e').
+fn is_uuid(uuid: &str) -> bool {
+    uuid.len() == 36
+        && uuid.char_indices().all(|(i, c)| match i {
+            8 | 13 | 18 | 23 => c == '-',
+            _ => c.is_ascii_digit() || ('a'..='f').contains(&c),
+        })
+}
+
+/// Returns the identity (without `IDENTITY_PREFIX`) of the `rule` if it has one.
+pub fn identity_of(rule: &str) -> Option<&str> {
+    rule.match_indices(IDENTITY_PREFIX).find_map(|(i, _)| {
+        let start = i + IDENTITY_PREFIX.len();
+        let uuid = rule.get(start..start + 36)?;
+        let before = rule[..i].trim_end_matches(['"', '\'']).trim_end();
+        let after = &rule[start + 36..];
+        let complete = after.is_empty() || after.starts_with([' ', '"', '\'']);
+        (before.ends_with("-m comment --comment") && is_uuid(uuid) && complete).then_some(uuid)
+    })
+}
+
+/// Removes the identity comment from the `rule`.
+pub fn strip_identity(rule: &str) -> String {
+    let mut args = split_rule(rule);
+    let position = args.windows(4).position(|window| {
+        window[..3] == ["-m", "comment", "--comment"]
+            && window[3].strip_prefix(IDENTITY_PREFIX).is_some_and(is_uuid)
+    });
+    if let Some(i) = position {
+        args.drain(i..i + 4);
+    }
+    join_rule(&args)
+}
+
+/// Joins the `rule`, given as separate arguments, replacing the invalid UTF-8 sequences.
+pub(crate) fn lossy_rule<S: AsRef<OsStr>>(rule: &[S]) -> String {
+    join_rule(
+        &rule
+            .iter()
+            .map(|arg| arg.as_ref().to_string_lossy())
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Prepends a new identity comment to the `rule` unless it has one.
+pub(crate) fn identified(rule: &str) -> Result<Cow<'_, str>, Box<dyn Error>> {
+    if identity_of(rule).is_some() {
+        return Ok(Cow::Borrowed(rule));
+    }
+    Ok(Cow::Owned(format!(
+        "-m comment --comment {}{} {}",
+        IDENTITY_PREFIX,
+        new_uuid()?,
+        rule
+    )))
+}
+
+/// Generates a random (version 4) UUID.
+fn new_uuid() -> Result<String, Box<dyn Error>> {
+    let mut bytes = [0u8; 16];
+    File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    Ok(format_uuid(bytes, 0x40))
+}
+
+/// Derives a (version 8) UUID from the `rule` once normalized, so the identity given to a rule
+/// by `auto_identity` can be computed again from the same rule.
+fn rule_uuid(rule: &str) -> String {
+    let mut bytes = [0u8; 16];
+    bytes[..8].copy_from_slice(&fingerprint_rules(&[rule]).to_be_bytes());
+    bytes[8..].copy_from_slice(&fingerprint_rules(&[rule, IDENTITY_PREFIX]).to_be_bytes());
+    format_uuid(bytes, 0x80)
+}
+
+/// Formats the `bytes` as a UUID of the given `version` (in the high nibble).
+fn format_uuid(mut bytes: [u8; 16], version: u8) -> String {
+    bytes[6] = (bytes[6] & 0x0f) | version;
+    bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+    let hex = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+impl IPTables {
+    /// Set whether rules created through this handle get an identity comment
+    /// (`-m comment --comment "ipt-rs:<uuid>"`), which allows finding them later even if
+    /// iptables rewrites the rest of the rule. The identity is derived from the rule, so the
+    /// same rule always gets the same identity.
+    pub fn set_auto_identity(&mut self, auto_identity: bool) {
+        self.auto_identity = auto_identity;
+    }
+
+    /// Finds the rule having the identity `id` in the table/chain.
+    /// Returns the rule as printed by `iptables -S` without the leading `-A CHAIN`.
+    pub fn find_by_id(
+        &self,
+        table: &str,
+        chain: &str,
+        id: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        Ok(self
+            .chain_rules(table, chain)?
+            .into_iter()
+            .find(|rule| identity_of(rule) == Some(id)))
+    }
+
+    /// Deletes the rule having the identity `id` from the table/chain.
+    /// Returns `false` if no such rule exists.
+    pub fn delete_by_id(&self, table: &str, chain: &str, id: &str) -> Result<bool, Box<dyn Error>> {
+        match self.find_by_id(table, chain, id)? {
+            Some(rule) => self.delete(table, chain, &rule).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Brings the rules of the table/chain which have no identity (e.g. created by shell
+    /// scripts) under the management of the application `tag`: each of them gets an identity
+    /// comment, and a provenance comment whose source is `tag` unless it has one. The chain is
+    /// rewritten atomically, so the packets are never evaluated against a partial chain; its
+    /// counters are reset.
+    /// Returns the adopted rules as (identity, rule).
+    pub fn adopt(
+        &self,
+        table: &str,
+        chain: &str,
+        tag: &str,
+    ) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        if tag.is_empty() {
+            return Err(error_from_str(
+                "the tag of the adopted rules can't be empty",
+            ));
+        }
+        let provenance = Provenance::new(tag, None);
+        let mut adopted = Vec::new();
+        let mut rules = self.chain_rules(table, chain)?;
+        for rule in rules.iter_mut() {
+            if identity_of(rule).is_some() {
+                continue;
+            }
+            if Provenance::of_rule(rule).is_none() {
+                *rule = with_provenance(rule, &provenance)?;
+            }
+            let id = new_uuid()?;
+            *rule = format!("-m comment --comment {}{} {}", IDENTITY_PREFIX, id, rule);
+            adopted.push((id, rule.clone()));
+        }
+        if !adopted.is_empty() {
+            self.replace_chain_rules(table, chain, &rules)?;
+        }
+        Ok(adopted)
+    }
+
+    /// Prepends the identity comment derived from the `rule` to it if `auto_identity` is enabled
+    /// and it has no identity.
+    pub(crate) fn with_identity<'a>(&self, rule: &'a str) -> Result<Cow<'a, str>, Box<dyn Error>> {
+        if !self.auto_identity || identity_of(rule).is_some() {
+            return Ok(Cow::Borrowed(rule));
+        }
+        Ok(Cow::Owned(format!(
+            "-m comment --comment {}{} {}",
+            IDENTITY_PREFIX,
+            rule_uuid(rule),
+            rule
+        )))
+    }
+
+    /// Prepends the identity comment derived from the `rule`, given as separate arguments, to it
+    /// if `auto_identity` is enabled and it has no identity.
+    pub(crate) fn with_identity_os<S: AsRef<OsStr>>(
+        &self,
+        rule: &[S],
+    ) -> Result<Vec<OsString>, Box<dyn Error>> {
+        let mut args = Vec::with_capacity(rule.len() + 4);
+        let joined = lossy_rule(rule);
+        if self.auto_identity && identity_of(&joined).is_none() {
+            let comment = format!("{}{}", IDENTITY_PREFIX, rule_uuid(&joined));
+            args.extend(["-m", "comment", "--comment"].map(OsString::from));
+            args.push(OsString::from(comment));
+        }
+        args.extend(rule.iter().map(|arg| arg.as_ref().to_os_string()));
+        Ok(args)
+    }
+
+    /// Finds the rule created from `rule` with the identity comment derived from it, or a rule
+    /// which equals `rule` once its identity comment is removed (e.g. an adopted rule), in a
+    /// single listing of the chain. The identity allows finding the rule even if iptables
+    /// rewrites it (e.g. `-s 10.0.0.1` becomes `-s 10.0.0.1/32`).
+    /// Returns `None` if `auto_identity` is disabled or `rule` has an identity itself.
+    pub(crate) fn find_identified(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        if !self.auto_identity || identity_of(rule).is_some() {
+            return Ok(None);
+        }
+        let id = rule_uuid(rule);
+        let normalized = normalize_rule(rule);
+        let identified = self
+            .chain_rules(table, chain)?
+            .into_iter()
+            .filter(|listed| identity_of(listed).is_some())
+            .collect::<Vec<_>>();
+        Ok(identified
+            .iter()
+            .find(|listed| identity_of(listed) == Some(id.as_str()))
+            .or_else(|| {
+                identified
+                    .iter()
+                    .find(|listed| strip_identity(listed) == normalized)
+            })
+            .cloned())
+    }
+}
@@ -0,0 +1,125 @@
+//! Sets of the ipset utility, used by the `set` match and the SET target.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::IPTables;
+use std::error::Error;
+
+/// A set of the ipset utility.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IpSet {
+    /// Name of the set.
+    pub name: String,
+
+    /// Type of the set (e.g. 'hash:ip' or 'hash:net').
+    pub set_type: String,
+
+    /// Default timeout of the entries in seconds, `None` if the set does not support timeouts.
+    pub timeout: Option<u32>,
+}
+
+impl IpSet {
+    /// Creates the description of a set without timeouts.
+    pub fn new(name: &str, set_type: &str) -> IpSet {
+        IpSet {
+            name: name.to_string(),
+            set_type: set_type.to_string(),
+            timeout: None,
+        }
+    }
+}
+
+/// Options of the SET target adding the packets' addresses to a set.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddSet {
+    /// Name of the set.
+    pub set: String,
+
+    /// Direction of the addresses, e.g. 'src' or 'src,dst' for sets with two dimensions.
+    pub flags: String,
+
+    /// Timeout of the added entries in seconds, requires a set supporting timeouts.
+    pub timeout: Option<u32>,
+}
+
+impl AddSet {
+    /// Returns the arguments of the target, without the leading `-j SET`.
+    /// Entries which are already in the set get their timeout refreshed (`--exist`).
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec![
+            "--add-set".to_string(),
+            self.set.clone(),
+            self.flags.clone(),
+            "--exist".to_string(),
+        ];
+        if let Some(timeout) = self.timeout {
+            args.push("--timeout".to_string());
+            args.push(timeout.to_string());
+        }
+        args
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let valid = self
+            .flags
+            .split(',')
+            .all(|flag| flag == "src" || flag == "dst");
+        match valid {
+            true => Ok(()),
+            false => Err(format!("invalid SET flags {}", self.flags)),
+        }
+    }
+}
+
+impl IPTables {
+    /// Creates the set using `ipset`, for the address family of the handle.
+    /// Succeeds without doing anything if an identical set already exists.
+    pub fn create_set(&self, set: &IpSet) -> Result<(), Box<dyn Error>> {
+        let family = match self.cmd {
+            "ip6tables" => "inet6",
+            _ => "inet",
+        };
+        let mut args = vec![
+            "-exist",
+            "create",
+            &set.name,
+            &set.set_type,
+            "family",
+            family,
+        ];
+        let timeout = set.timeout.map(|timeout| timeout.to_string());
+        if let Some(timeout) = &timeout {
+            args.extend(["timeout", timeout]);
+        }
+        self.run_program_checked("ipset", &args, None)?;
+        Ok(())
+    }
+
+    /// Destroys the set using `ipset`; the set must not be referenced by any rule.
+    pub fn destroy_set(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        self.run_program_checked("ipset", &["destroy", name], None)?;
+        Ok(())
+    }
+
+    /// Creates the `set` if needed, then appends a rule, matching `matches`, which adds the
+    /// addresses (in the direction of `flags`, e.g. 'src') of the packets to the set.
+    /// The entries are added with the timeout of the set, so sources hitting the rule can be
+    /// banned for a while by matching the set in other rules.
+    pub fn append_add_set(
+        &self,
+        table: &str,
+        chain: &str,
+        matches: RuleBuilder,
+        set: &IpSet,
+        flags: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let rule = matches
+            .target(Target::AddSet(AddSet {
+                set: set.name.clone(),
+                flags: flags.to_string(),
+                timeout: set.timeout,
+            }))
+            .build()?;
+        self.create_set(set)?;
+        self.append(table, chain, &rule)
+    }
+}
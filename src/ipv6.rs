@@ -0,0 +1,126 @@
+//! Matches and targets which only exist in ip6tables: extension headers and network prefix
+//! translation.
+
+use std::net::Ipv6Addr;
+
+/// Fragments matched by `RuleBuilder::fragment` (`-m frag`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fragment {
+    /// The first fragment of a packet (`--fragfirst`).
+    First,
+
+    /// The fragments followed by other fragments (`--fragmore`).
+    More,
+
+    /// The last fragment of a packet (`--fraglast`).
+    Last,
+
+    /// The fragments of the packet with the identification (`--fragid`).
+    Id(u32),
+}
+
+impl Fragment {
+    /// Returns the arguments of the match.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec!["-m".to_string(), "frag".to_string()];
+        match self {
+            Fragment::First => args.push("--fragfirst".to_string()),
+            Fragment::More => args.push("--fragmore".to_string()),
+            Fragment::Last => args.push("--fraglast".to_string()),
+            Fragment::Id(id) => args.extend(["--fragid".to_string(), id.to_string()]),
+        }
+        args
+    }
+}
+
+/// Extension headers matched by `RuleBuilder::ipv6_headers` (`-m ipv6header`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ipv6Header {
+    /// Hop-by-hop options.
+    HopByHop,
+
+    /// Destination options.
+    Destination,
+
+    /// Routing header.
+    Routing,
+
+    /// Fragment header.
+    Fragment,
+
+    /// Authentication header (IPsec AH).
+    Auth,
+
+    /// Encapsulating security payload (IPsec ESP).
+    Esp,
+
+    /// No next header.
+    None,
+
+    /// Any upper layer protocol.
+    Protocol,
+}
+
+impl Ipv6Header {
+    /// Returns the name of the header, as given to `--header`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Ipv6Header::HopByHop => "hop",
+            Ipv6Header::Destination => "dst",
+            Ipv6Header::Routing => "route",
+            Ipv6Header::Fragment => "frag",
+            Ipv6Header::Auth => "auth",
+            Ipv6Header::Esp => "esp",
+            Ipv6Header::None => "none",
+            Ipv6Header::Protocol => "prot",
+        }
+    }
+}
+
+/// Options of the stateless network prefix translation targets (`-j SNPT` and `-j DNPT`, only
+/// in the mangle table), given as prefixes with their lengths (e.g. 'fd00:1::/64').
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Npt {
+    /// Prefix of the addresses before the translation.
+    pub src_prefix: String,
+
+    /// Prefix of the addresses after the translation.
+    pub dst_prefix: String,
+}
+
+impl Npt {
+    /// Creates the translation of `src_prefix` into `dst_prefix`.
+    pub fn new(src_prefix: &str, dst_prefix: &str) -> Npt {
+        Npt {
+            src_prefix: src_prefix.to_string(),
+            dst_prefix: dst_prefix.to_string(),
+        }
+    }
+
+    /// Returns the arguments of the target, without the leading `-j SNPT` or `-j DNPT`.
+    pub fn args(&self) -> Vec<String> {
+        vec![
+            "--src-pfx".to_string(),
+            self.src_prefix.clone(),
+            "--dst-pfx".to_string(),
+            self.dst_prefix.clone(),
+        ]
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        let length = |prefix: &str| {
+            let (address, length) = prefix.split_once('/')?;
+            address.parse::<Ipv6Addr>().ok()?;
+            length.parse::<u8>().ok().filter(|length| *length <= 128)
+        };
+        match (length(&self.src_prefix), length(&self.dst_prefix)) {
+            (Some(src), Some(dst)) if src == dst => Ok(()),
+            (Some(_), Some(_)) => Err(format!(
+                "prefixes {} and {} have different lengths",
+                self.src_prefix, self.dst_prefix
+            )),
+            (None, _) => Err(format!("invalid IPv6 prefix {}", self.src_prefix)),
+            (_, None) => Err(format!("invalid IPv6 prefix {}", self.dst_prefix)),
+        }
+    }
+}
@@ -0,0 +1,80 @@
+//! Export and import of rulesets in JSON, for dashboards, inventories and other systems which
+//! do not parse the format of `iptables-save`.
+//!
+//! A ruleset is exported as an object with the version of the schema (currently 1) and its
+//! tables. Each table has its name, its chains and its rules in the `-A CHAIN ...` form, as
+//! printed by `iptables-save`. Chains have their name, the policy of built-in chains (`null`
+//! for user-defined chains) and the counters of the policy, if known:
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "tables": [
+//!     {
+//!       "name": "filter",
+//!       "chains": [
+//!         { "name": "INPUT", "policy": "ACCEPT", "counters": { "packets": 10, "bytes": 840 } },
+//!         { "name": "MYAPP", "policy": null, "counters": null }
+//!       ],
+//!       "rules": ["-A INPUT -j MYAPP", "-A MYAPP -p tcp -m tcp --dport 22 -j ACCEPT"]
+//!     }
+//!   ]
+//! }
+//! ```
+
+use crate::error_from_str;
+use crate::parse::SavedTable;
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+
+#[cfg(not(feature = "parse-only"))]
+use crate::parse::{format_save, parse_save};
+#[cfg(not(feature = "parse-only"))]
+use crate::restore::RestoreOptions;
+#[cfg(not(feature = "parse-only"))]
+use crate::IPTables;
+
+/// Version of the schema written by `to_json`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Serialize, Deserialize)]
+struct Ruleset {
+    version: u32,
+    tables: Vec<SavedTable>,
+}
+
+/// Exports the `tables` (e.g. parsed by `parse_save`) in JSON.
+pub fn to_json(tables: &[SavedTable]) -> Result<String, Box<dyn Error>> {
+    let ruleset = Ruleset {
+        version: SCHEMA_VERSION,
+        tables: tables.to_vec(),
+    };
+    Ok(serde_json::to_string_pretty(&ruleset)?)
+}
+
+/// Imports the tables of a ruleset exported in JSON by `to_json`. Rulesets of a newer version
+/// of the schema are rejected.
+pub fn from_json(data: &str) -> Result<Vec<SavedTable>, Box<dyn Error>> {
+    let ruleset: Ruleset = serde_json::from_str(data)?;
+    if ruleset.version > SCHEMA_VERSION {
+        return Err(error_from_str(&format!(
+            "unsupported version {} of the ruleset",
+            ruleset.version
+        )));
+    }
+    Ok(ruleset.tables)
+}
+
+#[cfg(not(feature = "parse-only"))]
+impl IPTables {
+    /// Exports the current ruleset in JSON, with the counters of the chains.
+    pub fn export_json(&self) -> Result<String, Box<dyn Error>> {
+        to_json(&parse_save(&self.save_with_counters()?))
+    }
+
+    /// Restores the tables of a ruleset exported in JSON, replacing their current rules like
+    /// `restore`. The other tables are left untouched.
+    pub fn import_json(&self, data: &str) -> Result<(), Box<dyn Error>> {
+        self.restore(&format_save(&from_json(data)?), RestoreOptions::default())
+    }
+}
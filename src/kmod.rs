@@ -0,0 +1,110 @@
+//! Checks of the kernel modules providing the matches and targets, so that a missing module is
+//! reported by name before the rules are applied.
+
+use crate::IPTables;
+use std::fs;
+use std::path::Path;
+
+/// Targets provided by a module, with the module of iptables and of ip6tables.
+const TARGET_MODULES: [(&str, &str, &str); 17] = [
+    ("AUDIT", "xt_AUDIT", "xt_AUDIT"),
+    ("CT", "xt_CT", "xt_CT"),
+    ("DNAT", "xt_nat", "xt_nat"),
+    ("DNPT", "ip6t_NPT", "ip6t_NPT"),
+    ("DSCP", "xt_DSCP", "xt_DSCP"),
+    ("HL", "xt_HL", "xt_HL"),
+    ("LOG", "xt_LOG", "xt_LOG"),
+    ("MARK", "xt_mark", "xt_mark"),
+    ("MASQUERADE", "xt_MASQUERADE", "xt_MASQUERADE"),
+    ("NFLOG", "xt_NFLOG", "xt_NFLOG"),
+    ("NFQUEUE", "xt_NFQUEUE", "xt_NFQUEUE"),
+    ("REJECT", "ipt_REJECT", "ip6t_REJECT"),
+    ("SET", "xt_set", "xt_set"),
+    ("SNAT", "xt_nat", "xt_nat"),
+    ("SNPT", "ip6t_NPT", "ip6t_NPT"),
+    ("TCPMSS", "xt_TCPMSS", "xt_TCPMSS"),
+    ("TTL", "xt_HL", "xt_HL"),
+];
+
+/// Returns the kernel module providing the match `name` (e.g. 'xt_hashlimit' for 'hashlimit'),
+/// or `None` if the match is part of ip_tables or ip6_tables.
+pub fn match_module(name: &str) -> Option<String> {
+    match name {
+        "icmp" | "icmp6" => None,
+        "tcp" | "udp" => Some("xt_tcpudp".to_string()),
+        "ttl" => Some("xt_hl".to_string()),
+        "ah" | "eui64" | "frag" | "hbh" | "dst" | "ipv6header" | "mh" | "rt" | "srh" => {
+            Some(format!("ip6t_{}", name))
+        }
+        _ => Some(format!("xt_{}", name)),
+    }
+}
+
+/// Returns the kernel module providing the target `name` (e.g. 'xt_NFQUEUE' for 'NFQUEUE'), or
+/// `None` for the standard targets, the chains and the targets which are not known.
+pub fn target_module(name: &str, is_ipv6: bool) -> Option<String> {
+    TARGET_MODULES
+        .iter()
+        .find(|(target, _, _)| *target == name)
+        .map(|(_, ipv4, ipv6)| match is_ipv6 {
+            true => ipv6.to_string(),
+            false => ipv4.to_string(),
+        })
+}
+
+/// Returns the modules needed by the rule given by `args`, each with the match or target which
+/// needs it (e.g. "match 'hashlimit'").
+pub fn required_modules(args: &[String], is_ipv6: bool) -> Vec<(String, String)> {
+    let mut modules = Vec::new();
+    let mut iter = args.iter();
+    while let Some(arg) = iter.next() {
+        let required = match arg.as_str() {
+            "-m" | "--match" => iter
+                .next()
+                .and_then(|name| Some((format!("match '{}'", name), match_module(name)?))),
+            "-j" | "--jump" => iter.next().and_then(|name| {
+                Some((format!("target '{}'", name), target_module(name, is_ipv6)?))
+            }),
+            _ => None,
+        };
+        if let Some(required) = required {
+            if !modules.contains(&required) {
+                modules.push(required);
+            }
+        }
+    }
+    modules
+}
+
+impl IPTables {
+    /// Checks if the kernel module `name` (e.g. 'xt_hashlimit') is loaded, built into the
+    /// kernel, or can be loaded (`modprobe -n`).
+    pub fn module_available(&self, name: &str) -> bool {
+        // The kernel lists the modules with underscores instead of dashes
+        let name = name.replace('-', "_");
+        if Path::new("/sys/module").join(&name).exists() {
+            return true;
+        }
+        let loaded = fs::read_to_string("/proc/modules").unwrap_or_default();
+        if loaded
+            .lines()
+            .any(|line| line.split(' ').next() == Some(&name))
+        {
+            return true;
+        }
+        if let Ok(release) = fs::read_to_string("/proc/sys/kernel/osrelease") {
+            let builtin = Path::new("/lib/modules")
+                .join(release.trim())
+                .join("modules.builtin");
+            let builtin = fs::read_to_string(builtin).unwrap_or_default();
+            let file = format!("/{}.ko", name);
+            if builtin.lines().any(|line| line.ends_with(&file)) {
+                return true;
+            }
+        }
+        match self.run_program("modprobe", &["-n", "-q", &name], None) {
+            Ok(output) => output.status.success(),
+            Err(_) => false,
+        }
+    }
+}
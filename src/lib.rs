@@ -6,81 +6,241 @@
 //!
 //! # Example
 //! ```
+//! # #[cfg(not(feature = "parse-only"))] {
 //! let ipt = iptables::new(false).unwrap();
 //! assert!(ipt.new_chain("nat", "NEWCHAINNAME").is_ok());
 //! assert!(ipt.append("nat", "NEWCHAINNAME", "-j ACCEPT").is_ok());
 //! assert!(ipt.exists("nat", "NEWCHAINNAME", "-j ACCEPT").unwrap());
 //! assert!(ipt.delete("nat", "NEWCHAINNAME", "-j ACCEPT").is_ok());
 //! assert!(ipt.delete_chain("nat", "NEWCHAINNAME").is_ok());
+//! # }
 //! ```
+//!
+//! With the `parse-only` feature, only the modules which do not execute iptables are available:
+//! `parse`, `graph`, `compose`, `simulate`, `template` and `error` (as well as `json` with the
+//! `json` feature).
 
+#[cfg(not(feature = "parse-only"))]
+pub mod accounting;
+#[cfg(not(feature = "parse-only"))]
+pub mod backend;
+#[cfg(not(feature = "parse-only"))]
+pub mod blue_green;
+#[cfg(not(feature = "parse-only"))]
+pub mod builder;
+#[cfg(not(feature = "parse-only"))]
+pub mod canary;
+pub mod compose;
+#[cfg(not(feature = "parse-only"))]
+pub mod conntrack;
+#[cfg(not(feature = "parse-only"))]
+mod copy;
+#[cfg(not(feature = "parse-only"))]
+pub mod diagnostics;
+#[cfg(not(feature = "parse-only"))]
+pub mod drift;
 pub mod error;
+#[cfg(not(feature = "parse-only"))]
+pub mod firewall;
+#[cfg(not(feature = "parse-only"))]
+pub mod firewalld;
+pub mod graph;
+#[cfg(not(feature = "parse-only"))]
+pub mod handle;
+#[cfg(all(feature = "helper", target_os = "linux", not(feature = "parse-only")))]
+pub mod helper;
+#[cfg(not(feature = "parse-only"))]
+pub mod icmp;
+#[cfg(not(feature = "parse-only"))]
+pub mod identity;
+#[cfg(not(feature = "parse-only"))]
+pub mod ipset;
+#[cfg(not(feature = "parse-only"))]
+pub mod ipv6;
+#[cfg(feature = "json")]
+pub mod json;
+#[cfg(not(feature = "parse-only"))]
+pub mod kmod;
+#[cfg(not(feature = "parse-only"))]
+pub mod log_registry;
+#[cfg(not(feature = "parse-only"))]
+mod managed;
+#[cfg(not(feature = "parse-only"))]
+pub mod managers;
+#[cfg(not(feature = "parse-only"))]
+pub mod mark;
+#[cfg(all(feature = "monitor", not(feature = "parse-only")))]
+pub mod monitor;
+#[cfg(not(feature = "parse-only"))]
+pub mod nat;
+#[cfg(not(feature = "parse-only"))]
+pub mod nat_pool;
+#[cfg(not(feature = "parse-only"))]
+pub mod nfacct;
+#[cfg(all(feature = "nflog", target_os = "linux", not(feature = "parse-only")))]
+pub mod nflog;
+#[cfg(not(feature = "parse-only"))]
+pub mod nflog_groups;
+#[cfg(not(feature = "parse-only"))]
+pub mod nfqueue;
+#[cfg(not(feature = "parse-only"))]
+pub mod orchestrate;
+#[cfg(not(feature = "parse-only"))]
+mod os_str;
+#[cfg(not(feature = "parse-only"))]
+pub mod output;
+pub mod parse;
+#[cfg(not(feature = "parse-only"))]
+pub mod persist;
+#[cfg(not(feature = "parse-only"))]
+pub mod policy;
+#[cfg(not(feature = "parse-only"))]
+pub mod port_forward;
+#[cfg(not(feature = "parse-only"))]
+pub mod priority;
+#[cfg(not(feature = "parse-only"))]
+pub mod progress;
+#[cfg(not(feature = "parse-only"))]
+pub mod provenance;
+#[cfg(not(feature = "parse-only"))]
+pub mod qos;
+#[cfg(not(feature = "parse-only"))]
+mod query;
+#[cfg(not(feature = "parse-only"))]
+pub mod quota;
+#[cfg(not(feature = "parse-only"))]
+pub mod rate_limit;
+#[cfg(not(feature = "parse-only"))]
+pub mod restore;
+#[cfg(not(feature = "parse-only"))]
+pub mod retry;
+#[cfg(not(feature = "parse-only"))]
+pub mod shared;
+pub mod simulate;
+#[cfg(all(feature = "json", not(feature = "parse-only")))]
+pub mod state;
+#[cfg(not(feature = "parse-only"))]
+pub mod statistic;
+#[cfg(not(feature = "parse-only"))]
+pub mod tcpmss;
+pub mod template;
+#[cfg(all(feature = "testing", not(feature = "parse-only")))]
+pub mod testing;
+#[cfg(not(feature = "parse-only"))]
+pub mod trace;
+#[cfg(not(feature = "parse-only"))]
+pub mod ttl;
+#[cfg(not(feature = "parse-only"))]
+pub mod validate;
+#[cfg(not(feature = "parse-only"))]
+pub mod variant;
 
-use error::IptablesError;
-use lazy_static::lazy_static;
+#[cfg(not(feature = "parse-only"))]
+use backend::{Backend, SerialBackend, SystemBackend};
+#[cfg(not(feature = "parse-only"))]
+use error::{IPTError, IptablesError};
+#[cfg(all(target_os = "linux", feature = "lock", not(feature = "parse-only")))]
 use nix::fcntl::{flock, FlockArg};
-use regex::{Match, Regex};
+#[cfg(not(feature = "parse-only"))]
+use output::CommandOutput;
+#[cfg(not(feature = "parse-only"))]
+use parse::RuleSpec;
+#[cfg(not(feature = "parse-only"))]
+use progress::Progress;
+#[cfg(not(feature = "parse-only"))]
+use rate_limit::RateLimiter;
+#[cfg(not(feature = "parse-only"))]
+use retry::{is_lock_error, RetryPolicy};
+#[cfg(not(feature = "parse-only"))]
+use shared::SharedIPTables;
+#[cfg(not(feature = "parse-only"))]
 use std::convert::From;
 use std::error::Error;
+#[cfg(not(feature = "parse-only"))]
 use std::ffi::OsStr;
+#[cfg(not(feature = "parse-only"))]
+use std::ffi::OsString;
+use std::fmt;
+#[cfg(all(target_os = "linux", feature = "lock", not(feature = "parse-only")))]
 use std::fs::File;
+#[cfg(all(target_os = "linux", feature = "lock", not(feature = "parse-only")))]
 use std::os::unix::io::AsRawFd;
-use std::process::{Command, Output};
+#[cfg(not(feature = "parse-only"))]
+use std::path::Path;
+#[cfg(not(feature = "parse-only"))]
+use std::process::Output;
+use std::str::FromStr;
+#[cfg(not(feature = "parse-only"))]
+use std::sync::{Arc, OnceLock};
+#[cfg(not(feature = "parse-only"))]
+use std::thread;
+#[cfg(not(feature = "parse-only"))]
+use std::time::{Duration, Instant};
+#[cfg(not(feature = "parse-only"))]
 use std::vec::Vec;
 
-// List of built-in chains taken from: man 8 iptables
-const BUILTIN_CHAINS_FILTER: &[&str] = &["INPUT", "FORWARD", "OUTPUT"];
-const BUILTIN_CHAINS_MANGLE: &[&str] = &["PREROUTING", "OUTPUT", "INPUT", "FORWARD", "POSTROUTING"];
-const BUILTIN_CHAINS_NAT: &[&str] = &["PREROUTING", "POSTROUTING", "OUTPUT"];
-const BUILTIN_CHAINS_RAW: &[&str] = &["PREROUTING", "OUTPUT"];
-const BUILTIN_CHAINS_SECURITY: &[&str] = &["INPUT", "OUTPUT", "FORWARD"];
-
-lazy_static! {
-    static ref RE_SPLIT: Regex = Regex::new(r#"["'].+?["']|[^ ]+"#).unwrap();
-}
-
-trait SplitQuoted {
-    fn split_quoted(&self) -> Vec<&str>;
-}
-
-impl SplitQuoted for str {
-    fn split_quoted(&self) -> Vec<&str> {
-        RE_SPLIT
-            // Iterate over matched segments
-            .find_iter(self)
-            // Get match as str
-            .map(|m| Match::as_str(&m))
-            // Remove any surrounding quotes (they will be reinserted by `Command`)
-            .map(|s| s.trim_matches(|c| c == '"' || c == '\''))
-            // Collect
-            .collect::<Vec<_>>()
-    }
-}
+/// Interval between the checks of `IPTables::wait_for_rule`.
+#[cfg(not(feature = "parse-only"))]
+const WAIT_INTERVAL: Duration = Duration::from_millis(100);
 
 fn error_from_str(msg: &str) -> Box<dyn Error> {
     msg.into()
 }
 
-fn output_to_result(output: Output) -> Result<(), Box<dyn Error>> {
+/// Turns a failed exit status of `program` into an error carrying the command line.
+#[cfg(not(feature = "parse-only"))]
+fn check_output<S: AsRef<OsStr>>(
+    program: &str,
+    args: &[S],
+    output: Output,
+) -> Result<Output, Box<dyn Error>> {
     if !output.status.success() {
-        return Err(Box::new(IptablesError::from(output)));
+        let error = Box::new(IptablesError::from(output));
+        return Err(IPTError::command(program, args, error));
     }
-    Ok(())
+    Ok(output)
 }
 
-fn get_builtin_chains(table: &str) -> Result<&[&str], Box<dyn Error>> {
-    match table {
-        "filter" => Ok(BUILTIN_CHAINS_FILTER),
-        "mangle" => Ok(BUILTIN_CHAINS_MANGLE),
-        "nat" => Ok(BUILTIN_CHAINS_NAT),
-        "raw" => Ok(BUILTIN_CHAINS_RAW),
-        "security" => Ok(BUILTIN_CHAINS_SECURITY),
-        _ => Err(error_from_str("given table is not supported by iptables")),
+/// Lock of the iptables utilities, released when dropped.
+#[cfg(all(target_os = "linux", feature = "lock", not(feature = "parse-only")))]
+type FileLock = File;
+
+/// Lock of the iptables utilities, which is a no-op without manual locking.
+#[cfg(all(
+    not(all(target_os = "linux", feature = "lock")),
+    not(feature = "parse-only")
+))]
+struct FileLock;
+
+/// Takes the exclusive lock of the file at `path`, returns `None` if it is held by another process.
+#[cfg(all(target_os = "linux", feature = "lock", not(feature = "parse-only")))]
+fn try_lock<P: AsRef<Path>>(path: P) -> Result<Option<FileLock>, Box<dyn Error>> {
+    let file_lock = File::create(path)?;
+    match flock(file_lock.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+        Ok(_) => Ok(Some(file_lock)),
+        Err(nix::errno::Errno::EAGAIN) => Ok(None),
+        Err(e) => Err(Box::new(e)),
     }
 }
 
+/// Locking the iptables utilities is only required on Linux, and is left to iptables itself
+/// (which must support -w) without the `lock` feature.
+#[cfg(all(
+    not(all(target_os = "linux", feature = "lock")),
+    not(feature = "parse-only")
+))]
+fn try_lock<P: AsRef<Path>>(_path: P) -> Result<Option<FileLock>, Box<dyn Error>> {
+    Ok(Some(FileLock))
+}
+
 /// Contains the iptables command and shows if it supports -w and -C options.
-/// Use `new` method to create a new instance of this struct.
+/// Use `new` method or `IPTables::with_features` to create a new instance of this struct.
+///
+/// `IPTables` is `Send` and `Sync`, so it can be shared between threads behind an `Arc`
+/// without a mutex; clones share the backend. The xtables lock serializes the commands across
+/// processes, see `IPTables::serialize_commands` to also serialize them inside the process.
+#[cfg(not(feature = "parse-only"))]
+#[derive(Clone)]
 pub struct IPTables {
     /// The utility command which must be 'iptables' or 'ip6tables'.
     pub cmd: &'static str,
@@ -91,123 +251,325 @@ pub struct IPTables {
     /// Indicates if iptables has -w (--wait) option
     pub has_wait: bool,
 
+    /// Indicates if iptables-restore has -w (--wait) option
+    pub has_restore_wait: bool,
+
     /// Indicates if iptables will be run with -n (--numeric) option
     pub is_numeric: bool,
+
+    /// Strategy used to retry commands when the xtables lock is held by another process
+    pub retry_policy: RetryPolicy,
+
+    /// Maximum duration of each iptables command, after which it is killed
+    pub timeout: Option<Duration>,
+
+    /// Indicates if rules created through this handle get an identity comment
+    pub auto_identity: bool,
+
+    /// Indicates if mutating calls are verified to have taken effect
+    pub verify_writes: bool,
+
+    /// Indicates if the methods taking rules as strings are disabled
+    pub strict_args: bool,
+
+    /// Prefix of the chains created by this handle, which is the only one allowed to delete them
+    pub chain_prefix: Option<String>,
+
+    /// The backend which executes the commands
+    pub backend: Arc<dyn Backend>,
+
+    /// Limit of the rate of the commands, shared by the clones of the handle
+    pub rate_limiter: Option<Arc<RateLimiter>>,
+
+    /// Callback receiving the progress of the long operations
+    pub progress: Option<Progress>,
+}
+
+/// Fails to compile if `IPTables` is no longer `Send` and `Sync`.
+#[cfg(not(feature = "parse-only"))]
+const _: fn() = || {
+    fn assert_send_sync<T: Send + Sync>() {}
+    assert_send_sync::<IPTables>();
+};
+
+/// Optional features of the iptables utilities, which depend on their version.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features {
+    /// Indicates if iptables has -C (--check) option
+    pub check: bool,
+
+    /// Indicates if iptables has -w (--wait) option
+    pub wait: bool,
+
+    /// Indicates if iptables-restore has -w (--wait) option
+    pub restore_wait: bool,
+}
+
+/// The default policy of a built-in chain.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Policy {
+    /// Lets the packets through.
+    Accept,
+
+    /// Drops the packets.
+    Drop,
+}
+
+impl Policy {
+    /// Returns the policy as used by iptables (e.g. 'ACCEPT').
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Policy::Accept => "ACCEPT",
+            Policy::Drop => "DROP",
+        }
+    }
+}
+
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Policy {
+    type Err = Box<dyn Error>;
+
+    fn from_str(policy: &str) -> Result<Policy, Box<dyn Error>> {
+        match policy {
+            "ACCEPT" => Ok(Policy::Accept),
+            "DROP" => Ok(Policy::Drop),
+            _ => Err(error_from_str(&format!("invalid policy {}", policy))),
+        }
+    }
 }
 
 /// Returns `None` because iptables only works on linux
-#[cfg(not(target_os = "linux"))]
+#[cfg(all(
+    not(target_os = "linux"),
+    not(feature = "test-backend"),
+    not(feature = "parse-only")
+))]
 pub fn new(is_ipv6: bool) -> Result<IPTables, Box<dyn Error>> {
     Err(error_from_str("iptables only works on Linux"))
 }
 
+/// Creates a new `IPTables` using a `MockBackend`, since iptables only works on linux.
+/// The handle assumes that all optional features of iptables are available.
+#[cfg(all(
+    not(target_os = "linux"),
+    feature = "test-backend",
+    not(feature = "parse-only")
+))]
+pub fn new(is_ipv6: bool) -> Result<IPTables, Box<dyn Error>> {
+    let cmd = if is_ipv6 { "ip6tables" } else { "iptables" };
+    let mut ipt = IPTables::with_features(
+        cmd,
+        Features {
+            check: true,
+            wait: true,
+            restore_wait: true,
+        },
+    );
+    ipt.set_backend(Arc::new(backend::MockBackend::new()));
+    Ok(ipt)
+}
+
 /// Creates a new `IPTables` Result with the command of 'iptables' if `is_ipv6` is `false`, otherwise the command is 'ip6tables'.
-#[cfg(target_os = "linux")]
+#[cfg(all(target_os = "linux", not(feature = "parse-only")))]
 pub fn new(is_ipv6: bool) -> Result<IPTables, Box<dyn Error>> {
     let cmd = if is_ipv6 { "ip6tables" } else { "iptables" };
+    Ok(IPTables::with_features(
+        cmd,
+        probe_features(&SystemBackend, cmd)?,
+    ))
+}
+
+/// Returns the handle of iptables (or ip6tables if `is_ipv6` is `true`) shared by the whole
+/// process, created by `new` on the first call. Components of the process using it instead of
+/// their own handles never run their commands concurrently, see `SharedIPTables`.
+#[cfg(not(feature = "parse-only"))]
+pub fn global(is_ipv6: bool) -> Result<&'static SharedIPTables, Box<dyn Error>> {
+    static IPV4: OnceLock<SharedIPTables> = OnceLock::new();
+    static IPV6: OnceLock<SharedIPTables> = OnceLock::new();
+    let cell = if is_ipv6 { &IPV6 } else { &IPV4 };
+    if let Some(shared) = cell.get() {
+        return Ok(shared);
+    }
+    // Creating the handle may fail, e.g. without privileges, and is then retried on the next
+    // call; a handle created concurrently by another thread is kept instead of this one
+    let ipt = new(is_ipv6)?;
+    Ok(cell.get_or_init(|| SharedIPTables::new(ipt)))
+}
 
-    let version_output = Command::new(cmd).arg("--version").output()?;
-    let re = Regex::new(r"v(\d+)\.(\d+)\.(\d+)")?;
+/// Probes the optional features of the version of `cmd` executed by the `backend`.
+#[cfg(all(target_os = "linux", not(feature = "parse-only")))]
+pub(crate) fn probe_features(backend: &dyn Backend, cmd: &str) -> Result<Features, Box<dyn Error>> {
+    let version_output = backend.run(cmd, &["--version".into()], None, None)?;
     let version_string = String::from_utf8_lossy(version_output.stdout.as_slice());
-    let versions = re
-        .captures(&version_string)
-        .ok_or("invalid version number")?;
-    let v_major = versions
-        .get(1)
-        .ok_or("unable to get major version number")?
-        .as_str()
-        .parse::<i32>()?;
-    let v_minor = versions
-        .get(2)
-        .ok_or("unable to get minor version number")?
-        .as_str()
-        .parse::<i32>()?;
-    let v_patch = versions
-        .get(3)
-        .ok_or("unable to get patch version number")?
-        .as_str()
-        .parse::<i32>()?;
-
-    Ok(IPTables {
-        cmd,
-        has_check: (v_major > 1)
+    let [v_major, v_minor, v_patch] =
+        parse_version(&version_string).ok_or("invalid version number")?;
+
+    Ok(Features {
+        check: (v_major > 1)
             || (v_major == 1 && v_minor > 4)
             || (v_major == 1 && v_minor == 4 && v_patch > 10),
-        has_wait: (v_major > 1)
+        wait: (v_major > 1)
             || (v_major == 1 && v_minor > 4)
             || (v_major == 1 && v_minor == 4 && v_patch > 19),
-        is_numeric: false,
+        restore_wait: restore_has_wait(backend, cmd),
+    })
+}
+
+/// Parses the first version number (e.g. 'v1.8.7') of the output of `iptables --version`.
+#[cfg(all(target_os = "linux", not(feature = "parse-only")))]
+fn parse_version(output: &str) -> Option<[i32; 3]> {
+    output.split_whitespace().find_map(|word| {
+        let mut numbers = word.strip_prefix('v')?.split('.');
+        let mut version = [0; 3];
+        for number in version.iter_mut() {
+            *number = numbers.next()?.parse().ok()?;
+        }
+        Some(version)
     })
 }
 
+/// Checks if the restore utility of `cmd` (e.g. 'iptables-restore') mentions -w (--wait) in its help.
+#[cfg(all(target_os = "linux", not(feature = "parse-only")))]
+fn restore_has_wait(backend: &dyn Backend, cmd: &str) -> bool {
+    match backend.run(&format!("{}-restore", cmd), &["--help".into()], None, None) {
+        Ok(output) => [output.stdout, output.stderr]
+            .iter()
+            .any(|out| String::from_utf8_lossy(out).contains("--wait")),
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(feature = "parse-only"))]
 impl IPTables {
-    /// Get the default policy for a table/chain.
+    /// Creates a new `IPTables` with the command `cmd` (which must be 'iptables' or 'ip6tables')
+    /// and the given `features`, without probing the version of iptables.
+    pub fn with_features(cmd: &'static str, features: Features) -> IPTables {
+        IPTables {
+            cmd,
+            has_check: features.check,
+            has_wait: features.wait,
+            has_restore_wait: features.restore_wait,
+            is_numeric: false,
+            retry_policy: RetryPolicy::default(),
+            timeout: None,
+            auto_identity: false,
+            verify_writes: false,
+            strict_args: false,
+            chain_prefix: None,
+            backend: Arc::new(SystemBackend),
+            rate_limiter: None,
+            progress: None,
+        }
+    }
+
+    /// Get the default policy for a table/chain, which must be a built-in chain.
     pub fn get_policy(&self, table: &str, chain: &str) -> Result<String, Box<dyn Error>> {
-        let builtin_chains = get_builtin_chains(table)?;
-        if !builtin_chains.iter().as_slice().contains(&chain) {
-            return Err(error_from_str(
-                "given chain is not a default chain in the given table, can't get policy",
-            ));
+        let prefix = format!("-P {} ", chain);
+        let policy = self.list(table, chain)?.iter().find_map(|rule| {
+            rule.strip_prefix(&prefix)
+                .map(|policy| policy.trim().to_string())
+        });
+        if let Some(policy) = policy {
+            return Ok(policy);
         }
 
-        let stdout = match self.is_numeric {
-            false => self.run(&["-t", table, "-L", chain])?.stdout,
-            true => self.run(&["-t", table, "-L", chain, "-n"])?.stdout,
-        };
+        // Fall back to the header of the listing, whose format is less stable
+        let stdout = self.run(&["-t", table, "-L", chain, "-n"])?.stdout;
         let output = String::from_utf8_lossy(stdout.as_slice());
         for item in output.trim().split('\n') {
             let fields = item.split(' ').collect::<Vec<&str>>();
-            if fields.len() > 1 && fields[0] == "Chain" && fields[1] == chain {
+            if fields.len() > 3 && fields[0] == "Chain" && fields[1] == chain {
+                if fields[2] != "(policy" {
+                    break;
+                }
                 return Ok(fields[3].replace(")", ""));
             }
         }
         Err(error_from_str(
-            "could not find the default policy for table and chain",
+            "given chain is not a default chain in the given table, can't get policy",
         ))
     }
 
     /// Set the default policy for a table/chain.
-    pub fn set_policy(&self, table: &str, chain: &str, policy: &str) -> Result<(), Box<dyn Error>> {
-        let builtin_chains = get_builtin_chains(table)?;
-        if !builtin_chains.iter().as_slice().contains(&chain) {
-            return Err(error_from_str(
-                "given chain is not a default chain in the given table, can't set policy",
-            ));
-        }
-
-        self.run(&["-t", table, "-P", chain, policy])
-            .and_then(output_to_result)
+    /// Returns the previous policy of the chain, so it can be restored later.
+    pub fn set_policy(
+        &self,
+        table: &str,
+        chain: &str,
+        policy: Policy,
+    ) -> Result<Policy, Box<dyn Error>> {
+        let previous = self.get_policy(table, chain)?.parse::<Policy>()?;
+        self.run_checked(&["-t", table, "-P", chain, policy.as_str()])?;
+        self.verify_write("set_policy", || {
+            Ok(self.get_policy(table, chain)? == policy.as_str())
+        })?;
+        Ok(previous)
     }
 
     /// Executes a given `command` on the chain.
     /// Returns the command output if successful.
-    pub fn execute(&self, table: &str, command: &str) -> Result<Output, Box<dyn Error>> {
-        self.run(&[&["-t", table], command.split_quoted().as_slice()].concat())
+    pub fn execute(&self, table: &str, command: &str) -> Result<CommandOutput, Box<dyn Error>> {
+        let args = self.rule_args(&["-t", table], command)?;
+        let output = self.run(&args)?;
+
+        let mut argv = vec![self.cmd.to_string()];
+        argv.extend(args.iter().cloned());
+        if self.has_wait {
+            argv.push("--wait".to_string());
+        }
+        Ok(CommandOutput::new(argv, output))
     }
 
     /// Checks for the existence of the `rule` in the table/chain.
     /// Returns true if the rule exists.
-    #[cfg(target_os = "linux")]
     pub fn exists(&self, table: &str, chain: &str, rule: &str) -> Result<bool, Box<dyn Error>> {
+        let args = self.rule_args(&["-t", table, "-C", chain], rule)?;
+        if self.find_identified(table, chain, rule)?.is_some() {
+            return Ok(true);
+        }
+
         if !self.has_check {
             return self.exists_old_version(table, chain, rule);
         }
 
-        self.run(&[&["-t", table, "-C", chain], rule.split_quoted().as_slice()].concat())
-            .map(|output| output.status.success())
+        self.run(&args).map(|output| output.status.success())
     }
 
     /// Checks for the existence of the `chain` in the table.
     /// Returns true if the chain exists.
-    #[cfg(target_os = "linux")]
     pub fn chain_exists(&self, table: &str, chain: &str) -> Result<bool, Box<dyn Error>> {
-        match self.is_numeric {
-            false => self
-                .run(&["-t", table, "-L", chain])
-                .map(|output| output.status.success()),
-            true => self
-                .run(&["-t", table, "-L", chain, "-n"])
-                .map(|output| output.status.success()),
+        // Listing a single rule only prints that rule, but succeeds for an existing chain even
+        // if the rule does not exist, so the other rules of the chain are not dumped
+        self.run(&["-t", table, "-S", chain, "1"])
+            .map(|output| output.status.success())
+    }
+
+    /// Waits until the `rule` exists in the table/chain if `present` is `true`, or until it no
+    /// longer exists otherwise, e.g. for a rule managed by another tool.
+    /// Returns `false` if the condition still does not hold after `timeout`.
+    pub fn wait_for_rule(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+        present: bool,
+        timeout: Duration,
+    ) -> Result<bool, Box<dyn Error>> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.exists(table, chain, rule)? == present {
+                return Ok(true);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
+            }
+            thread::sleep(WAIT_INTERVAL.min(deadline - now));
         }
     }
 
@@ -217,14 +579,14 @@ impl IPTables {
         chain: &str,
         rule: &str,
     ) -> Result<bool, Box<dyn Error>> {
-        match self.is_numeric {
-            false => self.run(&["-t", table, "-S"]).map(|output| {
-                String::from_utf8_lossy(&output.stdout).contains(&format!("-A {} {}", chain, rule))
-            }),
-            true => self.run(&["-t", table, "-S", "-n"]).map(|output| {
-                String::from_utf8_lossy(&output.stdout).contains(&format!("-A {} {}", chain, rule))
-            }),
-        }
+        let rule = format!("-A {} {}", chain, parse::normalize_rule(rule));
+        let output = match self.is_numeric {
+            false => self.run(&["-t", table, "-S"])?,
+            true => self.run(&["-t", table, "-S", "-n"])?,
+        };
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .any(|line| line.trim_end() == rule))
     }
 
     /// Inserts `rule` in the `position` to the table/chain.
@@ -235,14 +597,11 @@ impl IPTables {
         rule: &str,
         position: i32,
     ) -> Result<(), Box<dyn Error>> {
-        self.run(
-            &[
-                &["-t", table, "-I", chain, &position.to_string()],
-                rule.split_quoted().as_slice(),
-            ]
-            .concat(),
-        )
-        .and_then(output_to_result)
+        let rule = self.with_identity(rule)?;
+        self.run_checked(
+            &self.rule_args(&["-t", table, "-I", chain, &position.to_string()], &rule)?,
+        )?;
+        self.verify_write("insert", || self.exists(table, chain, &rule))
     }
 
     /// Inserts `rule` in the `position` to the table/chain if it does not exist.
@@ -260,6 +619,22 @@ impl IPTables {
         self.insert(table, chain, rule, position)
     }
 
+    /// Inserts `rule` in the `position` to the table/chain.
+    /// Succeeds without doing anything if the rule already exists.
+    pub fn insert_idempotent(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+        position: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.exists(table, chain, rule)? {
+            return Ok(());
+        }
+
+        self.insert(table, chain, rule, position)
+    }
+
     /// Replaces `rule` in the `position` to the table/chain.
     pub fn replace(
         &self,
@@ -268,20 +643,18 @@ impl IPTables {
         rule: &str,
         position: i32,
     ) -> Result<(), Box<dyn Error>> {
-        self.run(
-            &[
-                &["-t", table, "-R", chain, &position.to_string()],
-                rule.split_quoted().as_slice(),
-            ]
-            .concat(),
-        )
-        .and_then(output_to_result)
+        let rule = self.with_identity(rule)?;
+        self.run_checked(
+            &self.rule_args(&["-t", table, "-R", chain, &position.to_string()], &rule)?,
+        )?;
+        self.verify_write("replace", || self.exists(table, chain, &rule))
     }
 
     /// Appends `rule` to the table/chain.
     pub fn append(&self, table: &str, chain: &str, rule: &str) -> Result<(), Box<dyn Error>> {
-        self.run(&[&["-t", table, "-A", chain], rule.split_quoted().as_slice()].concat())
-            .and_then(output_to_result)
+        let rule = self.with_identity(rule)?;
+        self.run_checked(&self.rule_args(&["-t", table, "-A", chain], &rule)?)?;
+        self.verify_write("append", || self.exists(table, chain, &rule))
     }
 
     /// Appends `rule` to the table/chain if it does not exist.
@@ -298,6 +671,21 @@ impl IPTables {
         self.append(table, chain, rule)
     }
 
+    /// Appends `rule` to the table/chain.
+    /// Succeeds without doing anything if the rule already exists.
+    pub fn append_idempotent(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if self.exists(table, chain, rule)? {
+            return Ok(());
+        }
+
+        self.append(table, chain, rule)
+    }
+
     /// Appends or replaces `rule` to the table/chain if it does not exist.
     pub fn append_replace(
         &self,
@@ -314,8 +702,33 @@ impl IPTables {
 
     /// Deletes `rule` from the table/chain.
     pub fn delete(&self, table: &str, chain: &str, rule: &str) -> Result<(), Box<dyn Error>> {
-        self.run(&[&["-t", table, "-D", chain], rule.split_quoted().as_slice()].concat())
-            .and_then(output_to_result)
+        if let Some(identified) = self.find_identified(table, chain, rule)? {
+            return self.delete(table, chain, &identified);
+        }
+
+        let count = match self.verify_writes {
+            true => self.chain_rules(table, chain)?.len(),
+            false => 0,
+        };
+        self.run_checked(&self.rule_args(&["-t", table, "-D", chain], rule)?)?;
+        self.verify_write("delete", || {
+            Ok(self.chain_rules(table, chain)?.len() + 1 == count)
+        })
+    }
+
+    /// Deletes `rule` from the table/chain.
+    /// Succeeds without doing anything if the rule does not exist.
+    pub fn delete_idempotent(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        if !self.exists(table, chain, rule)? {
+            return Ok(());
+        }
+
+        self.delete(table, chain, rule)
     }
 
     /// Deletes all repetition of the `rule` from the table/chain.
@@ -343,6 +756,18 @@ impl IPTables {
         }
     }
 
+    /// Lists the built-in chains of the table, i.e. the chains having a default policy, as
+    /// reported by the running iptables (which may differ between kernels and variants).
+    pub fn builtin_chains(&self, table: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        let output = self.run_checked(&["-t", table, "-S"])?;
+        Ok(String::from_utf8_lossy(output.stdout.as_slice())
+            .lines()
+            .filter_map(|line| line.strip_prefix("-P "))
+            .filter_map(|policy| policy.split(' ').next())
+            .map(String::from)
+            .collect())
+    }
+
     /// Lists the name of each chain in the table.
     pub fn list_chains(&self, table: &str) -> Result<Vec<String>, Box<dyn Error>> {
         let mut list = Vec::new();
@@ -359,14 +784,17 @@ impl IPTables {
 
     /// Creates a new user-defined chain.
     pub fn new_chain(&self, table: &str, chain: &str) -> Result<(), Box<dyn Error>> {
-        self.run(&["-t", table, "-N", chain])
-            .and_then(output_to_result)
+        let chain = &self.prefixed_chain(chain);
+        self.run_checked(&["-t", table, "-N", chain])?;
+        self.verify_write("new_chain", || self.chain_exists(table, chain))
     }
 
     /// Flushes (deletes all rules) a chain.
     pub fn flush_chain(&self, table: &str, chain: &str) -> Result<(), Box<dyn Error>> {
-        self.run(&["-t", table, "-F", chain])
-            .and_then(output_to_result)
+        self.run_checked(&["-t", table, "-F", chain])?;
+        self.verify_write("flush_chain", || {
+            Ok(self.chain_rules(table, chain)?.is_empty())
+        })
     }
 
     /// Renames a chain in the table.
@@ -376,19 +804,53 @@ impl IPTables {
         old_chain: &str,
         new_chain: &str,
     ) -> Result<(), Box<dyn Error>> {
-        self.run(&["-t", table, "-E", old_chain, new_chain])
-            .and_then(output_to_result)
+        self.check_chain_prefix(old_chain)?;
+        let new_chain = &self.prefixed_chain(new_chain);
+        self.run_checked(&["-t", table, "-E", old_chain, new_chain])?;
+        self.verify_write("rename_chain", || {
+            Ok(self.chain_exists(table, new_chain)? && !self.chain_exists(table, old_chain)?)
+        })
     }
 
     /// Deletes a user-defined chain in the table.
     pub fn delete_chain(&self, table: &str, chain: &str) -> Result<(), Box<dyn Error>> {
-        self.run(&["-t", table, "-X", chain])
-            .and_then(output_to_result)
+        self.check_chain_prefix(chain)?;
+        self.run_checked(&["-t", table, "-X", chain])?;
+        self.verify_write("delete_chain", || {
+            self.chain_exists(table, chain).map(|exists| !exists)
+        })
+    }
+
+    /// Deletes the user-defined `root_chain` of the table, along with every user-defined chain
+    /// only reachable through it, after flushing them.
+    /// Returns the names of the deleted chains, starting with `root_chain`.
+    pub fn delete_chain_tree(
+        &self,
+        table: &str,
+        root_chain: &str,
+    ) -> Result<Vec<String>, Box<dyn Error>> {
+        let tree = self.chain_graph(table)?.subtree(root_chain);
+        for chain in tree.iter() {
+            self.check_chain_prefix(chain)?;
+        }
+        for chain in tree.iter() {
+            self.flush_chain(table, chain)?;
+        }
+        for chain in tree.iter() {
+            self.delete_chain(table, chain)?;
+        }
+        Ok(tree)
     }
 
     /// Flushes all chains in a table.
     pub fn flush_table(&self, table: &str) -> Result<(), Box<dyn Error>> {
-        self.run(&["-t", table, "-F"]).and_then(output_to_result)
+        self.run_checked(&["-t", table, "-F"])?;
+        self.verify_write("flush_table", || {
+            Ok(!self
+                .list_table(table)?
+                .iter()
+                .any(|rule| rule.starts_with("-A ")))
+        })
     }
 
     fn get_list<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Vec<String>, Box<dyn Error>> {
@@ -406,39 +868,179 @@ impl IPTables {
         self.is_numeric = numeric;
     }
 
-    fn run<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Output, Box<dyn Error>> {
-        let mut file_lock = None;
+    /// Set the strategy used to retry commands when the xtables lock is held by another process.
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
 
-        let mut output_cmd = Command::new(self.cmd);
-        let output;
+    /// Set the maximum duration of each iptables command. Commands exceeding it are killed
+    /// and `IPTError::Timeout` is returned.
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
 
-        if self.has_wait {
-            output = output_cmd.args(args).arg("--wait").output()?;
-        } else {
-            file_lock = Some(File::create("/var/run/xtables_old.lock")?);
-
-            let mut need_retry = true;
-            while need_retry {
-                match flock(
-                    file_lock.as_ref().unwrap().as_raw_fd(),
-                    FlockArg::LockExclusiveNonblock,
-                ) {
-                    Ok(_) => need_retry = false,
-                    Err(e) if e == nix::errno::Errno::EAGAIN => {
-                        // FIXME: may cause infinite loop
-                        need_retry = true;
-                    }
-                    Err(e) => {
-                        return Err(Box::new(e));
-                    }
+    /// Set the maximum number of commands run by this handle per second, after which commands
+    /// wait for their turn; `None` or zero removes the limit. Clones made afterwards share the
+    /// limit.
+    pub fn set_rate_limit(&mut self, commands_per_second: Option<u32>) {
+        self.rate_limiter = commands_per_second
+            .filter(|rate| *rate > 0)
+            .map(|rate| Arc::new(RateLimiter::new(rate)));
+    }
+
+    /// Set whether the methods taking rules as strings (e.g. `append`), and the helpers built on
+    /// them, fail with `IPTError::InvalidRule` instead of splitting the strings into arguments.
+    /// Only the methods taking separate arguments (e.g. `append_os`, with `RuleBuilder::args`)
+    /// can then be used, so externally influenced strings are never tokenized implicitly.
+    pub fn set_strict_args(&mut self, strict_args: bool) {
+        self.strict_args = strict_args;
+    }
+
+    /// Set the prefix (e.g. 'MYAPP-') of the chains of the application. The prefix is added to
+    /// the name of the chains created or renamed by this handle if they lack it, and deleting or
+    /// renaming a chain without it fails with `IPTError::PolicyDenied`, so the chains of other
+    /// software are never removed by mistake.
+    pub fn set_chain_prefix(&mut self, chain_prefix: Option<&str>) {
+        self.chain_prefix = chain_prefix.map(String::from);
+    }
+
+    /// Returns the name of the `chain` with the prefix of the chains of the application, see
+    /// `set_chain_prefix`.
+    pub fn prefixed_chain(&self, chain: &str) -> String {
+        match &self.chain_prefix {
+            Some(prefix) if !chain.starts_with(prefix.as_str()) => format!("{}{}", prefix, chain),
+            _ => chain.to_string(),
+        }
+    }
+
+    /// Fails if the `chain` lacks the prefix of the chains of the application.
+    fn check_chain_prefix(&self, chain: &str) -> Result<(), Box<dyn Error>> {
+        match &self.chain_prefix {
+            Some(prefix) if !chain.starts_with(prefix.as_str()) => Err(Box::new(
+                IPTError::PolicyDenied(format!("chain {} does not start with {}", chain, prefix)),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Set whether each mutating call is followed by a check that the change took effect.
+    /// If the check fails, `IPTError::VerificationFailed` is returned.
+    pub fn set_verify_writes(&mut self, verify_writes: bool) {
+        self.verify_writes = verify_writes;
+    }
+
+    /// Builds the arguments of a command from its leading arguments and a rule, which is
+    /// validated by `RuleSpec` first. Fails in the `strict_args` mode.
+    fn rule_args(&self, head: &[&str], rule: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.strict_args {
+            return Err(Box::new(IPTError::InvalidRule(
+                "rules given as strings are disabled by strict_args".to_string(),
+            )));
+        }
+        let spec = RuleSpec::try_from(rule)?;
+        Ok(head
+            .iter()
+            .map(|arg| arg.to_string())
+            .chain(spec.args().iter().cloned())
+            .collect())
+    }
+
+    fn verify_write<F>(&self, operation: &str, check: F) -> Result<(), Box<dyn Error>>
+    where
+        F: FnOnce() -> Result<bool, Box<dyn Error>>,
+    {
+        if self.verify_writes && !check()? {
+            return Err(Box::new(IPTError::VerificationFailed(
+                operation.to_string(),
+            )));
+        }
+        Ok(())
+    }
+
+    /// Runs iptables like `run`, turning a failed exit status into an error.
+    fn run_checked<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Output, Box<dyn Error>> {
+        self.run(args)
+            .and_then(|output| check_output(self.cmd, args, output))
+    }
+
+    /// Runs iptables with `args`, retrying while the xtables lock is held by another process.
+    /// Errors carry the command line, but a failed exit status is not an error.
+    fn run<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Output, Box<dyn Error>> {
+        self.run_with_retry(args)
+            .map_err(|e| IPTError::command(self.cmd, args, e))
+    }
+
+    fn run_with_retry<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Output, Box<dyn Error>> {
+        let mut attempt = 1;
+        loop {
+            match self.try_run(args)? {
+                Some(output) if attempt >= self.retry_policy.max_attempts => return Ok(output),
+                Some(output) if !is_lock_error(&output) => return Ok(output),
+                None if attempt >= self.retry_policy.max_attempts => {
+                    return Err(Box::new(IPTError::Locked))
                 }
+                _ => thread::sleep(self.retry_policy.delay(attempt)),
             }
-            output = output_cmd.args(args).output()?;
+            attempt += 1;
         }
+    }
 
-        if let Some(f) = file_lock {
-            drop(f)
+    /// Runs the command once, returns `None` if the lock used for old iptables versions
+    /// (without -w option) is held by another process.
+    fn try_run<S: AsRef<OsStr>>(&self, args: &[S]) -> Result<Option<Output>, Box<dyn Error>> {
+        if self.has_wait {
+            let mut args = args.iter().map(AsRef::as_ref).collect::<Vec<&OsStr>>();
+            args.push(OsStr::new("--wait"));
+            return self.run_program(self.cmd, &args, None).map(Some);
         }
-        Ok(output)
+
+        // The lock is released when `_file_lock` goes out of scope
+        let _file_lock = match try_lock("/var/run/xtables_old.lock")? {
+            Some(file_lock) => file_lock,
+            None => return Ok(None),
+        };
+        self.run_program(self.cmd, args, None).map(Some)
+    }
+
+    /// Set the backend which executes the commands of this handle.
+    pub fn set_backend(&mut self, backend: Arc<dyn Backend>) {
+        self.backend = backend;
+    }
+
+    /// Runs the commands of this handle, and of its clones made afterwards, one at a time (see
+    /// `SerialBackend`), e.g. for iptables versions without -w whose lock is only taken by
+    /// this crate as a best effort.
+    pub fn serialize_commands(&mut self) {
+        self.backend = Arc::new(SerialBackend::new(self.backend.clone()));
+    }
+
+    /// Runs `program` (which may be any of the iptables utilities) using the backend.
+    pub(crate) fn run_program<S: AsRef<OsStr>>(
+        &self,
+        program: &str,
+        args: &[S],
+        input: Option<&str>,
+    ) -> Result<Output, Box<dyn Error>> {
+        let args = args
+            .iter()
+            .map(|arg| arg.as_ref().to_os_string())
+            .collect::<Vec<OsString>>();
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire();
+        }
+        self.backend.run(program, &args, input, self.timeout)
+    }
+
+    /// Runs `program` like `run_program`, turning a failed exit status into an error.
+    /// Errors carry the command line.
+    pub(crate) fn run_program_checked<S: AsRef<OsStr>>(
+        &self,
+        program: &str,
+        args: &[S],
+        input: Option<&str>,
+    ) -> Result<Output, Box<dyn Error>> {
+        self.run_program(program, args, input)
+            .map_err(|e| IPTError::command(program, args, e))
+            .and_then(|output| check_output(program, args, output))
     }
 }
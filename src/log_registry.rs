@@ -0,0 +1,196 @@
+//! Registry of the prefixes of the LOG rules, matching the lines logged by the kernel back to
+//! the rules which logged them.
+
+use crate::parse::{parse_rules, parse_save, quote_comment};
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+use std::io::{self, BufRead};
+
+/// Maximum length of a tag, so that the prefixes fit in the 29 characters allowed by LOG.
+const MAX_TAG_LEN: usize = 16;
+
+/// A LOG rule registered in a `LogRegistry`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoggedRule {
+    /// Prefix (`--log-prefix`) of the rule, e.g. 'myapp-3: '.
+    pub prefix: String,
+
+    /// Table of the rule.
+    pub table: String,
+
+    /// Chain of the rule.
+    pub chain: String,
+
+    /// Specification of the rule, including its LOG target.
+    pub rule: String,
+}
+
+/// A line logged by a rule of a `LogRegistry`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogLine<'r> {
+    /// The rule which logged the line.
+    pub rule: &'r LoggedRule,
+
+    /// Fields of the packet (e.g. ('SRC', '10.0.0.1')), in the order of the line. Flags such as
+    /// 'SYN' have an empty value.
+    pub fields: Vec<(String, String)>,
+}
+
+impl LogLine<'_> {
+    /// Returns the value of the field `name` (e.g. 'SRC', 'DPT').
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Unique prefixes of the LOG rules of an application, of the form 'tag-N: '.
+///
+/// The LOG rules already in the ruleset with a prefix of the tag are registered when the
+/// registry is created, so the prefixes stay unique across restarts of the application.
+pub struct LogRegistry<'a> {
+    ipt: &'a IPTables,
+    tag: String,
+    rules: Vec<LoggedRule>,
+    next: u32,
+}
+
+impl IPTables {
+    /// Creates the registry of the LOG rules with prefixes of `tag`, made of at most 16 ASCII
+    /// letters, digits, `-` and `_`.
+    pub fn log_registry(&self, tag: &str) -> Result<LogRegistry<'_>, Box<dyn Error>> {
+        if tag.is_empty() || tag.len() > MAX_TAG_LEN || !tag.chars().all(is_tag_char) {
+            return Err(error_from_str(&format!("invalid log tag {}", tag)));
+        }
+        let mut registry = LogRegistry {
+            ipt: self,
+            tag: tag.to_string(),
+            rules: Vec::new(),
+            next: 1,
+        };
+        for table in parse_save(&self.save_ruleset()?) {
+            for rule in parse_rules(&table.rules.join("\n")) {
+                if rule.target().as_deref() != Some("LOG") {
+                    continue;
+                }
+                let prefix = match rule.option(&["--log-prefix"]) {
+                    Some(prefix) => prefix,
+                    None => continue,
+                };
+                if let Some(n) = registry.number_of(&prefix) {
+                    registry.next = registry.next.max(n + 1);
+                    registry.rules.push(LoggedRule {
+                        prefix,
+                        table: table.name.clone(),
+                        chain: rule.chain,
+                        rule: rule.spec,
+                    });
+                }
+            }
+        }
+        Ok(registry)
+    }
+}
+
+impl LogRegistry<'_> {
+    /// Returns the tag of the prefixes.
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    /// Returns the registered rules.
+    pub fn rules(&self) -> &[LoggedRule] {
+        &self.rules
+    }
+
+    /// Appends `rule` (without target) to the table/chain with a LOG target and a new prefix.
+    pub fn append(
+        &mut self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+    ) -> Result<&LoggedRule, Box<dyn Error>> {
+        let prefix = format!("{}-{}: ", self.tag, self.next);
+        let rule = format!("{} -j LOG --log-prefix {}", rule, quote_comment(&prefix))
+            .trim_start()
+            .to_string();
+        self.ipt.append(table, chain, &rule)?;
+        self.next += 1;
+        self.rules.push(LoggedRule {
+            prefix,
+            table: table.to_string(),
+            chain: chain.to_string(),
+            rule,
+        });
+        Ok(&self.rules[self.rules.len() - 1])
+    }
+
+    /// Deletes the rule with the `prefix` and unregisters it.
+    pub fn delete(&mut self, prefix: &str) -> Result<(), Box<dyn Error>> {
+        let index = self
+            .rules
+            .iter()
+            .position(|rule| rule.prefix == prefix)
+            .ok_or_else(|| error_from_str(&format!("no rule logs with prefix {}", prefix)))?;
+        let rule = &self.rules[index];
+        self.ipt.delete(&rule.table, &rule.chain, &rule.rule)?;
+        self.rules.remove(index);
+        Ok(())
+    }
+
+    /// Finds the rule which logged the kernel log `line` (e.g. read from `dmesg` or the
+    /// journal), or `None` if it was not logged by a registered rule.
+    pub fn classify(&self, line: &str) -> Option<LogLine<'_>> {
+        // The kernel prints the prefix right before the fields of the packet
+        let start = line.find("IN=")?;
+        let head = &line[..start];
+        let rule = self.rules.iter().find(|rule| {
+            head.strip_suffix(rule.prefix.as_str())
+                .is_some_and(|before| !before.ends_with(is_tag_char))
+        })?;
+        let fields = log_fields(&line[start..]);
+        Some(LogLine { rule, fields })
+    }
+
+    /// Classifies the lines read from `reader`, skipping those not logged by a registered rule.
+    pub fn classify_lines<'r, R: BufRead + 'r>(
+        &'r self,
+        reader: R,
+    ) -> impl Iterator<Item = Result<LogLine<'r>, io::Error>> + 'r {
+        reader.lines().filter_map(move |line| match line {
+            Ok(line) => self.classify(&line).map(Ok),
+            Err(error) => Some(Err(error)),
+        })
+    }
+
+    /// Returns N if `prefix` is 'tag-N: '.
+    fn number_of(&self, prefix: &str) -> Option<u32> {
+        let n = prefix
+            .strip_prefix(self.tag.as_str())?
+            .strip_prefix('-')?
+            .strip_suffix(": ")?;
+        match n.chars().all(|c| c.is_ascii_digit()) {
+            true => n.parse().ok(),
+            false => None,
+        }
+    }
+}
+
+/// Splits the fields of a packet logged by the kernel (e.g. 'IN=eth0 OUT= ... SYN URGP=0'),
+/// giving an empty value to the flags.
+pub(crate) fn log_fields(fields: &str) -> Vec<(String, String)> {
+    fields
+        .split_whitespace()
+        .map(|field| match field.split_once('=') {
+            Some((key, value)) => (key.to_string(), value.to_string()),
+            None => (field.to_string(), String::new()),
+        })
+        .collect()
+}
+
+/// Checks if `c` may be part of a tag.
+fn is_tag_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '-' || c == '_'
+}
@@ -0,0 +1,175 @@
+//! Maintenance of the chains managed by an application, identified by the prefix of their
+//! names.
+
+use crate::parse::{fingerprint_rules, normalize_rule, parse_rules, parse_save, split_rule};
+use crate::IPTables;
+use std::cmp::Reverse;
+use std::error::Error;
+
+/// Prefix of the comments identifying the rules pinned by `pin_first`.
+const PIN_PREFIX: &str = "ipt-rs-pin:";
+
+impl IPTables {
+    /// Removes the chains of every table whose name starts with `prefix` but which are not in
+    /// `known` (given with or without the prefix), e.g. left behind by a controller which
+    /// crashed while reconfiguring. The rules of the other chains jumping to them are deleted,
+    /// then they are flushed and deleted. Returns the removed chains as (table, chain).
+    pub fn gc_managed(
+        &self,
+        prefix: &str,
+        known: &[&str],
+    ) -> Result<Vec<(String, String)>, Box<dyn Error>> {
+        let is_known = |chain: &str| {
+            known
+                .iter()
+                .any(|k| *k == chain || chain.strip_prefix(prefix) == Some(*k))
+        };
+        // The stale chains of every table are found first, so the progress has a total
+        let mut plans = Vec::new();
+        for table in parse_save(&self.save_ruleset()?) {
+            let stale = table
+                .chains
+                .iter()
+                .map(|chain| chain.name.clone())
+                .filter(|chain| chain.starts_with(prefix) && !is_known(chain))
+                .collect::<Vec<_>>();
+            if stale.is_empty() {
+                continue;
+            }
+
+            // Positions shift as rules are deleted, so the last ones are deleted first
+            let mut jumps = parse_rules(&table.rules.join("\n"))
+                .into_iter()
+                .filter(|rule| !stale.contains(&rule.chain))
+                .filter(|rule| matches!(rule.target(), Some(target) if stale.contains(&target)))
+                .collect::<Vec<_>>();
+            jumps.sort_by_key(|rule| Reverse(rule.position));
+            plans.push((table.name, jumps, stale));
+        }
+
+        let total = plans
+            .iter()
+            .map(|(_, jumps, stale)| jumps.len() + 2 * stale.len())
+            .sum();
+        let mut done = 0;
+        let mut report = |operation: String| {
+            done += 1;
+            self.report_progress(done, total, &operation);
+        };
+        let mut removed = Vec::new();
+        for (table, jumps, stale) in plans {
+            for jump in jumps {
+                self.delete_at(&table, &jump.chain, jump.position)?;
+                report(format!(
+                    "delete rule {} of {}/{}",
+                    jump.position, table, jump.chain
+                ));
+            }
+            for chain in &stale {
+                self.flush_chain(&table, chain)?;
+                report(format!("flush chain {}/{}", table, chain));
+            }
+            for chain in stale {
+                self.delete_chain(&table, &chain)?;
+                report(format!("delete chain {}/{}", table, chain));
+                removed.push((table.clone(), chain));
+            }
+        }
+        Ok(removed)
+    }
+
+    /// Makes sure that the rule at `position` (starting from 1) of the table/`from_chain` jumps
+    /// to `to_chain` (`-j to_chain`), and that it is the only one: the jump is inserted if
+    /// missing, or moved if it drifted (e.g. other rules were inserted before it).
+    /// Returns `true` if the chain was changed.
+    pub fn ensure_jump(
+        &self,
+        table: &str,
+        from_chain: &str,
+        to_chain: &str,
+        position: i32,
+    ) -> Result<bool, Box<dyn Error>> {
+        let jumps = self.rule_positions(table, from_chain, &format!("-j {}", to_chain))?;
+        if jumps == [position] {
+            return Ok(false);
+        }
+        for position in jumps.iter().rev() {
+            self.delete_at(table, from_chain, *position)?;
+        }
+        self.run_checked(&[
+            "-t",
+            table,
+            "-I",
+            from_chain,
+            &position.to_string(),
+            "-j",
+            to_chain,
+        ])?;
+        Ok(true)
+    }
+
+    /// Deletes the rules of the table/`from_chain` jumping to `to_chain` (`-j to_chain`).
+    /// Returns `true` if any rule was deleted.
+    pub fn remove_jump(
+        &self,
+        table: &str,
+        from_chain: &str,
+        to_chain: &str,
+    ) -> Result<bool, Box<dyn Error>> {
+        let jumps = self.rule_positions(table, from_chain, &format!("-j {}", to_chain))?;
+        for position in jumps.iter().rev() {
+            self.delete_at(table, from_chain, *position)?;
+        }
+        Ok(!jumps.is_empty())
+    }
+
+    /// Makes sure that `rule` is the first rule of the table/chain, and that it is there only
+    /// once, e.g. for a rule allowing the management SSH access. The rule is inserted again if
+    /// other rules were inserted before it, so this can be called periodically (or on the
+    /// events of a `Monitor`) to keep it first. Returns `true` if the chain was changed.
+    /// The rule is given a comment derived from `rule`, which finds it whatever the form
+    /// iptables lists it in.
+    pub fn pin_first(&self, table: &str, chain: &str, rule: &str) -> Result<bool, Box<dyn Error>> {
+        let key = format!("{}{:016x}", PIN_PREFIX, fingerprint_rules(&[rule]));
+        let positions = parse_rules(&self.list(table, chain)?.join("\n"))
+            .into_iter()
+            .filter(|listed| {
+                split_rule(&listed.spec)
+                    .windows(2)
+                    .any(|pair| pair[0] == "--comment" && pair[1] == key)
+            })
+            .map(|listed| listed.position)
+            .collect::<Vec<_>>();
+        if positions == [1] {
+            return Ok(false);
+        }
+        for position in positions.iter().rev() {
+            self.delete_at(table, chain, *position)?;
+        }
+        let rule = format!("-m comment --comment {} {}", key, rule);
+        self.insert(table, chain, &rule, 1)?;
+        Ok(true)
+    }
+
+    /// Returns the positions of the rules of the table/chain which are the same as `rule` once
+    /// normalized by `normalize_rule`, in increasing order.
+    fn rule_positions(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+    ) -> Result<Vec<i32>, Box<dyn Error>> {
+        let rule = normalize_rule(rule);
+        Ok(parse_rules(&self.list(table, chain)?.join("\n"))
+            .into_iter()
+            .filter(|listed| normalize_rule(&listed.spec) == rule)
+            .map(|listed| listed.position)
+            .collect())
+    }
+
+    /// Deletes the rule at `position` (starting from 1) of the table/chain.
+    fn delete_at(&self, table: &str, chain: &str, position: i32) -> Result<(), Box<dyn Error>> {
+        self.run_checked(&["-t", table, "-D", chain, &position.to_string()])?;
+        Ok(())
+    }
+}
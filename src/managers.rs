@@ -0,0 +1,166 @@
+//! Detection of the other firewall managers of the host, which may fight over the same chains.
+
+use crate::parse::parse_save;
+use crate::IPTables;
+use std::error::Error;
+use std::path::Path;
+
+/// Path of the socket of the docker daemon.
+const DOCKER_SOCKET: &str = "/var/run/docker.sock";
+
+/// Tables created by the nf_tables backends of iptables, arptables and ebtables, which are not
+/// native nftables rules.
+const IPTABLES_NFT_TABLES: [&str; 6] = ["filter", "nat", "mangle", "raw", "security", "broute"];
+
+/// A software managing firewall rules.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Manager {
+    /// Docker, owning the DOCKER chains.
+    Docker,
+
+    /// firewalld, owning the chains of its zones and the direct chains.
+    Firewalld,
+
+    /// ufw, owning the ufw-* chains.
+    Ufw,
+
+    /// Kubernetes (kube-proxy), owning the KUBE-* chains.
+    Kubernetes,
+
+    /// Native nftables rules, in tables not created by iptables.
+    Nftables,
+}
+
+impl Manager {
+    /// Returns the name of the manager.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Manager::Docker => "docker",
+            Manager::Firewalld => "firewalld",
+            Manager::Ufw => "ufw",
+            Manager::Kubernetes => "kubernetes",
+            Manager::Nftables => "nftables",
+        }
+    }
+
+    /// Returns the manager which owns the `chain`, guessed from its name.
+    pub fn of_chain(chain: &str) -> Option<Manager> {
+        const FIREWALLD_PREFIXES: [&str; 7] =
+            ["IN_", "FWDI_", "FWDO_", "FWD_", "OUT_", "PRE_", "POST_"];
+        if chain == "DOCKER" || chain.starts_with("DOCKER-") {
+            Some(Manager::Docker)
+        } else if chain.starts_with("ufw-") || chain.starts_with("ufw6-") {
+            Some(Manager::Ufw)
+        } else if chain.starts_with("KUBE-") {
+            Some(Manager::Kubernetes)
+        } else if chain.ends_with("_direct")
+            || chain.ends_with("_ZONES")
+            || chain.ends_with("_ZONES_SOURCE")
+            || FIREWALLD_PREFIXES.iter().any(|p| chain.starts_with(p))
+        {
+            Some(Manager::Firewalld)
+        } else {
+            None
+        }
+    }
+}
+
+/// A manager found by `IPTables::detect_managers`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DetectedManager {
+    /// The manager.
+    pub manager: Manager,
+
+    /// Whether the service of the manager is running, as far as it can be told.
+    pub running: bool,
+
+    /// Chains of the ruleset owned by the manager, as (table, chain).
+    pub chains: Vec<(String, String)>,
+
+    /// nftables tables owned by the manager, as 'family name' (e.g. 'inet firewalld').
+    pub tables: Vec<String>,
+}
+
+impl IPTables {
+    /// Inspects the ruleset and the running services for the other firewall managers of the
+    /// host, so that applications can warn before competing with them for the same chains.
+    /// Only the managers which are running or own chains or tables are returned.
+    pub fn detect_managers(&self) -> Result<Vec<DetectedManager>, Box<dyn Error>> {
+        let mut detected = [
+            Manager::Docker,
+            Manager::Firewalld,
+            Manager::Ufw,
+            Manager::Kubernetes,
+            Manager::Nftables,
+        ]
+        .map(|manager| DetectedManager {
+            manager,
+            running: false,
+            chains: Vec::new(),
+            tables: Vec::new(),
+        });
+
+        let output =
+            self.run_program_checked(&format!("{}-save", self.cmd), &[] as &[&str], None)?;
+        for table in parse_save(&String::from_utf8_lossy(&output.stdout)) {
+            for chain in table.chains {
+                if let Some(manager) = Manager::of_chain(&chain.name) {
+                    detected[manager as usize]
+                        .chains
+                        .push((table.name.clone(), chain.name));
+                }
+            }
+        }
+
+        detected[Manager::Docker as usize].running = Path::new(DOCKER_SOCKET).exists();
+        detected[Manager::Firewalld as usize].running =
+            self.succeeds("firewall-cmd", &["--state"], "running");
+        detected[Manager::Ufw as usize].running =
+            self.succeeds("ufw", &["status"], "Status: active");
+        detected[Manager::Kubernetes as usize].running =
+            !detected[Manager::Kubernetes as usize].chains.is_empty();
+        for table in self.native_nft_tables() {
+            let manager = match table.ends_with(" firewalld") {
+                true => Manager::Firewalld,
+                false => Manager::Nftables,
+            };
+            detected[manager as usize].tables.push(table);
+        }
+        detected[Manager::Nftables as usize].running =
+            !detected[Manager::Nftables as usize].tables.is_empty();
+
+        Ok(detected
+            .into_iter()
+            .filter(|d| d.running || !d.chains.is_empty() || !d.tables.is_empty())
+            .collect())
+    }
+
+    /// Checks if `program` succeeds and prints `expected`; missing programs don't succeed.
+    fn succeeds(&self, program: &str, args: &[&str], expected: &str) -> bool {
+        match self.run_program(program, args, None) {
+            Ok(output) => {
+                output.status.success()
+                    && String::from_utf8_lossy(&output.stdout).contains(expected)
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Returns the nftables tables (e.g. 'inet firewalld') which were not created by the
+    /// nf_tables backends of the xtables utilities, or none if nft is not available.
+    fn native_nft_tables(&self) -> Vec<String> {
+        let output = match self.run_program("nft", &["list", "tables"], None) {
+            Ok(output) if output.status.success() => output,
+            _ => return Vec::new(),
+        };
+        String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| {
+                let (family, name) = line.trim().strip_prefix("table ")?.split_once(' ')?;
+                let iptables = matches!(family, "ip" | "ip6" | "arp" | "bridge")
+                    && IPTABLES_NFT_TABLES.contains(&name);
+                (!iptables).then(|| format!("{} {}", family, name))
+            })
+            .collect()
+    }
+}
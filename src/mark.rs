@@ -0,0 +1,173 @@
+//! Netfilter marks of packets, and allocation of the bits of the mark between subsystems.
+
+use crate::error_from_str;
+use std::error::Error;
+
+/// A mark value with the mask of the bits it applies to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mark {
+    /// Value of the bits of the mask.
+    pub value: u32,
+
+    /// Bits of the mark which are matched or changed.
+    pub mask: u32,
+}
+
+impl Mark {
+    /// Creates a mark applying to all the bits.
+    pub fn new(value: u32) -> Mark {
+        Mark::masked(value, u32::MAX)
+    }
+
+    /// Creates a mark applying only to the bits of `mask`.
+    pub fn masked(value: u32, mask: u32) -> Mark {
+        Mark { value, mask }
+    }
+
+    /// Returns the arguments of the mark match (`-m mark --mark`).
+    pub fn match_args(&self) -> Vec<String> {
+        let mark = match self.mask {
+            u32::MAX => format!("{:#x}", self.value),
+            mask => format!("{:#x}/{:#x}", self.value, mask),
+        };
+        vec![
+            "-m".to_string(),
+            "mark".to_string(),
+            "--mark".to_string(),
+            mark,
+        ]
+    }
+
+    /// Returns the arguments of the MARK target setting the bits of the mask, without the
+    /// leading `-j MARK`.
+    pub fn target_args(&self) -> Vec<String> {
+        vec![
+            "--set-xmark".to_string(),
+            format!("{:#x}/{:#x}", self.value, self.mask),
+        ]
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.value & !self.mask != 0 {
+            return Err(format!(
+                "mark {:#x} has bits outside of its mask {:#x}",
+                self.value, self.mask
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Bits of the mark allocated to a subsystem by `MarkAllocator`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct MarkField {
+    /// Name of the subsystem using the bits.
+    pub name: String,
+
+    /// The allocated bits, which are contiguous.
+    pub mask: u32,
+}
+
+impl MarkField {
+    /// Returns the mark storing `value` in the bits of the field, e.g. with a mask of 0xf00,
+    /// the value 2 is stored as 0x200/0xf00.
+    pub fn mark(&self, value: u32) -> Result<Mark, Box<dyn Error>> {
+        let shift = self.mask.trailing_zeros();
+        if self.mask == 0 || value > self.mask >> shift {
+            return Err(error_from_str(&format!(
+                "value {} does not fit in the mark bits {:#x} of {}",
+                value, self.mask, self.name
+            )));
+        }
+        Ok(Mark::masked(value << shift, self.mask))
+    }
+}
+
+/// Hands out non-overlapping bits of the mark to the subsystems sharing it (e.g. policy
+/// routing, traffic shaping and a VPN), so their MARK rules don't overwrite each other.
+#[derive(Debug, Clone, Default)]
+pub struct MarkAllocator {
+    fields: Vec<MarkField>,
+    reserved: u32,
+}
+
+impl MarkAllocator {
+    /// Creates an allocator where all the bits are free.
+    pub fn new() -> MarkAllocator {
+        MarkAllocator::default()
+    }
+
+    /// Creates an allocator where the `fields` are already allocated, e.g. loaded from a
+    /// state file.
+    pub fn from_fields(fields: Vec<MarkField>) -> MarkAllocator {
+        MarkAllocator {
+            fields,
+            reserved: 0,
+        }
+    }
+
+    /// Reserves the bits of `mask`, which are used by other software (e.g. 0xc000 by
+    /// Kubernetes), so they are never allocated.
+    pub fn reserve(&mut self, mask: u32) {
+        self.reserved |= mask;
+    }
+
+    /// Returns the allocated fields.
+    pub fn fields(&self) -> &[MarkField] {
+        &self.fields
+    }
+
+    /// Returns the bits which are allocated or reserved.
+    pub fn used(&self) -> u32 {
+        self.fields
+            .iter()
+            .fold(self.reserved, |used, field| used | field.mask)
+    }
+
+    /// Allocates `bits` contiguous bits of the mark to the subsystem `name`, from the lowest
+    /// free ones. Allocating the same name again returns its field if it has the same size.
+    pub fn allocate(&mut self, name: &str, bits: u32) -> Result<MarkField, Box<dyn Error>> {
+        if bits == 0 || bits > 32 {
+            return Err(error_from_str(
+                "a mark field must have between 1 and 32 bits",
+            ));
+        }
+        let width = match bits {
+            32 => u32::MAX,
+            bits => (1 << bits) - 1,
+        };
+        if let Some(field) = self.fields.iter().find(|field| field.name == name) {
+            if field.mask.count_ones() != bits {
+                return Err(error_from_str(&format!(
+                    "{} already has {} mark bits",
+                    name,
+                    field.mask.count_ones()
+                )));
+            }
+            return Ok(field.clone());
+        }
+
+        let used = self.used();
+        let mask = (0..=32 - bits)
+            .map(|shift| width << shift)
+            .find(|mask| mask & used == 0)
+            .ok_or_else(|| {
+                error_from_str(&format!(
+                    "no {} free contiguous mark bits for {}",
+                    bits, name
+                ))
+            })?;
+        let field = MarkField {
+            name: name.to_string(),
+            mask,
+        };
+        self.fields.push(field.clone());
+        Ok(field)
+    }
+
+    /// Releases the bits allocated to the subsystem `name`.
+    pub fn release(&mut self, name: &str) {
+        self.fields.retain(|field| field.name != name);
+    }
+}
@@ -0,0 +1,179 @@
+//! Monitoring of the changes made to the ruleset, by this process or any other one.
+
+use crate::parse::{diff_tables, parse_save, SavedTable, TableDiff};
+use crate::variant::Variant;
+use crate::IPTables;
+use std::collections::VecDeque;
+use std::error::Error;
+use std::thread;
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use std::io::{BufRead, BufReader, Lines};
+#[cfg(target_os = "linux")]
+use std::process::{Child, ChildStdout, Command, Stdio};
+
+/// Rules added to or removed from a chain.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RulesetEvent {
+    /// Name of the table.
+    pub table: String,
+
+    /// Name of the chain.
+    pub chain: String,
+
+    /// Rules (without the leading `-A CHAIN`) added to the chain.
+    pub added: Vec<String>,
+
+    /// Rules (without the leading `-A CHAIN`) removed from the chain.
+    pub removed: Vec<String>,
+}
+
+/// What wakes up the monitor to look for changes.
+enum Trigger {
+    /// Notifications of `nft monitor`, one line per change.
+    #[cfg(target_os = "linux")]
+    Nft(Child, Lines<BufReader<ChildStdout>>),
+
+    /// Periodic polling.
+    Poll(Duration),
+}
+
+/// Iterator over the changes of the ruleset, created by `IPTables::monitor`.
+/// Each item is computed by comparing the ruleset to its previous version, so changes made and
+/// reverted in quick succession may not be reported.
+pub struct Monitor<'a> {
+    ipt: &'a IPTables,
+    trigger: Trigger,
+    tables: Vec<SavedTable>,
+    events: VecDeque<RulesetEvent>,
+}
+
+impl IPTables {
+    /// Monitors the changes of the ruleset.
+    /// On the nf_tables backend, changes are reported as soon as `nft monitor` notifies them;
+    /// otherwise (or if `nft` is not installed), the ruleset is polled every `interval`.
+    /// As `nft monitor` runs on this system rather than through the `Backend` of the handle,
+    /// it is only used if the backend is local (see `Backend::is_local`).
+    pub fn monitor(&self, interval: Duration) -> Result<Monitor<'_>, Box<dyn Error>> {
+        let tables = parse_save(&self.save_ruleset()?);
+        let trigger = match self.variant()? {
+            Variant::Nft if self.backend.is_local() => {
+                nft_monitor()?.unwrap_or(Trigger::Poll(interval))
+            }
+            _ => Trigger::Poll(interval),
+        };
+        Ok(Monitor {
+            ipt: self,
+            trigger,
+            tables,
+            events: VecDeque::new(),
+        })
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn nft_monitor() -> Result<Option<Trigger>, Box<dyn Error>> {
+    let mut child = match Command::new("nft")
+        .args(["monitor", "rules"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::null())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(Box::new(e)),
+    };
+    let stdout = child
+        .stdout
+        .take()
+        .ok_or("unable to open stdout of nft monitor")?;
+    Ok(Some(Trigger::Nft(child, BufReader::new(stdout).lines())))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn nft_monitor() -> Result<Option<Trigger>, Box<dyn Error>> {
+    Ok(None)
+}
+
+/// Splits the changes of a table into the changes of each of its chains.
+fn chain_events(diff: TableDiff) -> Vec<RulesetEvent> {
+    let mut events: Vec<RulesetEvent> = Vec::new();
+    let rules = diff
+        .added_rules
+        .into_iter()
+        .map(|rule| (true, rule))
+        .chain(diff.removed_rules.into_iter().map(|rule| (false, rule)));
+    for (added, rule) in rules {
+        let rule = rule.strip_prefix("-A ").unwrap_or(&rule);
+        let (chain, spec) = rule.split_once(' ').unwrap_or((rule, ""));
+        let index = match events.iter().position(|event| event.chain == chain) {
+            Some(index) => index,
+            None => {
+                events.push(RulesetEvent {
+                    table: diff.table.clone(),
+                    chain: chain.to_string(),
+                    ..Default::default()
+                });
+                events.len() - 1
+            }
+        };
+        match added {
+            true => events[index].added.push(spec.to_string()),
+            false => events[index].removed.push(spec.to_string()),
+        }
+    }
+    events
+}
+
+impl Monitor<'_> {
+    /// Waits for the next possible change of the ruleset.
+    /// Returns `false` if no more changes can be reported.
+    fn wait(&mut self) -> Result<bool, Box<dyn Error>> {
+        match &mut self.trigger {
+            #[cfg(target_os = "linux")]
+            Trigger::Nft(_, lines) => match lines.next() {
+                Some(line) => line.map(|_| true).map_err(|e| e.into()),
+                None => Ok(false),
+            },
+            Trigger::Poll(interval) => {
+                thread::sleep(*interval);
+                Ok(true)
+            }
+        }
+    }
+}
+
+impl Iterator for Monitor<'_> {
+    type Item = Result<RulesetEvent, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.events.is_empty() {
+            match self.wait() {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(e) => return Some(Err(e)),
+            }
+            let tables = match self.ipt.save_ruleset() {
+                Ok(ruleset) => parse_save(&ruleset),
+                Err(e) => return Some(Err(e)),
+            };
+            for diff in diff_tables(&self.tables, &tables) {
+                self.events.extend(chain_events(diff));
+            }
+            self.tables = tables;
+        }
+        self.events.pop_front().map(Ok)
+    }
+}
+
+impl Drop for Monitor<'_> {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        if let Trigger::Nft(child, _) = &mut self.trigger {
+            let _ = child.kill();
+            let _ = child.wait();
+        }
+    }
+}
@@ -0,0 +1,91 @@
+//! Source and destination NAT of both families, with the checks needed by ip6tables NAT.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::variant::Variant;
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+use std::net::IpAddr;
+
+/// Formats the value of `--to-destination` or `--to-source`, putting IPv6 addresses between
+/// brackets when a port is given (e.g. '[fd00::2]:8080').
+pub fn nat_address(address: IpAddr, port: Option<u16>) -> String {
+    match (address, port) {
+        (IpAddr::V6(address), Some(port)) => format!("[{}]:{}", address, port),
+        (address, Some(port)) => format!("{}:{}", address, port),
+        (address, None) => address.to_string(),
+    }
+}
+
+impl IPTables {
+    /// Rewrites the destination of the connections matching `rule` (without target) to
+    /// `address` (and `port`), appending the rule to the nat PREROUTING chain unless it exists.
+    /// Returns the appended rule.
+    pub fn dnat(
+        &self,
+        rule: RuleBuilder,
+        address: IpAddr,
+        port: Option<u16>,
+    ) -> Result<String, Box<dyn Error>> {
+        self.check_nat_address(address)?;
+        let rule = rule.target(Target::Dnat(nat_address(address, port)));
+        self.append_nat("PREROUTING", rule)
+    }
+
+    /// Rewrites the source of the connections matching `rule` (without target) to `address`,
+    /// appending the rule to the nat POSTROUTING chain unless it exists.
+    /// Returns the appended rule.
+    pub fn snat(&self, rule: RuleBuilder, address: IpAddr) -> Result<String, Box<dyn Error>> {
+        self.check_nat_address(address)?;
+        let rule = rule.target(Target::Snat(nat_address(address, None)));
+        self.append_nat("POSTROUTING", rule)
+    }
+
+    /// Rewrites the source of the connections leaving through `out_interface` to the address
+    /// of the interface, appending the rule to the nat POSTROUTING chain unless it exists.
+    /// Returns the appended rule.
+    pub fn masquerade(&self, out_interface: &str) -> Result<String, Box<dyn Error>> {
+        let rule = RuleBuilder::new()
+            .out_interface(out_interface)
+            .target(Target::Masquerade);
+        self.append_nat("POSTROUTING", rule)
+    }
+
+    /// Checks that the kernel supports the nat table of ip6tables, i.e. the ip6table_nat module
+    /// (or nft_chain_nat with iptables-nft) is available. Always succeeds for iptables.
+    pub fn check_nat_support(&self) -> Result<(), Box<dyn Error>> {
+        if self.cmd != "ip6tables" {
+            return Ok(());
+        }
+        let module = match self.variant()? {
+            Variant::Legacy => "ip6table_nat",
+            Variant::Nft => "nft_chain_nat",
+        };
+        if self.module_available(module) {
+            return Ok(());
+        }
+        Err(error_from_str(&format!(
+            "the kernel does not support IPv6 NAT: module {} is not available, \
+             load it with 'modprobe {}' or use a kernel built with CONFIG_IP6_NF_NAT (Linux 3.7 \
+             or newer)",
+            module, module
+        )))
+    }
+
+    /// Checks that `address` has the family of this handle.
+    fn check_nat_address(&self, address: IpAddr) -> Result<(), Box<dyn Error>> {
+        match (address, self.cmd) {
+            (IpAddr::V4(_), "ip6tables") | (IpAddr::V6(_), "iptables") => Err(error_from_str(
+                &format!("address {} can't be used with {}", address, self.cmd),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    fn append_nat(&self, chain: &str, rule: RuleBuilder) -> Result<String, Box<dyn Error>> {
+        rule.check_family(self)?;
+        let rule = rule.build()?;
+        self.check_nat_support()?;
+        self.append_idempotent("nat", chain, &rule)?;
+        Ok(rule)
+    }
+}
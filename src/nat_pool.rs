@@ -0,0 +1,122 @@
+//! Pools of backends sharing the connections through DNAT rules, e.g. for load balancers.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::statistic::Statistic;
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+
+/// A chain of the nat table distributing the connections equally between the healthy backends
+/// of a pool. The rules of the chain are regenerated atomically whenever a backend is added,
+/// removed or changes health.
+///
+/// Connections reach the pool through a jump to its chain (e.g. from PREROUTING, matching the
+/// virtual address of the service); they fall through the chain if no backend is healthy.
+pub struct NatPool<'a> {
+    ipt: &'a IPTables,
+    chain: String,
+    matches: RuleBuilder,
+    backends: Vec<(String, bool)>,
+}
+
+impl IPTables {
+    /// Creates the chain of a pool in the nat table, if it does not exist yet.
+    /// Each DNAT rule of the pool also matches `matches` (e.g. the protocol).
+    pub fn nat_pool(
+        &self,
+        chain: &str,
+        matches: RuleBuilder,
+    ) -> Result<NatPool<'_>, Box<dyn Error>> {
+        let chain = &self.prefixed_chain(chain);
+        if !self.chain_exists("nat", chain)? {
+            self.new_chain("nat", chain)?;
+        }
+        let pool = NatPool {
+            ipt: self,
+            chain: chain.to_string(),
+            matches,
+            backends: Vec::new(),
+        };
+        pool.regenerate()?;
+        Ok(pool)
+    }
+}
+
+impl NatPool<'_> {
+    /// Returns the chain of the pool.
+    pub fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    /// Returns the backends of the pool along with their health.
+    pub fn backends(&self) -> impl Iterator<Item = (&str, bool)> {
+        self.backends
+            .iter()
+            .map(|(destination, healthy)| (destination.as_str(), *healthy))
+    }
+
+    /// Returns the healthy backends of the pool, which receive the new connections.
+    pub fn healthy_backends(&self) -> Vec<&str> {
+        self.backends()
+            .filter(|(_, healthy)| *healthy)
+            .map(|(destination, _)| destination)
+            .collect()
+    }
+
+    /// Adds a healthy backend, given as a DNAT destination (e.g. '10.0.0.2:8080').
+    pub fn add_backend(&mut self, destination: &str) -> Result<(), Box<dyn Error>> {
+        if self.backends.iter().any(|(d, _)| d == destination) {
+            return Err(error_from_str(&format!(
+                "backend {} is already in the pool",
+                destination
+            )));
+        }
+        self.backends.push((destination.to_string(), true));
+        self.regenerate()
+    }
+
+    /// Removes a backend; existing connections to it are not affected.
+    pub fn remove_backend(&mut self, destination: &str) -> Result<(), Box<dyn Error>> {
+        self.backends.retain(|(d, _)| d != destination);
+        self.regenerate()
+    }
+
+    /// Marks a backend as healthy or unhealthy; only healthy backends receive new connections.
+    /// Does not regenerate the rules if the health of the backend does not change.
+    pub fn set_healthy(&mut self, destination: &str, healthy: bool) -> Result<(), Box<dyn Error>> {
+        let backend = self
+            .backends
+            .iter_mut()
+            .find(|(d, _)| d == destination)
+            .ok_or_else(|| {
+                error_from_str(&format!("backend {} is not in the pool", destination))
+            })?;
+        if backend.1 == healthy {
+            return Ok(());
+        }
+        backend.1 = healthy;
+        self.regenerate()
+    }
+
+    /// Deletes the chain of the pool, which must not be referenced anymore.
+    pub fn delete(self) -> Result<(), Box<dyn Error>> {
+        self.ipt.flush_chain("nat", &self.chain)?;
+        self.ipt.delete_chain("nat", &self.chain)
+    }
+
+    /// Replaces the rules of the chain by a ladder of DNAT rules to the healthy backends.
+    fn regenerate(&self) -> Result<(), Box<dyn Error>> {
+        let healthy = self.healthy_backends();
+        let rules = Statistic::random_ladder(healthy.len())
+            .into_iter()
+            .zip(healthy)
+            .map(|(statistic, destination)| {
+                let mut rule = self.matches.clone();
+                if let Some(statistic) = statistic {
+                    rule = rule.statistic(statistic);
+                }
+                rule.target(Target::Dnat(destination.to_string())).build()
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        self.ipt.replace_chain_rules("nat", &self.chain, &rules)
+    }
+}
@@ -0,0 +1,52 @@
+//! Named accounting objects of the nfacct utility, shared by the rules using the `nfacct` match.
+
+use crate::parse::{parse_nfacct, Counters};
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+
+/// Longest name of an accounting object.
+pub const MAX_NAME_LEN: usize = 31;
+
+impl IPTables {
+    /// Creates the accounting object `name` using `nfacct`.
+    /// Succeeds without doing anything if the object already exists.
+    pub fn create_nfacct(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        if self.list_nfacct()?.iter().any(|(n, _)| n == name) {
+            return Ok(());
+        }
+        self.run_program_checked("nfacct", &["add", name], None)?;
+        Ok(())
+    }
+
+    /// Deletes the accounting object `name`, which must not be referenced by any rule.
+    pub fn delete_nfacct(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        self.run_program_checked("nfacct", &["del", name], None)?;
+        Ok(())
+    }
+
+    /// Returns the accounting objects along with their counters.
+    pub fn list_nfacct(&self) -> Result<Vec<(String, Counters)>, Box<dyn Error>> {
+        let output = self.run_program_checked("nfacct", &["list"], None)?;
+        Ok(parse_nfacct(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Returns the counters of the accounting object `name`.
+    pub fn get_nfacct(&self, name: &str) -> Result<Counters, Box<dyn Error>> {
+        self.nfacct_counters(&["get", name])
+    }
+
+    /// Resets the counters of the accounting object `name` and returns their values before the
+    /// reset, atomically so that no packet is lost between reading and resetting.
+    pub fn reset_nfacct(&self, name: &str) -> Result<Counters, Box<dyn Error>> {
+        self.nfacct_counters(&["get", name, "reset"])
+    }
+
+    fn nfacct_counters(&self, args: &[&str]) -> Result<Counters, Box<dyn Error>> {
+        let output = self.run_program_checked("nfacct", args, None)?;
+        parse_nfacct(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .next()
+            .map(|(_, counters)| counters)
+            .ok_or_else(|| error_from_str("unable to parse the output of nfacct"))
+    }
+}
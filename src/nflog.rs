@@ -0,0 +1,265 @@
+//! Subscription to the packets logged by NFLOG rules, through the nfnetlink_log interface.
+
+use crate::parse::quote_comment;
+use crate::IPTables;
+use nix::sys::socket::{
+    bind, recv, send, socket, AddressFamily, MsgFlags, NetlinkAddr, SockFlag, SockProtocol,
+    SockType,
+};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs;
+use std::os::fd::{AsRawFd, OwnedFd};
+
+// Constants taken from linux/netlink.h and linux/netfilter/nfnetlink_log.h
+const NLMSG_HDRLEN: usize = 16;
+const NFGENMSG_LEN: usize = 4;
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 1;
+const NLM_F_ACK: u16 = 4;
+const NFULNL_MSG_PACKET: u16 = 4 << 8;
+const NFULNL_MSG_CONFIG: u16 = (4 << 8) | 1;
+const NFULA_CFG_CMD: u16 = 1;
+const NFULA_CFG_MODE: u16 = 2;
+const NFULNL_CFG_CMD_BIND: u8 = 1;
+const NFULNL_CFG_CMD_UNBIND: u8 = 2;
+const NFULNL_COPY_PACKET: u8 = 2;
+const NFULA_PACKET_HDR: u16 = 1;
+const NFULA_MARK: u16 = 2;
+const NFULA_IFINDEX_INDEV: u16 = 4;
+const NFULA_IFINDEX_OUTDEV: u16 = 5;
+const NFULA_PAYLOAD: u16 = 9;
+const NFULA_PREFIX: u16 = 10;
+
+/// Number of bytes of each packet copied to userspace, enough for the IP header.
+const COPY_RANGE: u32 = 40;
+
+/// Metadata of a packet logged by an NFLOG rule.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LoggedPacket {
+    /// Prefix (`--nflog-prefix`) of the rule which logged the packet.
+    pub prefix: String,
+
+    /// Interface on which the packet was received, if any.
+    pub in_interface: Option<String>,
+
+    /// Interface on which the packet is sent, if any.
+    pub out_interface: Option<String>,
+
+    /// Link-layer protocol of the packet (e.g. 0x0800 for IPv4).
+    pub hw_protocol: u16,
+
+    /// Netfilter mark of the packet.
+    pub mark: u32,
+
+    /// Total length of the packet, as read from its IP header.
+    pub length: Option<u32>,
+}
+
+/// Subscription to an NFLOG group, iterating over the logged packets.
+/// The group is unbound when the subscription is dropped.
+pub struct NflogSubscription {
+    fd: OwnedFd,
+    group: u16,
+    seq: u32,
+    buffer: Vec<u8>,
+    packets: VecDeque<LoggedPacket>,
+}
+
+impl NflogSubscription {
+    /// Subscribes to the packets logged to the NFLOG `group` (`--nflog-group`).
+    /// Requires the CAP_NET_ADMIN capability.
+    pub fn new(group: u16) -> Result<NflogSubscription, Box<dyn Error>> {
+        let fd = socket(
+            AddressFamily::Netlink,
+            SockType::Raw,
+            SockFlag::SOCK_CLOEXEC,
+            SockProtocol::NetlinkNetFilter,
+        )?;
+        bind(fd.as_raw_fd(), &NetlinkAddr::new(0, 0))?;
+
+        let mut subscription = NflogSubscription {
+            fd,
+            group,
+            seq: 0,
+            buffer: vec![0; 65536],
+            packets: VecDeque::new(),
+        };
+        subscription.configure(NFULA_CFG_CMD, &[NFULNL_CFG_CMD_BIND])?;
+        let mut mode = COPY_RANGE.to_be_bytes().to_vec();
+        mode.extend([NFULNL_COPY_PACKET, 0]);
+        subscription.configure(NFULA_CFG_MODE, &mode)?;
+        Ok(subscription)
+    }
+
+    /// Returns the NFLOG group of the subscription.
+    pub fn group(&self) -> u16 {
+        self.group
+    }
+
+    /// Sends a configuration message with a single attribute and waits for its acknowledgment.
+    fn configure(&mut self, attr: u16, value: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.seq += 1;
+        let attr_len = 4 + value.len();
+        let len = NLMSG_HDRLEN + NFGENMSG_LEN + align(attr_len);
+
+        let mut msg = Vec::with_capacity(len);
+        msg.extend((len as u32).to_ne_bytes());
+        msg.extend(NFULNL_MSG_CONFIG.to_ne_bytes());
+        msg.extend((NLM_F_REQUEST | NLM_F_ACK).to_ne_bytes());
+        msg.extend(self.seq.to_ne_bytes());
+        msg.extend(0u32.to_ne_bytes());
+        msg.extend([0, 0]);
+        msg.extend(self.group.to_be_bytes());
+        msg.extend((attr_len as u16).to_ne_bytes());
+        msg.extend(attr.to_ne_bytes());
+        msg.extend(value);
+        msg.resize(len, 0);
+        send(self.fd.as_raw_fd(), &msg, MsgFlags::empty())?;
+
+        loop {
+            let size = recv(self.fd.as_raw_fd(), &mut self.buffer, MsgFlags::empty())?;
+            for (kind, payload) in messages(&self.buffer[..size]) {
+                if kind == NLMSG_ERROR && payload.len() >= 4 {
+                    let errno =
+                        i32::from_ne_bytes([payload[0], payload[1], payload[2], payload[3]]);
+                    if errno == 0 {
+                        return Ok(());
+                    }
+                    return Err(Box::new(nix::errno::Errno::from_i32(-errno)));
+                }
+                if kind == NFULNL_MSG_PACKET {
+                    self.packets.extend(parse_packet(payload));
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for NflogSubscription {
+    type Item = Result<LoggedPacket, Box<dyn Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.packets.is_empty() {
+            let size = match recv(self.fd.as_raw_fd(), &mut self.buffer, MsgFlags::empty()) {
+                Ok(size) => size,
+                Err(e) => return Some(Err(Box::new(e))),
+            };
+            for (kind, payload) in messages(&self.buffer[..size]) {
+                if kind == NFULNL_MSG_PACKET {
+                    self.packets.extend(parse_packet(payload));
+                }
+            }
+        }
+        self.packets.pop_front().map(Ok)
+    }
+}
+
+impl Drop for NflogSubscription {
+    fn drop(&mut self) {
+        let _ = self.configure(NFULA_CFG_CMD, &[NFULNL_CFG_CMD_UNBIND]);
+    }
+}
+
+impl IPTables {
+    /// Subscribes to the NFLOG `group`, then appends `rule` to the table/chain with an NFLOG
+    /// target logging to the group with the given `prefix`.
+    /// The returned subscription yields the packets matching the rule.
+    pub fn append_nflog(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+        group: u16,
+        prefix: &str,
+    ) -> Result<NflogSubscription, Box<dyn Error>> {
+        let subscription = NflogSubscription::new(group)?;
+        self.append(
+            table,
+            chain,
+            &format!(
+                "{} -j NFLOG --nflog-group {} --nflog-prefix {}",
+                rule,
+                group,
+                quote_comment(prefix)
+            ),
+        )?;
+        Ok(subscription)
+    }
+}
+
+fn align(len: usize) -> usize {
+    (len + 3) & !3
+}
+
+/// Splits a buffer received from netlink into the types and payloads of its messages.
+fn messages(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut messages = Vec::new();
+    while buf.len() >= NLMSG_HDRLEN {
+        let len = u32::from_ne_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        if len < NLMSG_HDRLEN || len > buf.len() {
+            break;
+        }
+        let kind = u16::from_ne_bytes([buf[4], buf[5]]);
+        messages.push((kind, &buf[NLMSG_HDRLEN..len]));
+        buf = &buf[align(len).min(buf.len())..];
+    }
+    messages
+}
+
+/// Parses the attributes of an NFULNL_MSG_PACKET message (after its netlink header).
+fn parse_packet(payload: &[u8]) -> Option<LoggedPacket> {
+    let mut attrs = payload.get(NFGENMSG_LEN..)?;
+    let mut packet = LoggedPacket::default();
+    while attrs.len() >= 4 {
+        let len = u16::from_ne_bytes([attrs[0], attrs[1]]) as usize;
+        if len < 4 || len > attrs.len() {
+            break;
+        }
+        // Mask out NLA_F_NESTED and NLA_F_NET_BYTEORDER
+        let kind = u16::from_ne_bytes([attrs[2], attrs[3]]) & 0x3fff;
+        let value = &attrs[4..len];
+        match kind {
+            NFULA_PACKET_HDR if value.len() >= 2 => {
+                packet.hw_protocol = u16::from_be_bytes([value[0], value[1]])
+            }
+            NFULA_MARK => packet.mark = be_u32(value).unwrap_or_default(),
+            NFULA_IFINDEX_INDEV => packet.in_interface = be_u32(value).and_then(interface_name),
+            NFULA_IFINDEX_OUTDEV => packet.out_interface = be_u32(value).and_then(interface_name),
+            NFULA_PREFIX => {
+                let prefix = value.split(|b| *b == 0).next().unwrap_or_default();
+                packet.prefix = String::from_utf8_lossy(prefix).into_owned();
+            }
+            NFULA_PAYLOAD => packet.length = ip_length(value),
+            _ => {}
+        }
+        attrs = &attrs[align(len).min(attrs.len())..];
+    }
+    Some(packet)
+}
+
+fn be_u32(value: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(value.get(..4)?.try_into().ok()?))
+}
+
+/// Reads the total length of a packet from its IPv4 or IPv6 header.
+fn ip_length(packet: &[u8]) -> Option<u32> {
+    match packet.first()? >> 4 {
+        4 => Some(u16::from_be_bytes([*packet.get(2)?, *packet.get(3)?]) as u32),
+        6 => Some(u16::from_be_bytes([*packet.get(4)?, *packet.get(5)?]) as u32 + 40),
+        _ => None,
+    }
+}
+
+/// Finds the name of the network interface having the `index`.
+fn interface_name(index: u32) -> Option<String> {
+    fs::read_dir("/sys/class/net")
+        .ok()?
+        .filter_map(Result::ok)
+        .find(|entry| {
+            fs::read_to_string(entry.path().join("ifindex"))
+                .map(|content| content.trim() == index.to_string())
+                .unwrap_or(false)
+        })
+        .map(|entry| entry.file_name().to_string_lossy().into_owned())
+}
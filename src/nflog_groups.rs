@@ -0,0 +1,126 @@
+//! Allocation of the NFLOG groups between the components of a host, so that they don't log to
+//! the same group.
+
+use crate::parse::{parse_rules, parse_save};
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Allocator of the NFLOG groups (`--nflog-group`), recording the owner of each group in a
+/// state file shared by the components.
+///
+/// A group is free if it is neither recorded in the state file nor used by an NFLOG rule of
+/// the ruleset, so the groups used by other tools are never allocated. Group 0, the default of
+/// NFLOG, is never allocated. The state file is replaced atomically on every change.
+pub struct NflogGroups<'a> {
+    ipt: &'a IPTables,
+    path: PathBuf,
+}
+
+impl IPTables {
+    /// Creates the allocator of the NFLOG groups recorded in the state file `path`, which is
+    /// created by the first allocation.
+    pub fn nflog_groups<P: AsRef<Path>>(&self, path: P) -> NflogGroups<'_> {
+        NflogGroups {
+            ipt: self,
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+}
+
+impl NflogGroups<'_> {
+    /// Returns the path of the state file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Returns the allocated groups along with their owners, ordered by group.
+    pub fn allocations(&self) -> Result<Vec<(u16, String)>, Box<dyn Error>> {
+        let data = match fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+        let mut allocations = data
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                line.split_once(' ')
+                    .and_then(|(group, owner)| Some((group.parse().ok()?, owner.to_string())))
+                    .ok_or_else(|| {
+                        error_from_str(&format!(
+                            "invalid line in {}: {}",
+                            self.path.display(),
+                            line
+                        ))
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        allocations.sort();
+        Ok(allocations)
+    }
+
+    /// Returns the group allocated to `owner` (e.g. the name of the component), allocating the
+    /// lowest free group if it has none yet.
+    pub fn allocate_nflog_group(&self, owner: &str) -> Result<u16, Box<dyn Error>> {
+        if owner.is_empty() || owner.contains(|c: char| c.is_whitespace() || c.is_control()) {
+            return Err(error_from_str(&format!("invalid owner {:?}", owner)));
+        }
+        let mut allocations = self.allocations()?;
+        if let Some((group, _)) = allocations.iter().find(|(_, o)| o == owner) {
+            return Ok(*group);
+        }
+        let used = self.used_groups()?;
+        let group = (1..=u16::MAX)
+            .find(|group| {
+                !used.contains(group)
+                    && !allocations.iter().any(|(allocated, _)| allocated == group)
+            })
+            .ok_or_else(|| error_from_str("no NFLOG group is free"))?;
+        allocations.push((group, owner.to_string()));
+        self.write(&allocations)?;
+        Ok(group)
+    }
+
+    /// Releases the group allocated to `owner`. The NFLOG rules logging to the group should be
+    /// deleted first, otherwise the group is not free until they are.
+    /// Returns `false` if the owner has no group.
+    pub fn release(&self, owner: &str) -> Result<bool, Box<dyn Error>> {
+        let mut allocations = self.allocations()?;
+        let count = allocations.len();
+        allocations.retain(|(_, o)| o != owner);
+        if allocations.len() == count {
+            return Ok(false);
+        }
+        self.write(&allocations)?;
+        Ok(true)
+    }
+
+    /// Returns the groups of the NFLOG rules of the ruleset.
+    fn used_groups(&self) -> Result<Vec<u16>, Box<dyn Error>> {
+        let mut groups = Vec::new();
+        for table in parse_save(&self.ipt.save_ruleset()?) {
+            for rule in parse_rules(&table.rules.join("\n")) {
+                if rule.target().as_deref() == Some("NFLOG") {
+                    let group = rule.option(&["--nflog-group"]);
+                    groups.push(group.and_then(|group| group.parse().ok()).unwrap_or(0));
+                }
+            }
+        }
+        Ok(groups)
+    }
+
+    fn write(&self, allocations: &[(u16, String)]) -> Result<(), Box<dyn Error>> {
+        let data = allocations
+            .iter()
+            .map(|(group, owner)| format!("{} {}\n", group, owner))
+            .collect::<String>();
+        let mut temporary = self.path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, data)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
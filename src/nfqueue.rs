@@ -0,0 +1,116 @@
+//! Rules passing packets to userspace through NFQUEUE.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::parse::Rule;
+use crate::IPTables;
+use std::error::Error;
+
+/// The queue(s) which NFQUEUE passes packets to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Queue {
+    /// A single queue (`--queue-num`).
+    Num(u16),
+
+    /// A range of queues sharing the packets by flow (`--queue-balance first:last`).
+    Balance(u16, u16),
+}
+
+/// Options of the NFQUEUE target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Nfqueue {
+    /// Queue(s) receiving the packets.
+    pub queue: Queue,
+
+    /// Whether packets are accepted when no application listens on the queue (fail-open,
+    /// `--queue-bypass`), instead of being dropped (fail-closed).
+    pub fail_open: bool,
+
+    /// Whether the queue is selected by the CPU handling the packet (`--queue-cpu-fanout`),
+    /// only with `Queue::Balance`.
+    pub cpu_fanout: bool,
+}
+
+impl Nfqueue {
+    /// Creates fail-closed options passing the packets to `queue`.
+    pub fn new(queue: Queue) -> Nfqueue {
+        Nfqueue {
+            queue,
+            fail_open: false,
+            cpu_fanout: false,
+        }
+    }
+
+    /// Returns the arguments of the target, without the leading `-j NFQUEUE`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = match self.queue {
+            Queue::Num(num) => vec!["--queue-num".to_string(), num.to_string()],
+            Queue::Balance(first, last) => {
+                vec!["--queue-balance".to_string(), format!("{}:{}", first, last)]
+            }
+        };
+        if self.fail_open {
+            args.push("--queue-bypass".to_string());
+        }
+        if self.cpu_fanout {
+            args.push("--queue-cpu-fanout".to_string());
+        }
+        args
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match self.queue {
+            Queue::Balance(first, last) if first > last => {
+                Err(format!("invalid NFQUEUE balance range {}:{}", first, last))
+            }
+            Queue::Num(_) if self.cpu_fanout => {
+                Err("--queue-cpu-fanout requires a balance range".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Returns the queue numbers referenced by the NFQUEUE target of the `rule`.
+fn queues_of(rule: &Rule) -> Vec<u16> {
+    if rule.target().as_deref() != Some("NFQUEUE") {
+        return Vec::new();
+    }
+    if let Some(range) = rule.option(&["--queue-balance"]) {
+        let mut bounds = range.split(':').map(|bound| bound.parse::<u16>());
+        return match (bounds.next(), bounds.next()) {
+            (Some(Ok(first)), Some(Ok(last))) => (first..=last).collect(),
+            _ => Vec::new(),
+        };
+    }
+    // NFQUEUE without options uses the queue 0
+    rule.option(&["--queue-num"])
+        .map_or(Some(0), |num| num.parse().ok())
+        .into_iter()
+        .collect()
+}
+
+impl IPTables {
+    /// Appends a rule, matching `matches`, passing the packets to userspace through NFQUEUE.
+    pub fn append_nfqueue(
+        &self,
+        table: &str,
+        chain: &str,
+        matches: RuleBuilder,
+        nfqueue: Nfqueue,
+    ) -> Result<(), Box<dyn Error>> {
+        let rule = matches.target(Target::Nfqueue(nfqueue)).build()?;
+        self.append(table, chain, &rule)
+    }
+
+    /// Returns the queue numbers referenced by NFQUEUE rules of the table, sorted and deduplicated.
+    pub fn queues_in_use(&self, table: &str) -> Result<Vec<u16>, Box<dyn Error>> {
+        let mut queues = self
+            .find_rules(table, |rule| rule.target().as_deref() == Some("NFQUEUE"))?
+            .iter()
+            .flat_map(queues_of)
+            .collect::<Vec<_>>();
+        queues.sort_unstable();
+        queues.dedup();
+        Ok(queues)
+    }
+}
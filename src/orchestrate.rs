@@ -0,0 +1,210 @@
+//! Application of the same ruleset to the firewalls of several hosts, e.g. the nodes of a
+//! cluster, through the backend of each host (local, remote or in a network namespace).
+
+use crate::drift::drift;
+use crate::parse::{format_save, parse_save, SavedTable};
+use crate::progress::Progress;
+use crate::restore::RestoreOptions;
+use crate::IPTables;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
+
+/// Outcome of the application of the ruleset to a host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostOutcome {
+    /// The tables of the host were already the ones of the ruleset.
+    Unchanged,
+
+    /// The ruleset was applied.
+    Applied,
+
+    /// The application failed with the given error.
+    Failed(String),
+
+    /// The ruleset was applied, then the previous tables were restored because another host
+    /// failed in all-or-nothing mode.
+    RolledBack,
+
+    /// The host was not reconciled because another host failed in all-or-nothing mode.
+    Skipped,
+}
+
+/// Outcome of a host, as returned by `Orchestrator::apply`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostResult {
+    /// Name of the host.
+    pub host: String,
+
+    /// The outcome.
+    pub outcome: HostOutcome,
+}
+
+struct Host {
+    name: String,
+    ipt: IPTables,
+}
+
+/// Applies a desired ruleset to a set of hosts, each given by a handle with its own backend.
+///
+/// Each host is reconciled on its own: the chains, the rules and the policies of the tables of
+/// the ruleset are compared to the live ones (see `IPTables::check_drift`), and the tables are
+/// restored only if they differ; the other tables of the host are left untouched. At most
+/// `parallelism` hosts are reconciled at the same time.
+///
+/// In all-or-nothing mode, the first failure stops the reconciliation of the hosts which have
+/// not started yet, and the hosts to which the ruleset was applied get their previous tables
+/// back, so that the hosts keep a consistent ruleset.
+pub struct Orchestrator {
+    hosts: Vec<Host>,
+    parallelism: usize,
+    all_or_nothing: bool,
+    progress: Option<Progress>,
+}
+
+impl Default for Orchestrator {
+    fn default() -> Orchestrator {
+        Orchestrator {
+            hosts: Vec::new(),
+            parallelism: 1,
+            all_or_nothing: false,
+            progress: None,
+        }
+    }
+}
+
+impl Orchestrator {
+    /// Creates an orchestrator without hosts, reconciling them one at a time.
+    pub fn new() -> Orchestrator {
+        Orchestrator::default()
+    }
+
+    /// Adds the host `name` managed through `ipt`.
+    pub fn add_host(&mut self, name: &str, ipt: IPTables) {
+        self.hosts.push(Host {
+            name: name.to_string(),
+            ipt,
+        });
+    }
+
+    /// Set the maximum number of hosts reconciled at the same time (at least 1).
+    pub fn set_parallelism(&mut self, parallelism: usize) {
+        self.parallelism = parallelism.max(1);
+    }
+
+    /// Set whether a failure rolls back the hosts to which the ruleset was applied.
+    pub fn set_all_or_nothing(&mut self, all_or_nothing: bool) {
+        self.all_or_nothing = all_or_nothing;
+    }
+
+    /// Set the callback receiving the progress of `apply`, in number of reconciled hosts. In
+    /// all-or-nothing mode, the hosts rolled back after a failure were already counted as
+    /// done; their rollbacks are not reported.
+    pub fn set_progress(&mut self, progress: Option<Progress>) {
+        self.progress = progress;
+    }
+
+    /// Applies the `tables` to every host, returning the outcome of each host in the order
+    /// they were added.
+    pub fn apply(&self, tables: &[SavedTable]) -> Vec<HostResult> {
+        let data = format_save(tables);
+        let next = AtomicUsize::new(0);
+        let done = AtomicUsize::new(0);
+        let failed = AtomicBool::new(false);
+        let outcomes = Mutex::new(vec![(HostOutcome::Skipped, None); self.hosts.len()]);
+        thread::scope(|scope| {
+            for _ in 0..self.parallelism.min(self.hosts.len()) {
+                scope.spawn(|| loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    if index >= self.hosts.len()
+                        || (self.all_or_nothing && failed.load(Ordering::SeqCst))
+                    {
+                        break;
+                    }
+                    let (outcome, previous) = match reconcile(&self.hosts[index].ipt, tables, &data)
+                    {
+                        Ok(Some(previous)) => (HostOutcome::Applied, Some(previous)),
+                        Ok(None) => (HostOutcome::Unchanged, None),
+                        Err(error) => {
+                            failed.store(true, Ordering::SeqCst);
+                            (HostOutcome::Failed(error.to_string()), None)
+                        }
+                    };
+                    let mut outcomes = outcomes.lock().unwrap_or_else(|e| e.into_inner());
+                    outcomes[index] = (outcome, previous);
+                    drop(outcomes);
+                    if let Some(progress) = &self.progress {
+                        let done = done.fetch_add(1, Ordering::SeqCst) + 1;
+                        let operation = format!("reconcile {}", self.hosts[index].name);
+                        progress(done, self.hosts.len(), &operation);
+                    }
+                });
+            }
+        });
+
+        let outcomes = outcomes.into_inner().unwrap_or_else(|e| e.into_inner());
+        let rollback = self.all_or_nothing && failed.into_inner();
+        self.hosts
+            .iter()
+            .zip(outcomes)
+            .map(|(host, (outcome, previous))| {
+                let outcome = match previous {
+                    Some(previous) if rollback => {
+                        match host.ipt.restore(&previous, RestoreOptions::default()) {
+                            Ok(()) => HostOutcome::RolledBack,
+                            Err(error) => {
+                                HostOutcome::Failed(format!("could not roll back: {}", error))
+                            }
+                        }
+                    }
+                    _ => outcome,
+                };
+                HostResult {
+                    host: host.name.clone(),
+                    outcome,
+                }
+            })
+            .collect()
+    }
+}
+
+/// Restores the `tables` (given as `data`) on the host of `ipt` if they differ from its live
+/// ones. Returns the previous version of the tables if they were restored.
+fn reconcile(
+    ipt: &IPTables,
+    tables: &[SavedTable],
+    data: &str,
+) -> Result<Option<String>, Box<dyn Error>> {
+    let live = parse_save(&ipt.save_ruleset()?);
+    // Tables which are not loaded yet are empty, so rolling them back flushes them
+    let previous = tables
+        .iter()
+        .map(|table| match live.iter().find(|l| l.name == table.name) {
+            Some(live) => live.clone(),
+            None => SavedTable {
+                name: table.name.clone(),
+                ..Default::default()
+            },
+        })
+        .collect::<Vec<_>>();
+    if drift(tables, &previous).is_empty() && same_policies(tables, &previous) {
+        return Ok(None);
+    }
+    ipt.restore(data, RestoreOptions::default())?;
+    Ok(Some(format_save(&previous)))
+}
+
+/// Checks if the chains of the `desired` tables have the same policies as the `live` ones.
+fn same_policies(desired: &[SavedTable], live: &[SavedTable]) -> bool {
+    desired.iter().zip(live).all(|(desired, live)| {
+        desired.chains.iter().all(|chain| {
+            let policy = live
+                .chains
+                .iter()
+                .find(|c| c.name == chain.name)
+                .and_then(|c| c.policy.as_ref());
+            chain.policy.is_none() || chain.policy.as_ref() == policy
+        })
+    })
+}
@@ -0,0 +1,140 @@
+//! Variants of the core methods accepting rules as `OsStr` arguments, since comments and
+//! interface names are not guaranteed to be valid UTF-8.
+
+use crate::identity::lossy_rule;
+use crate::parse::{join_rule, split_rule};
+use crate::IPTables;
+use std::error::Error;
+use std::ffi::{OsStr, OsString};
+
+fn args_os<S: AsRef<OsStr>>(head: &[&str], rule: &[S]) -> Vec<OsString> {
+    head.iter()
+        .map(OsString::from)
+        .chain(rule.iter().map(|arg| arg.as_ref().to_os_string()))
+        .collect()
+}
+
+/// Joins the `rule`, given as separate arguments, if they are all valid UTF-8.
+fn utf8_rule<S: AsRef<OsStr>>(rule: &[S]) -> Option<String> {
+    rule.iter()
+        .map(|arg| arg.as_ref().to_str())
+        .collect::<Option<Vec<_>>>()
+        .map(|args| join_rule(&args))
+}
+
+impl IPTables {
+    /// Checks for the existence of the `rule`, given as separate arguments, in the table/chain.
+    /// Returns true if the rule exists.
+    pub fn exists_os<S: AsRef<OsStr>>(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &[S],
+    ) -> Result<bool, Box<dyn Error>> {
+        if self.find_identified_os(table, chain, rule)?.is_some() {
+            return Ok(true);
+        }
+
+        if !self.has_check {
+            return self.exists_old_version(table, chain, &lossy_rule(rule));
+        }
+
+        self.run(&args_os(&["-t", table, "-C", chain], rule))
+            .map(|output| output.status.success())
+    }
+
+    /// Inserts `rule`, given as separate arguments, in the `position` to the table/chain.
+    pub fn insert_os<S: AsRef<OsStr>>(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &[S],
+        position: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        let rule = self.with_identity_os(rule)?;
+        self.run_checked(&args_os(
+            &["-t", table, "-I", chain, &position.to_string()],
+            &rule,
+        ))?;
+        self.verify_write("insert", || self.exists_os(table, chain, &rule))
+    }
+
+    /// Replaces `rule`, given as separate arguments, in the `position` to the table/chain.
+    pub fn replace_os<S: AsRef<OsStr>>(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &[S],
+        position: i32,
+    ) -> Result<(), Box<dyn Error>> {
+        let rule = self.with_identity_os(rule)?;
+        self.run_checked(&args_os(
+            &["-t", table, "-R", chain, &position.to_string()],
+            &rule,
+        ))?;
+        self.verify_write("replace", || self.exists_os(table, chain, &rule))
+    }
+
+    /// Appends `rule`, given as separate arguments, to the table/chain.
+    pub fn append_os<S: AsRef<OsStr>>(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &[S],
+    ) -> Result<(), Box<dyn Error>> {
+        let rule = self.with_identity_os(rule)?;
+        self.run_checked(&args_os(&["-t", table, "-A", chain], &rule))?;
+        self.verify_write("append", || self.exists_os(table, chain, &rule))
+    }
+
+    /// Deletes `rule`, given as separate arguments, from the table/chain.
+    pub fn delete_os<S: AsRef<OsStr>>(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &[S],
+    ) -> Result<(), Box<dyn Error>> {
+        if let Some(identified) = self.find_identified_os(table, chain, rule)? {
+            return self.delete_os(table, chain, &split_rule(&identified));
+        }
+
+        let count = match self.verify_writes {
+            true => self.chain_rules(table, chain)?.len(),
+            false => 0,
+        };
+        self.run_checked(&args_os(&["-t", table, "-D", chain], rule))?;
+        self.verify_write("delete", || {
+            Ok(self.chain_rules(table, chain)?.len() + 1 == count)
+        })
+    }
+
+    /// Finds the rule created with an identity comment for the `rule`, see `find_identified`.
+    /// Rules which are not valid UTF-8 are never found.
+    fn find_identified_os<S: AsRef<OsStr>>(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &[S],
+    ) -> Result<Option<String>, Box<dyn Error>> {
+        match utf8_rule(rule) {
+            Some(rule) => self.find_identified(table, chain, &rule),
+            None => Ok(None),
+        }
+    }
+
+    /// Lists rules in the table/chain without decoding them as UTF-8.
+    #[cfg(unix)]
+    pub fn list_os(&self, table: &str, chain: &str) -> Result<Vec<OsString>, Box<dyn Error>> {
+        use std::os::unix::ffi::OsStringExt;
+
+        let stdout = match self.is_numeric {
+            false => self.run(&["-t", table, "-S", chain])?.stdout,
+            true => self.run(&["-t", table, "-S", chain, "-n"])?.stdout,
+        };
+        Ok(stdout
+            .split(|b| *b == b'\n')
+            .filter(|line| !line.is_empty())
+            .map(|line| OsString::from_vec(line.to_vec()))
+            .collect())
+    }
+}
@@ -0,0 +1,48 @@
+//! Output of the commands executed by this crate.
+
+use std::process::{ExitStatus, Output};
+
+/// Decoded output of an executed iptables command.
+#[derive(Debug, Clone)]
+pub struct CommandOutput {
+    /// The program and the arguments which were run.
+    pub argv: Vec<String>,
+
+    /// Exit status of the command.
+    pub status: ExitStatus,
+
+    /// Standard output of the command, decoded lossily as UTF-8.
+    pub stdout: String,
+
+    /// Standard error of the command, decoded lossily as UTF-8.
+    pub stderr: String,
+}
+
+impl CommandOutput {
+    pub(crate) fn new(argv: Vec<String>, output: Output) -> CommandOutput {
+        CommandOutput {
+            argv,
+            status: output.status,
+            stdout: String::from_utf8_lossy(output.stdout.as_slice()).into(),
+            stderr: String::from_utf8_lossy(output.stderr.as_slice()).into(),
+        }
+    }
+
+    /// Returns the exit code of the command, or -1 if it was terminated by a signal.
+    pub fn code(&self) -> i32 {
+        self.status.code().unwrap_or(-1)
+    }
+
+    /// Indicates if the command exited successfully.
+    pub fn success(&self) -> bool {
+        self.status.success()
+    }
+
+    /// Returns the non-empty lines of the standard output.
+    pub fn lines(&self) -> Vec<&str> {
+        self.stdout
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .collect()
+    }
+}
@@ -0,0 +1,667 @@
+//! Parsers for the rules and the output of iptables utilities.
+//! This module does not execute any command and is available with the `parse-only` feature.
+
+use crate::error::IPTError;
+use std::collections::HashMap;
+use std::fmt;
+
+/// Options whose values are quoted by iptables using `quote_comment` rules.
+const QUOTED_OPTIONS: &[&str] = &[
+    "--comment",
+    "--log-prefix",
+    "--nflog-prefix",
+    "--string",
+    "--hex-string",
+];
+
+/// A chain declared in a table of `iptables-save` output.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct SavedChain {
+    /// Name of the chain.
+    pub name: String,
+
+    /// Default policy of the chain, `None` for user-defined chains.
+    pub policy: Option<String>,
+
+    /// Counters of the policy of the chain (i.e. of the packets which reached the end of a
+    /// built-in chain), if printed.
+    pub counters: Option<Counters>,
+}
+
+/// A table of `iptables-save` output.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct SavedTable {
+    /// Name of the table (e.g. 'filter' or 'nat').
+    pub name: String,
+
+    /// Chains declared in the table.
+    pub chains: Vec<SavedChain>,
+
+    /// Rules of the table in the `-A CHAIN ...` form.
+    pub rules: Vec<String>,
+}
+
+/// Parses the output of `iptables-save` into its tables.
+pub fn parse_save(output: &str) -> Vec<SavedTable> {
+    let mut tables: Vec<SavedTable> = Vec::new();
+    for line in output.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') || line == "COMMIT" {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('*') {
+            tables.push(SavedTable {
+                name: name.to_string(),
+                ..Default::default()
+            });
+            continue;
+        }
+
+        let table = match tables.last_mut() {
+            Some(table) => table,
+            None => continue,
+        };
+
+        if let Some(declaration) = line.strip_prefix(':') {
+            let fields = declaration.split(' ').collect::<Vec<&str>>();
+            table.chains.push(SavedChain {
+                name: fields[0].to_string(),
+                policy: fields
+                    .get(1)
+                    .filter(|policy| **policy != "-")
+                    .map(|policy| policy.to_string()),
+                counters: fields
+                    .get(2)
+                    .and_then(|counters| parse_save_counters(counters)),
+            });
+        } else {
+            // Rules dumped with their counters (`iptables-save -c`) start with `[packets:bytes]`
+            let rule = match line.split_once(' ') {
+                Some((counters, rule)) if parse_save_counters(counters).is_some() => rule,
+                _ => line,
+            };
+            if rule.starts_with("-A ") {
+                table.rules.push(rule.to_string());
+            }
+        }
+    }
+    tables
+}
+
+/// Formats the `tables` in the format of `iptables-save`, the inverse of `parse_save`.
+/// Chains without counters are declared with zero counters.
+pub fn format_save(tables: &[SavedTable]) -> String {
+    let mut data = String::new();
+    for table in tables {
+        data.push_str(&format!("*{}\n", table.name));
+        for chain in &table.chains {
+            let counters = chain.counters.unwrap_or_default();
+            data.push_str(&format!(
+                ":{} {} [{}:{}]\n",
+                chain.name,
+                chain.policy.as_deref().unwrap_or("-"),
+                counters.packets,
+                counters.bytes
+            ));
+        }
+        for rule in &table.rules {
+            data.push_str(rule);
+            data.push('\n');
+        }
+        data.push_str("COMMIT\n");
+    }
+    data
+}
+
+/// Extracts the block of the `table` (from `*table` to its `COMMIT`) from the output of
+/// `iptables-save`, or returns `None` if the table is not contained in the output.
+pub fn save_table_block(output: &str, table: &str) -> Option<String> {
+    let mut block = String::new();
+    let mut in_table = false;
+    for line in output.lines() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix('*') {
+            in_table = name == table;
+        }
+        if in_table {
+            block.push_str(line);
+            block.push('\n');
+            if trimmed == "COMMIT" {
+                return Some(block);
+            }
+        }
+    }
+    None
+}
+
+/// A rule of a table along with its location.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Rule {
+    /// Name of the chain containing the rule.
+    pub chain: String,
+
+    /// Position (starting from 1) of the rule in its chain.
+    pub position: i32,
+
+    /// Specification of the rule, without the leading `-A CHAIN`.
+    pub spec: String,
+}
+
+impl Rule {
+    /// Parses a rule, either in the `-A CHAIN ...` form or as a bare specification.
+    /// The position of the parsed rule is 0 (unknown).
+    pub fn parse(rule: &str) -> Rule {
+        let rule = rule.trim();
+        let (chain, spec) = match rule.strip_prefix("-A ") {
+            Some(rule) => rule.split_once(' ').unwrap_or((rule, "")),
+            None => ("", rule),
+        };
+        Rule {
+            chain: chain.to_string(),
+            position: 0,
+            spec: spec.to_string(),
+        }
+    }
+
+    /// Returns the source address (`-s`) of the rule.
+    pub fn source(&self) -> Option<String> {
+        self.option(&["-s", "--source"])
+    }
+
+    /// Returns the destination address (`-d`) of the rule.
+    pub fn destination(&self) -> Option<String> {
+        self.option(&["-d", "--destination"])
+    }
+
+    /// Returns the protocol (`-p`) of the rule.
+    pub fn protocol(&self) -> Option<String> {
+        self.option(&["-p", "--protocol"])
+    }
+
+    /// Returns the input interface (`-i`) of the rule.
+    pub fn in_interface(&self) -> Option<String> {
+        self.option(&["-i", "--in-interface"])
+    }
+
+    /// Returns the destination ports (`--dport` or multiport `--dports`) of the rule.
+    /// Ranges are kept as is (e.g. '8000:8080').
+    pub fn dports(&self) -> Vec<String> {
+        self.option(&[
+            "--dport",
+            "--destination-port",
+            "--dports",
+            "--destination-ports",
+        ])
+        .map(|ports| ports.split(',').map(String::from).collect())
+        .unwrap_or_default()
+    }
+
+    /// Returns the target (`-j`) or the chain which the rule goes to (`-g`).
+    pub fn target(&self) -> Option<String> {
+        self.option(&["-j", "--jump", "-g", "--goto"])
+    }
+
+    /// Returns the comment (`-m comment --comment`) of the rule.
+    pub fn comment(&self) -> Option<String> {
+        self.option(&["--comment"])
+    }
+
+    /// Returns the value of the last occurrence of any of the option `names` (e.g. `["-s", "--source"]`).
+    pub fn option(&self, names: &[&str]) -> Option<String> {
+        let mut args = split_rule(&self.spec);
+        args.iter()
+            .rposition(|arg| names.contains(&arg.as_str()))
+            .filter(|i| i + 1 < args.len())
+            .map(|i| args.swap_remove(i + 1))
+    }
+
+    /// Replaces the value of every occurrence of any of the option `names` by `value`,
+    /// e.g. to move a rule from 'eth0' to 'eth1' with `set_option(&["-i"], "eth1")`.
+    /// Returns `false` if the rule has none of the options.
+    pub fn set_option(&mut self, names: &[&str], value: &str) -> bool {
+        let mut args = split_rule(&self.spec);
+        let mut found = false;
+        for i in 0..args.len().saturating_sub(1) {
+            if names.contains(&args[i].as_str()) {
+                args[i + 1] = value.to_string();
+                found = true;
+            }
+        }
+        if found {
+            self.spec = join_rule(&args);
+        }
+        found
+    }
+
+    /// Returns a hash of the specification of the rule as normalized by `normalize_rule`, which
+    /// is the same on every host and with every version of the crate. The chain and the
+    /// position of the rule are not part of the hash.
+    pub fn fingerprint(&self) -> u64 {
+        fnv1a(FNV_OFFSET, normalize_rule(&self.spec).as_bytes())
+    }
+}
+
+/// Returns a hash of the rules (e.g. the output of `iptables -S` for a chain), each normalized
+/// by `normalize_rule`, which depends on their order and is stable like `Rule::fingerprint`.
+pub fn fingerprint_rules<S: AsRef<str>>(rules: &[S]) -> u64 {
+    rules.iter().fold(FNV_OFFSET, |hash, rule| {
+        let hash = fnv1a(hash, normalize_rule(rule.as_ref()).as_bytes());
+        fnv1a(hash, b"\n")
+    })
+}
+
+/// Initial value of the 64-bit FNV-1a hash.
+const FNV_OFFSET: u64 = 0xcbf2_9ce4_8422_2325;
+
+/// Continues the 64-bit FNV-1a `hash` with `data`. Unlike the hashers of the standard library,
+/// its values are specified and never change.
+fn fnv1a(hash: u64, data: &[u8]) -> u64 {
+    data.iter().fold(hash, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Parses the rules of the output of `iptables -S` (or the rules of `iptables-save`),
+/// numbering them in their chains.
+pub fn parse_rules(output: &str) -> Vec<Rule> {
+    let mut rules: Vec<Rule> = Vec::new();
+    let mut positions: HashMap<&str, i32> = HashMap::new();
+    for line in output.lines().map(str::trim) {
+        let (chain, spec) = match line.strip_prefix("-A ") {
+            Some(rule) => rule.split_once(' ').unwrap_or((rule, "")),
+            None => continue,
+        };
+        let position = positions.entry(chain).or_insert(0);
+        *position += 1;
+        rules.push(Rule {
+            chain: chain.to_string(),
+            position: *position,
+            spec: spec.to_string(),
+        });
+    }
+    rules
+}
+
+/// Packet and byte counters of a rule.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize, serde::Deserialize))]
+pub struct Counters {
+    /// Number of packets matched by the rule.
+    pub packets: u64,
+
+    /// Number of bytes matched by the rule.
+    pub bytes: u64,
+}
+
+/// Parses a counter, either exact (`-x`) or abbreviated with a unit (e.g. '1520K').
+/// Units are the decimal ones used by iptables: K, M, G, T and P.
+pub fn parse_counter(value: &str) -> Option<u64> {
+    let (number, multiplier) = match value.chars().last()? {
+        'K' => (&value[..value.len() - 1], 1_000),
+        'M' => (&value[..value.len() - 1], 1_000_000),
+        'G' => (&value[..value.len() - 1], 1_000_000_000),
+        'T' => (&value[..value.len() - 1], 1_000_000_000_000),
+        'P' => (&value[..value.len() - 1], 1_000_000_000_000_000),
+        _ => (value, 1),
+    };
+    number.parse::<u64>().ok()?.checked_mul(multiplier)
+}
+
+/// Parses the counters printed by `iptables-save` in the `[packets:bytes]` form.
+pub fn parse_save_counters(value: &str) -> Option<Counters> {
+    let (packets, bytes) = value
+        .strip_prefix('[')?
+        .strip_suffix(']')?
+        .split_once(':')?;
+    Some(Counters {
+        packets: packets.parse().ok()?,
+        bytes: bytes.parse().ok()?,
+    })
+}
+
+/// Parses the counters of the rules of the output of `iptables -L -v -x` for a single chain,
+/// in the order of the rules.
+pub fn parse_counters(output: &str) -> Vec<Counters> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            Some(Counters {
+                packets: parse_counter(fields.next()?)?,
+                bytes: parse_counter(fields.next()?)?,
+            })
+        })
+        .collect()
+}
+
+/// Parses the accounting objects listed by `nfacct list` or `nfacct get`, with lines like
+/// '{ pkts = 00000000000000000012, bytes = 00000000000000001520 } = http;'.
+pub fn parse_nfacct(output: &str) -> Vec<(String, Counters)> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let (values, name) = line.trim().strip_prefix('{')?.split_once('}')?;
+            let name = name.trim().strip_prefix('=')?.trim().strip_suffix(';')?;
+            let mut counters = Counters::default();
+            for value in values.split(',') {
+                let (key, value) = value.split_once('=')?;
+                let value = value.split_whitespace().next()?;
+                match key.trim() {
+                    "pkts" => counters.packets = value.parse().ok()?,
+                    "bytes" => counters.bytes = value.parse().ok()?,
+                    _ => {}
+                }
+            }
+            Some((name.trim().to_string(), counters))
+        })
+        .collect()
+}
+
+/// Parses the number of references to the `chain` from the header of the output of
+/// `iptables -L`, e.g. 'Chain MYCHAIN (2 references)'. Built-in chains have no references.
+pub fn parse_chain_references(output: &str, chain: &str) -> Option<u32> {
+    output.lines().find_map(|line| {
+        let header = line.strip_prefix("Chain ")?.strip_prefix(chain)?;
+        let header = header.strip_prefix(" (")?.strip_suffix(')')?;
+        if header.starts_with("policy ") {
+            return Some(0);
+        }
+        header.split(' ').next()?.parse().ok()
+    })
+}
+
+/// Summary of a chain, as returned by `IPTables::stats`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ChainStats {
+    /// Name of the chain.
+    pub name: String,
+
+    /// Default policy of the chain, `None` for user-defined chains.
+    pub policy: Option<String>,
+
+    /// Counters of the policy of a built-in chain.
+    pub policy_counters: Option<Counters>,
+
+    /// Number of references (jumps from other rules) to a user-defined chain.
+    pub references: u32,
+
+    /// Number of rules of the chain.
+    pub rules: usize,
+
+    /// Sum of the counters of the rules of the chain.
+    pub counters: Counters,
+}
+
+/// Parses the summary of each chain from the output of `iptables -L -v -x -n` for a whole
+/// table, in which each chain starts with a header like 'Chain INPUT (policy ACCEPT 12 packets,
+/// 1520 bytes)' or 'Chain MYCHAIN (2 references)'.
+pub fn parse_table_stats(output: &str) -> Vec<ChainStats> {
+    let mut chains: Vec<ChainStats> = Vec::new();
+    for line in output.lines() {
+        if let Some(header) = line.strip_prefix("Chain ") {
+            let (name, details) = header.split_once(" (").unwrap_or((header, ""));
+            let details = details.trim_end_matches(')').split(' ').collect::<Vec<_>>();
+            let mut chain = ChainStats {
+                name: name.to_string(),
+                ..Default::default()
+            };
+            match details.as_slice() {
+                ["policy", policy, packets, "packets,", bytes, "bytes"] => {
+                    chain.policy = Some(policy.to_string());
+                    chain.policy_counters = parse_counter(packets)
+                        .zip(parse_counter(bytes))
+                        .map(|(packets, bytes)| Counters { packets, bytes });
+                }
+                ["policy", policy, ..] => chain.policy = Some(policy.to_string()),
+                [references, _] => chain.references = references.parse().unwrap_or_default(),
+                _ => {}
+            }
+            chains.push(chain);
+            continue;
+        }
+        let chain = match chains.last_mut() {
+            Some(chain) => chain,
+            None => continue,
+        };
+        if let Some(counters) = parse_counters(line).first() {
+            chain.rules += 1;
+            chain.counters.packets += counters.packets;
+            chain.counters.bytes += counters.bytes;
+        }
+    }
+    chains
+}
+
+/// Parses the names of the chains of the `table` from the output of `nft list chains`.
+pub fn parse_nft_chains(output: &str, table: &str) -> Vec<String> {
+    let mut chains = Vec::new();
+    let mut current_table = None;
+    for line in output.lines().map(str::trim) {
+        let fields = line.split_whitespace().collect::<Vec<_>>();
+        match fields.as_slice() {
+            ["table", _family, name, "{"] => current_table = Some(*name),
+            ["chain", name, "{"] if current_table == Some(table) => chains.push(name.to_string()),
+            _ => {}
+        }
+    }
+    chains
+}
+
+/// Changes of a table between two versions of a ruleset.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TableDiff {
+    /// Name of the table.
+    pub table: String,
+
+    /// Chains which only exist in the new version.
+    pub added_chains: Vec<String>,
+
+    /// Chains which only exist in the old version.
+    pub removed_chains: Vec<String>,
+
+    /// Rules (in the `-A CHAIN ...` form) which only exist in the new version.
+    pub added_rules: Vec<String>,
+
+    /// Rules (in the `-A CHAIN ...` form) which only exist in the old version.
+    pub removed_rules: Vec<String>,
+}
+
+impl TableDiff {
+    /// Indicates if the table is the same in both versions.
+    pub fn is_empty(&self) -> bool {
+        self.added_chains.is_empty()
+            && self.removed_chains.is_empty()
+            && self.added_rules.is_empty()
+            && self.removed_rules.is_empty()
+    }
+}
+
+/// Maximum length of an argument of a rule accepted by `RuleSpec`, well above the longest
+/// option values accepted by iptables (e.g. BPF bytecode).
+pub const MAX_ARG_LEN: usize = 4096;
+
+/// A rule given as a string, validated before it reaches any command: it must not contain
+/// control characters, its quotes must be balanced and none of its arguments may be longer
+/// than `MAX_ARG_LEN`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuleSpec {
+    args: Vec<String>,
+}
+
+impl RuleSpec {
+    /// Returns the arguments of the rule, split like `split_rule` does.
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+}
+
+impl TryFrom<&str> for RuleSpec {
+    type Error = IPTError;
+
+    fn try_from(rule: &str) -> Result<RuleSpec, IPTError> {
+        if let Some(c) = rule.chars().find(|c| c.is_control()) {
+            return Err(IPTError::InvalidRule(format!("control character {:?}", c)));
+        }
+        let (args, balanced) = split_args(rule);
+        if !balanced {
+            return Err(IPTError::InvalidRule("unbalanced quotes".to_string()));
+        }
+        if let Some(arg) = args.iter().find(|arg| arg.len() > MAX_ARG_LEN) {
+            return Err(IPTError::InvalidRule(format!(
+                "argument of {} bytes, the maximum is {}",
+                arg.len(),
+                MAX_ARG_LEN
+            )));
+        }
+        Ok(RuleSpec { args })
+    }
+}
+
+impl fmt::Display for RuleSpec {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&join_rule(&self.args))
+    }
+}
+
+/// Splits a rule into its arguments like a shell would do, removing the quotes surrounding them.
+/// Within double quotes, a backslash escapes `"`, `'` and `\` (as printed by `iptables -S`).
+pub fn split_rule(rule: &str) -> Vec<String> {
+    split_args(rule).0
+}
+
+/// Splits a rule like `split_rule`, also returning `false` if a quote is left open.
+fn split_args(rule: &str) -> (Vec<String>, bool) {
+    let mut args = Vec::new();
+    let mut arg = String::new();
+    let mut in_arg = false;
+    let mut quote = None;
+
+    let mut chars = rule.chars();
+    while let Some(c) = chars.next() {
+        match (quote, c) {
+            (Some('"'), '"') | (Some('\''), '\'') => quote = None,
+            (Some('"'), '\\') => match chars.next() {
+                Some(escaped @ ('"' | '\'' | '\\')) => arg.push(escaped),
+                Some(other) => {
+                    arg.push('\\');
+                    arg.push(other);
+                }
+                None => arg.push('\\'),
+            },
+            (Some(_), _) => arg.push(c),
+            (None, '"' | '\'') => {
+                quote = Some(c);
+                in_arg = true;
+            }
+            (None, _) if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut arg));
+                    in_arg = false;
+                }
+            }
+            (None, _) => {
+                arg.push(c);
+                in_arg = true;
+            }
+        }
+    }
+    if in_arg {
+        args.push(arg);
+    }
+    (args, quote.is_none())
+}
+
+/// Quotes a comment the way iptables prints it: comments containing any character other than
+/// ASCII letters, digits, `-` and `_` are double-quoted, escaping `"`, `'` and `\` with a backslash.
+/// Useful for building rule strings containing arbitrary comments.
+pub fn quote_comment(comment: &str) -> String {
+    let plain = !comment.is_empty()
+        && comment
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if plain {
+        return comment.to_string();
+    }
+
+    let mut quoted = String::with_capacity(comment.len() + 2);
+    quoted.push('"');
+    for c in comment.chars() {
+        if c == '"' || c == '\'' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Joins the arguments of a rule into the form printed by `iptables -S`, in which the values of
+/// comments, log prefixes and strings are quoted by `quote_comment` and other arguments are only
+/// double-quoted if they contain whitespace.
+pub fn join_rule<S: AsRef<str>>(args: &[S]) -> String {
+    let mut previous = "";
+    let mut joined = Vec::with_capacity(args.len());
+    for arg in args.iter().map(AsRef::as_ref) {
+        joined.push(if QUOTED_OPTIONS.contains(&previous) {
+            quote_comment(arg)
+        } else if arg.is_empty() || arg.contains(char::is_whitespace) {
+            format!("\"{}\"", arg)
+        } else {
+            arg.to_string()
+        });
+        previous = arg;
+    }
+    joined.join(" ")
+}
+
+/// Normalizes the spacing and quoting of a rule to the form printed by `iptables -S`.
+pub fn normalize_rule(rule: &str) -> String {
+    join_rule(&split_rule(rule))
+}
+
+/// Compares two versions of a ruleset, table by table.
+/// Rules are compared as normalized by `normalize_rule`, regardless of their order.
+/// Returns the changes of the tables which differ.
+pub fn diff_tables(old: &[SavedTable], new: &[SavedTable]) -> Vec<TableDiff> {
+    let empty = SavedTable::default();
+    let mut names = old.iter().map(|t| &t.name).collect::<Vec<_>>();
+    names.extend(
+        new.iter()
+            .map(|t| &t.name)
+            .filter(|n| !old.iter().any(|t| t.name == **n)),
+    );
+
+    names
+        .into_iter()
+        .map(|name| {
+            let old = old.iter().find(|t| t.name == *name).unwrap_or(&empty);
+            let new = new.iter().find(|t| t.name == *name).unwrap_or(&empty);
+            let chains = |table: &SavedTable| -> Vec<String> {
+                table.chains.iter().map(|c| c.name.clone()).collect()
+            };
+            let rules = |table: &SavedTable| -> Vec<String> {
+                table.rules.iter().map(|r| normalize_rule(r)).collect()
+            };
+            TableDiff {
+                table: name.clone(),
+                added_chains: subtract(chains(new), &chains(old)),
+                removed_chains: subtract(chains(old), &chains(new)),
+                added_rules: subtract(rules(new), &rules(old)),
+                removed_rules: subtract(rules(old), &rules(new)),
+            }
+        })
+        .filter(|diff| !diff.is_empty())
+        .collect()
+}
+
+/// Removes one occurrence of each item of `other` from `items`.
+pub(crate) fn subtract(mut items: Vec<String>, other: &[String]) -> Vec<String> {
+    for item in other {
+        if let Some(i) = items.iter().position(|x| x == item) {
+            items.remove(i);
+        }
+    }
+    items
+}
@@ -0,0 +1,120 @@
+//! Persistence of the ruleset across reboots, in the formats of the distributions or with a
+//! systemd unit.
+
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Directory of the rules loaded at boot by iptables-persistent (Debian, Ubuntu).
+const PERSISTENT_DIR: &str = "/etc/iptables";
+
+/// Configuration file of the iptables service of iptables-services (RHEL, Fedora).
+const SERVICES_CONFIG: &str = "/etc/sysconfig/iptables-config";
+
+/// Directory of the system units of systemd.
+const UNIT_DIR: &str = "/etc/systemd/system";
+
+/// Directory of the iptables utilities, as systemd needs absolute paths.
+const SBIN_DIR: &str = "/usr/sbin";
+
+/// How `IPTables::persist` saved the ruleset.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PersistMethod {
+    /// The ruleset was written to the file, e.g. '/etc/iptables/rules.v4'.
+    RulesFile(PathBuf),
+
+    /// The ruleset was saved by `service iptables save` (or `service ip6tables save`).
+    Service,
+}
+
+impl IPTables {
+    /// Saves the current ruleset where the distribution loads it at boot: the rules file of
+    /// iptables-persistent if it is installed, otherwise through the iptables service of
+    /// iptables-services. Fails if neither is installed.
+    pub fn persist(&self) -> Result<PersistMethod, Box<dyn Error>> {
+        if Path::new(PERSISTENT_DIR).is_dir() {
+            let file = match self.cmd {
+                "ip6tables" => "rules.v6",
+                _ => "rules.v4",
+            };
+            let path = Path::new(PERSISTENT_DIR).join(file);
+            self.save_to_file(&path)?;
+            return Ok(PersistMethod::RulesFile(path));
+        }
+        if Path::new(SERVICES_CONFIG).is_file() {
+            self.run_program_checked("service", &[self.cmd, "save"], None)?;
+            return Ok(PersistMethod::Service);
+        }
+        Err(error_from_str(
+            "neither iptables-persistent nor iptables-services is installed",
+        ))
+    }
+
+    /// Writes the current ruleset to `path` in the format of `iptables-save`. The file is
+    /// replaced atomically, so it is never left half written.
+    pub fn save_to_file<P: AsRef<Path>>(&self, path: P) -> Result<(), Box<dyn Error>> {
+        let path = path.as_ref();
+        let output =
+            self.run_program_checked(&format!("{}-save", self.cmd), &[] as &[&str], None)?;
+        let mut temporary = path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, &output.stdout)?;
+        fs::rename(&temporary, path)?;
+        Ok(())
+    }
+
+    /// Returns a systemd oneshot unit restoring the ruleset saved in `save_file` (e.g. by
+    /// `save_to_file`) at boot, before the network is configured.
+    pub fn restore_unit<P: AsRef<Path>>(&self, save_file: P) -> String {
+        // '%' starts the specifiers of systemd
+        let save_file = save_file.as_ref().to_string_lossy().replace('%', "%%");
+        format!(
+            "[Unit]\n\
+             Description=Restore the {cmd} rules saved in {file}\n\
+             DefaultDependencies=no\n\
+             Wants=network-pre.target\n\
+             Before=network-pre.target shutdown.target\n\
+             Conflicts=shutdown.target\n\
+             ConditionFileNotEmpty={file}\n\
+             \n\
+             [Service]\n\
+             Type=oneshot\n\
+             RemainAfterExit=yes\n\
+             ExecStart={dir}/{cmd}-restore {quoted}\n\
+             \n\
+             [Install]\n\
+             WantedBy=multi-user.target\n",
+            cmd = self.cmd,
+            dir = SBIN_DIR,
+            file = save_file,
+            quoted = quote_unit_argument(&save_file),
+        )
+    }
+
+    /// Installs the unit of `restore_unit` as `name` (e.g. 'myapp-firewall.service') in the
+    /// system units, then enables it with systemctl. Returns the path of the unit file.
+    pub fn install_restore_unit<P: AsRef<Path>>(
+        &self,
+        name: &str,
+        save_file: P,
+    ) -> Result<PathBuf, Box<dyn Error>> {
+        if name.contains('/') || !name.ends_with(".service") {
+            return Err(error_from_str(&format!("invalid unit name {}", name)));
+        }
+        let path = Path::new(UNIT_DIR).join(name);
+        fs::write(&path, self.restore_unit(save_file))?;
+        self.run_program_checked("systemctl", &["daemon-reload"], None)?;
+        self.run_program_checked("systemctl", &["enable", name], None)?;
+        Ok(path)
+    }
+}
+
+/// Quotes an argument of `ExecStart` if needed, following the rules of systemd.
+fn quote_unit_argument(argument: &str) -> String {
+    if !argument.contains(|c: char| c.is_whitespace() || c == '"' || c == '\\' || c == '\'') {
+        return argument.to_string();
+    }
+    let escaped = argument.replace('\\', "\\\\").replace('"', "\\\"");
+    format!("\"{}\"", escaped)
+}
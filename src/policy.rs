@@ -0,0 +1,311 @@
+//! Allow-list of the tables, chains and targets which a handle may touch, enforced on the
+//! commands before they reach the backend.
+
+use crate::backend::Backend;
+use crate::error::IPTError;
+use crate::parse::split_rule;
+use std::error::Error;
+use std::ffi::OsString;
+use std::process::Output;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Kind of argument taken by an option of iptables.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Argument {
+    None,
+    Required,
+    Optional,
+}
+
+/// An option of iptables as (short form, long name, argument). The short form is empty for
+/// the options which have none.
+type IptOption = (&'static str, &'static str, Argument);
+
+/// Options of iptables itself, without the ones of the extensions.
+const OPTIONS: &[IptOption] = &[
+    ("-A", "append", Argument::Required),
+    ("-C", "check", Argument::Required),
+    ("-D", "delete", Argument::Required),
+    ("-I", "insert", Argument::Required),
+    ("-R", "replace", Argument::Required),
+    ("-S", "list-rules", Argument::Optional),
+    ("-L", "list", Argument::Optional),
+    ("-F", "flush", Argument::Optional),
+    ("-Z", "zero", Argument::Optional),
+    ("-N", "new-chain", Argument::Required),
+    ("-X", "delete-chain", Argument::Optional),
+    ("-P", "policy", Argument::Required),
+    ("-E", "rename-chain", Argument::Required),
+    ("-t", "table", Argument::Required),
+    ("-j", "jump", Argument::Required),
+    ("-g", "goto", Argument::Required),
+    ("-p", "protocol", Argument::Required),
+    ("-s", "source", Argument::Required),
+    ("-s", "src", Argument::Required),
+    ("-d", "destination", Argument::Required),
+    ("-d", "dst", Argument::Required),
+    ("-i", "in-interface", Argument::Required),
+    ("-o", "out-interface", Argument::Required),
+    ("-m", "match", Argument::Required),
+    ("-c", "set-counters", Argument::Required),
+    ("-M", "modprobe", Argument::Required),
+    ("-w", "wait", Argument::Optional),
+    ("-W", "wait-interval", Argument::Optional),
+    ("-v", "verbose", Argument::None),
+    ("-n", "numeric", Argument::None),
+    ("-x", "exact", Argument::None),
+    ("-f", "fragment", Argument::None),
+    ("-4", "ipv4", Argument::None),
+    ("-6", "ipv6", Argument::None),
+    ("-V", "version", Argument::None),
+    ("-h", "help", Argument::None),
+    ("", "line-numbers", Argument::None),
+];
+
+/// Number of the first `OPTIONS` (the commands, the table and the targets) whose abbreviations
+/// are recognized. Abbreviations of the other options are left to the extensions, so that
+/// e.g. '--set' of the recent match is not taken for '--set-counters'.
+const ABBREVIATED: usize = 16;
+
+/// Finds the option of iptables named `name`, or abbreviated as `name`.
+/// Returns `None` if it is not an option of iptables itself, e.g. an option of a match.
+fn long_option(name: &str) -> Result<Option<&'static IptOption>, String> {
+    if let Some(option) = OPTIONS.iter().find(|option| option.1 == name) {
+        return Ok(Some(option));
+    }
+    let mut matches = OPTIONS[..ABBREVIATED]
+        .iter()
+        .filter(|option| !name.is_empty() && option.1.starts_with(name));
+    match (matches.next(), matches.next()) {
+        (Some(option), None) => Ok(Some(option)),
+        (Some(_), Some(_)) => Err(format!("ambiguous option --{}", name)),
+        (None, _) => Ok(None),
+    }
+}
+
+/// Rewrites the `args` of iptables with the short form of its options, each followed by its
+/// value as a separate argument, as getopt reads them: values may be attached (e.g.
+/// '-AINPUT') or given after '=' (e.g. '--append=INPUT'), long options may be abbreviated
+/// (e.g. '--app') and flags may be grouped (e.g. '-nvL').
+/// Fails on the options which are unknown, unless they follow a match, a protocol or a
+/// target whose extension may define them.
+fn canonical_args(args: &[String]) -> Result<Vec<String>, String> {
+    let mut canonical = Vec::new();
+    let mut extension = false;
+    let mut iter = args.iter().peekable();
+    while let Some(arg) = iter.next() {
+        let (option, attached) = if let Some(long) = arg.strip_prefix("--") {
+            let (name, value) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value)),
+                None => (long, None),
+            };
+            match long_option(name)? {
+                Some(option) => (option, value),
+                None if extension => {
+                    canonical.push(format!("--{}", name));
+                    canonical.extend(value.map(String::from));
+                    continue;
+                }
+                None => return Err(format!("option --{}", name)),
+            }
+        } else if let Some(mut rest) = arg.strip_prefix('-').filter(|rest| !rest.is_empty()) {
+            loop {
+                let c = rest.chars().next().unwrap_or_default();
+                let option = OPTIONS
+                    .iter()
+                    .find(|option| option.0.len() == 2 && option.0.ends_with(c))
+                    .ok_or_else(|| format!("option -{}", c))?;
+                rest = &rest[c.len_utf8()..];
+                if option.2 != Argument::None || rest.is_empty() {
+                    break (option, Some(rest).filter(|rest| !rest.is_empty()));
+                }
+                canonical.push(option.0.to_string());
+            }
+        } else {
+            canonical.push(arg.clone());
+            continue;
+        };
+
+        canonical.push(match option.0 {
+            "" => format!("--{}", option.1),
+            short => short.to_string(),
+        });
+        extension |= matches!(option.0, "-m" | "-p" | "-j" | "-g");
+        let value = match (option.2, attached) {
+            (_, Some(value)) => Some(value.to_string()),
+            (Argument::Required, None) => iter.next().cloned(),
+            (Argument::Optional, None) => iter.next_if(|arg| !arg.starts_with('-')).cloned(),
+            (Argument::None, None) => None,
+        };
+        canonical.extend(value);
+    }
+    Ok(canonical)
+}
+
+/// Backend wrapping another one, which rejects with `IPTError::PolicyDenied` the commands
+/// touching anything but the allowed chains, or jumping to targets which are not allowed.
+///
+/// Listing and checking the rules and saving the ruleset are always allowed, as well as
+/// probing the version of the utilities. Other programs than iptables (e.g. ipset) must be
+/// allowed explicitly.
+/// It can be used inside an application for defense in depth, or as the backend of the helper
+/// daemon to limit what its clients can do.
+pub struct PolicyFilter {
+    backend: Arc<dyn Backend>,
+    chains: Vec<(String, String)>,
+    targets: Vec<String>,
+    programs: Vec<String>,
+}
+
+impl PolicyFilter {
+    /// Creates a filter in front of `backend` which allows nothing yet.
+    pub fn new(backend: Arc<dyn Backend>) -> PolicyFilter {
+        PolicyFilter {
+            backend,
+            chains: Vec::new(),
+            targets: Vec::new(),
+            programs: Vec::new(),
+        }
+    }
+
+    /// Allows the chains of the `table` matching `pattern`, either a name or a prefix followed
+    /// by '*' (e.g. 'MYAPP-*'). Rules of the allowed chains may jump to each other.
+    pub fn allow_chain(&mut self, table: &str, pattern: &str) {
+        self.chains.push((table.to_string(), pattern.to_string()));
+    }
+
+    /// Allows the rules to jump to `target` (e.g. 'ACCEPT' or 'DNAT').
+    pub fn allow_target(&mut self, target: &str) {
+        self.targets.push(target.to_string());
+    }
+
+    /// Allows running `program` with any arguments (e.g. 'ipset').
+    pub fn allow_program(&mut self, program: &str) {
+        self.programs.push(program.to_string());
+    }
+
+    /// Checks if the `chain` of the `table` is allowed.
+    fn is_allowed_chain(&self, table: &str, chain: &str) -> bool {
+        self.chains.iter().any(|(t, pattern)| {
+            t == table
+                && match pattern.strip_suffix('*') {
+                    Some(prefix) => chain.starts_with(prefix),
+                    None => chain == pattern,
+                }
+        })
+    }
+
+    /// Checks the command of iptables or ip6tables given by `args`.
+    fn check_command(&self, args: &[String]) -> Result<(), String> {
+        let args = canonical_args(args)?;
+        let mut table = "filter";
+        let mut commands = Vec::new();
+        let mut targets = Vec::new();
+        let mut iter = args.iter().peekable();
+        while let Some(arg) = iter.next() {
+            match arg.as_str() {
+                "-t" => table = iter.next().map(String::as_str).unwrap_or(table),
+                "-j" | "-g" => targets.extend(iter.next()),
+                "-A" | "-C" | "-D" | "-I" | "-R" | "-S" | "-L" | "-F" | "-Z" | "-N" | "-X"
+                | "-P" => {
+                    commands.push((arg.as_str(), iter.next_if(|arg| !arg.starts_with('-'))));
+                }
+                "-E" => {
+                    commands.push((arg.as_str(), iter.next()));
+                    commands.push((arg.as_str(), iter.next()));
+                }
+                _ => {}
+            }
+        }
+
+        for (command, chain) in commands {
+            let read_only = matches!(command, "-C" | "-S" | "-L");
+            match chain {
+                _ if read_only => {}
+                Some(chain) if self.is_allowed_chain(table, chain) => {}
+                Some(chain) => return Err(format!("chain {} of the {} table", chain, table)),
+                None => return Err(format!("{} of the whole {} table", command, table)),
+            }
+        }
+        for target in targets {
+            if !self.targets.contains(target) && !self.is_allowed_chain(table, target) {
+                return Err(format!("target {} in the {} table", target, table));
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks each line of the `input` of iptables-restore, which must not flush the tables.
+    fn check_restore(&self, args: &[String], input: Option<&str>) -> Result<(), String> {
+        let input = match input {
+            Some(input) => input,
+            None => return Ok(()),
+        };
+        if !args.iter().any(|arg| arg == "-n" || arg == "--noflush") {
+            return Err("restore flushing whole tables".to_string());
+        }
+        let mut table = "filter";
+        for line in input.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line == "COMMIT" {
+                continue;
+            }
+            if let Some(name) = line.strip_prefix('*') {
+                table = name;
+                continue;
+            }
+            if let Some(declaration) = line.strip_prefix(':') {
+                // Declaring a chain flushes it
+                let chain = declaration.split_whitespace().next().unwrap_or_default();
+                if !self.is_allowed_chain(table, chain) {
+                    return Err(format!("chain {} of the {} table", chain, table));
+                }
+                continue;
+            }
+            // Rules may be prefixed by their counters, e.g. '[0:0] -A INPUT ...'
+            let rule = match line.strip_prefix('[') {
+                Some(rest) => rest.split_once(']').map(|(_, rule)| rule).unwrap_or(line),
+                None => line,
+            };
+            let mut args = vec!["-t".to_string(), table.to_string()];
+            args.extend(split_rule(rule));
+            self.check_command(&args)?;
+        }
+        Ok(())
+    }
+
+    /// Checks the command running `program` with `args`.
+    fn check(&self, program: &str, args: &[String], input: Option<&str>) -> Result<(), String> {
+        if self.programs.iter().any(|p| p == program) {
+            return Ok(());
+        }
+        let utility = program
+            .strip_prefix("ip6tables")
+            .or_else(|| program.strip_prefix("iptables"));
+        match utility {
+            Some(utility) if utility.ends_with("-save") => Ok(()),
+            Some(utility) if utility.ends_with("-restore") => self.check_restore(args, input),
+            Some("" | "-legacy" | "-nft") => self.check_command(args),
+            _ => Err(format!("program {}", program)),
+        }
+    }
+}
+
+impl Backend for PolicyFilter {
+    fn run(
+        &self,
+        program: &str,
+        args: &[OsString],
+        input: Option<&str>,
+        timeout: Option<Duration>,
+    ) -> Result<Output, Box<dyn Error>> {
+        let strings = args
+            .iter()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        if let Err(denied) = self.check(program, &strings, input) {
+            return Err(Box::new(IPTError::PolicyDenied(denied)));
+        }
+        self.backend.run(program, args, input, timeout)
+    }
+}
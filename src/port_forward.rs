@@ -0,0 +1,170 @@
+//! Forwarding of ports to another host with DNAT.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+
+/// Transport protocol(s) of the forwarded ports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// TCP only.
+    Tcp,
+
+    /// UDP only.
+    Udp,
+
+    /// Both TCP and UDP, with one rule for each.
+    Both,
+}
+
+impl Protocol {
+    /// Returns the names of the protocols, as given to `-p`.
+    fn names(&self) -> &'static [&'static str] {
+        match self {
+            Protocol::Tcp => &["tcp"],
+            Protocol::Udp => &["udp"],
+            Protocol::Both => &["tcp", "udp"],
+        }
+    }
+}
+
+/// A range of ports, including both bounds.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortRange {
+    /// First port of the range.
+    pub first: u16,
+
+    /// Last port of the range.
+    pub last: u16,
+}
+
+impl PortRange {
+    /// Creates the range from `first` to `last`.
+    pub fn new(first: u16, last: u16) -> PortRange {
+        PortRange { first, last }
+    }
+
+    /// Creates the range of a single port.
+    pub fn single(port: u16) -> PortRange {
+        PortRange::new(port, port)
+    }
+
+    /// Returns the number of ports in the range.
+    pub fn len(&self) -> u32 {
+        (self.last as u32 + 1).saturating_sub(self.first as u32)
+    }
+
+    /// Checks if the range contains no port, i.e. its bounds are inverted.
+    pub fn is_empty(&self) -> bool {
+        self.first > self.last
+    }
+
+    /// Formats the range with the given separator (':' for matches, '-' for NAT).
+    fn format(&self, separator: char) -> String {
+        match self.first == self.last {
+            true => self.first.to_string(),
+            false => format!("{}{}{}", self.first, separator, self.last),
+        }
+    }
+}
+
+/// Forwarding of the ports of this host to another host (`-j DNAT` in nat PREROUTING).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PortForward {
+    /// Protocol(s) of the forwarded ports.
+    pub protocol: Protocol,
+
+    /// Ports of this host which are forwarded.
+    pub ports: PortRange,
+
+    /// Address of the host receiving the connections.
+    pub destination: String,
+
+    /// Ports of the destination, either a single port receiving every forwarded port, or a
+    /// range of the same size as `ports` whose ports are mapped one-to-one.
+    pub to_ports: PortRange,
+
+    /// Interface receiving the forwarded connections, any if `None`.
+    pub in_interface: Option<String>,
+}
+
+impl PortForward {
+    /// Creates the forwarding of the `ports` to the same ports of the `destination`.
+    pub fn new(protocol: Protocol, ports: PortRange, destination: &str) -> PortForward {
+        PortForward {
+            protocol,
+            ports,
+            destination: destination.to_string(),
+            to_ports: ports,
+            in_interface: None,
+        }
+    }
+
+    /// Returns the value of `--to-destination`, e.g. '10.0.0.2:8000-8010'.
+    /// A range shifted from `ports` uses the base port of `ports` (e.g.
+    /// '10.0.0.2:9000-9010/8000'), which requires Linux 4.19 or newer.
+    pub fn to_destination(&self) -> String {
+        let address = match self.destination.contains(':') {
+            true => format!("[{}]", self.destination),
+            false => self.destination.clone(),
+        };
+        let mut destination = format!("{}:{}", address, self.to_ports.format('-'));
+        if self.to_ports.len() > 1 && self.to_ports.first != self.ports.first {
+            destination.push_str(&format!("/{}", self.ports.first));
+        }
+        destination
+    }
+
+    fn validate(&self) -> Result<(), Box<dyn Error>> {
+        if self.ports.is_empty() || self.to_ports.is_empty() {
+            return Err(error_from_str(
+                "invalid port range, the first port is after the last one",
+            ));
+        }
+        if self.to_ports.len() > 1 && self.to_ports.len() != self.ports.len() {
+            return Err(error_from_str(&format!(
+                "ports {} can't be mapped to ports {}, the ranges must have the same size",
+                self.ports.format(':'),
+                self.to_ports.format('-')
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns the rules of the forwarding, one for each protocol.
+    pub fn rules(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        self.validate()?;
+        self.protocol
+            .names()
+            .iter()
+            .map(|protocol| {
+                let mut rule = RuleBuilder::new().protocol(protocol);
+                if let Some(interface) = &self.in_interface {
+                    rule = rule.in_interface(interface);
+                }
+                rule.arg(&["-m", protocol, "--dport", &self.ports.format(':')])
+                    .target(Target::Dnat(self.to_destination()))
+                    .build()
+            })
+            .collect()
+    }
+}
+
+impl IPTables {
+    /// Forwards ports to another host, appending the rules to the nat PREROUTING chain.
+    /// Succeeds without doing anything for the rules which already exist.
+    pub fn port_forward(&self, forward: &PortForward) -> Result<(), Box<dyn Error>> {
+        for rule in forward.rules()? {
+            self.append_idempotent("nat", "PREROUTING", &rule)?;
+        }
+        Ok(())
+    }
+
+    /// Removes the rules of the forwarding, if they exist.
+    pub fn remove_port_forward(&self, forward: &PortForward) -> Result<(), Box<dyn Error>> {
+        for rule in forward.rules()? {
+            self.delete_idempotent("nat", "PREROUTING", &rule)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! Insertion of rules ordered by priorities recorded in their comments, instead of positions
+//! computed by the callers.
+
+use crate::parse::split_rule;
+use crate::IPTables;
+use std::error::Error;
+
+/// Prefix of the comments recording the priority of rules.
+pub const PRIORITY_PREFIX: &str = "ipt-rs-prio:";
+
+/// Returns the priority recorded in a comment of the `rule` by `insert_sorted`, if any.
+pub fn priority_of(rule: &str) -> Option<u32> {
+    split_rule(rule)
+        .windows(2)
+        .filter(|pair| pair[0] == "--comment")
+        .find_map(|pair| pair[1].strip_prefix(PRIORITY_PREFIX)?.parse().ok())
+}
+
+impl IPTables {
+    /// Inserts `rule` in the table/chain with a comment recording its `priority`, before the
+    /// first rule of a higher priority, so that the rules with lower priorities are matched
+    /// first whatever the positions of the rules of other tools. Without a rule of a higher
+    /// priority, it is inserted after the last rule with a priority, or appended if there is
+    /// none. Rules of the same priority keep their order of insertion.
+    /// Returns the position (starting from 1) of the inserted rule.
+    pub fn insert_sorted(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+        priority: u32,
+    ) -> Result<i32, Box<dyn Error>> {
+        let rules = self.chain_rules(table, chain)?;
+        let priorities = rules.iter().map(|r| priority_of(r)).collect::<Vec<_>>();
+        let index = priorities
+            .iter()
+            .position(|p| p.is_some_and(|p| p > priority))
+            .or_else(|| priorities.iter().rposition(Option::is_some).map(|i| i + 1))
+            .unwrap_or(rules.len());
+        let rule = format!(
+            "-m comment --comment {}{} {}",
+            PRIORITY_PREFIX, priority, rule
+        );
+        let position = index as i32 + 1;
+        self.insert(table, chain, &rule, position)?;
+        Ok(position)
+    }
+}
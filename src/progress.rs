@@ -0,0 +1,26 @@
+//! Progress of the long operations, e.g. to show a progress bar in a CLI while thousands of
+//! rules are applied.
+
+use crate::IPTables;
+use std::panic::RefUnwindSafe;
+use std::sync::Arc;
+
+/// Callback receiving the progress of a long operation as (done, total, current operation),
+/// e.g. (3, 10, "delete chain filter/MYAPP-OLD").
+pub type Progress = Arc<dyn Fn(usize, usize, &str) + Send + Sync + RefUnwindSafe>;
+
+impl IPTables {
+    /// Set the callback receiving the progress of the long operations of this handle: the
+    /// restores (including the methods built on them, e.g. `apply_composed`), in number of
+    /// rules, and `gc_managed`, in number of commands.
+    pub fn set_progress(&mut self, progress: Option<Progress>) {
+        self.progress = progress;
+    }
+
+    /// Reports the progress of an operation to the callback, if any.
+    pub(crate) fn report_progress(&self, done: usize, total: usize, operation: &str) {
+        if let Some(progress) = &self.progress {
+            progress(done, total, operation);
+        }
+    }
+}
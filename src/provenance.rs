@@ -0,0 +1,189 @@
+//! Provenance of the rules (e.g. the configuration file and key which they come from), recorded
+//! in a comment of each rule so that it stays with the rule in the kernel.
+
+use crate::parse::{join_rule, quote_comment, split_rule};
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Prefix of the comments recording the provenance of rules.
+pub const PROVENANCE_PREFIX: &str = "ipt-rs-from:";
+
+/// Maximum length of a comment, as limited by the comment match.
+const MAX_COMMENT_LEN: usize = 256;
+
+/// Where a rule comes from, recorded in the comment
+/// `ipt-rs-from:src=<source>;key=<key>;at=<created>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Provenance {
+    /// Source of the rule, e.g. the path of a configuration file.
+    pub source: String,
+
+    /// Key of the rule in its source, if any.
+    pub key: Option<String>,
+
+    /// Creation time of the rule, in seconds since the Unix epoch.
+    pub created: u64,
+}
+
+impl Provenance {
+    /// Creates the provenance of a rule created now from the `source` and its `key`.
+    pub fn new(source: &str, key: Option<&str>) -> Provenance {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default();
+        Provenance {
+            source: source.to_string(),
+            key: key.map(String::from),
+            created,
+        }
+    }
+
+    /// Returns the comment recording the provenance, failing if it exceeds the 256 characters
+    /// allowed by the comment match.
+    pub fn comment(&self) -> Result<String, Box<dyn Error>> {
+        let mut comment = format!("{}src={}", PROVENANCE_PREFIX, escape(&self.source));
+        if let Some(key) = &self.key {
+            comment.push_str(&format!(";key={}", escape(key)));
+        }
+        comment.push_str(&format!(";at={}", self.created));
+        if comment.len() > MAX_COMMENT_LEN {
+            return Err(error_from_str(&format!(
+                "the provenance comment of {} is longer than {} characters",
+                self.source, MAX_COMMENT_LEN
+            )));
+        }
+        Ok(comment)
+    }
+
+    /// Parses a comment made by `comment`.
+    pub fn parse(comment: &str) -> Option<Provenance> {
+        let mut source = None;
+        let mut key = None;
+        let mut created = None;
+        for field in comment.strip_prefix(PROVENANCE_PREFIX)?.split(';') {
+            match field.split_once('=')? {
+                ("src", value) => source = Some(unescape(value)?),
+                ("key", value) => key = Some(unescape(value)?),
+                ("at", value) => created = Some(value.parse().ok()?),
+                _ => return None,
+            }
+        }
+        Some(Provenance {
+            source: source?,
+            key,
+            created: created?,
+        })
+    }
+
+    /// Returns the provenance recorded in a comment of the `rule`, if any.
+    pub fn of_rule(rule: &str) -> Option<Provenance> {
+        split_rule(rule)
+            .windows(2)
+            .filter(|pair| pair[0] == "--comment")
+            .find_map(|pair| Provenance::parse(&pair[1]))
+    }
+}
+
+/// A rule listed by `IPTables::list_provenance`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListedRule {
+    /// The rule, without the leading `-A CHAIN`.
+    pub rule: String,
+
+    /// Provenance of the rule, if it records one.
+    pub provenance: Option<Provenance>,
+}
+
+/// Adds the comment of the `provenance` to the `rule`, in place of its previous provenance.
+pub fn with_provenance(rule: &str, provenance: &Provenance) -> Result<String, Box<dyn Error>> {
+    Ok(format!(
+        "-m comment --comment {} {}",
+        quote_comment(&provenance.comment()?),
+        strip_provenance(rule)
+    ))
+}
+
+/// Removes the provenance comment from the `rule`.
+pub fn strip_provenance(rule: &str) -> String {
+    let mut args = split_rule(rule);
+    let position = args.windows(4).position(|window| {
+        window[..3] == ["-m", "comment", "--comment"] && window[3].starts_with(PROVENANCE_PREFIX)
+    });
+    if let Some(i) = position {
+        args.drain(i..i + 4);
+    }
+    join_rule(&args)
+}
+
+impl IPTables {
+    /// Appends `rule` to the table/chain with the comment of its `provenance`.
+    pub fn append_with_provenance(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+        provenance: &Provenance,
+    ) -> Result<(), Box<dyn Error>> {
+        self.append(table, chain, &with_provenance(rule, provenance)?)
+    }
+
+    /// Inserts `rule` in the `position` to the table/chain with the comment of its
+    /// `provenance`.
+    pub fn insert_with_provenance(
+        &self,
+        table: &str,
+        chain: &str,
+        rule: &str,
+        position: i32,
+        provenance: &Provenance,
+    ) -> Result<(), Box<dyn Error>> {
+        self.insert(table, chain, &with_provenance(rule, provenance)?, position)
+    }
+
+    /// Lists the rules of the table/chain along with their provenance.
+    pub fn list_provenance(
+        &self,
+        table: &str,
+        chain: &str,
+    ) -> Result<Vec<ListedRule>, Box<dyn Error>> {
+        Ok(self
+            .chain_rules(table, chain)?
+            .into_iter()
+            .map(|rule| ListedRule {
+                provenance: Provenance::of_rule(&rule),
+                rule,
+            })
+            .collect())
+    }
+}
+
+/// Escapes the separators of the fields, the quotes and the control characters as `%XX`.
+fn escape(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, '%' | ';' | '=' | '"' | '\'' | '\\') || c.is_control() {
+            escaped.push_str(&format!("%{:02X}", c as u32));
+        } else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+/// Reverts `escape`.
+fn unescape(value: &str) -> Option<String> {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '%' => {
+                let hex = chars.by_ref().take(2).collect::<String>();
+                unescaped.push(char::from(u8::from_str_radix(&hex, 16).ok()?));
+            }
+            c => unescaped.push(c),
+        }
+    }
+    Some(unescaped)
+}
@@ -0,0 +1,169 @@
+//! Classification of the traffic into DSCP classes, e.g. for the queueing disciplines of the
+//! network.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::mark::Mark;
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+
+/// A Differentiated Services Code Point, the 6 bits of the traffic class of a packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Dscp(pub u8);
+
+impl Dscp {
+    /// Best effort (default) class.
+    pub const BE: Dscp = Dscp(0);
+
+    /// Expedited forwarding class, e.g. for voice.
+    pub const EF: Dscp = Dscp(46);
+
+    /// Returns the code point of a class given by name (e.g. 'EF', 'AF21', 'CS3' or 'BE').
+    pub fn class(name: &str) -> Result<Dscp, Box<dyn Error>> {
+        let invalid = || error_from_str(&format!("unknown DSCP class {}", name));
+        let digit = |c: u8, max: u8| match c.is_ascii_digit() && c - b'0' <= max {
+            true => Some(c - b'0'),
+            false => None,
+        };
+        let name = name.to_ascii_uppercase();
+        match name.as_bytes() {
+            b"BE" => Ok(Dscp::BE),
+            b"EF" => Ok(Dscp::EF),
+            [b'C', b'S', n] => digit(*n, 7).map(|n| Dscp(n * 8)).ok_or_else(invalid),
+            [b'A', b'F', class, drop] => match (digit(*class, 4), digit(*drop, 3)) {
+                (Some(class), Some(drop)) if class > 0 && drop > 0 => {
+                    Ok(Dscp(class * 8 + drop * 2))
+                }
+                _ => Err(invalid()),
+            },
+            _ => Err(invalid()),
+        }
+    }
+
+    /// Returns the arguments of the DSCP target, without the leading `-j DSCP`.
+    pub fn target_args(&self) -> Vec<String> {
+        vec!["--set-dscp".to_string(), format!("{:#04x}", self.0)]
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        if self.0 > 63 {
+            return Err(format!("DSCP {} is greater than 63", self.0));
+        }
+        Ok(())
+    }
+}
+
+/// Traffic classified by `QosClasses`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Traffic {
+    /// Packets to the port of the protocol, e.g. ('udp', 5060).
+    DestinationPort(String, u16),
+
+    /// Packets from the port of the protocol, e.g. ('tcp', 443).
+    SourcePort(String, u16),
+
+    /// Packets of the processes of the cgroup v2, given by its path relative to the root of
+    /// the hierarchy (e.g. 'system.slice/backup.service'), only for the local traffic.
+    Cgroup(String),
+
+    /// Packets with the mark.
+    Mark(Mark),
+}
+
+impl Traffic {
+    /// Returns the rule matching the traffic, without target.
+    pub fn rule(&self) -> RuleBuilder {
+        match self {
+            Traffic::DestinationPort(protocol, port) => {
+                RuleBuilder::new().protocol(protocol).dport(*port)
+            }
+            Traffic::SourcePort(protocol, port) => {
+                RuleBuilder::new().protocol(protocol).sport(*port)
+            }
+            Traffic::Cgroup(path) => RuleBuilder::new().cgroup(path),
+            Traffic::Mark(mark) => RuleBuilder::new().mark(*mark),
+        }
+    }
+}
+
+/// A chain of the mangle table setting the DSCP of the packets according to their traffic.
+/// The rules of the chain are regenerated atomically whenever the classes change.
+///
+/// Packets reach the chain through a jump to it (e.g. from POSTROUTING). The traffic
+/// descriptors are tried in order and a packet gets the class of the first one it matches;
+/// packets matching none are left unchanged.
+pub struct QosClasses<'a> {
+    ipt: &'a IPTables,
+    chain: String,
+    classes: Vec<(Traffic, Dscp)>,
+}
+
+impl IPTables {
+    /// Creates the classification chain in the mangle table, if it does not exist yet, without
+    /// any class.
+    pub fn qos_classes(&self, chain: &str) -> Result<QosClasses<'_>, Box<dyn Error>> {
+        let chain = &self.prefixed_chain(chain);
+        if !self.chain_exists("mangle", chain)? {
+            self.new_chain("mangle", chain)?;
+        }
+        let classes = QosClasses {
+            ipt: self,
+            chain: chain.to_string(),
+            classes: Vec::new(),
+        };
+        classes.regenerate()?;
+        Ok(classes)
+    }
+}
+
+impl QosClasses<'_> {
+    /// Returns the chain of the classification.
+    pub fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    /// Returns the traffic descriptors along with their classes, in order.
+    pub fn classes(&self) -> &[(Traffic, Dscp)] {
+        &self.classes
+    }
+
+    /// Sets the class of the `traffic`, which is added after the other descriptors if it is
+    /// new. Does not regenerate the rules if the class does not change.
+    pub fn set_class(&mut self, traffic: Traffic, dscp: Dscp) -> Result<(), Box<dyn Error>> {
+        dscp.validate().map_err(|msg| error_from_str(&msg))?;
+        match self.classes.iter_mut().find(|(t, _)| *t == traffic) {
+            Some((_, class)) if *class == dscp => return Ok(()),
+            Some((_, class)) => *class = dscp,
+            None => self.classes.push((traffic, dscp)),
+        }
+        self.regenerate()
+    }
+
+    /// Removes the class of the `traffic`.
+    pub fn remove_class(&mut self, traffic: &Traffic) -> Result<(), Box<dyn Error>> {
+        self.classes.retain(|(t, _)| t != traffic);
+        self.regenerate()
+    }
+
+    /// Replaces all the classes at once, regenerating the rules a single time.
+    pub fn set_classes(&mut self, classes: Vec<(Traffic, Dscp)>) -> Result<(), Box<dyn Error>> {
+        self.classes = classes;
+        self.regenerate()
+    }
+
+    /// Deletes the chain of the classification, which must not be referenced anymore.
+    pub fn delete(self) -> Result<(), Box<dyn Error>> {
+        self.ipt.flush_chain("mangle", &self.chain)?;
+        self.ipt.delete_chain("mangle", &self.chain)
+    }
+
+    /// Replaces the rules of the chain by a DSCP rule followed by a RETURN rule for each
+    /// traffic descriptor.
+    fn regenerate(&self) -> Result<(), Box<dyn Error>> {
+        let mut rules = Vec::with_capacity(self.classes.len() * 2);
+        for (traffic, dscp) in &self.classes {
+            rules.push(traffic.rule().target(Target::Dscp(*dscp)).build()?);
+            rules.push(traffic.rule().target(Target::Return).build()?);
+        }
+        self.ipt.replace_chain_rules("mangle", &self.chain, &rules)
+    }
+}
@@ -0,0 +1,124 @@
+//! Queries over the rules of a table.
+
+use crate::graph::ChainGraph;
+use crate::parse::{
+    fingerprint_rules, parse_chain_references, parse_counters, parse_nft_chains, parse_rules,
+    parse_save, parse_table_stats, ChainStats, Counters, Rule,
+};
+use crate::variant::Variant;
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+use std::io::{self, ErrorKind};
+
+impl IPTables {
+    /// Scans every chain of the table and returns the rules matching the `predicate`.
+    pub fn find_rules<P>(&self, table: &str, predicate: P) -> Result<Vec<Rule>, Box<dyn Error>>
+    where
+        P: Fn(&Rule) -> bool,
+    {
+        Ok(parse_rules(&self.list_table(table)?.join("\n"))
+            .into_iter()
+            .filter(|rule| predicate(rule))
+            .collect())
+    }
+
+    /// Finds the rules of the table which jump (`-j`) or go (`-g`) to the `chain`.
+    /// Returns the chain and the position of each of them.
+    pub fn references_to(
+        &self,
+        table: &str,
+        chain: &str,
+    ) -> Result<Vec<(String, i32)>, Box<dyn Error>> {
+        Ok(self
+            .find_rules(table, |rule| rule.target().as_deref() == Some(chain))?
+            .into_iter()
+            .map(|rule| (rule.chain, rule.position))
+            .collect())
+    }
+
+    /// Builds the graph of the jumps between the chains of the table.
+    pub fn chain_graph(&self, table: &str) -> Result<ChainGraph, Box<dyn Error>> {
+        Ok(ChainGraph::new(
+            self.list_chains(table)?,
+            &self.find_rules(table, |_| true)?,
+        ))
+    }
+
+    /// Returns a hash of the rules (and the policy) of the table/chain, see `fingerprint_rules`.
+    /// Hosts with the same fingerprint for a chain have the same rules in the same order.
+    pub fn chain_fingerprint(&self, table: &str, chain: &str) -> Result<u64, Box<dyn Error>> {
+        Ok(fingerprint_rules(&self.list(table, chain)?))
+    }
+
+    /// Returns the exact counters of the rules of the table/chain, in the order of the rules.
+    pub fn counters(&self, table: &str, chain: &str) -> Result<Vec<Counters>, Box<dyn Error>> {
+        let output = self.run_checked(&["-t", table, "-L", chain, "-v", "-x", "-n"])?;
+        Ok(parse_counters(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Returns a summary of each chain of the table (its policy, references, number of rules
+    /// and their total counters) with a single command.
+    pub fn stats(&self, table: &str) -> Result<Vec<ChainStats>, Box<dyn Error>> {
+        let output = self.run_checked(&["-t", table, "-L", "-v", "-x", "-n"])?;
+        Ok(parse_table_stats(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    /// Returns the number of references (jumps from other rules) to the table/chain, which must
+    /// be zero for `delete_chain` to succeed. Built-in chains have no references.
+    pub fn chain_references(&self, table: &str, chain: &str) -> Result<u32, Box<dyn Error>> {
+        let output = self.run_checked(&["-t", table, "-L", chain, "-n"])?;
+        parse_chain_references(&String::from_utf8_lossy(&output.stdout), chain)
+            .ok_or_else(|| error_from_str("unable to parse the references of the chain"))
+    }
+
+    /// Returns the counters of the policy of a built-in table/chain, i.e. of the packets which
+    /// reached the end of the chain and were handled by its default policy.
+    pub fn get_policy_counters(
+        &self,
+        table: &str,
+        chain: &str,
+    ) -> Result<Counters, Box<dyn Error>> {
+        let output =
+            self.run_program_checked(&format!("{}-save", self.cmd), &["-t", table], None)?;
+        parse_save(&String::from_utf8_lossy(&output.stdout))
+            .into_iter()
+            .filter(|saved| saved.name == table)
+            .flat_map(|saved| saved.chains)
+            .find(|saved| saved.name == chain && saved.policy.is_some())
+            .and_then(|saved| saved.counters)
+            .ok_or_else(|| {
+                error_from_str(
+                    "given chain is not a default chain in the given table, can't get counters",
+                )
+            })
+    }
+
+    /// Lists the name of each chain in the table without dumping its rules when possible,
+    /// i.e. using `nft list chains` on the nf_tables backend.
+    /// Falls back to `list_chains` on the legacy backend or if `nft` is not installed.
+    pub fn list_chain_names_fast(&self, table: &str) -> Result<Vec<String>, Box<dyn Error>> {
+        if self.variant()? == Variant::Legacy {
+            return self.list_chains(table);
+        }
+
+        let family = match self.cmd {
+            "ip6tables" => "ip6",
+            _ => "ip",
+        };
+        let output = match self.run_program("nft", &["list", "chains", family], None) {
+            Ok(output) if output.status.success() => output,
+            Ok(_) => return self.list_chains(table),
+            Err(e)
+                if e.downcast_ref::<io::Error>().map(io::Error::kind)
+                    == Some(ErrorKind::NotFound) =>
+            {
+                return self.list_chains(table)
+            }
+            Err(e) => return Err(e),
+        };
+        Ok(parse_nft_chains(
+            &String::from_utf8_lossy(&output.stdout),
+            table,
+        ))
+    }
+}
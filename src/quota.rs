@@ -0,0 +1,167 @@
+//! Data caps of the hosts behind a router, e.g. for hotspots, enforced with quota rules.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+
+/// Usage of the data cap of a host, as reported by `DataCapManager::usage`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataCapUsage {
+    /// Address of the host.
+    pub address: String,
+
+    /// Number of bytes the host may receive.
+    pub limit: u64,
+
+    /// Number of bytes received by the host since its cap was installed or renewed.
+    pub used: u64,
+
+    /// Whether the host reached its cap, i.e. its packets are dropped.
+    pub exhausted: bool,
+}
+
+/// A chain of the filter table capping the number of bytes received by each host.
+///
+/// Every host has two rules: a quota rule returning its packets until the cap is reached,
+/// then a rule dropping them. Forwarded packets reach the caps through a jump to the chain
+/// (e.g. from FORWARD); the packets of the hosts without a cap fall through the chain.
+pub struct DataCapManager<'a> {
+    ipt: &'a IPTables,
+    chain: String,
+    caps: Vec<(String, u64)>,
+}
+
+impl IPTables {
+    /// Creates the chain of the data caps in the filter table, if it does not exist yet, and
+    /// removes its rules.
+    pub fn data_cap_manager(&self, chain: &str) -> Result<DataCapManager<'_>, Box<dyn Error>> {
+        let chain = &self.prefixed_chain(chain);
+        if !self.chain_exists("filter", chain)? {
+            self.new_chain("filter", chain)?;
+        }
+        self.flush_chain("filter", chain)?;
+        Ok(DataCapManager {
+            ipt: self,
+            chain: chain.to_string(),
+            caps: Vec::new(),
+        })
+    }
+}
+
+impl DataCapManager<'_> {
+    /// Returns the chain of the data caps.
+    pub fn chain(&self) -> &str {
+        &self.chain
+    }
+
+    /// Returns the hosts along with their caps in bytes.
+    pub fn caps(&self) -> impl Iterator<Item = (&str, u64)> {
+        self.caps
+            .iter()
+            .map(|(address, limit)| (address.as_str(), *limit))
+    }
+
+    /// Caps the number of bytes received by the host at `address`.
+    /// Changing the cap of a host renews it, i.e. its usage starts from zero again.
+    pub fn set_cap(&mut self, address: &str, limit: u64) -> Result<(), Box<dyn Error>> {
+        if let Some(index) = self.position(address) {
+            self.caps[index].1 = limit;
+            return self.renew_at(index);
+        }
+        let (quota, drop) = cap_rules(address, limit)?;
+        self.ipt.append("filter", &self.chain, &quota)?;
+        self.ipt.append("filter", &self.chain, &drop)?;
+        self.caps.push((address.to_string(), limit));
+        Ok(())
+    }
+
+    /// Removes the cap of the host at `address`, whose traffic is not limited anymore.
+    pub fn remove_cap(&mut self, address: &str) -> Result<(), Box<dyn Error>> {
+        let index = self
+            .position(address)
+            .ok_or_else(|| error_from_str(&format!("{} has no data cap", address)))?;
+        let (quota, drop) = cap_rules(address, self.caps[index].1)?;
+        self.ipt.delete("filter", &self.chain, &quota)?;
+        self.ipt.delete("filter", &self.chain, &drop)?;
+        self.caps.remove(index);
+        Ok(())
+    }
+
+    /// Returns the usage of the caps, read from the counters of their rules.
+    pub fn usage(&self) -> Result<Vec<DataCapUsage>, Box<dyn Error>> {
+        let counters = self.ipt.counters("filter", &self.chain)?;
+        if counters.len() != self.caps.len() * 2 {
+            return Err(error_from_str(&format!(
+                "chain {} has {} rules instead of {}",
+                self.chain,
+                counters.len(),
+                self.caps.len() * 2
+            )));
+        }
+        Ok(self
+            .caps
+            .iter()
+            .zip(counters.chunks(2))
+            .map(|((address, limit), counters)| DataCapUsage {
+                address: address.clone(),
+                limit: *limit,
+                used: counters[0].bytes,
+                exhausted: counters[0].bytes >= *limit || counters[1].packets > 0,
+            })
+            .collect())
+    }
+
+    /// Renews the cap of the host at `address` (e.g. at the start of a billing period) by
+    /// recreating its rules, which resets its quota and counters.
+    pub fn renew(&self, address: &str) -> Result<(), Box<dyn Error>> {
+        let index = self
+            .position(address)
+            .ok_or_else(|| error_from_str(&format!("{} has no data cap", address)))?;
+        self.renew_at(index)
+    }
+
+    /// Renews the caps which are exhausted and returns the addresses of their hosts.
+    pub fn renew_exhausted(&self) -> Result<Vec<String>, Box<dyn Error>> {
+        let mut renewed = Vec::new();
+        for usage in self.usage()? {
+            if usage.exhausted {
+                self.renew(&usage.address)?;
+                renewed.push(usage.address);
+            }
+        }
+        Ok(renewed)
+    }
+
+    /// Deletes the chain of the data caps, which must not be referenced anymore.
+    pub fn delete(self) -> Result<(), Box<dyn Error>> {
+        self.ipt.flush_chain("filter", &self.chain)?;
+        self.ipt.delete_chain("filter", &self.chain)
+    }
+
+    fn position(&self, address: &str) -> Option<usize> {
+        self.caps.iter().position(|(a, _)| a == address)
+    }
+
+    /// Replaces the rules of the cap at `index` in place, the other caps keep their quota.
+    fn renew_at(&self, index: usize) -> Result<(), Box<dyn Error>> {
+        let (address, limit) = &self.caps[index];
+        let (quota, drop) = cap_rules(address, *limit)?;
+        let position = index as i32 * 2 + 1;
+        self.ipt.replace("filter", &self.chain, &quota, position)?;
+        self.ipt.replace("filter", &self.chain, &drop, position + 1)
+    }
+}
+
+/// Returns the quota rule and the drop rule of the cap of a host.
+fn cap_rules(address: &str, limit: u64) -> Result<(String, String), Box<dyn Error>> {
+    let quota = RuleBuilder::new()
+        .destination(address)
+        .quota(limit)
+        .target(Target::Return)
+        .build()?;
+    let drop = RuleBuilder::new()
+        .destination(address)
+        .target(Target::Drop)
+        .build()?;
+    Ok((quota, drop))
+}
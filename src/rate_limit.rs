@@ -0,0 +1,64 @@
+//! Limit of the rate of the commands of a handle, so that a runaway loop does not monopolize
+//! the xtables lock.
+
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Token bucket allowing `rate` commands per second, with bursts of up to `rate` commands.
+/// Commands exceeding the rate wait for their turn.
+#[derive(Debug)]
+pub struct RateLimiter {
+    rate: u32,
+    bucket: Mutex<Bucket>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    /// Available tokens, negative when commands are waiting for their turn.
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl RateLimiter {
+    /// Creates a limiter allowing `rate` commands per second, which must not be zero.
+    pub fn new(rate: u32) -> RateLimiter {
+        assert!(rate > 0, "the rate of commands must not be zero");
+        RateLimiter {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: f64::from(rate),
+                refilled_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Returns the number of commands allowed per second.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Waits until the next command is allowed by the rate.
+    pub fn acquire(&self) {
+        let wait = {
+            let mut bucket = self
+                .bucket
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            let rate = f64::from(self.rate);
+            let now = Instant::now();
+            let elapsed = now.duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(rate);
+            bucket.refilled_at = now;
+            // The token is taken right away, so the commands run in the order they waited
+            bucket.tokens -= 1.0;
+            match bucket.tokens < 0.0 {
+                true => Duration::from_secs_f64(-bucket.tokens / rate),
+                false => Duration::ZERO,
+            }
+        };
+        if !wait.is_zero() {
+            thread::sleep(wait);
+        }
+    }
+}
@@ -0,0 +1,195 @@
+//! Batch operations based on `iptables-save` and `iptables-restore`.
+
+use crate::error::IPTError;
+use crate::parse::save_table_block;
+use crate::{error_from_str, try_lock, IPTables};
+use std::error::Error;
+use std::thread;
+
+/// Lock file used by iptables (with -w option) to serialize access to the legacy backend.
+pub(crate) const XTABLES_LOCK: &str = "/run/xtables.lock";
+
+/// Options of `IPTables::restore`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RestoreOptions {
+    /// Restores the packet and byte counters of the data (`-c`), as dumped by
+    /// `save_with_counters`.
+    pub counters: bool,
+
+    /// Flushes the tables contained in the data before restoring them, otherwise their rules
+    /// are appended to the current ones (`--noflush`).
+    pub flush: bool,
+
+    /// Restores only the given table of the data (`--table`), ignoring the other ones.
+    pub table: Option<String>,
+}
+
+impl RestoreOptions {
+    fn args(&self) -> Vec<&str> {
+        let mut args = Vec::new();
+        if self.counters {
+            args.push("--counters");
+        }
+        if !self.flush {
+            args.push("--noflush");
+        }
+        if let Some(table) = &self.table {
+            args.extend(["--table", table]);
+        }
+        args
+    }
+}
+
+impl Default for RestoreOptions {
+    fn default() -> Self {
+        RestoreOptions {
+            counters: false,
+            flush: true,
+            table: None,
+        }
+    }
+}
+
+impl IPTables {
+    /// Applies `changes` and runs the connectivity `probe` afterwards (e.g. connecting to a
+    /// canary host). The ruleset is rolled back to its previous state if `changes` fails or
+    /// the probe returns `false`, which protects against locking oneself out.
+    /// Returns `false` if the probe failed and the ruleset was rolled back.
+    pub fn apply_and_probe<C, P>(&self, changes: C, probe: P) -> Result<bool, Box<dyn Error>>
+    where
+        C: FnOnce(&IPTables) -> Result<(), Box<dyn Error>>,
+        P: Fn() -> bool,
+    {
+        let snapshot = self.save_with_counters()?;
+        let options = RestoreOptions {
+            counters: true,
+            ..Default::default()
+        };
+
+        if let Err(e) = changes(self) {
+            if let Err(rollback) = self.restore(&snapshot, options.clone()) {
+                return Err(IPTError::rollback_failed(&e.to_string(), rollback));
+            }
+            return Err(e);
+        }
+
+        if !probe() {
+            self.restore(&snapshot, options)
+                .map_err(|e| IPTError::rollback_failed("the probe failed", e))?;
+            return Ok(false);
+        }
+        Ok(true)
+    }
+
+    /// Dumps the whole ruleset, including the packet and byte counters of each rule, using
+    /// `iptables-save -c`. The counters are preserved when restoring it with the `counters`
+    /// option of `restore`.
+    pub fn save_with_counters(&self) -> Result<String, Box<dyn Error>> {
+        let output =
+            self.run_program_checked(&format!("{}-save", self.cmd), &["--counters"], None)?;
+        Ok(String::from_utf8_lossy(output.stdout.as_slice()).into_owned())
+    }
+
+    /// Restores the tables contained in `data`, in the format of `iptables-save`, using
+    /// `iptables-restore` with the given `options`.
+    pub fn restore(&self, data: &str, options: RestoreOptions) -> Result<(), Box<dyn Error>> {
+        let total = data.lines().filter(|line| line.starts_with("-A ")).count();
+        let operation = format!("restore {} rules", total);
+        self.report_progress(0, total, &operation);
+        self.restore_ruleset(data, &options.args())?;
+        self.report_progress(total, total, &operation);
+        Ok(())
+    }
+
+    /// Restores only the `table` from `data`, in the format of `iptables-save`, leaving the
+    /// other tables of the data and of the system untouched.
+    pub fn restore_table(&self, table: &str, data: &str) -> Result<(), Box<dyn Error>> {
+        let block = save_table_block(data, table)
+            .ok_or_else(|| error_from_str(&format!("table {} is not in the data", table)))?;
+        self.restore(&block, RestoreOptions::default())
+    }
+
+    /// Dumps the whole ruleset using `iptables-save`.
+    pub(crate) fn save_ruleset(&self) -> Result<String, Box<dyn Error>> {
+        let output =
+            self.run_program_checked(&format!("{}-save", self.cmd), &[] as &[&str], None)?;
+        Ok(String::from_utf8_lossy(output.stdout.as_slice()).into_owned())
+    }
+
+    /// Atomically replaces the rules of the table/chain by `rules` (without the leading
+    /// `-A CHAIN`), leaving the other chains untouched.
+    pub(crate) fn replace_chain_rules(
+        &self,
+        table: &str,
+        chain: &str,
+        rules: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        // With --noflush, declaring a chain only flushes it if it is user-defined
+        let mut data = format!("*{}\n:{} - [0:0]\n-F {}\n", table, chain, chain);
+        for rule in rules {
+            data.push_str(&format!("-A {} {}\n", chain, rule));
+        }
+        data.push_str("COMMIT\n");
+        let options = RestoreOptions {
+            flush: false,
+            ..Default::default()
+        };
+        self.restore(&data, options)
+    }
+
+    /// Atomically appends `rules` (without the leading `-A CHAIN`) to the table/chain.
+    pub(crate) fn append_chain_rules(
+        &self,
+        table: &str,
+        chain: &str,
+        rules: &[String],
+    ) -> Result<(), Box<dyn Error>> {
+        // The chain is not declared, otherwise it would be flushed
+        let mut data = format!("*{}\n", table);
+        for rule in rules {
+            data.push_str(&format!("-A {} {}\n", chain, rule));
+        }
+        data.push_str("COMMIT\n");
+        let options = RestoreOptions {
+            flush: false,
+            ..Default::default()
+        };
+        self.restore(&data, options)
+    }
+
+    /// Replaces the tables contained in `data` using `iptables-restore` with extra `args`.
+    pub(crate) fn restore_ruleset(&self, data: &str, args: &[&str]) -> Result<(), Box<dyn Error>> {
+        self.restore_ruleset_with(&format!("{}-restore", self.cmd), data, args)
+    }
+
+    /// Replaces the tables contained in `data` using the restore binary `cmd` (e.g.
+    /// `iptables-nft-restore`) with extra `args`.
+    /// Old versions of iptables-restore do not support -w (--wait), so the xtables lock is
+    /// taken manually to avoid racing with other tools.
+    pub(crate) fn restore_ruleset_with(
+        &self,
+        cmd: &str,
+        data: &str,
+        args: &[&str],
+    ) -> Result<(), Box<dyn Error>> {
+        if self.has_restore_wait {
+            self.run_program_checked(cmd, &[args, &["--wait"]].concat(), Some(data))?;
+            return Ok(());
+        }
+
+        let mut attempt = 1;
+        // The lock is released when `_file_lock` goes out of scope
+        let _file_lock = loop {
+            match try_lock(XTABLES_LOCK)? {
+                Some(file_lock) => break file_lock,
+                None if attempt >= self.retry_policy.max_attempts => {
+                    return Err(IPTError::command(cmd, args, Box::new(IPTError::Locked)));
+                }
+                None => thread::sleep(self.retry_policy.delay(attempt)),
+            }
+            attempt += 1;
+        };
+        self.run_program_checked(cmd, args, Some(data))?;
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+//! Retry strategy used when the xtables lock is held by another process.
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::process::Output;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Strategy for retrying commands which fail because another process is holding the xtables lock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RetryPolicy {
+    /// Maximum number of attempts, including the first one.
+    pub max_attempts: u32,
+
+    /// Delay before the first retry, which is doubled after each failed attempt.
+    pub backoff: Duration,
+
+    /// Upper bound of the random delay added to each backoff.
+    pub jitter: Duration,
+}
+
+impl RetryPolicy {
+    /// Creates a policy which never retries.
+    pub fn never() -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: 1,
+            backoff: Duration::ZERO,
+            jitter: Duration::ZERO,
+        }
+    }
+
+    /// Returns the delay to wait after the failed `attempt` (starting from 1).
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .backoff
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let jitter = match self.jitter.as_nanos() as u64 {
+            0 => Duration::ZERO,
+            max => Duration::from_nanos(random_u64() % max),
+        };
+        backoff.saturating_add(jitter)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 5,
+            backoff: Duration::from_millis(50),
+            jitter: Duration::from_millis(50),
+        }
+    }
+}
+
+/// Checks if iptables failed because another process is holding the xtables lock.
+pub(crate) fn is_lock_error(output: &Output) -> bool {
+    !output.status.success()
+        && String::from_utf8_lossy(output.stderr.as_slice())
+            .to_lowercase()
+            .contains("holding the xtables lock")
+}
+
+pub(crate) fn random_u64() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos() as u64)
+        .unwrap_or_default();
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u64(nanos);
+    hasher.finish()
+}
@@ -0,0 +1,53 @@
+//! Handle shared by the components of a process, which serializes their commands so that
+//! sequences of rules are never interleaved.
+
+use crate::IPTables;
+use std::ops::Deref;
+use std::sync::{Mutex, MutexGuard};
+
+/// An `IPTables` used by several components of a process (see `iptables::global`), which
+/// get it one at a time through `lock`.
+///
+/// Commands of concurrent components would otherwise race on the xtables lock, and the rules
+/// of their sequences (e.g. creating a chain then appending its rules) could be interleaved.
+pub struct SharedIPTables {
+    ipt: IPTables,
+    lock: Mutex<()>,
+}
+
+/// Exclusive access to the handle of a `SharedIPTables`, released when dropped.
+pub struct SharedGuard<'a> {
+    ipt: &'a IPTables,
+    _guard: MutexGuard<'a, ()>,
+}
+
+impl SharedIPTables {
+    /// Shares `ipt` between the components of the process.
+    pub fn new(ipt: IPTables) -> SharedIPTables {
+        SharedIPTables {
+            ipt,
+            lock: Mutex::new(()),
+        }
+    }
+
+    /// Waits until no other component uses the handle, then returns it. Every command run
+    /// until the guard is dropped is serialized against the other components.
+    pub fn lock(&self) -> SharedGuard<'_> {
+        SharedGuard {
+            ipt: &self.ipt,
+            // The handle has no state which a panicking component could leave inconsistent
+            _guard: self
+                .lock
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner()),
+        }
+    }
+}
+
+impl Deref for SharedGuard<'_> {
+    type Target = IPTables;
+
+    fn deref(&self) -> &IPTables {
+        self.ipt
+    }
+}
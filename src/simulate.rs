@@ -0,0 +1,374 @@
+//! Evaluation of a described packet against the rules of a table without a kernel, e.g. to
+//! debug a ruleset or to test a policy in CI.
+
+use crate::error_from_str;
+use crate::parse::{parse_rules, split_rule, Rule, SavedTable};
+use std::error::Error;
+use std::net::IpAddr;
+
+#[cfg(not(feature = "parse-only"))]
+use crate::parse::parse_save;
+#[cfg(not(feature = "parse-only"))]
+use crate::IPTables;
+
+/// Maximum depth of the jumps between chains, as the kernel limits it too.
+const MAX_DEPTH: usize = 64;
+
+/// Matches whose options are evaluated.
+const SUPPORTED_MATCHES: [&str; 7] = [
+    "comment",
+    "conntrack",
+    "mark",
+    "multiport",
+    "state",
+    "tcp",
+    "udp",
+];
+
+/// Targets which don't end the traversal of the chains.
+const NON_TERMINATING_TARGETS: [&str; 17] = [
+    "AUDIT",
+    "CHECKSUM",
+    "CLASSIFY",
+    "CONNMARK",
+    "CONNSECMARK",
+    "CT",
+    "DSCP",
+    "HL",
+    "LOG",
+    "MARK",
+    "NFLOG",
+    "NOTRACK",
+    "SECMARK",
+    "SET",
+    "TCPMSS",
+    "TRACE",
+    "TTL",
+];
+
+/// Description of a packet evaluated by `trace_packet`. The options of the rules about the
+/// properties which are not given (`None`) never match.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Packet {
+    /// Protocol of the packet (e.g. 'tcp').
+    pub protocol: Option<String>,
+
+    /// Source address.
+    pub source: Option<IpAddr>,
+
+    /// Destination address.
+    pub destination: Option<IpAddr>,
+
+    /// Source port, for the protocols with ports.
+    pub sport: Option<u16>,
+
+    /// Destination port, for the protocols with ports.
+    pub dport: Option<u16>,
+
+    /// Interface on which the packet was received.
+    pub in_interface: Option<String>,
+
+    /// Interface on which the packet is sent.
+    pub out_interface: Option<String>,
+
+    /// State of the connection of the packet (e.g. 'NEW' or 'ESTABLISHED').
+    pub state: Option<String>,
+
+    /// Netfilter mark of the packet.
+    pub mark: u32,
+}
+
+/// Final verdict of `trace_packet`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Verdict {
+    /// The packet is accepted, by a rule or the policy.
+    Accept,
+
+    /// The packet is dropped, by a rule or the policy.
+    Drop,
+
+    /// The packet is rejected.
+    Reject,
+
+    /// The packet is handled by another terminating target (e.g. 'DNAT' or 'NFQUEUE').
+    Target(String),
+
+    /// The packet returned from the entry chain, a user-defined chain without policy.
+    Return,
+}
+
+/// Result of `trace_packet`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Trace {
+    /// The rules which matched the packet, in the order of the traversal.
+    pub matched: Vec<Rule>,
+
+    /// The rules which were skipped because they have options the evaluation does not
+    /// support (e.g. `-m recent`), which may change the verdict.
+    pub unsupported: Vec<Rule>,
+
+    /// The final verdict.
+    pub verdict: Verdict,
+}
+
+/// Evaluates the `packet` against the rules of the `table`, starting from the `chain` (e.g.
+/// 'INPUT'). Jumps, gotos, RETURN and the policies are followed like the kernel does.
+///
+/// Only the protocol, the addresses, the interfaces, the ports (including multiport), the
+/// connection state and the mark are evaluated; the rules with other options are reported in
+/// `Trace::unsupported` and considered as not matching.
+pub fn trace_packet(
+    table: &SavedTable,
+    chain: &str,
+    packet: &Packet,
+) -> Result<Trace, Box<dyn Error>> {
+    let entry = table
+        .chains
+        .iter()
+        .find(|c| c.name == chain)
+        .ok_or_else(|| {
+            error_from_str(&format!("chain {} is not in table {}", chain, table.name))
+        })?;
+    let mut simulation = Simulation {
+        table,
+        rules: parse_rules(&table.rules.join("\n")),
+        packet,
+        matched: Vec::new(),
+        unsupported: Vec::new(),
+    };
+    let verdict = match simulation.run(chain, 0)? {
+        Some(verdict) => verdict,
+        None => match entry.policy.as_deref() {
+            Some("ACCEPT") => Verdict::Accept,
+            Some("DROP") => Verdict::Drop,
+            Some(policy) => Verdict::Target(policy.to_string()),
+            None => Verdict::Return,
+        },
+    };
+    Ok(Trace {
+        matched: simulation.matched,
+        unsupported: simulation.unsupported,
+        verdict,
+    })
+}
+
+#[cfg(not(feature = "parse-only"))]
+impl IPTables {
+    /// Evaluates the `packet` against the current rules of the table, see `trace_packet`.
+    pub fn trace_packet(
+        &self,
+        table: &str,
+        chain: &str,
+        packet: &Packet,
+    ) -> Result<Trace, Box<dyn Error>> {
+        let tables = parse_save(&self.save_ruleset()?);
+        let saved = tables
+            .iter()
+            .find(|t| t.name == table)
+            .ok_or_else(|| error_from_str(&format!("table {} is not loaded", table)))?;
+        trace_packet(saved, chain, packet)
+    }
+}
+
+struct Simulation<'a> {
+    table: &'a SavedTable,
+    rules: Vec<Rule>,
+    packet: &'a Packet,
+    matched: Vec<Rule>,
+    unsupported: Vec<Rule>,
+}
+
+impl Simulation<'_> {
+    /// Runs the rules of the `chain`; returns `None` if the packet returns from it.
+    fn run(&mut self, chain: &str, depth: usize) -> Result<Option<Verdict>, Box<dyn Error>> {
+        if depth > MAX_DEPTH {
+            return Err(error_from_str(&format!(
+                "too many nested jumps from chain {}, there may be a loop",
+                chain
+            )));
+        }
+        let rules = self
+            .rules
+            .iter()
+            .filter(|rule| rule.chain == chain)
+            .cloned()
+            .collect::<Vec<_>>();
+        for rule in rules {
+            let args = split_rule(&rule.spec);
+            match matches(&args, self.packet) {
+                Some(true) => self.matched.push(rule),
+                Some(false) => continue,
+                None => {
+                    self.unsupported.push(rule);
+                    continue;
+                }
+            }
+            let (goto, target) = match args
+                .iter()
+                .position(|arg| matches!(arg.as_str(), "-j" | "--jump" | "-g" | "--goto"))
+            {
+                Some(i) if i + 1 < args.len() => {
+                    (matches!(args[i].as_str(), "-g" | "--goto"), &args[i + 1])
+                }
+                _ => continue,
+            };
+            match target.as_str() {
+                "ACCEPT" => return Ok(Some(Verdict::Accept)),
+                "DROP" => return Ok(Some(Verdict::Drop)),
+                "REJECT" => return Ok(Some(Verdict::Reject)),
+                "RETURN" => return Ok(None),
+                target if self.table.chains.iter().any(|c| c.name == target) => {
+                    let verdict = self.run(target, depth + 1)?;
+                    if verdict.is_some() || goto {
+                        return Ok(verdict);
+                    }
+                }
+                target if NON_TERMINATING_TARGETS.contains(&target) => {}
+                target => return Ok(Some(Verdict::Target(target.to_string()))),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Checks if the options of a rule given by `args` match the `packet`, or returns `None` if
+/// some of them are not supported.
+fn matches(args: &[String], packet: &Packet) -> Option<bool> {
+    let mut matched = true;
+    let mut negated = false;
+    let mut iter = args.iter().map(String::as_str);
+    while let Some(arg) = iter.next() {
+        if arg == "!" {
+            negated = true;
+            continue;
+        }
+        let result = match arg {
+            "-j" | "--jump" | "-g" | "--goto" => break,
+            "-m" | "--match" => {
+                if !SUPPORTED_MATCHES.contains(&iter.next()?) {
+                    return None;
+                }
+                continue;
+            }
+            "--comment" => {
+                iter.next()?;
+                continue;
+            }
+            "-p" | "--protocol" => {
+                let protocol = iter.next()?;
+                protocol == "all"
+                    || packet
+                        .protocol
+                        .as_deref()
+                        .is_some_and(|p| p.eq_ignore_ascii_case(protocol))
+            }
+            "-s" | "--source" => address_matches(iter.next()?, packet.source)?,
+            "-d" | "--destination" => address_matches(iter.next()?, packet.destination)?,
+            "-i" | "--in-interface" => {
+                interface_matches(iter.next()?, packet.in_interface.as_deref())
+            }
+            "-o" | "--out-interface" => {
+                interface_matches(iter.next()?, packet.out_interface.as_deref())
+            }
+            "--sport" | "--source-port" | "--sports" | "--source-ports" => {
+                ports_match(iter.next()?, packet.sport)?
+            }
+            "--dport" | "--destination-port" | "--dports" | "--destination-ports" => {
+                ports_match(iter.next()?, packet.dport)?
+            }
+            "--ports" => {
+                let ports = iter.next()?;
+                ports_match(ports, packet.sport)? || ports_match(ports, packet.dport)?
+            }
+            "--ctstate" | "--state" => {
+                let states = iter.next()?;
+                packet
+                    .state
+                    .as_deref()
+                    .is_some_and(|state| states.split(',').any(|s| s.eq_ignore_ascii_case(state)))
+            }
+            "--mark" => {
+                let mark = iter.next()?;
+                let (value, mask) = match mark.split_once('/') {
+                    Some((value, mask)) => (parse_number(value)?, parse_number(mask)?),
+                    None => (parse_number(mark)?, u32::MAX),
+                };
+                packet.mark & mask == value
+            }
+            _ => return None,
+        };
+        matched &= result != negated;
+        negated = false;
+    }
+    Some(matched)
+}
+
+/// Checks if the `address` is in one of the networks (e.g. '10.0.0.0/8,192.168.0.1').
+fn address_matches(networks: &str, address: Option<IpAddr>) -> Option<bool> {
+    let mut matched = false;
+    for network in networks.split(',') {
+        let (base, length) = match network.split_once('/') {
+            Some((base, length)) => (
+                base.parse::<IpAddr>().ok()?,
+                Some(length.parse::<u32>().ok()?),
+            ),
+            None => (network.parse::<IpAddr>().ok()?, None),
+        };
+        matched |= match (base, address) {
+            (IpAddr::V4(base), Some(IpAddr::V4(address))) => {
+                let length = length.unwrap_or(32).min(32);
+                let mask = u32::MAX.checked_shl(32 - length).unwrap_or(0);
+                u32::from(base) & mask == u32::from(address) & mask
+            }
+            (IpAddr::V6(base), Some(IpAddr::V6(address))) => {
+                let length = length.unwrap_or(128).min(128);
+                let mask = u128::MAX.checked_shl(128 - length).unwrap_or(0);
+                u128::from(base) & mask == u128::from(address) & mask
+            }
+            _ => false,
+        };
+    }
+    Some(matched)
+}
+
+/// Checks if the `interface` has the `name`, in which a trailing '+' is a wildcard.
+fn interface_matches(name: &str, interface: Option<&str>) -> bool {
+    match (name.strip_suffix('+'), interface) {
+        (Some(prefix), Some(interface)) => interface.starts_with(prefix),
+        (None, Some(interface)) => interface == name,
+        (_, None) => false,
+    }
+}
+
+/// Checks if the `port` is in the ports, e.g. '22', '8000:8080' or '80,443'.
+fn ports_match(ports: &str, port: Option<u16>) -> Option<bool> {
+    let mut matched = false;
+    for range in ports.split(',') {
+        let (first, last) = match range.split_once(':') {
+            // The bounds of a range can be omitted, e.g. ':1023'
+            Some((first, last)) => (bound(first, 0)?, bound(last, u16::MAX)?),
+            None => {
+                let port = range.parse().ok()?;
+                (port, port)
+            }
+        };
+        matched |= port.is_some_and(|port| first <= port && port <= last);
+    }
+    Some(matched)
+}
+
+/// Parses a bound of a port range, which is `default` if omitted.
+fn bound(port: &str, default: u16) -> Option<u16> {
+    match port {
+        "" => Some(default),
+        port => port.parse().ok(),
+    }
+}
+
+/// Parses a number written in decimal or in hexadecimal with a leading '0x'.
+fn parse_number(value: &str) -> Option<u32> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    }
+}
@@ -0,0 +1,248 @@
+//! Persistent state of the objects created through the crate (chains, tags, leases and mark
+//! allocations), so that an application resumes managing them after a restart.
+//!
+//! The state is stored in JSON with the version of its schema (currently 1):
+//!
+//! ```json
+//! {
+//!   "version": 1,
+//!   "chains": [{ "table": "filter", "chain": "MYAPP-INPUT" }],
+//!   "tags": ["myapp"],
+//!   "leases": [{ "resource": "nflog-group", "value": "3", "owner": "myapp" }],
+//!   "marks": [{ "name": "vpn", "mask": 255 }]
+//! }
+//! ```
+
+use crate::mark::{MarkAllocator, MarkField};
+use crate::{error_from_str, try_lock, WAIT_INTERVAL};
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+/// Version of the schema written by `StateStore`.
+pub const STATE_VERSION: u32 = 1;
+
+/// A chain created by the application.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ManagedChain {
+    /// Table of the chain.
+    pub table: String,
+
+    /// Name of the chain.
+    pub chain: String,
+}
+
+/// A value of a resource (e.g. an NFLOG group or a port) leased to an owner.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lease {
+    /// Kind of the resource, e.g. 'nflog-group'.
+    pub resource: String,
+
+    /// The leased value.
+    pub value: String,
+
+    /// Owner of the lease, e.g. the name of a component.
+    pub owner: String,
+}
+
+/// Objects recorded in a `StateStore`.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct State {
+    /// Chains created by the application.
+    pub chains: Vec<ManagedChain>,
+
+    /// Tags used by the application, e.g. of a `LogRegistry` or of identity comments.
+    pub tags: Vec<String>,
+
+    /// Leases of resources.
+    pub leases: Vec<Lease>,
+
+    /// Bits of the mark allocated to the subsystems.
+    pub marks: Vec<MarkField>,
+}
+
+impl State {
+    /// Records the table/chain, returns `false` if it is already recorded.
+    pub fn add_chain(&mut self, table: &str, chain: &str) -> bool {
+        if self.has_chain(table, chain) {
+            return false;
+        }
+        self.chains.push(ManagedChain {
+            table: table.to_string(),
+            chain: chain.to_string(),
+        });
+        true
+    }
+
+    /// Checks if the table/chain is recorded.
+    pub fn has_chain(&self, table: &str, chain: &str) -> bool {
+        self.chains
+            .iter()
+            .any(|c| c.table == table && c.chain == chain)
+    }
+
+    /// Forgets the table/chain, returns `false` if it is not recorded.
+    pub fn remove_chain(&mut self, table: &str, chain: &str) -> bool {
+        let count = self.chains.len();
+        self.chains.retain(|c| c.table != table || c.chain != chain);
+        self.chains.len() != count
+    }
+
+    /// Records the `tag`, returns `false` if it is already recorded.
+    pub fn add_tag(&mut self, tag: &str) -> bool {
+        if self.tags.iter().any(|t| t == tag) {
+            return false;
+        }
+        self.tags.push(tag.to_string());
+        true
+    }
+
+    /// Forgets the `tag`, returns `false` if it is not recorded.
+    pub fn remove_tag(&mut self, tag: &str) -> bool {
+        let count = self.tags.len();
+        self.tags.retain(|t| t != tag);
+        self.tags.len() != count
+    }
+
+    /// Returns the value of the `resource` leased to `owner`, if any.
+    pub fn lease(&self, resource: &str, owner: &str) -> Option<&str> {
+        self.leases
+            .iter()
+            .find(|lease| lease.resource == resource && lease.owner == owner)
+            .map(|lease| lease.value.as_str())
+    }
+
+    /// Leases the `value` of the `resource` to `owner`, replacing its previous lease of the
+    /// resource. Fails if the value is leased to another owner.
+    pub fn set_lease(
+        &mut self,
+        resource: &str,
+        value: &str,
+        owner: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let taken = self.leases.iter().find(|lease| {
+            lease.resource == resource && lease.value == value && lease.owner != owner
+        });
+        if let Some(lease) = taken {
+            return Err(error_from_str(&format!(
+                "{} {} is leased to {}",
+                resource, value, lease.owner
+            )));
+        }
+        self.release(resource, owner);
+        self.leases.push(Lease {
+            resource: resource.to_string(),
+            value: value.to_string(),
+            owner: owner.to_string(),
+        });
+        Ok(())
+    }
+
+    /// Releases the lease of the `resource` of `owner`, returns `false` if it has none.
+    pub fn release(&mut self, resource: &str, owner: &str) -> bool {
+        let count = self.leases.len();
+        self.leases
+            .retain(|lease| lease.resource != resource || lease.owner != owner);
+        self.leases.len() != count
+    }
+
+    /// Returns an allocator of the mark where the recorded fields are allocated.
+    pub fn mark_allocator(&self) -> MarkAllocator {
+        MarkAllocator::from_fields(self.marks.clone())
+    }
+
+    /// Records the fields allocated by the `allocator`.
+    pub fn set_marks(&mut self, allocator: &MarkAllocator) {
+        self.marks = allocator.fields().to_vec();
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct StateFile {
+    version: u32,
+    #[serde(flatten)]
+    state: State,
+}
+
+/// Storage of a `State` in a JSON file, shared by the processes of an application.
+///
+/// Updates hold the exclusive lock of the file `<path>.lock` while they read, change and
+/// write the state (without the `lock` feature, the processes must not update the state
+/// concurrently), and the file is replaced atomically so it is never read half-written.
+pub struct StateStore {
+    path: PathBuf,
+}
+
+impl StateStore {
+    /// Creates the store of the state file `path`, which is created by the first update.
+    pub fn new<P: AsRef<Path>>(path: P) -> StateStore {
+        StateStore {
+            path: path.as_ref().to_path_buf(),
+        }
+    }
+
+    /// Returns the path of the state file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Loads the state, which is empty if the state file does not exist. States of a newer
+    /// version of the schema are rejected.
+    pub fn load(&self) -> Result<State, Box<dyn Error>> {
+        let data = match fs::read_to_string(&self.path) {
+            Ok(data) => data,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(State::default()),
+            Err(error) => return Err(error.into()),
+        };
+        let file: StateFile = serde_json::from_str(&data)?;
+        if file.version > STATE_VERSION {
+            return Err(error_from_str(&format!(
+                "unsupported version {} of the state in {}",
+                file.version,
+                self.path.display()
+            )));
+        }
+        Ok(file.state)
+    }
+
+    /// Loads the state, changes it with `update` and writes it back if it changed. Nothing is
+    /// written if `update` fails.
+    pub fn update<T, F>(&self, update: F) -> Result<T, Box<dyn Error>>
+    where
+        F: FnOnce(&mut State) -> Result<T, Box<dyn Error>>,
+    {
+        let mut lock_path = self.path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        // The lock is released when `_file_lock` goes out of scope
+        let _file_lock = loop {
+            match try_lock(&lock_path)? {
+                Some(file_lock) => break file_lock,
+                None => thread::sleep(WAIT_INTERVAL),
+            }
+        };
+
+        let mut state = self.load()?;
+        let loaded = state.clone();
+        let result = update(&mut state)?;
+        if state != loaded {
+            self.write(state)?;
+        }
+        Ok(result)
+    }
+
+    fn write(&self, state: State) -> Result<(), Box<dyn Error>> {
+        let file = StateFile {
+            version: STATE_VERSION,
+            state,
+        };
+        let mut temporary = self.path.as_os_str().to_owned();
+        temporary.push(".tmp");
+        fs::write(&temporary, serde_json::to_string_pretty(&file)?)?;
+        fs::rename(&temporary, &self.path)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+//! The `statistic` match, used to distribute connections between several rules.
+
+/// Precision used by iptables when printing probabilities.
+const PROBABILITY_DECIMALS: usize = 11;
+
+/// Options of the `statistic` match.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Statistic {
+    /// Matches packets randomly with the given probability (`--mode random --probability`).
+    Random(f64),
+
+    /// Matches one packet out of `every`, starting with `packet` (`--mode nth`).
+    Nth {
+        /// Number of packets in a round.
+        every: u32,
+
+        /// Index (starting from 0) of the matched packet in a round.
+        packet: u32,
+    },
+}
+
+impl Statistic {
+    /// Returns the arguments of the match, including the leading `-m statistic`.
+    pub fn args(&self) -> Vec<String> {
+        let mut args = vec!["-m".to_string(), "statistic".to_string()];
+        match self {
+            Statistic::Random(probability) => args.extend([
+                "--mode".to_string(),
+                "random".to_string(),
+                "--probability".to_string(),
+                format!("{:.*}", PROBABILITY_DECIMALS, probability),
+            ]),
+            Statistic::Nth { every, packet } => args.extend([
+                "--mode".to_string(),
+                "nth".to_string(),
+                "--every".to_string(),
+                every.to_string(),
+                "--packet".to_string(),
+                packet.to_string(),
+            ]),
+        }
+        args
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match *self {
+            Statistic::Random(probability) if !(probability > 0.0 && probability <= 1.0) => {
+                Err(format!("invalid probability {}", probability))
+            }
+            Statistic::Nth { every, packet } if every == 0 || packet >= every => Err(format!(
+                "invalid nth statistic (every {}, packet {})",
+                every, packet
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Computes the random matches of `n` consecutive rules sharing the packets equally, e.g.
+    /// the DNAT rules of a load balancer: the rule `i` (from 0) matches with the probability
+    /// `1 / (n - i)` of the packets left by the previous ones.
+    /// The last rule matches all remaining packets, so its match is `None`.
+    pub fn random_ladder(n: usize) -> Vec<Option<Statistic>> {
+        (0..n)
+            .map(|i| match n - i {
+                1 => None,
+                left => Some(Statistic::Random(1.0 / left as f64)),
+            })
+            .collect()
+    }
+
+    /// Computes the round-robin matches of `n` consecutive rules sharing the packets equally:
+    /// the rule `i` (from 0) matches one packet out of the `n - i` left by the previous ones.
+    /// The last rule matches all remaining packets, so its match is `None`.
+    pub fn nth_ladder(n: usize) -> Vec<Option<Statistic>> {
+        (0..n)
+            .map(|i| match n - i {
+                1 => None,
+                left => Some(Statistic::Nth {
+                    every: left as u32,
+                    packet: 0,
+                }),
+            })
+            .collect()
+    }
+}
+
+/// Computes the share of the packets matched by each rule of a ladder of random matches,
+/// given their probabilities (1 for a rule without `statistic` match).
+pub fn ladder_shares(probabilities: &[f64]) -> Vec<f64> {
+    let mut left = 1.0;
+    probabilities
+        .iter()
+        .map(|probability| {
+            let share = left * probability;
+            left -= share;
+            share
+        })
+        .collect()
+}
+
+/// Checks that a ladder of random matches, given their probabilities, shares all the packets
+/// equally between its rules.
+pub fn validate_ladder(probabilities: &[f64]) -> Result<(), String> {
+    let shares = ladder_shares(probabilities);
+    let expected = 1.0 / probabilities.len() as f64;
+    // Probabilities are printed by iptables with 11 decimals
+    let tolerance = 1e-9;
+    if let Some(i) = shares
+        .iter()
+        .position(|share| (share - expected).abs() > tolerance)
+    {
+        return Err(format!(
+            "rule {} of the ladder gets {:.4} of the packets instead of {:.4}",
+            i + 1,
+            shares[i],
+            expected
+        ));
+    }
+    Ok(())
+}
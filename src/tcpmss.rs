@@ -0,0 +1,54 @@
+//! Clamping of the TCP maximum segment size, usually required on PPPoE and VPN gateways.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::IPTables;
+use std::error::Error;
+
+/// The maximum segment size set by the TCPMSS target.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mss {
+    /// Clamps the MSS to the path MTU minus 40 bytes (`--clamp-mss-to-pmtu`).
+    ClampToPmtu,
+
+    /// Sets the MSS to the given value (`--set-mss`).
+    Set(u16),
+}
+
+impl Mss {
+    /// Returns the arguments of the target, without the leading `-j TCPMSS`.
+    pub fn args(&self) -> Vec<String> {
+        match self {
+            Mss::ClampToPmtu => vec!["--clamp-mss-to-pmtu".to_string()],
+            Mss::Set(mss) => vec!["--set-mss".to_string(), mss.to_string()],
+        }
+    }
+}
+
+/// Matches the TCP SYN packets, which carry the MSS option.
+fn syn_packets() -> RuleBuilder {
+    RuleBuilder::new()
+        .protocol("tcp")
+        .arg(&["-m", "tcp", "--tcp-flags", "SYN,RST", "SYN"])
+}
+
+impl IPTables {
+    /// Clamps the MSS of the TCP connections forwarded through `out_interface` to the path MTU.
+    /// Succeeds without doing anything if the rule already exists.
+    pub fn clamp_mss_to_pmtu(&self, out_interface: &str) -> Result<(), Box<dyn Error>> {
+        let rule = syn_packets()
+            .out_interface(out_interface)
+            .target(Target::Tcpmss(Mss::ClampToPmtu))
+            .build()?;
+        self.append_idempotent("mangle", "FORWARD", &rule)
+    }
+
+    /// Sets the MSS of the TCP connections forwarded through `interface` to `value`.
+    /// Succeeds without doing anything if the rule already exists.
+    pub fn set_mss(&self, interface: &str, value: u16) -> Result<(), Box<dyn Error>> {
+        let rule = syn_packets()
+            .out_interface(interface)
+            .target(Target::Tcpmss(Mss::Set(value)))
+            .build()?;
+        self.append_idempotent("mangle", "FORWARD", &rule)
+    }
+}
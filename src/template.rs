@@ -0,0 +1,136 @@
+//! Rulesets referencing variables (e.g. `${WAN_IF}`), so that a single definition can be
+//! applied to hosts which differ by their interfaces or networks.
+
+use crate::error_from_str;
+use std::collections::HashMap;
+use std::error::Error;
+
+#[cfg(not(feature = "parse-only"))]
+use crate::restore::RestoreOptions;
+#[cfg(not(feature = "parse-only"))]
+use crate::IPTables;
+
+/// A ruleset in the format of `iptables-save`, or any rules, referencing variables written
+/// `${NAME}`, whose names are made of ASCII letters, digits and `_`.
+///
+/// # Example
+/// ```
+/// use iptables::template::Template;
+/// use std::collections::HashMap;
+///
+/// let template = Template::new("-A INPUT -i ${WAN_IF} -s ${ADMIN_NET} -j ACCEPT").unwrap();
+/// let variables = HashMap::from([
+///     ("WAN_IF".to_string(), "eth0".to_string()),
+///     ("ADMIN_NET".to_string(), "10.0.0.0/8".to_string()),
+/// ]);
+/// assert_eq!(
+///     template.render(&variables).unwrap(),
+///     "-A INPUT -i eth0 -s 10.0.0.0/8 -j ACCEPT"
+/// );
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Template {
+    text: String,
+    variables: Vec<String>,
+}
+
+impl Template {
+    /// Parses the template, failing on malformed references (e.g. an unterminated `${`).
+    pub fn new(text: &str) -> Result<Template, Box<dyn Error>> {
+        let mut variables = Vec::new();
+        for (_, name) in references(text)? {
+            if !variables.iter().any(|variable| variable == name) {
+                variables.push(name.to_string());
+            }
+        }
+        Ok(Template {
+            text: text.to_string(),
+            variables,
+        })
+    }
+
+    /// Returns the names of the variables referenced by the template, in order of first use.
+    pub fn variables(&self) -> &[String] {
+        &self.variables
+    }
+
+    /// Checks that all the variables of the template are bound by `values`, reporting all the
+    /// unbound ones at once.
+    pub fn check(&self, values: &HashMap<String, String>) -> Result<(), Box<dyn Error>> {
+        let unbound = self
+            .variables
+            .iter()
+            .filter(|name| !values.contains_key(*name))
+            .map(String::as_str)
+            .collect::<Vec<_>>();
+        if !unbound.is_empty() {
+            return Err(error_from_str(&format!(
+                "unbound variables: {}",
+                unbound.join(", ")
+            )));
+        }
+        for name in &self.variables {
+            let value = &values[name];
+            // Values are single arguments, so they can't add options or rules
+            if value.is_empty()
+                || value.contains(|c: char| {
+                    c.is_whitespace() || c.is_control() || c == '"' || c == '\''
+                })
+            {
+                return Err(error_from_str(&format!(
+                    "invalid value {:?} of variable {}",
+                    value, name
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the references by the `values` of the variables, after checking them.
+    pub fn render(&self, values: &HashMap<String, String>) -> Result<String, Box<dyn Error>> {
+        self.check(values)?;
+        let mut rendered = String::with_capacity(self.text.len());
+        let mut end = 0;
+        for (start, name) in references(&self.text)? {
+            rendered.push_str(&self.text[end..start]);
+            rendered.push_str(&values[name]);
+            // Skips the '${', the name and the '}'
+            end = start + name.len() + 3;
+        }
+        rendered.push_str(&self.text[end..]);
+        Ok(rendered)
+    }
+}
+
+#[cfg(not(feature = "parse-only"))]
+impl IPTables {
+    /// Renders the `template` of a ruleset in the format of `iptables-save` with the `values`
+    /// of its variables, then restores it. Nothing is applied if a variable is unbound.
+    pub fn apply_template(
+        &self,
+        template: &Template,
+        values: &HashMap<String, String>,
+        options: RestoreOptions,
+    ) -> Result<(), Box<dyn Error>> {
+        self.restore(&template.render(values)?, options)
+    }
+}
+
+/// Returns the references of `text` with their offsets.
+fn references(text: &str) -> Result<Vec<(usize, &str)>, Box<dyn Error>> {
+    let mut references = Vec::new();
+    let mut offset = 0;
+    while let Some(start) = text[offset..].find("${").map(|start| start + offset) {
+        let end = text[start..]
+            .find('}')
+            .map(|end| end + start)
+            .ok_or_else(|| error_from_str("unterminated variable reference"))?;
+        let name = &text[start + 2..end];
+        if name.is_empty() || !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+            return Err(error_from_str(&format!("invalid variable name {:?}", name)));
+        }
+        references.push((start, name));
+        offset = end + 1;
+    }
+    Ok(references)
+}
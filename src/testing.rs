@@ -0,0 +1,200 @@
+//! Helpers for the tests of the crates using iptables: scratch chains with unique names which
+//! are removed when dropped, skipping the tests which need privileges, and throwaway network
+//! namespaces for destructive tests.
+
+use crate::error::IPTError;
+use crate::IPTables;
+use std::error::Error;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(target_os = "linux")]
+use crate::backend::{Backend, NetnsBackend, SystemBackend};
+#[cfg(target_os = "linux")]
+use crate::{check_output, probe_features};
+#[cfg(target_os = "linux")]
+use std::ffi::OsString;
+#[cfg(target_os = "linux")]
+use std::ops::Deref;
+#[cfg(target_os = "linux")]
+use std::sync::Arc;
+
+/// Maximum length of the name of a chain accepted by iptables.
+const MAX_CHAIN_NAME_LEN: usize = 28;
+
+/// Number of the next chain named by `unique_chain_name` in this process.
+static NEXT_CHAIN: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of the next namespace created by `sandbox` in this process.
+#[cfg(target_os = "linux")]
+static NEXT_NAMESPACE: AtomicUsize = AtomicUsize::new(0);
+
+/// Returns a chain name starting with `prefix`, unique across the processes of the host and
+/// the calls in this process, e.g. 'TEST-1a2b-0'. The prefix is truncated if needed.
+pub fn unique_chain_name(prefix: &str) -> String {
+    let suffix = format!(
+        "{:x}-{}",
+        process::id(),
+        NEXT_CHAIN.fetch_add(1, Ordering::Relaxed)
+    );
+    let prefix = prefix
+        .chars()
+        .take(MAX_CHAIN_NAME_LEN.saturating_sub(suffix.len()))
+        .collect::<String>();
+    format!("{}{}", prefix, suffix)
+}
+
+/// Returns a handle of iptables (or ip6tables if `is_ipv6` is `true`) if the process may
+/// change the rules, otherwise prints why the test is skipped and returns `None`, e.g.:
+///
+/// ```no_run
+/// let ipt = match iptables::testing::privileged_handle(false) {
+///     Some(ipt) => ipt,
+///     None => return,
+/// };
+/// ```
+pub fn privileged_handle(is_ipv6: bool) -> Option<IPTables> {
+    let ipt = match crate::new(is_ipv6) {
+        Ok(ipt) => ipt,
+        Err(error) => {
+            eprintln!("skipping test, iptables is not available: {}", error);
+            return None;
+        }
+    };
+    match ipt.run_checked(&["-t", "filter", "-S", "INPUT"]) {
+        Ok(_) => Some(ipt),
+        Err(error) => {
+            match error.downcast_ref::<IPTError>() {
+                Some(error) if error.is_permission() => {
+                    eprintln!("skipping test, missing privileges: {}", error)
+                }
+                _ => eprintln!("skipping test, unable to list the rules: {}", error),
+            }
+            None
+        }
+    }
+}
+
+/// A chain with a unique name created for a test, which is flushed and deleted when dropped.
+/// Rules of other chains jumping to it must be deleted before, otherwise it is left behind.
+pub struct ScratchChain<'a> {
+    ipt: &'a IPTables,
+    table: String,
+    name: String,
+}
+
+impl IPTables {
+    /// Creates a chain in the `table` named by `unique_chain_name` with the `prefix`.
+    pub fn scratch_chain(
+        &self,
+        table: &str,
+        prefix: &str,
+    ) -> Result<ScratchChain<'_>, Box<dyn Error>> {
+        let name = self.prefixed_chain(&unique_chain_name(prefix));
+        self.new_chain(table, &name)?;
+        Ok(ScratchChain {
+            ipt: self,
+            table: table.to_string(),
+            name,
+        })
+    }
+}
+
+impl ScratchChain<'_> {
+    /// Returns the table of the chain.
+    pub fn table(&self) -> &str {
+        &self.table
+    }
+
+    /// Returns the name of the chain.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+impl Drop for ScratchChain<'_> {
+    fn drop(&mut self) {
+        // Errors can't be reported from drop, and a failed test must not hide its own failure
+        let _ = self.ipt.flush_chain(&self.table, &self.name);
+        let _ = self.ipt.delete_chain(&self.table, &self.name);
+    }
+}
+
+/// A throwaway network namespace with a handle managing its firewall, which is deleted along
+/// with its rules when dropped. Destructive tests (e.g. flushing tables or changing policies)
+/// can run in it without touching the firewall of the host.
+#[cfg(target_os = "linux")]
+pub struct Sandbox {
+    ipt: IPTables,
+    backend: Arc<dyn Backend>,
+    namespace: String,
+}
+
+/// Creates a `Sandbox` with a handle of iptables (or ip6tables if `is_ipv6` is `true`).
+/// Fails if the network namespace can't be created, e.g. without privileges.
+#[cfg(target_os = "linux")]
+pub fn sandbox(is_ipv6: bool) -> Result<Sandbox, Box<dyn Error>> {
+    Sandbox::with_backend(Arc::new(SystemBackend), is_ipv6)
+}
+
+#[cfg(target_os = "linux")]
+impl Sandbox {
+    /// Creates a `Sandbox` like `sandbox`, running `ip` with the `backend`.
+    pub fn with_backend(
+        backend: Arc<dyn Backend>,
+        is_ipv6: bool,
+    ) -> Result<Sandbox, Box<dyn Error>> {
+        let namespace = format!(
+            "iptables-test-{:x}-{}",
+            process::id(),
+            NEXT_NAMESPACE.fetch_add(1, Ordering::Relaxed)
+        );
+        let args = ["netns", "add", &namespace].map(OsString::from);
+        check_output("ip", &args, backend.run("ip", &args, None, None)?)?;
+
+        let cmd = if is_ipv6 { "ip6tables" } else { "iptables" };
+        let netns = Arc::new(NetnsBackend::new(backend.clone(), &namespace));
+        let features = match probe_features(netns.as_ref(), cmd) {
+            Ok(features) => features,
+            Err(error) => {
+                delete_namespace(backend.as_ref(), &namespace);
+                return Err(error);
+            }
+        };
+        let mut ipt = IPTables::with_features(cmd, features);
+        ipt.set_backend(netns);
+        Ok(Sandbox {
+            ipt,
+            backend,
+            namespace,
+        })
+    }
+
+    /// Returns the name of the network namespace.
+    pub fn namespace(&self) -> &str {
+        &self.namespace
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Deref for Sandbox {
+    type Target = IPTables;
+
+    fn deref(&self) -> &IPTables {
+        &self.ipt
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Sandbox {
+    fn drop(&mut self) {
+        delete_namespace(self.backend.as_ref(), &self.namespace);
+    }
+}
+
+/// Deletes the network `namespace`, ignoring errors as it is only used for cleaning up.
+#[cfg(target_os = "linux")]
+fn delete_namespace(backend: &dyn Backend, namespace: &str) {
+    let args = ["netns", "delete", namespace].map(OsString::from);
+    let _ = backend.run("ip", &args, None, None);
+}
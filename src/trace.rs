@@ -0,0 +1,157 @@
+//! Tracing of packets through the chains with the TRACE target of the raw table, and parsing of
+//! the lines logged by the kernel for the traced packets.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::log_registry::log_fields;
+use crate::parse::{join_rule, Rule};
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+use std::io::{self, BufRead};
+
+/// Comment of the TRACE rules installed by `enable_trace`.
+const TRACE_COMMENT: &str = "ipt-rs:trace";
+
+/// Kind of a step of a traced packet.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TraceKind {
+    /// The packet matched a rule.
+    Rule,
+
+    /// The packet returned from a user-defined chain, at the end of it or by RETURN.
+    Return,
+
+    /// The policy of a built-in chain applied to the packet.
+    Policy,
+}
+
+/// A line logged by the kernel for a traced packet, e.g.
+/// 'TRACE: filter:INPUT:rule:3 IN=eth0 OUT= SRC=10.0.0.1 ...'.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceLine {
+    /// Table of the step.
+    pub table: String,
+
+    /// Chain of the step.
+    pub chain: String,
+
+    /// Kind of the step.
+    pub kind: TraceKind,
+
+    /// Position (starting from 1) of the matched rule in the chain, or the number of rules of
+    /// the chain plus one for the policies and the returns at the end of a chain.
+    pub position: u32,
+
+    /// Fields of the packet (e.g. ('SRC', '10.0.0.1')), in the order of the line. Flags such as
+    /// 'SYN' have an empty value.
+    pub fields: Vec<(String, String)>,
+}
+
+impl TraceLine {
+    /// Returns the value of the field `name` (e.g. 'SRC', 'DPT').
+    pub fn field(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parses a kernel log `line` (e.g. read from `dmesg` or the journal) of a traced packet, or
+/// returns `None` if it is not a trace line.
+pub fn parse_trace_line(line: &str) -> Option<TraceLine> {
+    let (_, trace) = line.split_once("TRACE: ")?;
+    let (step, fields) = trace.split_once(' ').unwrap_or((trace, ""));
+    let mut parts = step.split(':');
+    let (table, chain) = (parts.next()?, parts.next()?);
+    let kind = match parts.next()? {
+        "rule" => TraceKind::Rule,
+        "return" => TraceKind::Return,
+        "policy" => TraceKind::Policy,
+        _ => return None,
+    };
+    let position = parts.next()?.parse().ok()?;
+    if parts.next().is_some() {
+        return None;
+    }
+    Some(TraceLine {
+        table: table.to_string(),
+        chain: chain.to_string(),
+        kind,
+        position,
+        fields: log_fields(fields),
+    })
+}
+
+/// Parses the lines read from `reader`, skipping those which are not trace lines.
+pub fn parse_trace_lines<R: BufRead>(
+    reader: R,
+) -> impl Iterator<Item = Result<TraceLine, io::Error>> {
+    reader.lines().filter_map(|line| match line {
+        Ok(line) => parse_trace_line(&line).map(Ok),
+        Err(error) => Some(Err(error)),
+    })
+}
+
+impl IPTables {
+    /// Traces the packets matched by the `selector` (whose target is ignored) through all the
+    /// tables, by inserting TRACE rules first in the PREROUTING and OUTPUT chains of the raw
+    /// table. The selector must match some options so that the log is not flooded; a selector
+    /// matching an input interface is only installed in PREROUTING, and one matching an output
+    /// interface only in OUTPUT.
+    ///
+    /// With the legacy variant, the steps of the packets are logged by the kernel (see
+    /// `parse_trace_line`) once a logger is set for the family, e.g. with
+    /// `sysctl net.netfilter.nf_log.2=nf_log_ipv4` (10 and `nf_log_ipv6` for IPv6). With the
+    /// nft variant, they are printed by `xtables-monitor --trace` instead.
+    pub fn enable_trace(&self, selector: &RuleBuilder) -> Result<(), Box<dyn Error>> {
+        let args = selector
+            .clone()
+            .comment(TRACE_COMMENT)
+            .target(Target::Jump("TRACE".to_string()))
+            .args()?;
+        // '-m comment --comment ipt-rs:trace -j TRACE'
+        if args.len() == 6 {
+            return Err(error_from_str(
+                "the selector of the traced packets must match some options",
+            ));
+        }
+        selector.check_family(self)?;
+
+        let has = |options: &[&str]| args.iter().any(|arg| options.contains(&arg.as_str()));
+        let mut chains = Vec::new();
+        if !has(&["-o", "--out-interface"]) {
+            chains.push("PREROUTING");
+        }
+        if !has(&["-i", "--in-interface"]) {
+            chains.push("OUTPUT");
+        }
+        if chains.is_empty() {
+            return Err(error_from_str(
+                "the selector of the traced packets can't match both an input and an output \
+                 interface",
+            ));
+        }
+        let rule = join_rule(&args);
+        for chain in chains {
+            self.insert_idempotent("raw", chain, &rule, 1)?;
+        }
+        Ok(())
+    }
+
+    /// Deletes the TRACE rules installed by `enable_trace`, returning their number.
+    pub fn disable_trace(&self) -> Result<usize, Box<dyn Error>> {
+        let mut deleted = 0;
+        for chain in ["PREROUTING", "OUTPUT"] {
+            for rule in self.chain_rules("raw", chain)? {
+                let parsed = Rule::parse(&rule);
+                if parsed.target().as_deref() == Some("TRACE")
+                    && parsed.comment().as_deref() == Some(TRACE_COMMENT)
+                {
+                    self.delete("raw", chain, &rule)?;
+                    deleted += 1;
+                }
+            }
+        }
+        Ok(deleted)
+    }
+}
@@ -0,0 +1,95 @@
+//! Matching and rewriting of the TTL (IPv4) and hop limit (IPv6) of packets.
+
+use crate::builder::{RuleBuilder, Target};
+use crate::IPTables;
+use std::error::Error;
+
+/// Comparison of the TTL or hop limit of a packet (`-m ttl` or `-m hl`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtlMatch {
+    /// The value equals the given one.
+    Eq(u8),
+
+    /// The value is less than the given one.
+    Lt(u8),
+
+    /// The value is greater than the given one.
+    Gt(u8),
+}
+
+impl TtlMatch {
+    /// Returns the arguments of the match, with options prefixed by `prefix` ('ttl' or 'hl').
+    pub(crate) fn args(&self, prefix: &str) -> Vec<String> {
+        let (op, value) = match self {
+            TtlMatch::Eq(value) => ("eq", value),
+            TtlMatch::Lt(value) => ("lt", value),
+            TtlMatch::Gt(value) => ("gt", value),
+        };
+        vec![
+            "-m".to_string(),
+            prefix.to_string(),
+            format!("--{}-{}", prefix, op),
+            value.to_string(),
+        ]
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match self {
+            TtlMatch::Lt(0) => Err("a TTL can't be less than 0".to_string()),
+            TtlMatch::Gt(255) => Err("a TTL can't be greater than 255".to_string()),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Change of the TTL or hop limit of a packet (`-j TTL` or `-j HL`, only in the mangle table).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtlAction {
+    /// Sets the value.
+    Set(u8),
+
+    /// Increments the value by the given amount.
+    Inc(u8),
+
+    /// Decrements the value by the given amount.
+    Dec(u8),
+}
+
+impl TtlAction {
+    /// Returns the arguments of the target, without the leading `-j TTL` or `-j HL`, with
+    /// options prefixed by `prefix` ('ttl' or 'hl').
+    pub(crate) fn args(&self, prefix: &str) -> Vec<String> {
+        let (op, value) = match self {
+            TtlAction::Set(value) => ("set", value),
+            TtlAction::Inc(value) => ("inc", value),
+            TtlAction::Dec(value) => ("dec", value),
+        };
+        vec![format!("--{}-{}", prefix, op), value.to_string()]
+    }
+
+    pub(crate) fn validate(&self) -> Result<(), String> {
+        match self {
+            TtlAction::Inc(0) | TtlAction::Dec(0) => {
+                Err("a TTL can't be incremented or decremented by 0".to_string())
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+impl IPTables {
+    /// Sets the TTL (or the hop limit with ip6tables) of the packets sent through
+    /// `out_interface` to `ttl`, e.g. to hide the hosts behind a router.
+    /// Succeeds without doing anything if the rule already exists.
+    pub fn set_outgoing_ttl(&self, out_interface: &str, ttl: u8) -> Result<(), Box<dyn Error>> {
+        let target = match self.cmd {
+            "ip6tables" => Target::Hl(TtlAction::Set(ttl)),
+            _ => Target::Ttl(TtlAction::Set(ttl)),
+        };
+        let rule = RuleBuilder::new()
+            .out_interface(out_interface)
+            .target(target)
+            .build()?;
+        self.append_idempotent("mangle", "POSTROUTING", &rule)
+    }
+}
@@ -0,0 +1,276 @@
+//! Checks of rules against the live ruleset before applying them, reporting every issue at once
+//! instead of failing on the first error of iptables.
+
+use crate::kmod::{required_modules, target_module};
+use crate::parse::{normalize_rule, parse_rules, RuleSpec};
+use crate::IPTables;
+use std::collections::HashMap;
+use std::error::Error;
+use std::net::IpAddr;
+
+/// Targets of the standard extensions, which are not chains.
+const EXTENSION_TARGETS: [&str; 22] = [
+    "ACCEPT",
+    "CHECKSUM",
+    "CLASSIFY",
+    "CLUSTERIP",
+    "CONNMARK",
+    "CONNSECMARK",
+    "DROP",
+    "ECN",
+    "IDLETIMER",
+    "LED",
+    "MASQUERADE",
+    "NETMAP",
+    "NOTRACK",
+    "QUEUE",
+    "RATEEST",
+    "REDIRECT",
+    "RETURN",
+    "SECMARK",
+    "SYNPROXY",
+    "TEE",
+    "TPROXY",
+    "TRACE",
+];
+
+/// A rule which is going to be appended or inserted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PlannedRule {
+    /// Table of the rule.
+    pub table: String,
+
+    /// Chain of the rule.
+    pub chain: String,
+
+    /// The rule.
+    pub rule: String,
+
+    /// Position (starting from 1) where the rule is inserted, `None` if it is appended.
+    pub position: Option<i32>,
+}
+
+impl PlannedRule {
+    /// Plans to append `rule` to the table/chain.
+    pub fn append(table: &str, chain: &str, rule: &str) -> PlannedRule {
+        PlannedRule {
+            table: table.to_string(),
+            chain: chain.to_string(),
+            rule: rule.to_string(),
+            position: None,
+        }
+    }
+
+    /// Plans to insert `rule` at `position` in the table/chain.
+    pub fn insert(table: &str, chain: &str, rule: &str, position: i32) -> PlannedRule {
+        PlannedRule {
+            position: Some(position),
+            ..PlannedRule::append(table, chain, rule)
+        }
+    }
+}
+
+/// Kind of a `ValidationIssue`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IssueKind {
+    /// The rule can't be parsed, see `RuleSpec`.
+    InvalidRule,
+
+    /// The chain of the rule does not exist.
+    UnknownChain,
+
+    /// The target of the rule is neither a chain of the table nor a known target.
+    UnknownTarget,
+
+    /// A kernel module needed by a match or the target is not available.
+    MissingModule,
+
+    /// The rule uses addresses or options of the other family.
+    WrongFamily,
+
+    /// The insertion position is outside of the chain.
+    PositionOutOfRange,
+
+    /// The rule already exists in the chain, or is planned twice.
+    Duplicate,
+}
+
+/// An issue found by `IPTables::validate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValidationIssue {
+    /// Index of the rule in the validated rules.
+    pub index: usize,
+
+    /// Kind of the issue.
+    pub kind: IssueKind,
+
+    /// Description of the issue.
+    pub message: String,
+}
+
+impl IPTables {
+    /// Checks the `rules`, in order, against the current ruleset without changing it, taking
+    /// the planned rules into account (e.g. for the positions of the next ones). Returns all
+    /// the issues found, none if the rules can be applied.
+    pub fn validate(&self, rules: &[PlannedRule]) -> Result<Vec<ValidationIssue>, Box<dyn Error>> {
+        let is_ipv6 = self.cmd == "ip6tables";
+        let mut issues = Vec::new();
+        let mut tables: HashMap<String, HashMap<String, Vec<String>>> = HashMap::new();
+        for (index, planned) in rules.iter().enumerate() {
+            let mut issue = |kind, message: String| {
+                issues.push(ValidationIssue {
+                    index,
+                    kind,
+                    message,
+                })
+            };
+            let args = match RuleSpec::try_from(planned.rule.as_str()) {
+                Ok(spec) => spec.args().to_vec(),
+                Err(error) => {
+                    issue(IssueKind::InvalidRule, error.to_string());
+                    continue;
+                }
+            };
+
+            if !tables.contains_key(&planned.table) {
+                let chains = self.table_chains(&planned.table)?;
+                tables.insert(planned.table.clone(), chains);
+            }
+            let chains = tables.get_mut(&planned.table).unwrap();
+
+            if let Some(target) = target_of(&args) {
+                if !chains.contains_key(target)
+                    && !EXTENSION_TARGETS.contains(&target)
+                    && target_module(target, is_ipv6).is_none()
+                {
+                    issue(
+                        IssueKind::UnknownTarget,
+                        format!(
+                            "target {} is neither a chain of table {} nor a known target",
+                            target, planned.table
+                        ),
+                    );
+                }
+            }
+            for (required_by, module) in required_modules(&args, is_ipv6) {
+                if !self.module_available(&module) {
+                    issue(
+                        IssueKind::MissingModule,
+                        format!("{} requires kernel module {}", required_by, module),
+                    );
+                }
+            }
+            for (family, required_by) in families(&args) {
+                if family != is_ipv6 {
+                    issue(
+                        IssueKind::WrongFamily,
+                        format!("{} can't be used with {}", required_by, self.cmd),
+                    );
+                }
+            }
+
+            let rules = match chains.get_mut(&planned.chain) {
+                Some(rules) => rules,
+                None => {
+                    issue(
+                        IssueKind::UnknownChain,
+                        format!(
+                            "chain {} does not exist in table {}",
+                            planned.chain, planned.table
+                        ),
+                    );
+                    continue;
+                }
+            };
+            let rule = normalize_rule(&planned.rule);
+            if rules.contains(&rule) {
+                issue(
+                    IssueKind::Duplicate,
+                    format!("rule '{}' is already in chain {}", rule, planned.chain),
+                );
+            }
+            match planned.position {
+                Some(position) if position < 1 || position as usize > rules.len() + 1 => {
+                    issue(
+                        IssueKind::PositionOutOfRange,
+                        format!(
+                            "position {} is out of chain {} which has {} rules",
+                            position,
+                            planned.chain,
+                            rules.len()
+                        ),
+                    );
+                }
+                Some(position) => rules.insert(position as usize - 1, rule),
+                None => rules.push(rule),
+            }
+        }
+        Ok(issues)
+    }
+
+    /// Returns the chains of the `table` with their normalized rules.
+    fn table_chains(&self, table: &str) -> Result<HashMap<String, Vec<String>>, Box<dyn Error>> {
+        let lines = self.list_table(table)?;
+        let mut chains = HashMap::new();
+        for line in &lines {
+            let declared = line
+                .strip_prefix("-P ")
+                .or_else(|| line.strip_prefix("-N "))
+                .and_then(|declaration| declaration.split(' ').next());
+            if let Some(chain) = declared {
+                chains.insert(chain.to_string(), Vec::new());
+            }
+        }
+        for rule in parse_rules(&lines.join("\n")) {
+            chains
+                .entry(rule.chain)
+                .or_insert_with(Vec::new)
+                .push(normalize_rule(&rule.spec));
+        }
+        Ok(chains)
+    }
+}
+
+/// Returns the target (`-j`) or the chain which the rule goes to (`-g`).
+fn target_of(args: &[String]) -> Option<&str> {
+    args.iter()
+        .rposition(|arg| matches!(arg.as_str(), "-j" | "--jump" | "-g" | "--goto"))
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+/// Returns the addresses, matches and targets of the rule which only exist in ip6tables
+/// (`true`) or in iptables (`false`).
+fn families(args: &[String]) -> Vec<(bool, String)> {
+    let mut families = Vec::new();
+    for pair in args.windows(2) {
+        let (option, value) = (pair[0].as_str(), pair[1].as_str());
+        let family = match option {
+            "-s" | "--source" | "-d" | "--destination" => value
+                .split(',')
+                .filter_map(|address| address.split('/').next()?.parse::<IpAddr>().ok())
+                .map(|address| (address.is_ipv6(), format!("address {}", address)))
+                .next(),
+            "-p" | "--protocol" => match value {
+                "icmp" => Some((false, "protocol icmp".to_string())),
+                "ipv6-icmp" | "icmpv6" => Some((true, format!("protocol {}", value))),
+                _ => None,
+            },
+            "-m" | "--match" => match value {
+                "icmp" | "ttl" => Some((false, format!("match '{}'", value))),
+                "icmp6" | "hl" | "rt" | "frag" | "ipv6header" | "hbh" | "dst" | "mh" => {
+                    Some((true, format!("match '{}'", value)))
+                }
+                _ => None,
+            },
+            "-j" | "--jump" => match value {
+                "TTL" => Some((false, "target 'TTL'".to_string())),
+                "HL" | "SNPT" | "DNPT" => Some((true, format!("target '{}'", value))),
+                _ => None,
+            },
+            _ => None,
+        };
+        families.extend(family);
+    }
+    families
+}
@@ -0,0 +1,128 @@
+//! Detection of the netfilter backend (legacy or nf_tables) used by iptables.
+
+use crate::parse::{diff_tables, parse_save, SavedTable};
+use crate::{error_from_str, IPTables};
+use std::error::Error;
+use std::io::{self, ErrorKind};
+
+/// The netfilter backend which an iptables binary manipulates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Variant {
+    /// The legacy `ip_tables` kernel interface.
+    Legacy,
+
+    /// The nf_tables kernel interface (`iptables-nft`).
+    Nft,
+}
+
+impl Variant {
+    /// Returns the suffix used by the binaries of the variant (e.g. 'iptables-legacy-save').
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Variant::Legacy => "legacy",
+            Variant::Nft => "nft",
+        }
+    }
+
+    fn other(&self) -> Variant {
+        match self {
+            Variant::Legacy => Variant::Nft,
+            Variant::Nft => Variant::Legacy,
+        }
+    }
+}
+
+impl IPTables {
+    /// Returns the netfilter backend used by the iptables command.
+    /// Versions which do not report a backend are considered as legacy.
+    pub fn variant(&self) -> Result<Variant, Box<dyn Error>> {
+        let output = self.run_program_checked(self.cmd, &["--version"], None)?;
+        let version = String::from_utf8_lossy(output.stdout.as_slice());
+        if version.contains("(nf_tables)") {
+            Ok(Variant::Nft)
+        } else {
+            Ok(Variant::Legacy)
+        }
+    }
+
+    /// Checks the backend which is NOT used by the iptables command for rules.
+    /// Returns the tables of the other backend which contain rules; these rules are not applied
+    /// by this handle and usually are residue of another tool using a different backend.
+    pub fn detect_backend_conflicts(&self) -> Result<Vec<SavedTable>, Box<dyn Error>> {
+        let other = self.variant()?.other();
+        Ok(self
+            .save_variant(other)?
+            .into_iter()
+            .filter(|table| !table.rules.is_empty())
+            .collect())
+    }
+
+    /// Migrates the ruleset of the legacy backend to the nf_tables backend.
+    /// The legacy ruleset is replayed using `iptables-nft-restore`, which replaces the nf_tables
+    /// tables having the same names, and the result is verified to contain every legacy rule.
+    /// If `flush_legacy` is `true`, the legacy tables are flushed afterwards, their user-defined
+    /// chains are deleted and the policies of their built-in chains are reset to ACCEPT.
+    pub fn migrate_to_nft(&self, flush_legacy: bool) -> Result<(), Box<dyn Error>> {
+        let legacy_save = format!("{}-legacy-save", self.cmd);
+        let output = self.run_program_checked(&legacy_save, &[] as &[&str], None)?;
+        let ruleset = String::from_utf8_lossy(output.stdout.as_slice()).into_owned();
+        let legacy_tables = parse_save(&ruleset);
+        if legacy_tables.is_empty() {
+            return Ok(());
+        }
+
+        self.restore_ruleset_with(&format!("{}-nft-restore", self.cmd), &ruleset, &[])?;
+
+        let nft_tables = self.save_variant(Variant::Nft)?;
+        if let Some(diff) = diff_tables(&legacy_tables, &nft_tables)
+            .into_iter()
+            .find(|diff| !diff.removed_chains.is_empty() || !diff.removed_rules.is_empty())
+        {
+            return Err(error_from_str(&format!(
+                "table {} is incomplete after the migration",
+                diff.table
+            )));
+        }
+
+        if flush_legacy {
+            let legacy = format!("{}-legacy", self.cmd);
+            for table in legacy_tables.iter() {
+                let mut commands = vec![vec!["-F"], vec!["-X"]];
+                for chain in table.chains.iter().filter(|chain| chain.policy.is_some()) {
+                    commands.push(vec!["-P", &chain.name, "ACCEPT"]);
+                }
+                for args in commands {
+                    self.run_program_checked(
+                        &legacy,
+                        &[&["-t", &table.name], args.as_slice()].concat(),
+                        None,
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Dumps the ruleset of the given backend using its `-save` binary.
+    /// Returns an empty list if the binary of the backend is not installed.
+    pub(crate) fn save_variant(&self, variant: Variant) -> Result<Vec<SavedTable>, Box<dyn Error>> {
+        let cmd = format!("{}-{}-save", self.cmd, variant.as_str());
+        let output = match self.run_program(&cmd, &[] as &[&str], None) {
+            Ok(output) => output,
+            Err(e)
+                if e.downcast_ref::<io::Error>().map(io::Error::kind)
+                    == Some(ErrorKind::NotFound) =>
+            {
+                return Ok(Vec::new())
+            }
+            Err(e) => return Err(e),
+        };
+        if !output.status.success() {
+            return Err(error_from_str(&format!("unable to run {}", cmd)));
+        }
+        Ok(parse_save(&String::from_utf8_lossy(
+            output.stdout.as_slice(),
+        )))
+    }
+}
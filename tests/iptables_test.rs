@@ -1,3 +1,5 @@
+#![cfg(not(feature = "parse-only"))]
+
 extern crate iptables;
 
 use std::panic;
@@ -11,23 +13,13 @@ fn test_new() {
 #[test]
 fn test_old() {
     nat(
-        iptables::IPTables {
-            cmd: "iptables",
-            has_wait: false,
-            has_check: false,
-            is_numeric: false,
-        },
+        iptables::IPTables::with_features("iptables", iptables::Features::default()),
         "NATOLD",
         "NATOLD2",
     );
 
     filter(
-        iptables::IPTables {
-            cmd: "iptables",
-            has_wait: false,
-            has_check: false,
-            is_numeric: false,
-        },
+        iptables::IPTables::with_features("iptables", iptables::Features::default()),
         "FILTEROLD",
     );
 }
@@ -161,6 +153,16 @@ fn test_get_policy() {
     assert!(ipt.get_policy("filter", "_").is_err());
 }
 
+#[test]
+fn test_builtin_chains() {
+    let ipt = iptables::new(false).unwrap();
+    assert_eq!(
+        ipt.builtin_chains("nat").unwrap(),
+        vec!["PREROUTING", "INPUT", "OUTPUT", "POSTROUTING"]
+    );
+    assert!(ipt.builtin_chains("not_existant").is_err());
+}
+
 #[test]
 #[ignore]
 fn test_set_policy() {
@@ -168,19 +170,3443 @@ fn test_set_policy() {
 
     // Since we can only set policies on built-in chains, we have to retain the policy of the chain
     // before setting it, to restore it to its original state.
-    let current_policy = ipt.get_policy("mangle", "FORWARD").unwrap();
+    let current_policy = ipt
+        .set_policy("mangle", "FORWARD", iptables::Policy::Drop)
+        .unwrap();
 
     // If the following assertions fail or any other panic occurs, we still have to ensure not to
     // change the policy for the user.
     let result = panic::catch_unwind(|| {
-        assert!(ipt.set_policy("mangle", "FORWARD", "DROP").is_ok());
         assert_eq!(ipt.get_policy("mangle", "FORWARD").unwrap(), "DROP");
+        assert_eq!(
+            ipt.set_policy("mangle", "FORWARD", iptables::Policy::Drop)
+                .unwrap(),
+            iptables::Policy::Drop
+        );
     });
 
     // Reset the policy to the retained value
-    ipt.set_policy("mangle", "FORWARD", &current_policy)
-        .unwrap();
+    ipt.set_policy("mangle", "FORWARD", current_policy).unwrap();
 
     // "Rethrow" a potential caught panic
     assert!(result.is_ok());
 }
+
+#[test]
+fn test_idempotent() {
+    let ipt = iptables::new(false).unwrap();
+    assert!(ipt.new_chain("filter", "IDEMPOTENTTEST").is_ok());
+    assert!(ipt.append("filter", "IDEMPOTENTTEST", "-j DROP").is_ok());
+    assert!(ipt
+        .append_idempotent("filter", "IDEMPOTENTTEST", "-j DROP")
+        .is_ok());
+    assert_eq!(ipt.list("filter", "IDEMPOTENTTEST").unwrap().len(), 2);
+    assert!(ipt.delete("filter", "IDEMPOTENTTEST", "-j DROP").is_ok());
+    assert!(ipt
+        .delete_idempotent("filter", "IDEMPOTENTTEST", "-j DROP")
+        .is_ok());
+    assert_eq!(ipt.list("filter", "IDEMPOTENTTEST").unwrap().len(), 1);
+    assert!(ipt.delete_chain("filter", "IDEMPOTENTTEST").is_ok());
+}
+
+#[test]
+fn test_os_args() {
+    let ipt = iptables::new(false).unwrap();
+    let rule = ["-m", "comment", "--comment", "os comment", "-j", "ACCEPT"];
+    assert!(ipt.new_chain("filter", "OSTEST").is_ok());
+    assert!(ipt.append_os("filter", "OSTEST", &rule).is_ok());
+    assert!(ipt.exists_os("filter", "OSTEST", &rule).unwrap());
+    assert!(ipt.delete_os("filter", "OSTEST", &rule).is_ok());
+    assert!(!ipt.exists_os("filter", "OSTEST", &rule).unwrap());
+    assert!(ipt.delete_chain("filter", "OSTEST").is_ok());
+}
+
+#[test]
+fn test_find_rules() {
+    let ipt = iptables::new(false).unwrap();
+    assert!(ipt.new_chain("filter", "FINDTEST").is_ok());
+    assert!(ipt.append("filter", "FINDTEST", "-j ACCEPT").is_ok());
+    assert!(ipt
+        .append(
+            "filter",
+            "FINDTEST",
+            "-m comment --comment \"find me\" -j ACCEPT"
+        )
+        .is_ok());
+    let found = ipt
+        .find_rules("filter", |rule| {
+            rule.chain == "FINDTEST" && rule.spec.contains("find me")
+        })
+        .unwrap();
+    assert_eq!(found.len(), 1);
+    assert_eq!(found[0].position, 2);
+    assert!(ipt.flush_chain("filter", "FINDTEST").is_ok());
+    assert!(ipt.delete_chain("filter", "FINDTEST").is_ok());
+}
+
+#[test]
+fn test_rule_handle() {
+    let ipt = iptables::new(false).unwrap();
+    assert!(ipt.new_chain("filter", "HANDLETEST").is_ok());
+
+    let mut handle = ipt
+        .append_handle(
+            "filter",
+            "HANDLETEST",
+            "-m comment --comment handle-test -j ACCEPT",
+        )
+        .unwrap();
+    assert_eq!(handle.position(), 1);
+    let tag = handle.tag().unwrap().to_string();
+    assert_eq!(
+        iptables::identity::identity_of(handle.rule()),
+        Some(tag.as_str())
+    );
+    assert!(handle.exists().unwrap());
+    assert!(handle
+        .replace("-m comment --comment handle-test -j DROP")
+        .is_ok());
+    assert_eq!(handle.tag(), Some(tag.as_str()));
+    assert!(handle.rule().ends_with("-j DROP"));
+    assert!(handle.exists().unwrap());
+    assert!(handle.delete().is_ok());
+
+    assert!(ipt.delete_chain("filter", "HANDLETEST").is_ok());
+}
+
+#[test]
+fn test_references_to() {
+    let ipt = iptables::new(false).unwrap();
+    assert!(ipt.new_chain("filter", "REFTARGET").is_ok());
+    assert!(ipt.new_chain("filter", "REFSOURCE").is_ok());
+    assert!(ipt.append("filter", "REFSOURCE", "-j ACCEPT").is_ok());
+    assert!(ipt.append("filter", "REFSOURCE", "-j REFTARGET").is_ok());
+    assert!(ipt.append("filter", "REFSOURCE", "-g REFTARGET").is_ok());
+
+    assert_eq!(
+        ipt.references_to("filter", "REFTARGET").unwrap(),
+        vec![("REFSOURCE".to_string(), 2), ("REFSOURCE".to_string(), 3)]
+    );
+    assert!(ipt.references_to("filter", "REFSOURCE").unwrap().is_empty());
+    let chains = ipt.list_chain_names_fast("filter").unwrap();
+    assert!(chains.iter().any(|chain| chain == "REFSOURCE"));
+    let graph = ipt.chain_graph("filter").unwrap();
+    assert_eq!(graph.jumps_from("REFSOURCE").count(), 2);
+    assert!(graph.find_cycle().is_none());
+    assert_eq!(ipt.counters("filter", "REFSOURCE").unwrap().len(), 3);
+
+    assert!(ipt.flush_chain("filter", "REFSOURCE").is_ok());
+    assert!(ipt.delete_chain("filter", "REFSOURCE").is_ok());
+    assert!(ipt.delete_chain("filter", "REFTARGET").is_ok());
+}
+
+#[test]
+fn test_blue_green() {
+    use iptables::blue_green::Color;
+
+    let ipt = iptables::new(false).unwrap();
+    let mut blue_green = ipt.blue_green("filter", "BGTEST").unwrap();
+    assert_eq!(blue_green.active(), Color::Blue);
+    assert!(blue_green.load_inactive(&["-j DROP".to_string()]).is_ok());
+    assert_eq!(blue_green.switch_active().unwrap(), Color::Green);
+    assert!(ipt.exists("filter", "BGTEST", "-j BGTEST-GREEN").unwrap());
+    assert!(ipt.exists("filter", "BGTEST-GREEN", "-j DROP").unwrap());
+    assert_eq!(
+        ipt.blue_green("filter", "BGTEST").unwrap().active(),
+        Color::Green
+    );
+
+    for chain in ["BGTEST", "BGTEST-BLUE", "BGTEST-GREEN"] {
+        assert!(ipt.flush_chain("filter", chain).is_ok());
+        assert!(ipt.delete_chain("filter", chain).is_ok());
+    }
+}
+
+#[test]
+fn test_canary_apply() {
+    use std::time::Duration;
+
+    let ipt = iptables::new(false).unwrap();
+    assert!(ipt.new_chain("filter", "CANARYTEST").is_ok());
+    let report = ipt
+        .canary_apply(
+            "filter",
+            "CANARYTEST",
+            &["-s 192.0.2.1/32 -j DROP", "-p tcp --dport 23 -j REJECT"],
+            Duration::from_millis(10),
+        )
+        .unwrap();
+    assert_eq!(report.rules.len(), 2);
+    assert_eq!(report.rules[0].0, "-s 192.0.2.1/32 -j DROP");
+    assert_eq!(report.affecting().count(), 0);
+    assert!(!ipt.chain_exists("filter", "CANARYTEST-CANARY").unwrap());
+    assert!(ipt.delete_chain("filter", "CANARYTEST").is_ok());
+}
+
+#[test]
+fn test_identity() {
+    let rule =
+        "-s 10.0.0.1/32 -m comment --comment ipt-rs:0f8fad5b-d9cb-469f-a165-70867728950e -j ACCEPT";
+
+    assert_eq!(
+        iptables::identity::identity_of(rule),
+        Some("0f8fad5b-d9cb-469f-a165-70867728950e")
+    );
+    assert_eq!(
+        iptables::identity::strip_identity(rule),
+        "-s 10.0.0.1/32 -j ACCEPT"
+    );
+    assert_eq!(iptables::identity::identity_of("-j ACCEPT"), None);
+}
+
+#[test]
+fn test_apply_and_probe() {
+    let ipt = iptables::new(false).unwrap();
+
+    let applied = ipt
+        .apply_and_probe(|ipt| ipt.new_chain("filter", "PROBETEST"), || false)
+        .unwrap();
+    assert!(!applied);
+    assert!(!ipt.chain_exists("filter", "PROBETEST").unwrap());
+
+    let applied = ipt
+        .apply_and_probe(|ipt| ipt.new_chain("filter", "PROBETEST"), || true)
+        .unwrap();
+    assert!(applied);
+    assert!(ipt.chain_exists("filter", "PROBETEST").unwrap());
+    assert!(ipt.delete_chain("filter", "PROBETEST").is_ok());
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_backend() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features(
+        "iptables",
+        iptables::Features {
+            check: true,
+            wait: true,
+            restore_wait: true,
+        },
+    );
+    ipt.set_backend(backend.clone());
+
+    backend.push_output(1, "", "iptables: Bad rule.");
+    assert!(!ipt.exists("filter", "INPUT", "-j ACCEPT").unwrap());
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_ok());
+    assert_eq!(
+        backend.calls()[1],
+        vec!["iptables", "-t", "filter", "-A", "INPUT", "-j", "ACCEPT", "--wait"]
+    );
+}
+
+#[cfg(all(feature = "test-backend", feature = "monitor"))]
+#[test]
+fn test_mock_monitor() {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j DROP\nCOMMIT\n",
+        "",
+    );
+    backend.push_output(0, "iptables v1.8.7 (legacy)\n", "");
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j ACCEPT\nCOMMIT\n",
+        "",
+    );
+
+    let mut monitor = ipt.monitor(Duration::from_millis(1)).unwrap();
+    let event = monitor.next().unwrap().unwrap();
+    assert_eq!(event.table, "filter");
+    assert_eq!(event.chain, "INPUT");
+    assert_eq!(event.added, vec!["-j ACCEPT"]);
+    assert_eq!(event.removed, vec!["-j DROP"]);
+
+    // The ruleset is polled on the nf_tables backend too, as `nft monitor` is not local
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j DROP\nCOMMIT\n",
+        "",
+    );
+    backend.push_output(0, "iptables v1.8.7 (nf_tables)\n", "");
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j REJECT\nCOMMIT\n",
+        "",
+    );
+    let mut monitor = ipt.monitor(Duration::from_millis(1)).unwrap();
+    let event = monitor.next().unwrap().unwrap();
+    assert_eq!(event.added, vec!["-j REJECT"]);
+    assert_eq!(backend.calls().len(), 6);
+}
+
+#[cfg(feature = "nflog")]
+#[test]
+fn test_nflog() {
+    let ipt = iptables::new(false).unwrap();
+    let rule = "-d 127.0.0.2/32 -p udp --dport 9";
+    let mut packets = ipt
+        .append_nflog("filter", "OUTPUT", rule, 42, "nflog test")
+        .unwrap();
+
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.send_to(b"x", "127.0.0.2:9").unwrap();
+    let packet = packets.next().unwrap().unwrap();
+    assert_eq!(packet.prefix, "nflog test");
+    assert_eq!(packet.out_interface.as_deref(), Some("lo"));
+    assert_eq!(packet.length, Some(29));
+
+    assert!(ipt
+        .delete(
+            "filter",
+            "OUTPUT",
+            &format!(
+                "{} -j NFLOG --nflog-group 42 --nflog-prefix \"nflog test\"",
+                rule
+            )
+        )
+        .is_ok());
+}
+
+#[test]
+fn test_nfqueue_builder() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::nfqueue::{Nfqueue, Queue};
+
+    let mut nfqueue = Nfqueue::new(Queue::Balance(2, 4));
+    nfqueue.fail_open = true;
+    assert_eq!(
+        RuleBuilder::new()
+            .protocol("udp")
+            .dport(53)
+            .target(Target::Nfqueue(nfqueue))
+            .build()
+            .unwrap(),
+        "-p udp --dport 53 -j NFQUEUE --queue-balance 2:4 --queue-bypass"
+    );
+    assert!(RuleBuilder::new()
+        .target(Target::Nfqueue(Nfqueue::new(Queue::Balance(4, 2))))
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_audit_target() {
+    use iptables::builder::{AuditType, RuleBuilder, Target};
+
+    assert_eq!(
+        RuleBuilder::new()
+            .source("192.0.2.0/24")
+            .target(Target::Audit(AuditType::Drop))
+            .build()
+            .unwrap(),
+        "-s 192.0.2.0/24 -j AUDIT --type drop"
+    );
+}
+
+#[test]
+fn test_statistic_ladder() {
+    use iptables::builder::RuleBuilder;
+    use iptables::statistic::{validate_ladder, Statistic};
+
+    let ladder = Statistic::random_ladder(3);
+    assert_eq!(ladder[0], Some(Statistic::Random(1.0 / 3.0)));
+    assert_eq!(ladder[1], Some(Statistic::Random(0.5)));
+    assert_eq!(ladder[2], None);
+    assert!(validate_ladder(&[1.0 / 3.0, 0.5, 1.0]).is_ok());
+    assert!(validate_ladder(&[1.0 / 3.0, 1.0 / 3.0, 1.0]).is_err());
+
+    assert_eq!(
+        Statistic::nth_ladder(2)[0],
+        Some(Statistic::Nth {
+            every: 2,
+            packet: 0
+        })
+    );
+    assert_eq!(
+        RuleBuilder::new()
+            .statistic(ladder[0].unwrap())
+            .build()
+            .unwrap(),
+        "-m statistic --mode random --probability 0.33333333333"
+    );
+    assert!(RuleBuilder::new()
+        .statistic(Statistic::Random(1.5))
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_ct_target() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::conntrack::Ct;
+
+    let ct = Ct {
+        zone: Some(7),
+        helper: Some("ftp".to_string()),
+    };
+    assert_eq!(
+        RuleBuilder::new()
+            .in_interface("vlan7")
+            .protocol("tcp")
+            .dport(21)
+            .target(Target::Ct(ct))
+            .build()
+            .unwrap(),
+        "-i vlan7 -p tcp --dport 21 -j CT --zone 7 --helper ftp"
+    );
+    let ct = Ct {
+        zone: None,
+        helper: Some(String::new()),
+    };
+    assert!(RuleBuilder::new().target(Target::Ct(ct)).build().is_err());
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_nfqueue() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    backend.push_output(
+        0,
+        "-P INPUT ACCEPT\n-A INPUT -p udp -j NFQUEUE --queue-num 3\n\
+         -A INPUT -j NFQUEUE --queue-balance 2:4 --queue-bypass\n-A INPUT -j NFQUEUE\n",
+        "",
+    );
+    assert_eq!(ipt.queues_in_use("filter").unwrap(), vec![0, 2, 3, 4]);
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_mss() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    assert!(ipt.clamp_mss_to_pmtu("ppp0").is_ok());
+    assert!(ipt.set_mss("wg0", 1380).is_ok());
+
+    // Each helper lists the chain (old versions have no -C) then appends the rule
+    let calls = backend.calls();
+    assert_eq!(
+        calls[1][1..].join(" "),
+        "-t mangle -A FORWARD -p tcp -m tcp --tcp-flags SYN,RST SYN -o ppp0 \
+         -j TCPMSS --clamp-mss-to-pmtu"
+    );
+    assert_eq!(
+        calls[3][1..].join(" "),
+        "-t mangle -A FORWARD -p tcp -m tcp --tcp-flags SYN,RST SYN -o wg0 \
+         -j TCPMSS --set-mss 1380"
+    );
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_add_set() {
+    use iptables::builder::RuleBuilder;
+    use iptables::ipset::IpSet;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    let mut set = IpSet::new("banned", "hash:ip");
+    set.timeout = Some(600);
+    let matches = RuleBuilder::new().protocol("tcp").dport(23);
+    assert!(ipt
+        .append_add_set("filter", "INPUT", matches.clone(), &set, "src")
+        .is_ok());
+    assert!(ipt
+        .append_add_set("filter", "INPUT", matches, &set, "source")
+        .is_err());
+
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(
+        calls[0].join(" "),
+        "ipset -exist create banned hash:ip family inet timeout 600"
+    );
+    assert_eq!(
+        calls[1][1..].join(" "),
+        "-t filter -A INPUT -p tcp --dport 23 -j SET --add-set banned src --exist --timeout 600"
+    );
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_nat_pool() {
+    use iptables::builder::RuleBuilder;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features(
+        "iptables",
+        iptables::Features {
+            check: true,
+            wait: true,
+            restore_wait: true,
+        },
+    );
+    ipt.set_backend(backend.clone());
+
+    let mut pool = ipt
+        .nat_pool("WEBPOOL", RuleBuilder::new().protocol("tcp"))
+        .unwrap();
+    pool.add_backend("10.0.0.1:80").unwrap();
+    pool.add_backend("10.0.0.2:80").unwrap();
+    pool.add_backend("10.0.0.3:80").unwrap();
+    pool.set_healthy("10.0.0.2:80", false).unwrap();
+    assert_eq!(pool.healthy_backends(), vec!["10.0.0.1:80", "10.0.0.3:80"]);
+    assert!(pool.set_healthy("10.0.0.4:80", false).is_err());
+
+    let calls = backend.calls();
+    let restore = calls.last().unwrap();
+    assert_eq!(restore[..], ["iptables-restore", "--noflush", "--wait"]);
+    assert_eq!(
+        backend.inputs().last().unwrap().as_deref(),
+        Some(
+            "*nat\n:WEBPOOL - [0:0]\n-F WEBPOOL\n\
+             -A WEBPOOL -p tcp -m statistic --mode random --probability 0.50000000000 \
+             -j DNAT --to-destination 10.0.0.1:80\n\
+             -A WEBPOOL -p tcp -j DNAT --to-destination 10.0.0.3:80\nCOMMIT\n"
+        )
+    );
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_get_policy() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    backend.push_output(0, "-P INPUT DROP\n-A INPUT -j ACCEPT\n", "");
+    assert_eq!(ipt.get_policy("filter", "INPUT").unwrap(), "DROP");
+
+    backend.push_output(0, "", "");
+    backend.push_output(
+        0,
+        "Chain INPUT (policy ACCEPT)\ntarget     prot opt source               destination\n",
+        "",
+    );
+    assert_eq!(ipt.get_policy("filter", "INPUT").unwrap(), "ACCEPT");
+    assert_eq!(
+        backend.calls()[2][1..],
+        ["-t", "filter", "-L", "INPUT", "-n"]
+    );
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_command_error() {
+    use iptables::error::{IPTError, IptablesError};
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    backend.push_output(1, "", "iptables: Chain already exists.\n");
+    let error = ipt.new_chain("nat", "MYCHAIN").unwrap_err();
+    match error.downcast_ref::<IPTError>() {
+        Some(IPTError::Command {
+            operation,
+            command,
+            source,
+        }) => {
+            assert_eq!(operation, "new chain nat MYCHAIN");
+            assert_eq!(command, "iptables -t nat -N MYCHAIN");
+            assert_eq!(source.downcast_ref::<IptablesError>().unwrap().code, 1);
+        }
+        _ => panic!("unexpected error: {}", error),
+    }
+
+    backend.push_output(2, "", "Bad argument\n");
+    let error = ipt
+        .append(
+            "nat",
+            "MYCHAIN",
+            "-m comment --comment \"web server\" -j ACCEPT",
+        )
+        .unwrap_err();
+    assert!(error.to_string().starts_with(
+        "append nat MYCHAIN failed (iptables -t nat -A MYCHAIN -m comment --comment \
+         \"web server\" -j ACCEPT)"
+    ));
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_error_classification() {
+    use iptables::error::IPTError;
+    use iptables::retry::RetryPolicy;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    ipt.set_retry_policy(RetryPolicy::never());
+
+    let classify = |stderr: &str| {
+        backend.push_output(1, "", stderr);
+        let error = ipt.flush_chain("filter", "MYCHAIN").unwrap_err();
+        let error = error.downcast_ref::<IPTError>().unwrap();
+        (
+            error.is_retryable(),
+            error.is_permission(),
+            error.is_not_found(),
+        )
+    };
+    assert_eq!(
+        classify("iptables: No chain/target/match by that name.\n"),
+        (false, false, true)
+    );
+    assert_eq!(
+        classify("iptables v1.8.7 (legacy): can't initialize iptables table `filter': Permission denied (you must be root)\n"),
+        (false, true, false)
+    );
+    assert_eq!(
+        classify("Another app is currently holding the xtables lock. Perhaps you want to use the -w option?\n"),
+        (true, false, false)
+    );
+    assert_eq!(classify("iptables: Bad argument\n"), (false, false, false));
+    assert!(IPTError::Timeout(std::time::Duration::from_secs(1)).is_retryable());
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_error_conversions() {
+    use iptables::error::IPTError;
+    use std::io;
+    use std::process::ExitCode;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    backend.push_output(
+        2,
+        "",
+        "iptables v1.8.7 (legacy): unknown option \"--bad\"\n",
+    );
+    let error = ipt.append("filter", "INPUT", "--bad").unwrap_err();
+    let error = *error.downcast::<IPTError>().unwrap();
+    assert_eq!(error.exit_code(), ExitCode::from(2));
+    assert_eq!(io::Error::from(error).kind(), io::ErrorKind::Other);
+
+    backend.push_output(4, "", "iptables: Permission denied (you must be root).\n");
+    let error = ipt.append("filter", "INPUT", "-j ACCEPT").unwrap_err();
+    let error = *error.downcast::<IPTError>().unwrap();
+    assert_eq!(error.exit_code(), ExitCode::from(77));
+    assert_eq!(
+        io::Error::from(error).kind(),
+        io::ErrorKind::PermissionDenied
+    );
+
+    let error = IPTError::Timeout(std::time::Duration::from_secs(1));
+    assert_eq!(error.exit_code(), ExitCode::from(75));
+    assert_eq!(io::Error::from(error).kind(), io::ErrorKind::TimedOut);
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_policy_counters() {
+    use iptables::parse::Counters;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let save = "*filter\n:INPUT DROP [1520:98342]\n:FORWARD ACCEPT [0:0]\n:MYCHAIN - [0:0]\n\
+                -A INPUT -j MYCHAIN\nCOMMIT\n";
+    backend.push_output(0, save, "");
+    backend.push_output(0, save, "");
+    assert_eq!(
+        ipt.get_policy_counters("filter", "INPUT").unwrap(),
+        Counters {
+            packets: 1520,
+            bytes: 98342
+        }
+    );
+    assert!(ipt.get_policy_counters("filter", "MYCHAIN").is_err());
+    assert_eq!(backend.calls()[0].join(" "), "iptables-save -t filter");
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_restore_counters() {
+    use iptables::restore::RestoreOptions;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features(
+        "iptables",
+        iptables::Features {
+            check: true,
+            wait: true,
+            restore_wait: true,
+        },
+    );
+    ipt.set_backend(backend.clone());
+    let save = "*filter\n:INPUT ACCEPT [10:600]\n[3:180] -A INPUT -j ACCEPT\nCOMMIT\n";
+    backend.push_output(0, save, "");
+    let snapshot = ipt.save_with_counters().unwrap();
+    assert_eq!(snapshot, save);
+
+    let options = RestoreOptions {
+        counters: true,
+        flush: false,
+        ..Default::default()
+    };
+    assert!(ipt.restore(&snapshot, options).is_ok());
+    assert!(ipt.restore(&snapshot, RestoreOptions::default()).is_ok());
+
+    let calls = backend.calls();
+    assert_eq!(calls[0], ["iptables-save", "--counters"]);
+    assert_eq!(
+        calls[1],
+        ["iptables-restore", "--counters", "--noflush", "--wait"]
+    );
+    assert_eq!(calls[2], ["iptables-restore", "--wait"]);
+
+    let options = RestoreOptions {
+        table: Some("nat".to_string()),
+        ..Default::default()
+    };
+    assert!(ipt.restore(&snapshot, options).is_ok());
+    assert_eq!(
+        backend.calls()[3],
+        ["iptables-restore", "--table", "nat", "--wait"]
+    );
+    assert_eq!(backend.inputs()[1].as_deref(), Some(save));
+    assert_eq!(
+        iptables::parse::parse_save(&snapshot)[0].rules,
+        vec!["-A INPUT -j ACCEPT"]
+    );
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_restore_table() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features(
+        "iptables",
+        iptables::Features {
+            check: true,
+            wait: true,
+            restore_wait: true,
+        },
+    );
+    ipt.set_backend(backend.clone());
+    let data = "# Generated by iptables-save\n*filter\n:INPUT DROP [0:0]\nCOMMIT\n\
+                *nat\n:PREROUTING ACCEPT [0:0]\n-A PREROUTING -j DNAT --to-destination 10.0.0.1\n\
+                COMMIT\n";
+    assert!(ipt.restore_table("nat", data).is_ok());
+    assert!(ipt.restore_table("mangle", data).is_err());
+    assert_eq!(backend.calls().len(), 1);
+    assert_eq!(
+        backend.inputs()[0].as_deref(),
+        Some(
+            "*nat\n:PREROUTING ACCEPT [0:0]\n-A PREROUTING -j DNAT --to-destination 10.0.0.1\n\
+             COMMIT\n"
+        )
+    );
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_copy_rules() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features(
+        "iptables",
+        iptables::Features {
+            check: true,
+            wait: true,
+            restore_wait: true,
+        },
+    );
+    ipt.set_backend(backend.clone());
+    backend.push_output(
+        0,
+        "-P INPUT DROP\n-A INPUT -i lo -j ACCEPT\n-A INPUT -p tcp -m tcp --dport 22 -j ACCEPT\n\
+         -A INPUT -s 10.0.0.0/8 -j DROP\n",
+        "",
+    );
+    let copied = ipt
+        .copy_rules("filter", "INPUT", "filter", "FORWARD", |rule| {
+            rule.in_interface().is_none()
+        })
+        .unwrap();
+    assert_eq!(copied, 2);
+    assert_eq!(
+        backend.calls()[1],
+        ["iptables-restore", "--noflush", "--wait"]
+    );
+    assert_eq!(
+        backend.inputs()[1].as_deref(),
+        Some(
+            "*filter\n-A FORWARD -p tcp -m tcp --dport 22 -j ACCEPT\n\
+             -A FORWARD -s 10.0.0.0/8 -j DROP\nCOMMIT\n"
+        )
+    );
+
+    backend.push_output(
+        0,
+        "-P INPUT DROP\n-A INPUT -i eth0 -j ACCEPT\n-A INPUT -i lo -j ACCEPT\n",
+        "",
+    );
+    let copied = ipt
+        .copy_rules_with("filter", "INPUT", "filter", "INPUT", |mut rule| {
+            if rule.in_interface().as_deref() != Some("eth0") {
+                return None;
+            }
+            rule.set_option(&["-i", "--in-interface"], "eth1");
+            Some(rule)
+        })
+        .unwrap();
+    assert_eq!(copied, 1);
+    assert_eq!(
+        backend.inputs()[3].as_deref(),
+        Some("*filter\n-A INPUT -i eth1 -j ACCEPT\nCOMMIT\n")
+    );
+}
+
+#[test]
+fn test_port_forward_rules() {
+    use iptables::port_forward::{PortForward, PortRange, Protocol};
+
+    let mut forward = PortForward::new(Protocol::Both, PortRange::new(8000, 8010), "10.0.0.2");
+    assert_eq!(
+        forward.rules().unwrap(),
+        vec![
+            "-p tcp -m tcp --dport 8000:8010 -j DNAT --to-destination 10.0.0.2:8000-8010",
+            "-p udp -m udp --dport 8000:8010 -j DNAT --to-destination 10.0.0.2:8000-8010",
+        ]
+    );
+
+    forward.protocol = Protocol::Tcp;
+    forward.to_ports = PortRange::new(9000, 9010);
+    forward.in_interface = Some("eth0".to_string());
+    assert_eq!(
+        forward.rules().unwrap(),
+        vec![
+            "-p tcp -i eth0 -m tcp --dport 8000:8010 -j DNAT \
+             --to-destination 10.0.0.2:9000-9010/8000"
+        ]
+    );
+
+    forward.to_ports = PortRange::single(80);
+    assert!(forward.to_destination().ends_with(":80"));
+    forward.to_ports = PortRange::new(9000, 9001);
+    assert!(forward.rules().is_err());
+
+    let forward = PortForward::new(Protocol::Udp, PortRange::single(53), "fd00::2");
+    assert_eq!(forward.to_destination(), "[fd00::2]:53");
+    assert!(
+        PortForward::new(Protocol::Tcp, PortRange::new(90, 80), "10.0.0.2")
+            .rules()
+            .is_err()
+    );
+}
+
+#[test]
+fn test_icmp_types() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::icmp::IcmpType;
+
+    assert_eq!(
+        RuleBuilder::new()
+            .icmp_type(IcmpType::EchoRequest)
+            .target(Target::Accept)
+            .build()
+            .unwrap(),
+        "-p icmp -m icmp --icmp-type echo-request -j ACCEPT"
+    );
+    assert_eq!(
+        RuleBuilder::new()
+            .icmpv6_type(IcmpType::NeighbourSolicitation)
+            .build()
+            .unwrap(),
+        "-p ipv6-icmp -m icmp6 --icmpv6-type neighbour-solicitation"
+    );
+    assert!(RuleBuilder::new()
+        .icmp_type(IcmpType::NeighbourAdvertisement)
+        .build()
+        .is_err());
+    assert!(RuleBuilder::new()
+        .icmpv6_type(IcmpType::TimestampRequest)
+        .build()
+        .is_err());
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_allow_ping() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features(
+        "ip6tables",
+        iptables::Features {
+            check: true,
+            wait: false,
+            restore_wait: false,
+        },
+    );
+    ipt.set_backend(backend.clone());
+    backend.push_output(1, "", "");
+    assert!(ipt.allow_ping(Some(5)).is_ok());
+    assert_eq!(
+        backend.calls()[1][1..].join(" "),
+        "-t filter -A INPUT -p ipv6-icmp -m icmp6 --icmpv6-type echo-request \
+         -m limit --limit 5/second -j ACCEPT"
+    );
+}
+
+#[test]
+fn test_interface_matches() {
+    use iptables::builder::{validate_interface, RuleBuilder, Target};
+
+    assert_eq!(
+        RuleBuilder::new()
+            .in_interface("br0")
+            .in_bridge_port("veth+")
+            .out_bridge_port("eth1")
+            .target(Target::Drop)
+            .build()
+            .unwrap(),
+        "-i br0 -m physdev --physdev-in veth+ --physdev-out eth1 -j DROP"
+    );
+    assert!(validate_interface("+").is_ok());
+    assert!(validate_interface("wg-office.10").is_ok());
+    assert!(validate_interface("").is_err());
+    assert!(validate_interface("a-very-long-name0").is_err());
+    assert!(validate_interface("eth0:1").is_err());
+    assert!(RuleBuilder::new().out_interface("eth 0").build().is_err());
+}
+
+#[test]
+fn test_string_and_bpf_matches() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::parse::split_rule;
+
+    let rule = RuleBuilder::new()
+        .string(b"say \"hi\"")
+        .target(Target::Drop)
+        .build()
+        .unwrap();
+    assert_eq!(
+        rule,
+        "-m string --algo bm --string \"say \\\"hi\\\"\" -j DROP"
+    );
+    assert_eq!(split_rule(&rule)[5], "say \"hi\"");
+    assert_eq!(
+        RuleBuilder::new().string(b"GET /\x00\xff").build().unwrap(),
+        "-m string --algo bm --hex-string \"GET|20 2f 00 ff|\""
+    );
+    assert_eq!(
+        RuleBuilder::new()
+            .bpf("4,48 0 0 9,21 0 1 6,6 0 0 1,6 0 0 0")
+            .build()
+            .unwrap(),
+        "-m bpf --bytecode \"4,48 0 0 9,21 0 1 6,6 0 0 1,6 0 0 0\""
+    );
+    assert!(RuleBuilder::new().bpf("2,6 0 0 1").build().is_err());
+    assert!(RuleBuilder::new().bpf("1,6 0 x 1").build().is_err());
+}
+
+#[test]
+fn test_ttl_rules() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::ttl::{TtlAction, TtlMatch};
+
+    assert_eq!(
+        RuleBuilder::new()
+            .ttl(TtlMatch::Lt(64))
+            .target(Target::Ttl(TtlAction::Set(64)))
+            .build()
+            .unwrap(),
+        "-m ttl --ttl-lt 64 -j TTL --ttl-set 64"
+    );
+    assert_eq!(
+        RuleBuilder::new()
+            .hop_limit(TtlMatch::Eq(255))
+            .target(Target::Hl(TtlAction::Inc(1)))
+            .build()
+            .unwrap(),
+        "-m hl --hl-eq 255 -j HL --hl-inc 1"
+    );
+    assert!(RuleBuilder::new().ttl(TtlMatch::Gt(255)).build().is_err());
+    assert!(RuleBuilder::new()
+        .target(Target::Ttl(TtlAction::Dec(0)))
+        .build()
+        .is_err());
+}
+
+#[test]
+fn test_length_and_pkttype_matches() {
+    use iptables::builder::{PktType, RuleBuilder, Target};
+
+    assert_eq!(
+        RuleBuilder::new()
+            .pkt_type(PktType::Broadcast)
+            .length(0, 128)
+            .target(Target::Drop)
+            .build()
+            .unwrap(),
+        "-m pkttype --pkt-type broadcast -m length --length 0:128 -j DROP"
+    );
+    assert_eq!(
+        RuleBuilder::new().length(40, 40).build().unwrap(),
+        "-m length --length 40"
+    );
+    assert!(RuleBuilder::new().length(1500, 64).build().is_err());
+}
+
+#[test]
+fn test_mark_allocator() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::mark::{Mark, MarkAllocator};
+
+    let mut allocator = MarkAllocator::new();
+    allocator.reserve(0xc000);
+    let routing = allocator.allocate("routing", 8).unwrap();
+    let shaping = allocator.allocate("shaping", 8).unwrap();
+    assert_eq!(routing.mask, 0xff);
+    assert_eq!(shaping.mask, 0xff0000);
+    assert_eq!(allocator.allocate("routing", 8).unwrap(), routing);
+    assert!(allocator.allocate("routing", 4).is_err());
+    assert!(allocator.allocate("huge", 17).is_err());
+
+    assert_eq!(shaping.mark(3).unwrap(), Mark::masked(0x30000, 0xff0000));
+    assert!(routing.mark(256).is_err());
+    assert_eq!(
+        RuleBuilder::new()
+            .mark(routing.mark(1).unwrap())
+            .target(Target::Mark(shaping.mark(2).unwrap()))
+            .build()
+            .unwrap(),
+        "-m mark --mark 0x1/0xff -j MARK --set-xmark 0x20000/0xff0000"
+    );
+    assert!(RuleBuilder::new()
+        .target(Target::Mark(Mark::masked(0x100, 0xff)))
+        .build()
+        .is_err());
+
+    allocator.release("routing");
+    assert_eq!(allocator.allocate("vpn", 4).unwrap().mask, 0xf);
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_data_caps() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let mut manager = ipt.data_cap_manager("CAPS").unwrap();
+    manager.set_cap("10.0.0.2", 1000000).unwrap();
+    manager.set_cap("10.0.0.3", 500).unwrap();
+    assert_eq!(
+        manager.caps().collect::<Vec<_>>(),
+        vec![("10.0.0.2", 1000000), ("10.0.0.3", 500)]
+    );
+
+    let listing = "Chain CAPS (1 references)\n\
+        \x20   pkts      bytes target     prot opt in     out     source               destination\n\
+        \x20    100     64000 RETURN     all  --  *      *       0.0.0.0/0            10.0.0.2             quota: 1000000 bytes\n\
+        \x20      0         0 DROP       all  --  *      *       0.0.0.0/0            10.0.0.2\n\
+        \x20      5       480 RETURN     all  --  *      *       0.0.0.0/0            10.0.0.3             quota: 500 bytes\n\
+        \x20      2      1500 DROP       all  --  *      *       0.0.0.0/0            10.0.0.3\n";
+    backend.push_output(0, listing, "");
+    backend.push_output(0, listing, "");
+    let usage = manager.usage().unwrap();
+    assert_eq!(usage[0].used, 64000);
+    assert!(!usage[0].exhausted);
+    assert_eq!(usage[1].used, 480);
+    assert!(usage[1].exhausted);
+
+    let start = backend.calls().len();
+    assert_eq!(manager.renew_exhausted().unwrap(), vec!["10.0.0.3"]);
+    let calls = backend.calls()[start + 1..]
+        .iter()
+        .map(|call| call.join(" "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        calls,
+        vec![
+            "iptables -t filter -R CAPS 3 -d 10.0.0.3 -m quota --quota 500 -j RETURN",
+            "iptables -t filter -R CAPS 4 -d 10.0.0.3 -j DROP",
+        ]
+    );
+
+    manager.remove_cap("10.0.0.2").unwrap();
+    assert_eq!(manager.caps().count(), 1);
+    assert!(manager.remove_cap("10.0.0.2").is_err());
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_per_ip_accounting() {
+    use std::sync::Arc;
+
+    assert_eq!(
+        iptables::accounting::per_ip("filter", "10.0.0.5/30")
+            .unwrap()
+            .addresses
+            .iter()
+            .map(|a| a.to_string())
+            .collect::<Vec<_>>(),
+        vec!["10.0.0.4", "10.0.0.5", "10.0.0.6", "10.0.0.7"]
+    );
+    assert_eq!(
+        iptables::accounting::per_ip("filter", "fd00::/127")
+            .unwrap()
+            .addresses
+            .len(),
+        2
+    );
+    assert!(iptables::accounting::per_ip("filter", "10.0.0.0/8").is_err());
+    assert!(iptables::accounting::per_ip("filter", "10.0.0.0/33").is_err());
+    assert!(iptables::accounting::per_ip("filter", "10.0.0").is_err());
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let accounting = iptables::accounting::per_ip("filter", "192.168.1.8/31").unwrap();
+    ipt.install_accounting(&accounting).unwrap();
+    assert_eq!(
+        backend.inputs().last().unwrap().as_deref(),
+        Some(
+            "*filter\n:ACCOUNTING - [0:0]\n-F ACCOUNTING\n\
+             -A ACCOUNTING -s 192.168.1.8\n\
+             -A ACCOUNTING -d 192.168.1.8\n-A ACCOUNTING -s 192.168.1.9\n\
+             -A ACCOUNTING -d 192.168.1.9\nCOMMIT\n"
+        )
+    );
+
+    backend.push_output(
+        0,
+        "Chain ACCOUNTING (1 references)\n\
+         \x20   pkts      bytes target     prot opt in     out     source               destination\n\
+         \x20     10       800            all  --  *      *       192.168.1.8          0.0.0.0/0\n\
+         \x20     12     15000            all  --  *      *       0.0.0.0/0            192.168.1.8\n\
+         \x20      0         0            all  --  *      *       192.168.1.9          0.0.0.0/0\n\
+         \x20      0         0            all  --  *      *       0.0.0.0/0            192.168.1.9\n",
+        "",
+    );
+    let usage = ipt.accounting_usage(&accounting).unwrap();
+    assert_eq!(usage[0].sent.bytes, 800);
+    assert_eq!(usage[0].received.packets, 12);
+    assert_eq!(usage[1].address.to_string(), "192.168.1.9");
+    assert_eq!(usage[1].received.bytes, 0);
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_nfacct() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::parse::Counters;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let list = "{ pkts = 00000000000000000012, bytes = 00000000000000001520 } = http;\n\
+                { pkts = 00000000000000000000, bytes = 00000000000000000000 } = dns;\n";
+    backend.push_output(0, list, "");
+    assert_eq!(
+        ipt.list_nfacct().unwrap(),
+        vec![
+            (
+                "http".to_string(),
+                Counters {
+                    packets: 12,
+                    bytes: 1520
+                }
+            ),
+            ("dns".to_string(), Counters::default()),
+        ]
+    );
+
+    backend.push_output(0, list, "");
+    ipt.create_nfacct("http").unwrap();
+    backend.push_output(0, list, "");
+    ipt.create_nfacct("ssh").unwrap();
+    backend.push_output(
+        0,
+        "{ pkts = 00000000000000000012, bytes = 00000000000000001520 } = http;\n",
+        "",
+    );
+    assert_eq!(ipt.reset_nfacct("http").unwrap().bytes, 1520);
+    let calls = backend.calls();
+    assert_eq!(calls[3].join(" "), "nfacct add ssh");
+    assert_eq!(calls[4].join(" "), "nfacct get http reset");
+
+    assert_eq!(
+        RuleBuilder::new()
+            .protocol("tcp")
+            .nfacct("http")
+            .target(Target::Accept)
+            .build()
+            .unwrap(),
+        "-p tcp -m nfacct --nfacct-name http -j ACCEPT"
+    );
+    assert!(RuleBuilder::new().nfacct(&"x".repeat(32)).build().is_err());
+}
+
+#[cfg(all(feature = "helper", feature = "test-backend", target_os = "linux"))]
+#[test]
+fn test_helper_round_trip() {
+    use iptables::backend::Backend;
+    use iptables::helper::{HelperClient, HelperServer};
+    use std::os::unix::fs::MetadataExt;
+    use std::sync::Arc;
+
+    let dir = std::env::temp_dir().join(format!("iptables-helper-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("helper.sock");
+    let _ = std::fs::remove_file(&path);
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    backend.push_output(0, "iptables v1.8.7 (legacy)\n", "");
+    backend.push_output(0, "Usage: iptables-restore [-w secs] [--wait]\n", "");
+    backend.push_output(0, "-P INPUT ACCEPT\n-A INPUT -j ACCEPT\n", "");
+    let mut server = HelperServer::bind(&path).unwrap();
+    server.allow_uid(std::fs::metadata(&dir).unwrap().uid());
+    server.set_backend(backend.clone());
+    std::thread::spawn(move || server.serve().is_ok());
+
+    let ipt = iptables::helper::client(&path, false).unwrap();
+    assert!(ipt.has_check && ipt.has_wait && ipt.has_restore_wait);
+    assert_eq!(
+        ipt.list("filter", "INPUT").unwrap(),
+        vec!["-P INPUT ACCEPT", "-A INPUT -j ACCEPT"]
+    );
+    assert_eq!(
+        backend.calls()[2].join(" "),
+        "iptables -t filter -S INPUT --wait"
+    );
+
+    let denied = HelperClient::new(&path).run("sh", &["-c".into(), "id".into()], None, None);
+    assert!(denied.unwrap_err().to_string().contains("not allowed"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_policy_filter() {
+    use iptables::error::IPTError;
+    use iptables::policy::PolicyFilter;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut policy = PolicyFilter::new(backend.clone());
+    policy.allow_chain("filter", "MYAPP-*");
+    policy.allow_target("ACCEPT");
+    let mut ipt = iptables::IPTables::with_features(
+        "iptables",
+        iptables::Features {
+            check: true,
+            wait: true,
+            restore_wait: true,
+        },
+    );
+    ipt.set_backend(Arc::new(policy));
+
+    assert!(ipt.new_chain("filter", "MYAPP-IN").is_ok());
+    assert!(ipt.append("filter", "MYAPP-IN", "-p tcp -j ACCEPT").is_ok());
+    assert!(ipt.append("filter", "MYAPP-IN", "-j MYAPP-LOG").is_ok());
+    assert!(ipt.list("filter", "INPUT").is_ok());
+    let calls = backend.calls().len();
+
+    let denied = |result: Result<(), Box<dyn std::error::Error>>| {
+        result
+            .unwrap_err()
+            .downcast_ref::<IPTError>()
+            .is_some_and(IPTError::is_policy_denied)
+    };
+    assert!(denied(ipt.append("filter", "INPUT", "-j ACCEPT")));
+    assert!(denied(ipt.append("nat", "MYAPP-IN", "-j ACCEPT")));
+    assert!(denied(ipt.append("filter", "MYAPP-IN", "-j DROP")));
+    assert!(denied(ipt.flush_table("filter")));
+    assert!(denied(ipt.rename_chain("filter", "MYAPP-IN", "OTHER")));
+    assert!(denied(ipt.destroy_set("blocked")));
+    assert!(denied(ipt.restore(
+        "*filter\n:INPUT ACCEPT [0:0]\nCOMMIT\n",
+        Default::default()
+    )));
+    assert_eq!(backend.calls().len(), calls);
+
+    let options = iptables::restore::RestoreOptions {
+        flush: false,
+        ..Default::default()
+    };
+    assert!(ipt
+        .restore(
+            "*filter\n:MYAPP-FWD - [0:0]\n[0:0] -A MYAPP-FWD -j ACCEPT\nCOMMIT\n",
+            options.clone()
+        )
+        .is_ok());
+    assert!(denied(ipt.restore(
+        "*filter\n-A FORWARD -j MYAPP-FWD\nCOMMIT\n",
+        options.clone()
+    )));
+    assert!(denied(
+        ipt.restore("*filter\n-AFORWARD -j MYAPP-FWD\nCOMMIT\n", options)
+    ));
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_policy_filter_spellings() {
+    use iptables::backend::Backend;
+    use iptables::error::IPTError;
+    use iptables::policy::PolicyFilter;
+    use std::ffi::OsString;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut policy = PolicyFilter::new(backend.clone());
+    policy.allow_chain("filter", "MYAPP-*");
+    policy.allow_target("ACCEPT");
+    let run = |args: &[&str]| {
+        let args = args.iter().map(OsString::from).collect::<Vec<_>>();
+        match policy.run("iptables", &args, None, None) {
+            Ok(_) => true,
+            Err(error) => {
+                assert!(matches!(
+                    error.downcast_ref::<IPTError>(),
+                    Some(IPTError::PolicyDenied(_))
+                ));
+                false
+            }
+        }
+    };
+
+    // Attached values
+    assert!(!run(&["-AINPUT", "-j", "ACCEPT"]));
+    assert!(!run(&["-FINPUT"]));
+    assert!(!run(&["-tnat", "-A", "MYAPP-IN", "-j", "ACCEPT"]));
+    assert!(!run(&["-A", "MYAPP-IN", "-jDROP"]));
+    assert!(run(&["-AMYAPP-IN", "-jACCEPT"]));
+
+    // Values after '='
+    assert!(!run(&["--append=INPUT", "-j", "ACCEPT"]));
+    assert!(!run(&["--table=nat", "-A", "MYAPP-IN", "-j", "ACCEPT"]));
+    assert!(!run(&["-A", "MYAPP-IN", "--jump=DROP"]));
+    assert!(run(&["--append=MYAPP-IN", "--jump=ACCEPT"]));
+
+    // Abbreviations
+    assert!(!run(&["--app", "INPUT", "-j", "ACCEPT"]));
+    assert!(!run(&["--fl"]));
+    assert!(!run(&["--ta", "nat", "-A", "MYAPP-IN", "-j", "ACCEPT"]));
+    assert!(!run(&["--de", "MYAPP-IN", "1"]));
+    assert!(run(&["--app", "MYAPP-IN", "--ju", "ACCEPT"]));
+
+    // Grouped flags and unknown options
+    assert!(!run(&["-nvF"]));
+    assert!(run(&["-nvL", "INPUT"]));
+    assert!(!run(&["-A", "MYAPP-IN", "-Q"]));
+    assert!(!run(&["--unknown", "-A", "MYAPP-IN", "-j", "ACCEPT"]));
+    assert!(run(&[
+        "-A",
+        "MYAPP-IN",
+        "-p",
+        "tcp",
+        "--dport=22",
+        "-m",
+        "recent",
+        "--set",
+        "-j",
+        "ACCEPT"
+    ]));
+    assert!(!run(&[
+        "-A", "MYAPP-IN", "-m", "recent", "--set", "-j", "DROP"
+    ]));
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_firewalld_passthrough() {
+    use iptables::restore::RestoreOptions;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features(
+        "iptables",
+        iptables::Features {
+            check: true,
+            wait: true,
+            restore_wait: true,
+        },
+    );
+    ipt.set_backend(backend.clone());
+    backend.push_output(0, "running\n", "");
+    assert!(ipt.use_firewalld(true));
+
+    ipt.append("filter", "INPUT", "-p tcp -j ACCEPT").unwrap();
+    ipt.delete("filter", "INPUT", "-p tcp -j ACCEPT").unwrap();
+    backend.push_output(1, "", "iptables: No chain/target/match by that name.\n");
+    let options = RestoreOptions {
+        flush: false,
+        ..Default::default()
+    };
+    ipt.restore(
+        "*nat\n:MYCHAIN - [0:0]\n-A MYCHAIN -j RETURN\nCOMMIT\n",
+        options,
+    )
+    .unwrap();
+    assert!(ipt
+        .restore("*filter\nCOMMIT\n", RestoreOptions::default())
+        .is_err());
+
+    let calls = backend
+        .calls()
+        .iter()
+        .map(|call| call.join(" "))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        calls,
+        vec![
+            "firewall-cmd --state",
+            "firewall-cmd --direct --passthrough ipv4 -t filter -A INPUT -p tcp -j ACCEPT",
+            "firewall-cmd --permanent --direct --add-passthrough ipv4 -t filter -A INPUT -p tcp -j ACCEPT",
+            "firewall-cmd --direct --passthrough ipv4 -t filter -D INPUT -p tcp -j ACCEPT",
+            "firewall-cmd --permanent --direct --remove-passthrough ipv4 -t filter -A INPUT -p tcp -j ACCEPT",
+            "firewall-cmd --direct --passthrough ipv4 -t nat -S MYCHAIN 1",
+            "firewall-cmd --direct --passthrough ipv4 -t nat -N MYCHAIN",
+            "firewall-cmd --permanent --direct --add-passthrough ipv4 -t nat -N MYCHAIN",
+            "firewall-cmd --direct --passthrough ipv4 -t nat -F MYCHAIN",
+            "firewall-cmd --direct --passthrough ipv4 -t nat -A MYCHAIN -j RETURN",
+            "firewall-cmd --permanent --direct --add-passthrough ipv4 -t nat -A MYCHAIN -j RETURN",
+        ]
+    );
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_detect_managers() {
+    use iptables::managers::Manager;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n:DOCKER - [0:0]\n:DOCKER-USER - [0:0]\n\
+         :ufw-before-input - [0:0]\n:MYAPP - [0:0]\nCOMMIT\n\
+         *nat\n:KUBE-SERVICES - [0:0]\nCOMMIT\n",
+        "",
+    );
+    backend.push_output(0, "running\n", "");
+    backend.push_output(1, "", "ERROR: You need to be root to run this script\n");
+    backend.push_output(
+        0,
+        "table ip filter\ntable ip nat\ntable inet firewalld\ntable inet myfw\n",
+        "",
+    );
+    let detected = ipt.detect_managers().unwrap();
+
+    let find = |manager| detected.iter().find(|d| d.manager == manager).unwrap();
+    assert_eq!(
+        find(Manager::Docker).chains,
+        vec![
+            ("filter".to_string(), "DOCKER".to_string()),
+            ("filter".to_string(), "DOCKER-USER".to_string())
+        ]
+    );
+    assert!(find(Manager::Firewalld).running);
+    assert_eq!(find(Manager::Firewalld).tables, vec!["inet firewalld"]);
+    assert!(!find(Manager::Ufw).running);
+    assert_eq!(find(Manager::Ufw).chains.len(), 1);
+    assert_eq!(
+        find(Manager::Kubernetes).chains,
+        vec![("nat".to_string(), "KUBE-SERVICES".to_string())]
+    );
+    assert_eq!(find(Manager::Nftables).tables, vec!["inet myfw"]);
+    assert_eq!(Manager::of_chain("MYAPP"), None);
+    assert_eq!(
+        Manager::of_chain("IN_public_allow"),
+        Some(Manager::Firewalld)
+    );
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_save_to_file() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("ip6tables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let save = "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j ACCEPT\nCOMMIT\n";
+    backend.push_output(0, save, "");
+    let path = std::env::temp_dir().join(format!("rules-{}.v6", std::process::id()));
+    ipt.save_to_file(&path).unwrap();
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), save);
+    assert_eq!(backend.calls()[0], ["ip6tables-save"]);
+
+    backend.push_output(1, "", "ip6tables-save: permission denied\n");
+    assert!(ipt.save_to_file(&path).is_err());
+    assert_eq!(std::fs::read_to_string(&path).unwrap(), save);
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_restore_unit() {
+    let ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    let unit = ipt.restore_unit("/var/lib/my app/rules.v4");
+    assert!(unit.contains("ConditionFileNotEmpty=/var/lib/my app/rules.v4\n"));
+    assert!(unit.contains("ExecStart=/usr/sbin/iptables-restore \"/var/lib/my app/rules.v4\"\n"));
+    assert!(unit.contains("Type=oneshot\nRemainAfterExit=yes\n"));
+    assert!(unit.ends_with("[Install]\nWantedBy=multi-user.target\n"));
+    assert!(ipt
+        .install_restore_unit("../evil.service", "/tmp/rules")
+        .is_err());
+}
+
+#[cfg(feature = "test-backend")]
+#[test]
+fn test_mock_diagnostics() {
+    use iptables::variant::Variant;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    backend.push_output(0, "iptables v1.8.9 (nf_tables)\n", "");
+    for _ in 0..4 {
+        backend.push_output(0, "v1.8.9 (nf_tables)\n", "");
+    }
+    backend.push_output(127, "", "");
+    backend.push_output(0, "iptables v1.8.9 (nf_tables)\n", "");
+    backend.push_output(
+        4,
+        "",
+        "iptables v1.8.9 (nf_tables): Permission denied (you must be root)\n",
+    );
+    let report = ipt.diagnostics();
+
+    assert_eq!(report.utilities.len(), 6);
+    assert_eq!(report.utilities[0].name, "iptables");
+    assert_eq!(
+        report.utilities[0].version.as_deref(),
+        Some("iptables v1.8.9 (nf_tables)")
+    );
+    assert_eq!(report.utilities[5].name, "ip6tables-restore");
+    assert_eq!(report.utilities[5].version, None);
+    assert_eq!(report.variant, Some(Variant::Nft));
+    assert!(!report.permitted);
+    assert!(report
+        .permission_error
+        .unwrap()
+        .starts_with("missing privileges"));
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_check_modules() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::kmod::required_modules;
+    use std::sync::Arc;
+
+    let rule = RuleBuilder::new()
+        .protocol("tcp")
+        .arg(&["-m", "tcp", "--dport", "22", "-m", "nosuchmatch"])
+        .target(Target::Reject);
+    assert_eq!(
+        required_modules(&rule.args().unwrap(), true),
+        vec![
+            ("match 'tcp'".to_string(), "xt_tcpudp".to_string()),
+            (
+                "match 'nosuchmatch'".to_string(),
+                "xt_nosuchmatch".to_string()
+            ),
+            ("target 'REJECT'".to_string(), "ip6t_REJECT".to_string()),
+        ]
+    );
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let rule = RuleBuilder::new().arg(&["-m", "nosuchmatch"]);
+    backend.push_output(1, "", "");
+    assert_eq!(
+        rule.check_modules(&ipt).unwrap_err().to_string(),
+        "match 'nosuchmatch' requires kernel module xt_nosuchmatch"
+    );
+    assert_eq!(
+        backend.calls().last().unwrap(),
+        &vec!["modprobe", "-n", "-q", "xt_nosuchmatch"]
+    );
+    assert!(rule.check_modules(&ipt).is_ok());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_shared_handle() {
+    use std::sync::Arc;
+    use std::thread;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    ipt.serialize_commands();
+
+    let shared = Arc::new(ipt.clone());
+    let threads = (0..4)
+        .map(|i| {
+            let ipt = shared.clone();
+            thread::spawn(move || {
+                ipt.append("filter", "INPUT", &format!("-p tcp --dport {}", i))
+                    .is_ok()
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        assert!(thread.join().unwrap());
+    }
+    assert!(ipt.new_chain("filter", "SHARED").is_ok());
+    assert_eq!(backend.calls().len(), 5);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_shared_sequences() {
+    use iptables::shared::SharedIPTables;
+    use std::sync::Arc;
+    use std::thread;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let shared = Arc::new(SharedIPTables::new(ipt));
+
+    let threads = (0..4)
+        .map(|i| {
+            let shared = shared.clone();
+            thread::spawn(move || {
+                let ipt = shared.lock();
+                let chain = format!("COMPONENT{}", i);
+                ipt.new_chain("filter", &chain).is_ok()
+                    && ipt.append("filter", &chain, "-j ACCEPT").is_ok()
+            })
+        })
+        .collect::<Vec<_>>();
+    for thread in threads {
+        assert!(thread.join().unwrap());
+    }
+
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 8);
+    for pair in calls.chunks(2) {
+        assert_eq!(pair[0][4], pair[1][4]);
+    }
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_rate_limit() {
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    ipt.set_rate_limit(Some(10));
+
+    let start = Instant::now();
+    for _ in 0..15 {
+        assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_ok());
+    }
+    assert!(start.elapsed() >= Duration::from_millis(400));
+    assert_eq!(backend.calls().len(), 15);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_check_drift() {
+    use iptables::drift::Snapshot;
+    use std::sync::Arc;
+
+    let baseline = Snapshot::parse(
+        "*filter\n:INPUT ACCEPT [0:0]\n:APP - [0:0]\n:OLD - [0:0]\n\
+         -A INPUT -j APP\n-A APP -p tcp --dport 22 -j ACCEPT\n-A APP -p tcp --dport 80 -j ACCEPT\n\
+         -A APP -j DROP\nCOMMIT\n",
+    );
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    backend.push_output(
+        0,
+        "*nat\n:PREROUTING ACCEPT [0:0]\nCOMMIT\n\
+         *filter\n:INPUT ACCEPT [0:0]\n:APP - [0:0]\n:DOCKER - [0:0]\n\
+         -A INPUT -j APP\n-A APP -p tcp --dport 80 -j ACCEPT\n-A APP -p tcp --dport 22 -j ACCEPT\n\
+         -A APP -p tcp --dport 8080 -j ACCEPT\n-A APP  -j DROP\nCOMMIT\n",
+        "",
+    );
+    let report = ipt.check_drift(&baseline).unwrap();
+
+    let entry = |table: &str, value: &str| (table.to_string(), value.to_string());
+    assert_eq!(report.added_chains, vec![entry("filter", "DOCKER")]);
+    assert_eq!(report.missing_chains, vec![entry("filter", "OLD")]);
+    assert_eq!(
+        report.added_rules,
+        vec![entry("filter", "-A APP -p tcp --dport 8080 -j ACCEPT")]
+    );
+    assert!(report.missing_rules.is_empty());
+    assert_eq!(report.reordered_chains, vec![entry("filter", "APP")]);
+    assert!(!report.is_empty());
+    assert_eq!(backend.calls()[0], vec!["iptables-save"]);
+}
+
+#[test]
+#[cfg(all(feature = "json", feature = "test-backend"))]
+fn test_mock_json() {
+    use iptables::json::{from_json, to_json};
+    use iptables::parse::{format_save, parse_save};
+    use std::sync::Arc;
+
+    let save = "*filter\n:INPUT ACCEPT [10:840]\n:MYAPP - [0:0]\n-A INPUT -j MYAPP\n\
+                -A MYAPP -p tcp -m tcp --dport 22 -j ACCEPT\nCOMMIT\n";
+    let tables = parse_save(save);
+    let json = to_json(&tables).unwrap();
+    assert!(json.contains("\"version\": 1"));
+    assert!(json.contains("\"packets\": 10"));
+    assert_eq!(from_json(&json).unwrap(), tables);
+    assert_eq!(format_save(&tables), save);
+    assert!(from_json("{\"version\": 2, \"tables\": []}").is_err());
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    backend.push_output(0, save, "");
+    assert_eq!(ipt.export_json().unwrap(), json);
+    assert!(ipt.import_json(&json).is_ok());
+    assert_eq!(backend.inputs().last().unwrap().as_deref(), Some(save));
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_wait_for_rule() {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let features = iptables::Features {
+        check: true,
+        ..Default::default()
+    };
+    let mut ipt = iptables::IPTables::with_features("iptables", features);
+    ipt.set_backend(backend.clone());
+
+    backend.push_output(1, "", "");
+    backend.push_output(1, "", "");
+    backend.push_output(0, "", "");
+    let timeout = Duration::from_secs(5);
+    assert!(ipt
+        .wait_for_rule("filter", "INPUT", "-j ACCEPT", true, timeout)
+        .unwrap());
+    assert_eq!(backend.calls().len(), 3);
+    assert_eq!(
+        backend.calls()[0],
+        vec!["iptables", "-t", "filter", "-C", "INPUT", "-j", "ACCEPT"]
+    );
+
+    assert!(!ipt
+        .wait_for_rule(
+            "filter",
+            "INPUT",
+            "-j ACCEPT",
+            false,
+            Duration::from_millis(250)
+        )
+        .unwrap());
+}
+
+#[test]
+#[cfg(all(feature = "testing", feature = "test-backend"))]
+fn test_mock_scratch_chain() {
+    use iptables::testing::unique_chain_name;
+    use std::sync::Arc;
+
+    let long = unique_chain_name("A-VERY-LONG-PREFIX-FOR-THE-TESTS-");
+    assert!(long.len() <= 28);
+    assert!(long.starts_with("A-VERY-LONG"));
+    assert_ne!(unique_chain_name("TEST-"), unique_chain_name("TEST-"));
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let name = {
+        let chain = ipt.scratch_chain("filter", "TEST-").unwrap();
+        assert_eq!(chain.table(), "filter");
+        assert!(chain.name().starts_with("TEST-"));
+        chain.name().to_string()
+    };
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 3);
+    assert_eq!(calls[0], vec!["iptables", "-t", "filter", "-N", &name]);
+    assert_eq!(calls[1], vec!["iptables", "-t", "filter", "-F", &name]);
+    assert_eq!(calls[2], vec!["iptables", "-t", "filter", "-X", &name]);
+}
+
+#[test]
+#[cfg(all(feature = "testing", feature = "test-backend", target_os = "linux"))]
+fn test_mock_sandbox() {
+    use iptables::testing::Sandbox;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    backend.push_output(0, "", "");
+    backend.push_output(0, "iptables v1.8.9 (nf_tables)\n", "");
+    let namespace = {
+        let sandbox = Sandbox::with_backend(backend.clone(), false).unwrap();
+        assert!(sandbox.has_check && sandbox.has_wait);
+        assert!(sandbox.flush_table("filter").is_ok());
+        sandbox.namespace().to_string()
+    };
+    let calls = backend.calls();
+    assert_eq!(calls[0], vec!["ip", "netns", "add", &namespace]);
+    assert_eq!(
+        calls[1],
+        vec!["ip", "netns", "exec", &namespace, "iptables", "--version"]
+    );
+    assert_eq!(
+        calls[3],
+        vec!["ip", "netns", "exec", &namespace, "iptables", "-t", "filter", "-F", "--wait"]
+    );
+    assert_eq!(calls[4], vec!["ip", "netns", "delete", &namespace]);
+
+    backend.push_output(
+        1,
+        "",
+        "mount --make-shared /run/netns failed: Operation not permitted\n",
+    );
+    assert!(Sandbox::with_backend(backend.clone(), false).is_err());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_rule_spec() {
+    use iptables::error::IPTError;
+    use iptables::parse::{RuleSpec, MAX_ARG_LEN};
+    use std::convert::TryFrom;
+    use std::sync::Arc;
+
+    let spec = RuleSpec::try_from("-p tcp -m comment --comment \"ssh access\" -j ACCEPT").unwrap();
+    assert_eq!(spec.args()[5], "ssh access");
+    assert_eq!(
+        spec.to_string(),
+        "-p tcp -m comment --comment \"ssh access\" -j ACCEPT"
+    );
+    assert!(RuleSpec::try_from("-j ACCEPT\n-A INPUT -j DROP").is_err());
+    assert!(RuleSpec::try_from("-s 10.0.0.1\0 -j DROP").is_err());
+    assert!(RuleSpec::try_from("--comment \"open").is_err());
+    let long = format!("--comment {}", "a".repeat(MAX_ARG_LEN + 1));
+    assert!(RuleSpec::try_from(long.as_str()).is_err());
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let features = iptables::Features {
+        check: true,
+        ..Default::default()
+    };
+    let mut ipt = iptables::IPTables::with_features("iptables", features);
+    ipt.set_backend(backend.clone());
+    let error = ipt.append("filter", "INPUT", "-j 'ACCEPT").unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<IPTError>(),
+        Some(IPTError::InvalidRule(_))
+    ));
+    assert!(ipt.exists("filter", "INPUT", "-j\u{7f}ACCEPT").is_err());
+    assert!(ipt.delete("filter", "INPUT", "-j ACCEPT\r").is_err());
+    assert!(backend.calls().is_empty());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_strict_args() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::error::IPTError;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    ipt.set_strict_args(true);
+
+    let error = ipt.append("filter", "INPUT", "-j ACCEPT").unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<IPTError>(),
+        Some(IPTError::InvalidRule(_))
+    ));
+    assert!(ipt.exists("filter", "INPUT", "-j ACCEPT").is_err());
+    assert!(ipt.execute("filter", "-F INPUT").is_err());
+    assert!(backend.calls().is_empty());
+
+    let rule = RuleBuilder::new()
+        .comment("a; b")
+        .target(Target::Accept)
+        .args()
+        .unwrap();
+    assert!(ipt.append_os("filter", "INPUT", &rule).is_ok());
+    assert_eq!(
+        backend.calls()[0],
+        vec![
+            "iptables",
+            "-t",
+            "filter",
+            "-A",
+            "INPUT",
+            "-m",
+            "comment",
+            "--comment",
+            "a; b",
+            "-j",
+            "ACCEPT"
+        ]
+    );
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_os_args_options() {
+    use iptables::error::IPTError;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let features = iptables::Features {
+        check: true,
+        ..iptables::Features::default()
+    };
+    let mut ipt = iptables::IPTables::with_features("iptables", features);
+    ipt.set_backend(backend.clone());
+    ipt.set_auto_identity(true);
+    ipt.set_verify_writes(true);
+    ipt.set_chain_prefix(Some("MYAPP-"));
+
+    // The rule gets an identity comment and is checked once appended, as with `append`
+    let rule = ["-p", "tcp", "--dport", "22", "-j", "ACCEPT"];
+    assert!(ipt.append_os("filter", "MYAPP-SSH", &rule).is_ok());
+    assert!(ipt
+        .append("filter", "MYAPP-SSH", "-p tcp --dport 22 -j ACCEPT")
+        .is_ok());
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 4);
+    let comment = calls[0][8].clone();
+    assert!(comment.starts_with("ipt-rs:"));
+    assert_eq!(calls[0][..8], calls[2][..8]);
+    assert_eq!(calls[0][9..], calls[2][9..]);
+    assert_eq!(calls[1][3], "-C");
+    assert_eq!(calls[0][4..], calls[1][4..]);
+
+    // The rule is deleted through its identity, and the deletion is checked
+    let listed = format!(
+        "-A MYAPP-SSH -m comment --comment {} {}",
+        comment,
+        rule.join(" ")
+    );
+    backend.push_output(0, &format!("{}\n", listed), "");
+    backend.push_output(0, &format!("{}\n", listed), "");
+    assert!(ipt.delete_os("filter", "MYAPP-SSH", &rule).is_ok());
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 8);
+    assert_eq!(
+        calls[6][..5],
+        ["iptables", "-t", "filter", "-D", "MYAPP-SSH"]
+    );
+    assert_eq!(calls[6][5..], calls[0][5..]);
+
+    // A deletion which leaves the rule in place fails the verification
+    backend.push_output(0, &format!("{}\n", listed), "");
+    backend.push_output(0, &format!("{}\n", listed), "");
+    backend.push_output(0, "", "");
+    backend.push_output(0, &format!("{}\n", listed), "");
+    let error = ipt.delete_os("filter", "MYAPP-SSH", &rule).unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<IPTError>(),
+        Some(IPTError::VerificationFailed(_))
+    ));
+
+    // A rule which already has an identity keeps it
+    let identified = ["-m", "comment", "--comment", &comment, "-j", "DROP"];
+    assert!(ipt.insert_os("filter", "MYAPP-SSH", &identified, 1).is_ok());
+    let calls = backend.calls();
+    assert_eq!(calls[calls.len() - 2][6..], identified);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_chain_prefix() {
+    use iptables::error::IPTError;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    ipt.set_chain_prefix(Some("MYAPP-"));
+
+    assert_eq!(ipt.prefixed_chain("WEB"), "MYAPP-WEB");
+    assert_eq!(ipt.prefixed_chain("MYAPP-WEB"), "MYAPP-WEB");
+    assert!(ipt.new_chain("filter", "WEB").is_ok());
+    assert!(ipt.rename_chain("filter", "MYAPP-WEB", "API").is_ok());
+    assert!(ipt.delete_chain("filter", "MYAPP-API").is_ok());
+
+    let error = ipt.delete_chain("filter", "DOCKER").unwrap_err();
+    assert!(error.downcast_ref::<IPTError>().unwrap().is_policy_denied());
+    assert!(ipt.rename_chain("filter", "DOCKER", "WEB").is_err());
+
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 3);
+    assert_eq!(
+        calls[0],
+        vec!["iptables", "-t", "filter", "-N", "MYAPP-WEB"]
+    );
+    assert_eq!(
+        calls[1],
+        vec!["iptables", "-t", "filter", "-E", "MYAPP-WEB", "MYAPP-API"]
+    );
+    assert_eq!(
+        calls[2],
+        vec!["iptables", "-t", "filter", "-X", "MYAPP-API"]
+    );
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_gc_managed() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n:MYAPP-WEB - [0:0]\n:MYAPP-OLD - [0:0]\n:DOCKER - [0:0]\n\
+         -A INPUT -j MYAPP-OLD\n-A INPUT -j MYAPP-WEB\n-A INPUT -p tcp -j MYAPP-OLD\n\
+         -A MYAPP-OLD -j ACCEPT\nCOMMIT\n",
+        "",
+    );
+    let removed = ipt.gc_managed("MYAPP-", &["WEB"]).unwrap();
+    assert_eq!(
+        removed,
+        vec![("filter".to_string(), "MYAPP-OLD".to_string())]
+    );
+
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 5);
+    assert_eq!(
+        calls[1],
+        vec!["iptables", "-t", "filter", "-D", "INPUT", "3"]
+    );
+    assert_eq!(
+        calls[2],
+        vec!["iptables", "-t", "filter", "-D", "INPUT", "1"]
+    );
+    assert_eq!(
+        calls[3],
+        vec!["iptables", "-t", "filter", "-F", "MYAPP-OLD"]
+    );
+    assert_eq!(
+        calls[4],
+        vec!["iptables", "-t", "filter", "-X", "MYAPP-OLD"]
+    );
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_ensure_jump() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    backend.push_output(0, "-P INPUT ACCEPT\n-A INPUT -j MYAPP\n", "");
+    assert!(!ipt.ensure_jump("filter", "INPUT", "MYAPP", 1).unwrap());
+
+    backend.push_output(
+        0,
+        "-P INPUT ACCEPT\n-A INPUT -j DOCKER\n-A INPUT -j MYAPP\n",
+        "",
+    );
+    assert!(ipt.ensure_jump("filter", "INPUT", "MYAPP", 1).unwrap());
+    let calls = backend.calls();
+    assert_eq!(
+        calls[2],
+        vec!["iptables", "-t", "filter", "-D", "INPUT", "2"]
+    );
+    assert_eq!(
+        calls[3],
+        vec!["iptables", "-t", "filter", "-I", "INPUT", "1", "-j", "MYAPP"]
+    );
+
+    backend.push_output(0, "-P INPUT ACCEPT\n", "");
+    assert!(!ipt.remove_jump("filter", "INPUT", "MYAPP").unwrap());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_pin_first() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let ssh = "-p tcp --dport 22 -j ACCEPT";
+
+    backend.push_output(0, "-P INPUT DROP\n-A INPUT -j DROP\n", "");
+    assert!(ipt.pin_first("filter", "INPUT", ssh).unwrap());
+    let insert = backend.calls().last().unwrap().clone();
+    assert_eq!(insert[3..8], ["-I", "INPUT", "1", "-m", "comment"]);
+    let key = insert[9].clone();
+    assert!(key.starts_with("ipt-rs-pin:"));
+
+    // The rule is listed in the canonical form of iptables
+    let pinned = format!(
+        "-A INPUT -m comment --comment \"{}\" -p tcp -m tcp --dport 22 -j ACCEPT\n",
+        key
+    );
+    for _ in 0..3 {
+        let calls = backend.calls().len();
+        backend.push_output(
+            0,
+            &format!("-P INPUT DROP\n{}-A INPUT -j DROP\n", pinned),
+            "",
+        );
+        assert!(!ipt.pin_first("filter", "INPUT", ssh).unwrap());
+        assert_eq!(backend.calls().len(), calls + 1);
+    }
+
+    backend.push_output(
+        0,
+        &format!("-P INPUT DROP\n-A INPUT -j DROP\n{}", pinned),
+        "",
+    );
+    assert!(ipt.pin_first("filter", "INPUT", ssh).unwrap());
+    let calls = backend.calls();
+    assert_eq!(
+        calls[calls.len() - 2],
+        vec!["iptables", "-t", "filter", "-D", "INPUT", "2"]
+    );
+    assert_eq!(calls[calls.len() - 1], insert);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_log_registry() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -p tcp --dport 22 -j LOG --log-prefix \"myapp-4: \"\n-A INPUT -j LOG --log-prefix \"other-9: \"\nCOMMIT\n",
+        "",
+    );
+    let mut registry = ipt.log_registry("myapp").unwrap();
+    assert_eq!(registry.rules().len(), 1);
+    assert_eq!(registry.rules()[0].chain, "INPUT");
+
+    let logged = registry.append("filter", "INPUT", "-p udp").unwrap();
+    assert_eq!(logged.prefix, "myapp-5: ");
+    assert_eq!(logged.rule, "-p udp -j LOG --log-prefix \"myapp-5: \"");
+
+    let log = "Oct 16 10:00:00 host kernel: [12.5] myapp-4: IN=eth0 OUT= SRC=10.0.0.1 DST=10.0.0.2 PROTO=TCP SPT=40000 DPT=22 SYN \n\
+               Oct 16 10:00:01 host kernel: [12.6] other-9: IN=eth0 OUT= SRC=10.0.0.3\n\
+               Oct 16 10:00:02 host kernel: [12.7] myapp-5: IN=eth1 OUT= SRC=10.0.0.4 PROTO=UDP\n";
+    let lines = registry
+        .classify_lines(log.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(lines[0].rule.prefix, "myapp-4: ");
+    assert_eq!(lines[0].field("DPT"), Some("22"));
+    assert_eq!(lines[0].field("OUT"), Some(""));
+    assert_eq!(lines[0].field("SYN"), Some(""));
+    assert_eq!(lines[1].rule.prefix, "myapp-5: ");
+    assert_eq!(lines[1].field("IN"), Some("eth1"));
+    assert!(registry
+        .classify("kernel: xmyapp-4: IN=eth0 OUT=")
+        .is_none());
+
+    assert!(ipt.log_registry("my app").is_err());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_nflog_groups() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let path = std::env::temp_dir().join(format!("nflog-groups-{}", std::process::id()));
+    let groups = ipt.nflog_groups(&path);
+
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j NFLOG --nflog-group 1\n-A INPUT -j NFLOG --nflog-group 3\nCOMMIT\n",
+        "",
+    );
+    assert_eq!(groups.allocate_nflog_group("ids").unwrap(), 2);
+    assert_eq!(groups.allocate_nflog_group("ids").unwrap(), 2);
+
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j NFLOG --nflog-group 1\n-A INPUT -j NFLOG --nflog-group 3\nCOMMIT\n",
+        "",
+    );
+    assert_eq!(groups.allocate_nflog_group("audit").unwrap(), 4);
+    assert_eq!(
+        groups.allocations().unwrap(),
+        vec![(2, "ids".to_string()), (4, "audit".to_string())]
+    );
+
+    assert!(groups.release("ids").unwrap());
+    assert!(!groups.release("ids").unwrap());
+    assert_eq!(groups.allocate_nflog_group("ids").unwrap(), 1);
+    assert!(groups.allocate_nflog_group("my app").is_err());
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_qos_classes() {
+    use iptables::mark::Mark;
+    use iptables::qos::{Dscp, Traffic};
+    use std::sync::Arc;
+
+    assert_eq!(Dscp::class("af21").unwrap(), Dscp(18));
+    assert_eq!(Dscp::class("CS6").unwrap(), Dscp(48));
+    assert!(Dscp::class("AF51").is_err());
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    let mut classes = ipt.qos_classes("QOS").unwrap();
+    classes
+        .set_class(Traffic::DestinationPort("udp".to_string(), 5060), Dscp::EF)
+        .unwrap();
+    classes
+        .set_class(Traffic::Mark(Mark::new(0x10)), Dscp::class("AF21").unwrap())
+        .unwrap();
+    assert_eq!(
+        backend.inputs().last().unwrap().as_deref(),
+        Some(
+            "*mangle\n:QOS - [0:0]\n-F QOS\n\
+             -A QOS -p udp --dport 5060 -j DSCP --set-dscp 0x2e\n\
+             -A QOS -p udp --dport 5060 -j RETURN\n\
+             -A QOS -m mark --mark 0x10 -j DSCP --set-dscp 0x12\n\
+             -A QOS -m mark --mark 0x10 -j RETURN\n\
+             COMMIT\n"
+        )
+    );
+
+    let calls = backend.calls().len();
+    classes
+        .set_class(Traffic::Mark(Mark::new(0x10)), Dscp(18))
+        .unwrap();
+    assert_eq!(backend.calls().len(), calls);
+    assert!(classes
+        .set_class(Traffic::Cgroup("app".to_string()), Dscp(64))
+        .is_err());
+}
+
+#[test]
+fn test_ipv6_extensions() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::icmp::IcmpType;
+    use iptables::ipv6::{Fragment, Ipv6Header, Npt};
+    use iptables::ttl::TtlMatch;
+
+    let rule = RuleBuilder::new()
+        .rt_type(0)
+        .rt_segments_left(1, 3)
+        .fragment(Fragment::First)
+        .ipv6_headers(&[Ipv6Header::Routing, Ipv6Header::Fragment], true)
+        .target(Target::Drop);
+    assert_eq!(
+        rule.build().unwrap(),
+        "-m rt --rt-type 0 -m rt --rt-segsleft 1:3 -m frag --fragfirst \
+         -m ipv6header --header route,frag --soft -j DROP"
+    );
+    assert_eq!(rule.is_ipv6().unwrap(), Some(true));
+
+    let npt = RuleBuilder::new().target(Target::Snpt(Npt::new("fd00:1::/64", "2001:db8:1::/64")));
+    assert_eq!(
+        npt.build().unwrap(),
+        "-j SNPT --src-pfx fd00:1::/64 --dst-pfx 2001:db8:1::/64"
+    );
+    assert!(RuleBuilder::new()
+        .target(Target::Dnpt(Npt::new("fd00:1::/64", "2001:db8::/48")))
+        .build()
+        .is_err());
+
+    assert_eq!(
+        RuleBuilder::new().ttl(TtlMatch::Eq(1)).is_ipv6().unwrap(),
+        Some(false)
+    );
+    assert_eq!(RuleBuilder::new().dport(22).is_ipv6().unwrap(), None);
+    let mixed = RuleBuilder::new()
+        .icmp_type(IcmpType::EchoRequest)
+        .fragment(Fragment::Last);
+    assert!(mixed.is_ipv6().is_err());
+
+    let ipv4 = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    let ipv6 = iptables::IPTables::with_features("ip6tables", iptables::Features::default());
+    assert!(rule.check_family(&ipv6).is_ok());
+    assert_eq!(
+        rule.check_family(&ipv4).unwrap_err().to_string(),
+        "match 'rt' can only be used with ip6tables"
+    );
+    assert_eq!(
+        npt.check_family(&ipv4).unwrap_err().to_string(),
+        "target 'SNPT' can only be used with ip6tables"
+    );
+    assert!(RuleBuilder::new().dport(22).check_family(&ipv4).is_ok());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_ipv6_nat() {
+    use iptables::builder::RuleBuilder;
+    use iptables::nat::nat_address;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+
+    let address: IpAddr = "fd00::2".parse().unwrap();
+    assert_eq!(nat_address(address, Some(8080)), "[fd00::2]:8080");
+    assert_eq!(nat_address("10.0.0.2".parse().unwrap(), None), "10.0.0.2");
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("ip6tables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let rule = RuleBuilder::new().protocol("tcp").dport(80);
+
+    assert!(ipt
+        .dnat(rule.clone(), "10.0.0.2".parse().unwrap(), None)
+        .is_err());
+    assert!(backend.calls().is_empty());
+
+    // The module is missing and can't be loaded
+    backend.push_output(0, "ip6tables v1.8.7 (legacy)\n", "");
+    backend.push_output(1, "", "");
+    let error = ipt.dnat(rule.clone(), address, Some(8080)).unwrap_err();
+    assert!(error.to_string().contains("modprobe ip6table_nat"));
+
+    backend.push_output(0, "ip6tables v1.8.7 (nf_tables)\n", "");
+    backend.push_output(0, "", "");
+    backend.push_output(1, "", "");
+    assert_eq!(
+        ipt.dnat(rule, address, Some(8080)).unwrap(),
+        "-p tcp --dport 80 -j DNAT --to-destination [fd00::2]:8080"
+    );
+    let calls = backend.calls();
+    assert_eq!(calls[calls.len() - 3][..3], ["modprobe", "-n", "-q"]);
+    assert_eq!(calls[calls.len() - 3][3], "nft_chain_nat");
+    assert_eq!(
+        calls[calls.len() - 1][..5],
+        ["ip6tables", "-t", "nat", "-A", "PREROUTING"]
+    );
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_firewall() {
+    use iptables::builder::{RuleBuilder, Target};
+    use iptables::firewall::Firewall;
+    use std::net::IpAddr;
+    use std::sync::Arc;
+
+    let handle = |cmd| {
+        let backend = Arc::new(iptables::backend::MockBackend::new());
+        let mut ipt = iptables::IPTables::with_features(cmd, iptables::Features::default());
+        ipt.set_backend(backend.clone());
+        (ipt, backend)
+    };
+    let (ipv4, backend4) = handle("iptables");
+    let (ipv6, backend6) = handle("ip6tables");
+    assert!(Firewall::from_handles(ipv4.clone(), ipv4.clone()).is_err());
+    let firewall = Firewall::from_handles(ipv4, ipv6).unwrap();
+
+    let address: IpAddr = "2001:db8::1".parse().unwrap();
+    let rule = RuleBuilder::new()
+        .source_ip(address, Some(64))
+        .target(Target::Accept);
+    firewall.append("filter", "INPUT", &rule).unwrap();
+    assert!(backend4.calls().is_empty());
+    assert_eq!(
+        backend6.calls()[0][3..],
+        ["-A", "INPUT", "-s", "2001:db8::1/64", "-j", "ACCEPT"]
+    );
+
+    let rule = RuleBuilder::new().source("10.0.0.0/8").target(Target::Drop);
+    firewall.append("filter", "INPUT", &rule).unwrap();
+    assert_eq!(backend4.calls().len(), 1);
+    assert_eq!(backend6.calls().len(), 1);
+
+    let rule = RuleBuilder::new()
+        .protocol("tcp")
+        .dport(22)
+        .target(Target::Accept);
+    firewall.append("filter", "INPUT", &rule).unwrap();
+    assert_eq!(backend4.calls().len(), 2);
+    assert_eq!(backend6.calls().len(), 2);
+
+    let mixed = RuleBuilder::new()
+        .source("10.0.0.1")
+        .destination("2001:db8::2")
+        .target(Target::Accept);
+    let error = firewall.append("filter", "INPUT", &mixed).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "the rule mixes address 10.0.0.1 of iptables with address 2001:db8::2 of ip6tables"
+    );
+    assert_eq!(backend4.calls().len(), 2);
+    assert_eq!(backend6.calls().len(), 2);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_template() {
+    use iptables::restore::RestoreOptions;
+    use iptables::template::Template;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    let template = Template::new(
+        "*filter\n-A INPUT -i ${WAN_IF} -s ${ADMIN_NET} -j ACCEPT\n-A FORWARD -i ${WAN_IF} -j DROP\nCOMMIT\n",
+    )
+    .unwrap();
+    assert_eq!(template.variables(), ["WAN_IF", "ADMIN_NET"]);
+    assert!(Template::new("-i ${WAN_IF").is_err());
+    assert!(Template::new("-i ${WAN-IF}").is_err());
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    let mut values = HashMap::new();
+    values.insert("WAN_IF".to_string(), "eth0".to_string());
+    let error = ipt
+        .apply_template(&template, &values, RestoreOptions::default())
+        .unwrap_err();
+    assert_eq!(error.to_string(), "unbound variables: ADMIN_NET");
+    values.insert("ADMIN_NET".to_string(), "10.0.0.0/8 -j DROP".to_string());
+    assert!(template.render(&values).is_err());
+    assert!(backend.calls().is_empty());
+
+    values.insert("ADMIN_NET".to_string(), "10.0.0.0/8".to_string());
+    ipt.apply_template(&template, &values, RestoreOptions::default())
+        .unwrap();
+    assert_eq!(
+        backend.inputs().last().unwrap().as_deref(),
+        Some("*filter\n-A INPUT -i eth0 -s 10.0.0.0/8 -j ACCEPT\n-A FORWARD -i eth0 -j DROP\nCOMMIT\n")
+    );
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_compose() {
+    use iptables::compose::Composer;
+    use iptables::restore::RestoreOptions;
+    use std::sync::Arc;
+
+    let base = "*filter\n:INPUT DROP [0:0]\n:FORWARD DROP [0:0]\n\
+                -A INPUT -m conntrack --ctstate ESTABLISHED -j ACCEPT\n\
+                -A INPUT -p tcp --dport 22 -j ACCEPT\nCOMMIT\n";
+    let host = "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -p tcp --dport 22 -j DROP\nCOMMIT\n";
+    let app = "*filter\n:APP - [0:0]\n-A INPUT -j APP\n-A APP -p tcp --dport 8080 -j ACCEPT\n\
+               -A INPUT -p tcp  --dport 22 -j ACCEPT\nCOMMIT\n";
+
+    let mut composer = Composer::new();
+    composer.add_save("base", 0, base);
+    composer.add_save("host", 0, host);
+    let conflicts = composer.conflicts();
+    assert_eq!(conflicts.len(), 2);
+    assert_eq!(
+        conflicts[0].to_string(),
+        "base and host set different policies (DROP, ACCEPT) in filter/INPUT"
+    );
+    assert_eq!(
+        conflicts[1].to_string(),
+        "base and host set different targets (-j ACCEPT, -j DROP) to '-p tcp --dport 22' in filter/INPUT"
+    );
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    assert!(ipt
+        .apply_composed(&composer, RestoreOptions::default())
+        .is_err());
+    assert!(backend.calls().is_empty());
+
+    let mut composer = Composer::new();
+    composer.add_save("base", 0, base);
+    composer.add_save("host", 10, host);
+    composer.add_save("app", 5, app);
+    assert!(composer.conflicts().is_empty());
+    ipt.apply_composed(&composer, RestoreOptions::default())
+        .unwrap();
+    assert_eq!(
+        backend.inputs().last().unwrap().as_deref(),
+        Some(
+            "*filter\n:INPUT ACCEPT [0:0]\n:APP - [0:0]\n:FORWARD DROP [0:0]\n\
+             -A INPUT -p tcp --dport 22 -j DROP\n\
+             -A INPUT -j APP\n\
+             -A APP -p tcp --dport 8080 -j ACCEPT\n\
+             -A INPUT -p tcp  --dport 22 -j ACCEPT\n\
+             -A INPUT -m conntrack --ctstate ESTABLISHED -j ACCEPT\n\
+             COMMIT\n"
+        )
+    );
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_validate() {
+    use iptables::validate::{IssueKind, PlannedRule};
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    backend.push_output(
+        0,
+        "-P INPUT ACCEPT\n-P FORWARD ACCEPT\n-P OUTPUT ACCEPT\n-N APP\n-A INPUT -j APP\n",
+        "",
+    );
+    // modprobe fails for the module of the hashlimit match
+    backend.push_output(1, "", "");
+    let rules = [
+        PlannedRule::append("filter", "APP", "-p tcp --dport 22 -j ACCEPT"),
+        PlannedRule::append("filter", "APP", "-p tcp  --dport 22 -j ACCEPT"),
+        PlannedRule::insert("filter", "INPUT", "-j DROP", 4),
+        PlannedRule::append("filter", "MISSING", "-j ACCEPT"),
+        PlannedRule::append("filter", "INPUT", "-j MISSING"),
+        PlannedRule::append("filter", "INPUT", "-s 2001:db8::1 -j ACCEPT"),
+        PlannedRule::append(
+            "filter",
+            "INPUT",
+            "-m hashlimit --hashlimit-above 10/s -j DROP",
+        ),
+        PlannedRule::append("filter", "INPUT", "-s \"10.0.0.1 -j ACCEPT"),
+        PlannedRule::insert("filter", "INPUT", "-j APP", 1),
+    ];
+    let issues = ipt.validate(&rules).unwrap();
+    let kinds = issues
+        .iter()
+        .map(|issue| (issue.index, issue.kind))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        kinds,
+        vec![
+            (1, IssueKind::Duplicate),
+            (2, IssueKind::PositionOutOfRange),
+            (3, IssueKind::UnknownChain),
+            (4, IssueKind::UnknownTarget),
+            (5, IssueKind::WrongFamily),
+            (6, IssueKind::MissingModule),
+            (7, IssueKind::InvalidRule),
+            (8, IssueKind::Duplicate),
+        ]
+    );
+    assert_eq!(
+        issues[4].message,
+        "address 2001:db8::1 can't be used with iptables"
+    );
+    assert_eq!(
+        issues[5].message,
+        "match 'hashlimit' requires kernel module xt_hashlimit"
+    );
+    // Only the listing of the filter table and modprobe were run
+    assert_eq!(backend.calls().len(), 2);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_trace() {
+    use iptables::builder::RuleBuilder;
+    use iptables::trace::{parse_trace_line, parse_trace_lines, TraceKind};
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    assert!(ipt.enable_trace(&RuleBuilder::new()).is_err());
+    assert!(ipt
+        .enable_trace(&RuleBuilder::new().source("2001:db8::1"))
+        .is_err());
+    assert!(backend.calls().is_empty());
+
+    // The rule does not exist yet
+    backend.push_output(1, "", "");
+    let selector = RuleBuilder::new()
+        .in_interface("eth0")
+        .protocol("tcp")
+        .dport(22);
+    ipt.enable_trace(&selector).unwrap();
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(
+        calls[1][..6],
+        ["iptables", "-t", "raw", "-I", "PREROUTING", "1"]
+    );
+    assert_eq!(
+        calls[1][6..].join(" "),
+        "-i eth0 -p tcp --dport 22 -m comment --comment ipt-rs:trace -j TRACE"
+    );
+
+    backend.push_output(
+        0,
+        "-P PREROUTING ACCEPT\n\
+         -A PREROUTING -i eth0 -p tcp -m tcp --dport 22 -m comment --comment ipt-rs:trace -j TRACE\n\
+         -A PREROUTING -m comment --comment ipt-rs:trace -j CT --notrack\n",
+        "",
+    );
+    backend.push_output(0, "", "");
+    backend.push_output(0, "-P OUTPUT ACCEPT\n", "");
+    assert_eq!(ipt.disable_trace().unwrap(), 1);
+    assert_eq!(
+        backend.calls()[3][..5],
+        ["iptables", "-t", "raw", "-D", "PREROUTING"]
+    );
+
+    let log = "[ 1234.5678] TRACE: filter:INPUT:rule:3 IN=eth0 OUT= SRC=10.0.0.1 \
+               DST=10.0.0.2 PROTO=TCP SPT=40000 DPT=22 SYN URGP=0\n\
+               [ 1234.5679] other: IN=eth0\n\
+               [ 1234.5680] TRACE: filter:SSH:return:4 IN=eth0 OUT= SRC=10.0.0.1\n";
+    let lines = parse_trace_lines(log.as_bytes())
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(lines.len(), 2);
+    assert_eq!(
+        (lines[0].table.as_str(), lines[0].chain.as_str()),
+        ("filter", "INPUT")
+    );
+    assert_eq!((lines[0].kind, lines[0].position), (TraceKind::Rule, 3));
+    assert_eq!(lines[0].field("DPT"), Some("22"));
+    assert_eq!(lines[0].field("SYN"), Some(""));
+    assert_eq!(lines[1].kind, TraceKind::Return);
+    assert!(parse_trace_line("TRACE: filter:INPUT:unknown:1 IN=eth0").is_none());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_provenance() {
+    use iptables::drift::Snapshot;
+    use iptables::provenance::{strip_provenance, with_provenance, Provenance};
+    use std::sync::Arc;
+
+    let provenance = Provenance {
+        source: "/etc/fw/app rules.conf".to_string(),
+        key: Some("ssh;admin=1".to_string()),
+        created: 1700000000,
+    };
+    assert_eq!(
+        provenance.comment().unwrap(),
+        "ipt-rs-from:src=/etc/fw/app rules.conf;key=ssh%3Badmin%3D1;at=1700000000"
+    );
+    let rule = with_provenance("-p tcp --dport 22 -j ACCEPT", &provenance).unwrap();
+    assert_eq!(Provenance::of_rule(&rule), Some(provenance.clone()));
+    assert_eq!(strip_provenance(&rule), "-p tcp --dport 22 -j ACCEPT");
+    assert!(Provenance::new(&"x".repeat(300), None).comment().is_err());
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    ipt.append_with_provenance(
+        "filter",
+        "INPUT",
+        "-p tcp --dport 22 -j ACCEPT",
+        &provenance,
+    )
+    .unwrap();
+    assert_eq!(
+        backend.calls()[0][5..9],
+        [
+            "-m",
+            "comment",
+            "--comment",
+            "ipt-rs-from:src=/etc/fw/app rules.conf;key=ssh%3Badmin%3D1;at=1700000000"
+        ]
+    );
+
+    let listed = format!("-P INPUT DROP\n-A INPUT {}\n-A INPUT -j DROP\n", rule);
+    backend.push_output(0, &listed, "");
+    let rules = ipt.list_provenance("filter", "INPUT").unwrap();
+    assert_eq!(rules.len(), 2);
+    assert_eq!(rules[0].provenance, Some(provenance.clone()));
+    assert_eq!(rules[1].rule, "-j DROP");
+    assert_eq!(rules[1].provenance, None);
+
+    let baseline = Snapshot::parse(&format!(
+        "*filter\n:INPUT DROP [0:0]\n{}COMMIT\n",
+        &listed[14..]
+    ));
+    backend.push_output(
+        0,
+        "*filter\n:INPUT DROP [0:0]\n-A INPUT -j DROP\nCOMMIT\n",
+        "",
+    );
+    let report = ipt.check_drift(&baseline).unwrap();
+    assert_eq!(
+        report.provenances(),
+        vec![("filter", format!("-A INPUT {}", rule).as_str(), provenance)]
+    );
+}
+
+#[test]
+#[cfg(feature = "json")]
+fn test_state_store() {
+    use iptables::state::StateStore;
+
+    let path = std::env::temp_dir().join(format!("ipt-state-{}.json", std::process::id()));
+    let store = StateStore::new(&path);
+    assert_eq!(store.load().unwrap(), Default::default());
+
+    store
+        .update(|state| {
+            assert!(state.add_chain("filter", "MYAPP-INPUT"));
+            assert!(!state.add_chain("filter", "MYAPP-INPUT"));
+            state.add_tag("myapp");
+            state.set_lease("nflog-group", "3", "ids")?;
+            let mut marks = state.mark_allocator();
+            marks.allocate("vpn", 8)?;
+            state.set_marks(&marks);
+            Ok(())
+        })
+        .unwrap();
+
+    // A failed update writes nothing
+    let error = store.update(|state| {
+        state.remove_tag("myapp");
+        state.set_lease("nflog-group", "3", "audit")
+    });
+    assert_eq!(
+        error.unwrap_err().to_string(),
+        "nflog-group 3 is leased to ids"
+    );
+
+    let state = StateStore::new(&path).load().unwrap();
+    assert!(state.has_chain("filter", "MYAPP-INPUT"));
+    assert_eq!(state.tags, vec!["myapp"]);
+    assert_eq!(state.lease("nflog-group", "ids"), Some("3"));
+    let mut marks = state.mark_allocator();
+    assert_eq!(marks.allocate("vpn", 8).unwrap().mask, 0xff);
+    assert_eq!(marks.allocate("qos", 4).unwrap().mask, 0xf00);
+
+    std::fs::write(&path, r#"{"version": 2, "chains": []}"#).unwrap();
+    assert!(store.load().is_err());
+    std::fs::remove_file(&path).unwrap();
+    let _ = std::fs::remove_file(path.with_extension("json.lock"));
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_adopt() {
+    use iptables::identity::identity_of;
+    use iptables::provenance::Provenance;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    assert!(ipt.adopt("filter", "INPUT", "").is_err());
+
+    let managed = "-m comment --comment ipt-rs:0f8fad5b-d9cb-469f-a165-70867728950e -j DROP";
+    backend.push_output(
+        0,
+        &format!(
+            "-P INPUT DROP\n-A INPUT -p tcp -m tcp --dport 22 -j ACCEPT\n-A INPUT {}\n",
+            managed
+        ),
+        "",
+    );
+    let adopted = ipt.adopt("filter", "INPUT", "firewall.sh").unwrap();
+    assert_eq!(adopted.len(), 1);
+    let (id, rule) = &adopted[0];
+    assert_eq!(identity_of(rule), Some(id.as_str()));
+    assert_eq!(Provenance::of_rule(rule).unwrap().source, "firewall.sh");
+    assert!(rule.ends_with("-p tcp -m tcp --dport 22 -j ACCEPT"));
+    assert_eq!(
+        backend.inputs().last().unwrap().as_deref(),
+        Some(
+            format!(
+                "*filter\n:INPUT - [0:0]\n-F INPUT\n-A INPUT {}\n-A INPUT {}\nCOMMIT\n",
+                rule, managed
+            )
+            .as_str()
+        )
+    );
+
+    // Adopting again changes nothing
+    backend.push_output(0, &format!("-P INPUT DROP\n-A INPUT {}\n", rule), "");
+    let calls = backend.calls().len();
+    assert!(ipt
+        .adopt("filter", "INPUT", "firewall.sh")
+        .unwrap()
+        .is_empty());
+    assert_eq!(backend.calls().len(), calls + 1);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_insert_sorted() {
+    use iptables::priority::priority_of;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let position = |backend: &iptables::backend::MockBackend| {
+        let calls = backend.calls();
+        let insert = calls.last().unwrap();
+        (insert[3].clone(), insert[5].clone())
+    };
+
+    backend.push_output(0, "-P INPUT ACCEPT\n-A INPUT -j DOCKER\n", "");
+    assert_eq!(
+        ipt.insert_sorted("filter", "INPUT", "-j ACCEPT", 50)
+            .unwrap(),
+        2
+    );
+    assert_eq!(position(&backend), ("-I".to_string(), "2".to_string()));
+
+    let listed = "-P INPUT ACCEPT\n-A INPUT -j DOCKER\n\
+                  -A INPUT -m comment --comment ipt-rs-prio:10 -j SSH\n\
+                  -A INPUT -j OTHER\n\
+                  -A INPUT -m comment --comment ipt-rs-prio:50 -j ACCEPT\n\
+                  -A INPUT -j LAST\n";
+    backend.push_output(0, listed, "");
+    assert_eq!(
+        ipt.insert_sorted("filter", "INPUT", "-j WEB", 20).unwrap(),
+        4
+    );
+    backend.push_output(0, listed, "");
+    assert_eq!(
+        ipt.insert_sorted("filter", "INPUT", "-j WEB", 50).unwrap(),
+        5
+    );
+    backend.push_output(0, listed, "");
+    assert_eq!(
+        ipt.insert_sorted("filter", "INPUT", "-j WEB", 5).unwrap(),
+        2
+    );
+    assert_eq!(
+        backend.calls().last().unwrap()[6..],
+        ["-m", "comment", "--comment", "ipt-rs-prio:5", "-j", "WEB"]
+    );
+    assert_eq!(
+        priority_of("-m comment --comment ipt-rs-prio:5 -j WEB"),
+        Some(5)
+    );
+    assert_eq!(priority_of("-m comment --comment other -j WEB"), None);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_orchestrate() {
+    use iptables::backend::MockBackend;
+    use iptables::orchestrate::{HostOutcome, Orchestrator};
+    use iptables::parse::parse_save;
+    use std::sync::Arc;
+
+    let desired = "*filter\n:INPUT DROP [0:0]\n-A INPUT -p tcp --dport 22 -j ACCEPT\nCOMMIT\n";
+    let old = "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -p tcp --dport 22 -j ACCEPT\nCOMMIT\n";
+    let host = |orchestrator: &mut Orchestrator, name: &str, outputs: &[(i32, &str)]| {
+        let backend = Arc::new(MockBackend::new());
+        for (code, stdout) in outputs {
+            backend.push_output(*code, stdout, "");
+        }
+        let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+        ipt.set_backend(backend.clone());
+        orchestrator.add_host(name, ipt);
+        backend
+    };
+    let outcomes = |orchestrator: &Orchestrator| {
+        orchestrator
+            .apply(&parse_save(desired))
+            .into_iter()
+            .map(|result| (result.host, result.outcome))
+            .collect::<Vec<_>>()
+    };
+
+    let mut orchestrator = Orchestrator::new();
+    orchestrator.set_parallelism(2);
+    host(&mut orchestrator, "a", &[(0, desired)]);
+    let b = host(&mut orchestrator, "b", &[(0, old)]);
+    assert_eq!(
+        outcomes(&orchestrator),
+        vec![
+            ("a".to_string(), HostOutcome::Unchanged),
+            ("b".to_string(), HostOutcome::Applied)
+        ]
+    );
+    assert_eq!(b.inputs().last().unwrap().as_deref(), Some(desired));
+
+    let mut orchestrator = Orchestrator::new();
+    orchestrator.set_all_or_nothing(true);
+    let a = host(&mut orchestrator, "a", &[(0, old)]);
+    host(&mut orchestrator, "b", &[(0, old), (1, "")]);
+    let c = host(&mut orchestrator, "c", &[]);
+    let outcomes = outcomes(&orchestrator);
+    assert_eq!(outcomes[0].1, HostOutcome::RolledBack);
+    assert!(matches!(outcomes[1].1, HostOutcome::Failed(_)));
+    assert_eq!(outcomes[2].1, HostOutcome::Skipped);
+    assert_eq!(a.inputs().last().unwrap().as_deref(), Some(old));
+    assert!(c.calls().is_empty());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_progress() {
+    use iptables::orchestrate::Orchestrator;
+    use iptables::progress::Progress;
+    use std::sync::{Arc, Mutex};
+
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let recorder = reports.clone();
+    let progress: Progress = Arc::new(move |done, total, operation: &str| {
+        recorder
+            .lock()
+            .unwrap()
+            .push((done, total, operation.to_string()));
+    });
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    ipt.set_progress(Some(progress.clone()));
+
+    let data = "*filter\n-A INPUT -j ACCEPT\n-A INPUT -j DROP\nCOMMIT\n";
+    ipt.restore(data, iptables::restore::RestoreOptions::default())
+        .unwrap();
+    assert_eq!(
+        reports.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![
+            (0, 2, "restore 2 rules".to_string()),
+            (2, 2, "restore 2 rules".to_string())
+        ]
+    );
+
+    backend.push_output(
+        0,
+        "*filter\n:INPUT ACCEPT [0:0]\n:MYAPP-OLD - [0:0]\n-A INPUT -j MYAPP-OLD\nCOMMIT\n",
+        "",
+    );
+    ipt.gc_managed("MYAPP-", &[]).unwrap();
+    assert_eq!(
+        reports.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![
+            (1, 3, "delete rule 1 of filter/INPUT".to_string()),
+            (2, 3, "flush chain filter/MYAPP-OLD".to_string()),
+            (3, 3, "delete chain filter/MYAPP-OLD".to_string())
+        ]
+    );
+
+    ipt.set_progress(None);
+    let mut orchestrator = Orchestrator::new();
+    orchestrator.set_progress(Some(progress));
+    orchestrator.add_host("a", ipt);
+    orchestrator.apply(&[]);
+    assert_eq!(
+        reports.lock().unwrap().drain(..).collect::<Vec<_>>(),
+        vec![(1, 1, "reconcile a".to_string())]
+    );
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_find_identified() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let features = iptables::Features {
+        check: true,
+        ..Default::default()
+    };
+    let mut ipt = iptables::IPTables::with_features("iptables", features);
+    ipt.set_backend(backend.clone());
+    ipt.set_auto_identity(true);
+
+    // The identity is derived from the rule
+    assert!(ipt
+        .append("filter", "INPUT", "-s 1.2.3.4 -j ACCEPT")
+        .is_ok());
+    assert!(ipt
+        .append("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT")
+        .is_ok());
+    assert!(ipt
+        .append("filter", "INPUT", "-s 1.2.3.4 -j ACCEPT")
+        .is_ok());
+    let calls = backend.calls();
+    let first = calls[0][8].clone();
+    let second = calls[1][8].clone();
+    assert!(first.starts_with("ipt-rs:"));
+    assert_ne!(first, second);
+    assert_eq!(calls[2][8], first);
+    let adopted = "ipt-rs:0f8fad5b-d9cb-469f-a165-70867728950e";
+    let listing = format!(
+        "-P INPUT ACCEPT\n\
+         -A INPUT -m comment --comment \"{}\" -s 1.2.3.4/32 -j ACCEPT\n\
+         -A INPUT -m comment --comment \"{}\" -p tcp -m tcp --dport 22 -j ACCEPT\n\
+         -A INPUT -m comment --comment \"{}\" -j DROP\n",
+        first, second, adopted
+    );
+
+    // A bare host address is listed with its prefix length, and is found in a single listing
+    backend.push_output(0, &listing, "");
+    assert!(ipt
+        .exists("filter", "INPUT", "-s 1.2.3.4 -j ACCEPT")
+        .unwrap());
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 4);
+    assert_eq!(calls[3][1..], ["-t", "filter", "-S", "INPUT"]);
+
+    // The implicit tcp match is listed
+    backend.push_output(0, &listing, "");
+    ipt.delete("filter", "INPUT", "-p tcp --dport 22 -j ACCEPT")
+        .unwrap();
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 6);
+    assert_eq!(
+        calls[5][..],
+        [
+            "iptables",
+            "-t",
+            "filter",
+            "-D",
+            "INPUT",
+            "-m",
+            "comment",
+            "--comment",
+            &second,
+            "-p",
+            "tcp",
+            "-m",
+            "tcp",
+            "--dport",
+            "22",
+            "-j",
+            "ACCEPT"
+        ]
+    );
+
+    // An adopted rule is found by its text
+    backend.push_output(0, &listing, "");
+    assert!(ipt.exists("filter", "INPUT", "-j DROP").unwrap());
+    assert_eq!(backend.calls().len(), 7);
+
+    backend.push_output(0, &listing, "");
+    backend.push_output(1, "", "iptables: Bad rule");
+    assert!(!ipt
+        .exists("filter", "INPUT", "-s 5.6.7.8 -j ACCEPT")
+        .unwrap());
+    assert_eq!(backend.calls().len(), 9);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_migrate_to_nft() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let features = iptables::Features {
+        restore_wait: true,
+        ..Default::default()
+    };
+    let mut ipt = iptables::IPTables::with_features("iptables", features);
+    ipt.set_backend(backend.clone());
+    let ruleset = "*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j ACCEPT\nCOMMIT\n";
+    backend.push_output(0, ruleset, "");
+    backend.push_output(2, "", "iptables-nft-restore: line 3 failed");
+
+    let error = ipt.migrate_to_nft(false).unwrap_err();
+    assert!(error.to_string().contains("line 3 failed"), "{}", error);
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(calls[1][..], ["iptables-nft-restore", "--wait"]);
+    assert_eq!(backend.inputs()[1].as_deref(), Some(ruleset));
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_system_backend_timeout() {
+    use iptables::backend::{Backend, SystemBackend};
+    use iptables::error::IPTError;
+    use std::ffi::OsString;
+    use std::time::{Duration, Instant};
+
+    let start = Instant::now();
+    let timeout = Duration::from_millis(200);
+    let error = SystemBackend
+        .run("sleep", &[OsString::from("10")], None, Some(timeout))
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<IPTError>(),
+        Some(IPTError::Timeout(t)) if *t == timeout
+    ));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    // The child never reads its input, which does not fit in the pipe
+    let start = Instant::now();
+    let input = "-A INPUT -j ACCEPT\n".repeat(100_000);
+    let error = SystemBackend
+        .run(
+            "sleep",
+            &[OsString::from("10")],
+            Some(&input),
+            Some(timeout),
+        )
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<IPTError>(),
+        Some(IPTError::Timeout(_))
+    ));
+    assert!(start.elapsed() < Duration::from_secs(5));
+
+    let output = SystemBackend
+        .run("cat", &[], Some(&input), Some(Duration::from_secs(5)))
+        .unwrap();
+    assert_eq!(output.stdout, input.as_bytes());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_retry_lock() {
+    use iptables::retry::RetryPolicy;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let locked =
+        "Another app is currently holding the xtables lock. Perhaps you want to use the -w option?";
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let features = iptables::Features {
+        wait: true,
+        ..Default::default()
+    };
+    let mut ipt = iptables::IPTables::with_features("iptables", features);
+    ipt.set_backend(backend.clone());
+    ipt.set_retry_policy(RetryPolicy {
+        max_attempts: 3,
+        backoff: Duration::ZERO,
+        jitter: Duration::ZERO,
+    });
+
+    // A lock error is retried until the command succeeds
+    backend.push_output(4, "", locked);
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_ok());
+    assert_eq!(backend.calls().len(), 2);
+
+    // The error of the last attempt is returned
+    for _ in 0..3 {
+        backend.push_output(4, "", locked);
+    }
+    let error = ipt.append("filter", "INPUT", "-j ACCEPT").unwrap_err();
+    assert!(
+        error.to_string().contains("holding the xtables lock"),
+        "{}",
+        error
+    );
+    assert_eq!(backend.calls().len(), 5);
+
+    // Other errors are not retried
+    backend.push_output(1, "", "iptables: Bad rule");
+    backend.push_output(4, "", locked);
+    let error = ipt.append("filter", "INPUT", "-j ACCEPT").unwrap_err();
+    assert!(error.to_string().contains("Bad rule"), "{}", error);
+    assert_eq!(backend.calls().len(), 6);
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_execute_output() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let features = iptables::Features {
+        wait: true,
+        ..Default::default()
+    };
+    let mut ipt = iptables::IPTables::with_features("iptables", features);
+    ipt.set_backend(backend.clone());
+
+    backend.push_output(0, "-P INPUT ACCEPT\n\n-A INPUT -j ACCEPT\n", "a warning");
+    let output = ipt.execute("filter", "-S INPUT").unwrap();
+    assert_eq!(
+        output.argv,
+        vec!["iptables", "-t", "filter", "-S", "INPUT", "--wait"]
+    );
+    assert_eq!(output.code(), 0);
+    assert!(output.success());
+    assert_eq!(output.stdout, "-P INPUT ACCEPT\n\n-A INPUT -j ACCEPT\n");
+    assert_eq!(output.stderr, "a warning");
+    assert_eq!(
+        output.lines(),
+        vec!["-P INPUT ACCEPT", "-A INPUT -j ACCEPT"]
+    );
+
+    // A failed exit status is not an error
+    backend.push_output(1, "", "iptables: No chain/target/match by that name.");
+    let output = ipt.execute("filter", "-S MISSING").unwrap();
+    assert_eq!(output.code(), 1);
+    assert!(!output.success());
+    assert_eq!(
+        output.stderr,
+        "iptables: No chain/target/match by that name."
+    );
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_verify_writes() {
+    use iptables::error::IPTError;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let features = iptables::Features {
+        check: true,
+        ..Default::default()
+    };
+    let mut ipt = iptables::IPTables::with_features("iptables", features);
+    ipt.set_backend(backend.clone());
+    ipt.set_verify_writes(true);
+
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_ok());
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 2);
+    assert_eq!(
+        calls[1],
+        vec!["iptables", "-t", "filter", "-C", "INPUT", "-j", "ACCEPT"]
+    );
+
+    // The rule is missing after the append
+    backend.push_output(0, "", "");
+    backend.push_output(1, "", "iptables: Bad rule");
+    let error = ipt.append("filter", "INPUT", "-j ACCEPT").unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<IPTError>(),
+        Some(IPTError::VerificationFailed(operation)) if operation == "append"
+    ));
+
+    // The chain still has as many rules after the delete
+    let listing = "-P INPUT ACCEPT\n-A INPUT -j ACCEPT\n";
+    backend.push_output(0, listing, "");
+    backend.push_output(0, "", "");
+    backend.push_output(0, listing, "");
+    let error = ipt.delete("filter", "INPUT", "-j ACCEPT").unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<IPTError>(),
+        Some(IPTError::VerificationFailed(operation)) if operation == "delete"
+    ));
+
+    ipt.set_verify_writes(false);
+    let calls = backend.calls().len();
+    assert!(ipt.append("filter", "INPUT", "-j ACCEPT").is_ok());
+    assert_eq!(backend.calls().len(), calls + 1);
+}
+
+#[test]
+#[cfg(all(feature = "test-backend", feature = "lock", target_os = "linux"))]
+fn test_mock_restore_wait() {
+    use iptables::restore::RestoreOptions;
+    use iptables::retry::RetryPolicy;
+    use std::fs::File;
+    use std::sync::Arc;
+
+    let data = "*filter\n-A INPUT -j ACCEPT\nCOMMIT\n";
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let features = iptables::Features {
+        restore_wait: true,
+        ..Default::default()
+    };
+    let mut ipt = iptables::IPTables::with_features("iptables", features);
+    ipt.set_backend(backend.clone());
+    ipt.restore(data, RestoreOptions::default()).unwrap();
+    let calls = backend.calls();
+    assert_eq!(calls[0][0], "iptables-restore");
+    assert_eq!(calls[0].last().unwrap(), "--wait");
+
+    // Without --wait, the xtables lock is taken manually
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    ipt.set_retry_policy(RetryPolicy::never());
+    let lock = File::create("/run/xtables.lock").unwrap();
+    lock.lock().unwrap();
+    let error = ipt.restore(data, RestoreOptions::default()).unwrap_err();
+    assert!(error.to_string().contains("xtables lock"), "{}", error);
+    assert!(backend.calls().is_empty());
+
+    lock.unlock().unwrap();
+    ipt.restore(data, RestoreOptions::default()).unwrap();
+    let calls = backend.calls();
+    assert_eq!(calls.len(), 1);
+    assert!(!calls[0].contains(&"--wait".to_string()));
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_rule_handle() {
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    // The handles give an identity to the rules
+    assert!(ipt.append_handle("filter", "INPUT", "-j ACCEPT").is_err());
+    let append = &backend.calls()[0];
+    assert!(append.iter().any(|arg| arg.starts_with("ipt-rs:")));
+
+    // A rule appended concurrently after the rule does not shift the handle
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    ipt.set_backend(backend.clone());
+    let tagged = "-m comment --comment \"ipt-rs:7c9e6679-7425-40de-944b-e07fc1f90ae7\" -j ACCEPT";
+    backend.push_output(0, "", "");
+    backend.push_output(
+        0,
+        &format!("-P INPUT ACCEPT\n-A INPUT {}\n-A INPUT -j DROP\n", tagged),
+        "",
+    );
+    let handle = ipt.append_handle("filter", "INPUT", tagged).unwrap();
+    assert_eq!(handle.position(), 1);
+    assert_eq!(handle.rule(), tagged);
+
+    // Rules sharing an ordinary comment are told apart by their identity
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    ipt.set_backend(backend.clone());
+    let id = "0f8fad5b-d9cb-469f-a165-70867728950e";
+    let identified = format!(
+        "-m comment --comment \"ipt-rs:{}\" -m comment --comment web -j ACCEPT",
+        id
+    );
+    let listing = format!(
+        "-P INPUT ACCEPT\n-A INPUT -m comment --comment web -j ACCEPT\n-A INPUT {}\n",
+        identified
+    );
+    backend.push_output(0, "", "");
+    backend.push_output(0, &listing, "");
+    let mut handle = ipt
+        .insert_handle("filter", "INPUT", &identified, 2)
+        .unwrap();
+    assert_eq!(handle.tag(), Some(id));
+
+    backend.push_output(0, &listing, "");
+    backend.push_output(0, "", "");
+    backend.push_output(0, &listing.replace("-j ACCEPT\n", "-j DROP\n"), "");
+    handle.replace("-m comment --comment web -j DROP").unwrap();
+    let calls = backend.calls();
+    let replace = &calls[calls.len() - 2];
+    assert_eq!(replace[3..6], ["-R", "INPUT", "2"]);
+    assert!(replace.contains(&format!("ipt-rs:{}", id)));
+    assert_eq!(handle.tag(), Some(id));
+    assert_eq!(handle.position(), 2);
+
+    // A copy of the rule makes the handle ambiguous
+    backend.push_output(0, &format!("{}-A INPUT {}\n", listing, identified), "");
+    assert!(handle.exists().is_err());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_apply_and_probe_rollback() {
+    use iptables::error::IPTError;
+    use std::sync::Arc;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+    let snapshot = "*filter\n:INPUT ACCEPT [0:0]\nCOMMIT\n";
+
+    backend.push_output(0, snapshot, "");
+    backend.push_output(1, "", "iptables-restore: line 2 failed");
+    let error = ipt.apply_and_probe(|_| Ok(()), || false).unwrap_err();
+    match error.downcast_ref::<IPTError>() {
+        Some(IPTError::RollbackFailed { cause, source }) => {
+            assert_eq!(cause, "the probe failed");
+            assert!(source.to_string().contains("line 2 failed"), "{}", source);
+        }
+        _ => panic!("unexpected error: {}", error),
+    }
+
+    backend.push_output(0, snapshot, "");
+    backend.push_output(1, "", "iptables-restore: line 2 failed");
+    let error = ipt
+        .apply_and_probe(|_| Err("the changes failed".into()), || true)
+        .unwrap_err();
+    assert!(matches!(
+        error.downcast_ref::<IPTError>(),
+        Some(IPTError::RollbackFailed { cause, .. }) if cause == "the changes failed"
+    ));
+    assert!(error.source().is_some());
+}
+
+#[test]
+#[cfg(feature = "test-backend")]
+fn test_mock_canary_cleanup() {
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    let mut ipt = iptables::IPTables::with_features("iptables", iptables::Features::default());
+    ipt.set_backend(backend.clone());
+
+    ipt.canary_apply("filter", "INPUT", &["-p tcp -j DROP"], Duration::ZERO)
+        .unwrap();
+    let operations = |calls: Vec<Vec<String>>| {
+        calls
+            .into_iter()
+            .map(|call| call[3].clone())
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(
+        operations(backend.calls()),
+        ["-N", "-A", "-I", "-L", "-D", "-F", "-X"]
+    );
+
+    // The shadow chain is kept while a jump to it remains
+    let backend = Arc::new(iptables::backend::MockBackend::new());
+    ipt.set_backend(backend.clone());
+    for _ in 0..4 {
+        backend.push_output(0, "", "");
+    }
+    backend.push_output(
+        1,
+        "",
+        "iptables: Bad rule (does a matching rule exist in that chain?).",
+    );
+    assert!(ipt
+        .canary_apply("filter", "INPUT", &["-p tcp -j DROP"], Duration::ZERO)
+        .is_err());
+    assert_eq!(operations(backend.calls()), ["-N", "-A", "-I", "-L", "-D"]);
+}
@@ -0,0 +1,377 @@
+extern crate iptables;
+
+#[test]
+fn test_parse_save() {
+    let tables = iptables::parse::parse_save(
+        "# Generated by iptables-save v1.8.7\n\
+         *nat\n\
+         :PREROUTING ACCEPT [0:0]\n\
+         :MYCHAIN - [0:0]\n\
+         -A PREROUTING -j MYCHAIN\n\
+         COMMIT\n\
+         *filter\n\
+         :INPUT DROP [0:0]\n\
+         COMMIT\n",
+    );
+
+    assert_eq!(tables.len(), 2);
+    assert_eq!(tables[0].name, "nat");
+    assert_eq!(tables[0].chains.len(), 2);
+    assert_eq!(tables[0].chains[0].policy.as_deref(), Some("ACCEPT"));
+    assert_eq!(tables[0].chains[1].policy, None);
+    assert_eq!(tables[0].rules, vec!["-A PREROUTING -j MYCHAIN"]);
+    assert_eq!(tables[1].name, "filter");
+    assert!(tables[1].rules.is_empty());
+}
+
+#[test]
+fn test_diff_tables() {
+    use iptables::parse::{diff_tables, normalize_rule, parse_save};
+
+    assert_eq!(
+        normalize_rule("-m comment  --comment 'a comment' -j ACCEPT"),
+        "-m comment --comment \"a comment\" -j ACCEPT"
+    );
+
+    let old = parse_save("*filter\n:INPUT ACCEPT [0:0]\n-A INPUT -j DROP\nCOMMIT\n");
+    let new = parse_save(
+        "*filter\n:INPUT ACCEPT [0:0]\n:NEW - [0:0]\n-A INPUT -j ACCEPT\nCOMMIT\n*nat\nCOMMIT\n",
+    );
+    let diff = diff_tables(&old, &new);
+    assert_eq!(diff.len(), 1);
+    assert_eq!(diff[0].table, "filter");
+    assert_eq!(diff[0].added_chains, vec!["NEW"]);
+    assert_eq!(diff[0].added_rules, vec!["-A INPUT -j ACCEPT"]);
+    assert_eq!(diff[0].removed_rules, vec!["-A INPUT -j DROP"]);
+    assert!(diff_tables(&old, &old).is_empty());
+}
+
+#[test]
+fn test_quote_comment() {
+    use iptables::parse::{normalize_rule, quote_comment, split_rule};
+
+    assert_eq!(quote_comment("plain-comment_1"), "plain-comment_1");
+    assert_eq!(quote_comment(""), "\"\"");
+    assert_eq!(quote_comment("héllo wörld ✓"), "\"héllo wörld ✓\"");
+    assert_eq!(
+        quote_comment(r#"it's "quoted" \o/"#),
+        r#""it\'s \"quoted\" \\o/""#
+    );
+
+    for comment in ["héllo wörld ✓", r#"it's "quoted" \o/"#, "日本語"] {
+        let rule = format!("-m comment --comment {} -j ACCEPT", quote_comment(comment));
+        assert_eq!(
+            split_rule(&rule),
+            vec!["-m", "comment", "--comment", comment, "-j", "ACCEPT"]
+        );
+        assert_eq!(normalize_rule(&rule), rule);
+    }
+}
+
+#[test]
+fn test_parse_rules() {
+    let rules = iptables::parse::parse_rules(
+        "-P INPUT ACCEPT\n-N CUSTOM\n-A INPUT -s 10.0.0.0/8 -j CUSTOM\n\
+         -A CUSTOM -j DROP\n-A INPUT -j ACCEPT\n",
+    );
+    assert_eq!(rules.len(), 3);
+    assert_eq!(rules[0].chain, "INPUT");
+    assert_eq!(rules[0].position, 1);
+    assert_eq!(rules[0].spec, "-s 10.0.0.0/8 -j CUSTOM");
+    assert_eq!(rules[1].chain, "CUSTOM");
+    assert_eq!(rules[1].position, 1);
+    assert_eq!(rules[2].position, 2);
+}
+
+#[test]
+fn test_chain_graph() {
+    use iptables::graph::ChainGraph;
+    use iptables::parse::parse_rules;
+
+    let chains = vec!["INPUT".to_string(), "A".to_string(), "B".to_string()];
+    let graph = ChainGraph::new(
+        chains.clone(),
+        &parse_rules("-A INPUT -j A\n-A A -j LOG\n-A A -g B\n-A B -j ACCEPT\n"),
+    );
+    assert_eq!(graph.jumps.len(), 2);
+    assert_eq!(graph.jumps_to("B").next().unwrap().position, 2);
+    assert!(graph.find_cycle().is_none());
+
+    let graph = ChainGraph::new(
+        chains,
+        &parse_rules("-A INPUT -j A\n-A A -j B\n-A B -j A\n"),
+    );
+    assert_eq!(graph.find_cycle().unwrap(), vec!["A", "B", "A"]);
+}
+
+#[test]
+fn test_parse_nft_chains() {
+    let output = "table ip filter {\n\
+                  \tchain INPUT {\n\
+                  \t\ttype filter hook input priority filter; policy accept;\n\
+                  \t}\n\
+                  \tchain CUSTOM {\n\
+                  \t}\n\
+                  }\n\
+                  table ip nat {\n\
+                  \tchain PREROUTING {\n\
+                  \t}\n\
+                  }\n";
+    assert_eq!(
+        iptables::parse::parse_nft_chains(output, "filter"),
+        vec!["INPUT", "CUSTOM"]
+    );
+}
+
+#[test]
+fn test_rule_fields() {
+    use iptables::parse::Rule;
+
+    let rule = Rule::parse(
+        "-A INPUT -s 10.0.0.0/8 -d 192.168.1.1/32 -i eth0 -p tcp -m multiport \
+         --dports 80,443,8000:8080 -m comment --comment \"web traffic\" -j ACCEPT",
+    );
+    assert_eq!(rule.chain, "INPUT");
+    assert_eq!(rule.source().as_deref(), Some("10.0.0.0/8"));
+    assert_eq!(rule.destination().as_deref(), Some("192.168.1.1/32"));
+    assert_eq!(rule.in_interface().as_deref(), Some("eth0"));
+    assert_eq!(rule.protocol().as_deref(), Some("tcp"));
+    assert_eq!(rule.dports(), vec!["80", "443", "8000:8080"]);
+    assert_eq!(rule.comment().as_deref(), Some("web traffic"));
+    assert_eq!(rule.target().as_deref(), Some("ACCEPT"));
+
+    let rule = Rule::parse("-p udp --dport 53 -g DNS");
+    assert_eq!(rule.chain, "");
+    assert_eq!(rule.source(), None);
+    assert_eq!(rule.dports(), vec!["53"]);
+    assert_eq!(rule.target().as_deref(), Some("DNS"));
+}
+
+#[test]
+fn test_rule_fingerprint() {
+    use iptables::parse::{fingerprint_rules, Rule};
+
+    let rule = Rule::parse("-A INPUT -j ACCEPT");
+    assert_eq!(rule.fingerprint(), 0x0e6b_d192_6cd8_e192);
+    assert_eq!(
+        Rule::parse("  -j   'ACCEPT'").fingerprint(),
+        rule.fingerprint()
+    );
+    assert_ne!(Rule::parse("-j DROP").fingerprint(), rule.fingerprint());
+
+    let rules = [
+        "-P INPUT ACCEPT",
+        "-A INPUT -s 10.0.0.1/32 -j DROP",
+        "-A INPUT -j ACCEPT",
+    ];
+    let reordered = [rules[0], rules[2], rules[1]];
+    assert_eq!(
+        fingerprint_rules(&rules),
+        fingerprint_rules(&[
+            "-P INPUT ACCEPT",
+            "-A INPUT -s 10.0.0.1/32  -j DROP",
+            "-A INPUT -j \"ACCEPT\""
+        ])
+    );
+    assert_ne!(fingerprint_rules(&rules), fingerprint_rules(&reordered));
+}
+
+#[test]
+fn test_parse_counters() {
+    use iptables::parse::{parse_counter, parse_counters, Counters};
+
+    assert_eq!(parse_counter("1520"), Some(1520));
+    assert_eq!(parse_counter("1520K"), Some(1_520_000));
+    assert_eq!(parse_counter("3G"), Some(3_000_000_000));
+    assert_eq!(parse_counter("18446744073709551615"), Some(u64::MAX));
+    assert_eq!(parse_counter("prot"), None);
+
+    let counters = parse_counters(
+        "Chain INPUT (policy ACCEPT 0 packets, 0 bytes)\n\
+         \x20   pkts      bytes target     prot opt in     out     source               destination\n\
+         \x20     12     1520 ACCEPT     all  --  lo     *       0.0.0.0/0            0.0.0.0/0\n\
+         \x20     3M      12G DROP       all  --  *      *       10.0.0.0/8           0.0.0.0/0\n",
+    );
+    assert_eq!(
+        counters,
+        vec![
+            Counters {
+                packets: 12,
+                bytes: 1520
+            },
+            Counters {
+                packets: 3_000_000,
+                bytes: 12_000_000_000
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_error_types() {
+    use iptables::error::{IPTError, IptablesError};
+    use std::error::Error;
+    use std::time::Duration;
+
+    fn assert_send_sync<E: Error + Send + Sync + 'static>(_: &E) {}
+
+    let timeout = IPTError::Timeout(Duration::from_secs(1));
+    assert_send_sync(&timeout);
+    assert!(timeout.source().is_none());
+    assert_eq!(timeout.to_string(), "command timed out after 1s");
+
+    let error = IptablesError {
+        code: 1,
+        msg: "No chain/target/match by that name.".to_string(),
+    };
+    assert_send_sync(&error);
+    assert!(error.source().is_none());
+    assert_eq!(
+        error.to_string(),
+        "code: 1, msg: No chain/target/match by that name."
+    );
+}
+
+#[test]
+fn test_parse_chain_references() {
+    use iptables::parse::parse_chain_references;
+
+    let output =
+        "Chain MYCHAIN (2 references)\ntarget     prot opt source               destination\n";
+    assert_eq!(parse_chain_references(output, "MYCHAIN"), Some(2));
+    assert_eq!(parse_chain_references(output, "MY"), None);
+    assert_eq!(
+        parse_chain_references("Chain ORPHAN (0 references)\n", "ORPHAN"),
+        Some(0)
+    );
+    assert_eq!(
+        parse_chain_references("Chain INPUT (policy DROP)\n", "INPUT"),
+        Some(0)
+    );
+}
+
+#[test]
+fn test_parse_table_stats() {
+    use iptables::parse::{parse_table_stats, Counters};
+
+    let output = "Chain INPUT (policy DROP 12 packets, 1520 bytes)\n\
+        \x20   pkts      bytes target     prot opt in     out     source               destination\n\
+        \x20     10      840 MYAPP      all  --  *      *       0.0.0.0/0            0.0.0.0/0\n\
+        \x20      2      120 ACCEPT     icmp --  *      *       0.0.0.0/0            0.0.0.0/0\n\
+        \n\
+        Chain MYAPP (1 references)\n\
+        \x20   pkts      bytes target     prot opt in     out     source               destination\n";
+    let stats = parse_table_stats(output);
+    assert_eq!(stats.len(), 2);
+    assert_eq!(stats[0].name, "INPUT");
+    assert_eq!(stats[0].policy.as_deref(), Some("DROP"));
+    assert_eq!(
+        stats[0].policy_counters,
+        Some(Counters {
+            packets: 12,
+            bytes: 1520
+        })
+    );
+    assert_eq!(stats[0].rules, 2);
+    assert_eq!(
+        stats[0].counters,
+        Counters {
+            packets: 12,
+            bytes: 960
+        }
+    );
+    assert_eq!(stats[1].name, "MYAPP");
+    assert_eq!(stats[1].policy, None);
+    assert_eq!(stats[1].references, 1);
+    assert_eq!(stats[1].rules, 0);
+}
+
+#[test]
+fn test_chain_subtree() {
+    use iptables::graph::ChainGraph;
+    use iptables::parse::parse_rules;
+
+    let chains = ["INPUT", "ROOT", "A", "B", "SHARED", "C", "OTHER"]
+        .iter()
+        .map(|chain| chain.to_string())
+        .collect();
+    let graph = ChainGraph::new(
+        chains,
+        &parse_rules(
+            "-A INPUT -j ROOT\n-A INPUT -j OTHER\n-A ROOT -j A\n-A ROOT -j SHARED\n\
+             -A A -g B\n-A B -j A\n-A OTHER -j SHARED\n-A SHARED -j C\n",
+        ),
+    );
+    assert_eq!(graph.subtree("ROOT"), vec!["ROOT", "A", "B"]);
+    assert_eq!(graph.subtree("SHARED"), vec!["SHARED", "C"]);
+}
+
+#[test]
+fn test_trace_packet() {
+    use iptables::parse::parse_save;
+    use iptables::simulate::{trace_packet, Packet, Verdict};
+
+    let tables = parse_save(
+        "*filter\n:INPUT DROP [0:0]\n:FORWARD DROP [0:0]\n:SSH - [0:0]\n:WEB - [0:0]\n\
+         -A INPUT -m conntrack --ctstate RELATED,ESTABLISHED -j ACCEPT\n\
+         -A INPUT -i lo -j ACCEPT\n\
+         -A INPUT -p tcp -m tcp --dport 22 -j SSH\n\
+         -A INPUT -p tcp -m multiport --dports 80,443,8000:8080 -g WEB\n\
+         -A INPUT -m recent --name scan --rcheck -j DROP\n\
+         -A SSH ! -s 10.0.0.0/8 -j LOG --log-prefix \"ssh: \"\n\
+         -A SSH -s 10.0.0.0/8 -j ACCEPT\n\
+         -A WEB -i eth+ -m mark --mark 0x1/0xff -j REJECT\n\
+         COMMIT\n",
+    );
+    let filter = &tables[0];
+    let packet = |dport: u16, source: &str| Packet {
+        protocol: Some("tcp".to_string()),
+        source: Some(source.parse().unwrap()),
+        dport: Some(dport),
+        in_interface: Some("eth0".to_string()),
+        state: Some("NEW".to_string()),
+        ..Default::default()
+    };
+
+    let trace = trace_packet(filter, "INPUT", &packet(22, "10.1.2.3")).unwrap();
+    assert_eq!(trace.verdict, Verdict::Accept);
+    let matched = trace
+        .matched
+        .iter()
+        .map(|rule| (rule.chain.as_str(), rule.position))
+        .collect::<Vec<_>>();
+    assert_eq!(matched, vec![("INPUT", 3), ("SSH", 2)]);
+
+    // Logged then returned from SSH, the unsupported rule is skipped and the policy applies
+    let trace = trace_packet(filter, "INPUT", &packet(22, "192.0.2.1")).unwrap();
+    assert_eq!(trace.verdict, Verdict::Drop);
+    assert_eq!(trace.matched.len(), 2);
+    assert_eq!(trace.unsupported.len(), 1);
+    assert_eq!(trace.unsupported[0].position, 5);
+
+    // Returning from the chain of a goto returns from INPUT too
+    let trace = trace_packet(filter, "INPUT", &packet(8080, "192.0.2.1")).unwrap();
+    assert_eq!(trace.verdict, Verdict::Drop);
+    let trace = trace_packet(
+        filter,
+        "INPUT",
+        &Packet {
+            mark: 0x101,
+            ..packet(443, "192.0.2.1")
+        },
+    )
+    .unwrap();
+    assert_eq!(trace.verdict, Verdict::Reject);
+
+    let established = Packet {
+        state: Some("ESTABLISHED".to_string()),
+        ..packet(9000, "2001:db8::1")
+    };
+    let trace = trace_packet(filter, "INPUT", &established).unwrap();
+    assert_eq!(trace.verdict, Verdict::Accept);
+    assert_eq!(
+        trace_packet(filter, "WEB", &established).unwrap().verdict,
+        Verdict::Return
+    );
+    assert!(trace_packet(filter, "OUTPUT", &established).is_err());
+}